@@ -0,0 +1,494 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::db::{unix_millis_from_instant, unpoisoned, DbData};
+use crate::logging::{log, Level};
+use crate::parser::{ParserError, RespValue};
+use crate::rdb;
+use crate::server::Server;
+use crate::session::Session;
+
+/// The append-only file: every write that changed something, in RESP, in the
+/// order it ran.
+///
+/// A logged write executes while holding the file's lock, so the log order is
+/// the order the writes took effect, and BGREWRITEAOF can take its copy of the
+/// dataset at an exact position in the log. Writes are logged as they
+/// propagate (see `propagation`), so relative expiries are logged as absolute
+/// deadlines and a replay expires keys when the original did.
+#[derive(Debug, Default)]
+pub struct AppendOnly {
+    log: Mutex<Option<Log>>,
+    /// Whether the log is open, checked before taking its lock.
+    enabled: AtomicBool,
+    pub rewrite_in_progress: AtomicBool,
+}
+
+#[derive(Debug)]
+struct Log {
+    file: File,
+    path: PathBuf,
+    /// Database the last logged write ran against, so SELECT is only written
+    /// when it changes. `None` forces the next write to start with one.
+    db_index: Option<usize>,
+    /// Writes made while a rewrite is copying the dataset, added to the new
+    /// file before it replaces the old one.
+    rewrite_buffer: Option<Vec<u8>>,
+}
+
+impl Log {
+    fn append(&mut self, db_index: usize, frames: &[RespValue]) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        if self.db_index != Some(db_index) {
+            bytes.extend(command(&[b"SELECT", db_index.to_string().as_bytes()]).encode());
+            self.db_index = Some(db_index);
+        }
+        for frame in frames {
+            bytes.extend(frame.encode());
+        }
+
+        if let Some(buffer) = &mut self.rewrite_buffer {
+            buffer.extend_from_slice(&bytes);
+        }
+        self.file.write_all(&bytes)
+    }
+}
+
+impl AppendOnly {
+    /// Starts logging to `path`, appending to whatever is already there.
+    pub fn open(&self, path: &Path) -> io::Result<()> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        *unpoisoned(self.log.lock()) = Some(Log {
+            file,
+            path: path.to_path_buf(),
+            db_index: None,
+            rewrite_buffer: None,
+        });
+        self.enabled.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Stops logging. The file is left as it is, ending with the last write.
+    pub fn close(&self) {
+        *unpoisoned(self.log.lock()) = None;
+        self.enabled.store(false, Ordering::Release);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+
+    /// Runs a write, which returns its reply and the commands that repeat
+    /// it, and logs those. `None` logs nothing, for a write that failed or
+    /// changed nothing.
+    pub fn run_logged(
+        &self,
+        log_level: Level,
+        db_index: usize,
+        execute: impl FnOnce() -> (RespValue, Option<Vec<RespValue>>),
+    ) -> RespValue {
+        let mut log = unpoisoned(self.log.lock());
+        let (reply, frames) = execute();
+        if let (Some(log), Some(frames)) = (log.as_mut(), frames) {
+            // The write already happened; all we can do about a lost log entry is say so.
+            if let Err(e) = log.append(db_index, &frames) {
+                log!(
+                    log_level,
+                    Level::Warning,
+                    "Failed to append to {}: {e}",
+                    log.path.display()
+                );
+            }
+        }
+        reply
+    }
+}
+
+/// Starts logging to `path` while the server runs, as `CONFIG SET appendonly
+/// yes` does. Writes made before now aren't in the file, so it is rewritten
+/// from the current dataset first, which replaces whatever it held.
+pub fn enable(server: &Server, path: &Path) -> io::Result<()> {
+    if server.aof.rewrite_in_progress.load(Ordering::Acquire) {
+        return Err(io::Error::other(
+            "Background AOF rewrite already in progress",
+        ));
+    }
+    server.aof.open(path)?;
+    background_rewrite(server, path);
+    Ok(())
+}
+
+/// Starts rewriting the log as the shortest commands that rebuild the current
+/// dataset. Returns false if a rewrite is already running.
+pub fn background_rewrite(server: &Server, path: &Path) -> bool {
+    let aof = server.aof.clone();
+    if aof.rewrite_in_progress.swap(true, Ordering::AcqRel) {
+        return false;
+    }
+
+    // Logged writes hold the lock while they run, so none is half done here.
+    let snapshot = {
+        let mut log = unpoisoned(aof.log.lock());
+        if let Some(log) = log.as_mut() {
+            log.rewrite_buffer = Some(Vec::new());
+            log.db_index = None;
+        }
+        rdb::snapshot(server)
+    };
+
+    let path = path.to_path_buf();
+    let log_level = server.log_level;
+    std::thread::spawn(move || {
+        if let Err(e) = rewrite(&aof, &path, &snapshot) {
+            log!(
+                log_level,
+                Level::Warning,
+                "Rewriting {} failed: {e}",
+                path.display()
+            );
+            if let Some(log) = unpoisoned(aof.log.lock()).as_mut() {
+                log.rewrite_buffer = None;
+            }
+        }
+        aof.rewrite_in_progress.store(false, Ordering::Release);
+    });
+    true
+}
+
+fn rewrite(aof: &AppendOnly, path: &Path, snapshot: &rdb::Snapshot) -> io::Result<()> {
+    let mut temp_name = path.as_os_str().to_owned();
+    temp_name.push(".tmp");
+    let temp_path = PathBuf::from(temp_name);
+    let mut temp = File::create(&temp_path)?;
+    temp.write_all(&dataset_commands(snapshot))?;
+
+    // Only the buffered tail is written under the lock, so writers wait for
+    // that and the rename rather than for the whole dataset.
+    let mut log = unpoisoned(aof.log.lock());
+    if let Some(buffer) = log.as_mut().and_then(|log| log.rewrite_buffer.take()) {
+        temp.write_all(&buffer)?;
+    }
+    temp.sync_all()?;
+    fs::rename(&temp_path, path)?;
+    if let Some(log) = log.as_mut() {
+        log.file = OpenOptions::new().append(true).open(path)?;
+    }
+    Ok(())
+}
+
+/// One command per key, plus a PEXPIREAT for keys with a deadline.
+fn dataset_commands(snapshot: &rdb::Snapshot) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (index, entries) in snapshot.iter().enumerate() {
+        if entries.is_empty() {
+            continue;
+        }
+        out.extend(command(&[b"SELECT", index.to_string().as_bytes()]).encode());
+
+        for (key, db_value) in entries {
+            // Outlives `args`, which borrows the formatted scores.
+            let scores: Vec<(String, &Vec<u8>)>;
+            let mut args: Vec<&[u8]> = Vec::new();
+            match &db_value.data {
+                DbData::String(value) => args.extend([&b"SET"[..], key, value]),
+                DbData::List(list) => {
+                    args.extend([&b"RPUSH"[..], key]);
+                    args.extend(list.iter().map(Vec::as_slice));
+                }
+                DbData::Hash(hash) => {
+                    args.extend([&b"HSET"[..], key]);
+                    for (field, value) in hash {
+                        args.extend([field.as_slice(), value]);
+                    }
+                }
+                DbData::Set(set) => {
+                    args.extend([&b"SADD"[..], key]);
+                    args.extend(set.iter().map(Vec::as_slice));
+                }
+                DbData::SortedSet(set) => {
+                    args.extend([&b"ZADD"[..], key]);
+                    scores = set
+                        .iter()
+                        .map(|(member, score)| (score.to_string(), member))
+                        .collect();
+                    for (score, member) in &scores {
+                        args.extend([score.as_bytes(), member]);
+                    }
+                }
+            }
+            out.extend(command(&args).encode());
+
+            if let Some(expires_at) = db_value.expires_at {
+                let deadline = unix_millis_from_instant(expires_at).to_string();
+                out.extend(command(&[b"PEXPIREAT", key, deadline.as_bytes()]).encode());
+            }
+        }
+    }
+    out
+}
+
+fn command(args: &[&[u8]]) -> RespValue {
+    RespValue::Array(
+        args.iter()
+            .map(|arg| RespValue::BulkString(arg.to_vec()))
+            .collect(),
+    )
+}
+
+/// Runs every command in the log at `path` against `server`, which should not
+/// be logging yet. A missing file replays nothing. A partial command at the
+/// end, left by a crash mid-write, is ignored, but one that can't be parsed
+/// is an error, and nothing is replayed. Returns how many commands ran,
+/// or an error at the first one that fails, which only ever succeeded before
+/// if the log is damaged.
+pub fn replay(server: &Server, path: &Path) -> io::Result<usize> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    let (frames, consumed, stopped_at) = RespValue::parse_many(&bytes);
+    match stopped_at {
+        None => {}
+        Some(ParserError::InvalidFormat(e)) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unreadable command at byte {consumed}: {e}"),
+            ));
+        }
+        Some(ParserError::Incomplete) => log!(
+            server.log_level,
+            Level::Warning,
+            "Ignoring {} bytes of truncated command at the end of {}",
+            bytes.len() - consumed,
+            path.display()
+        ),
+    }
+
+    let mut session = Session::replaying();
+    let count = frames.len();
+    for (index, frame) in frames.into_iter().enumerate() {
+        for reply in session.execute(frame, server) {
+            if let RespValue::Error(e) = reply {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("command {} failed: {e}", index + 1),
+                ));
+            }
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands;
+    use crate::test_support::{send, temp_path};
+
+    #[test]
+    fn test_replay_restores_writes() {
+        let path = temp_path("replay", "aof");
+        let _ = fs::remove_file(&path);
+        let server = Server::default();
+        server.aof.open(&path).unwrap();
+        let mut session = Session::default();
+        send(&mut session, &server, &[b"SET", b"counter", b"1"]);
+        send(&mut session, &server, &[b"INCRBY", b"counter", b"4"]);
+        send(&mut session, &server, &[b"RPUSH", b"list", b"a", b"b"]);
+        send(&mut session, &server, &[b"LPOP", b"list"]);
+        send(&mut session, &server, &[b"GET", b"counter"]);
+        // Failed writes aren't logged, so replay doesn't trip over them.
+        send(&mut session, &server, &[b"INCR", b"list"]);
+        send(&mut session, &server, &[b"SELECT", b"2"]);
+        send(&mut session, &server, &[b"SET", b"elsewhere", b"x"]);
+
+        let restored = Server::default();
+        assert_eq!(replay(&restored, &path).unwrap(), 7);
+        fs::remove_file(&path).unwrap();
+
+        let mut session = Session::default();
+        assert_eq!(
+            send(&mut session, &restored, &[b"GET", b"counter"]),
+            b"$1\r\n5\r\n"
+        );
+        assert_eq!(
+            send(&mut session, &restored, &[b"LRANGE", b"list", b"0", b"-1"]),
+            b"*1\r\n$1\r\nb\r\n"
+        );
+        assert_eq!(
+            send(&mut session, &restored, &[b"EXISTS", b"elsewhere"]),
+            b":0\r\n"
+        );
+        send(&mut session, &restored, &[b"SELECT", b"2"]);
+        assert_eq!(
+            send(&mut session, &restored, &[b"EXISTS", b"elsewhere"]),
+            b":1\r\n"
+        );
+    }
+
+    #[test]
+    fn test_replay_runs_under_requirepass() {
+        let path = temp_path("replay-requirepass", "aof");
+        let log: Vec<u8> = [
+            command(&[b"SET", b"key", b"v"]),
+            command(&[b"RPUSH", b"list", b"a"]),
+        ]
+        .iter()
+        .flat_map(RespValue::encode)
+        .collect();
+        fs::write(&path, log).unwrap();
+
+        let restored = Server::default();
+        restored.config.write().unwrap().requirepass = "secret".to_string();
+        assert_eq!(replay(&restored, &path).unwrap(), 2);
+        fs::remove_file(&path).unwrap();
+        assert!(restored.stats.command_stats().is_empty());
+        assert_eq!(commands::run(&restored, &[b"GET", b"key"]), b"$1\r\nv\r\n");
+    }
+
+    #[test]
+    fn test_replay_fails_on_a_failing_command() {
+        let path = temp_path("replay-failing", "aof");
+        let log: Vec<u8> = [
+            command(&[b"RPUSH", b"list", b"a"]),
+            command(&[b"INCR", b"list"]),
+        ]
+        .iter()
+        .flat_map(RespValue::encode)
+        .collect();
+        fs::write(&path, log).unwrap();
+
+        let error = replay(&Server::default(), &path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().starts_with("command 2 failed: WRONGTYPE"));
+    }
+
+    #[test]
+    fn test_replay_rejects_a_corrupted_command() {
+        let path = temp_path("replay-corrupted", "aof");
+        let mut log = command(&[b"SET", b"key", b"v"]).encode();
+        log.extend(b"*2\r\n$3\r\nGET\r\n$x\r\n");
+        log.extend(command(&[b"SET", b"other", b"v"]).encode());
+        fs::write(&path, log).unwrap();
+
+        let restored = Server::default();
+        let error = replay(&restored, &path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(commands::run(&restored, &[b"DBSIZE"]), b":0\r\n");
+    }
+
+    #[test]
+    fn test_replay_ignores_a_truncated_tail() {
+        let path = temp_path("replay-truncated", "aof");
+        let mut log = command(&[b"SET", b"key", b"v"]).encode();
+        log.extend(b"*3\r\n$3\r\nSET\r\n$5\r\not");
+        fs::write(&path, log).unwrap();
+
+        let restored = Server::default();
+        assert_eq!(replay(&restored, &path).unwrap(), 1);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(commands::run(&restored, &[b"DBSIZE"]), b":1\r\n");
+    }
+
+    #[test]
+    fn test_rewrite_compacts_log() {
+        let path = temp_path("rewrite", "aof");
+        let _ = fs::remove_file(&path);
+        let server = Server::default();
+        server.aof.open(&path).unwrap();
+        let mut session = Session::default();
+        for value in 0..100 {
+            send(
+                &mut session,
+                &server,
+                &[b"SET", b"key", value.to_string().as_bytes()],
+            );
+        }
+        send(&mut session, &server, &[b"PEXPIRE", b"key", b"100000"]);
+
+        assert!(background_rewrite(&server, &path));
+        while server.aof.rewrite_in_progress.load(Ordering::Acquire) {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        // Writes after the rewrite land in the new file.
+        send(&mut session, &server, &[b"SET", b"after", b"1"]);
+
+        let (frames, _, _) = RespValue::parse_many(&fs::read(&path).unwrap());
+        assert_eq!(frames.len(), 5);
+        let restored = Server::default();
+        replay(&restored, &path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut session = Session::default();
+        assert_eq!(
+            send(&mut session, &restored, &[b"GET", b"key"]),
+            b"$2\r\n99\r\n"
+        );
+        assert_eq!(
+            send(&mut session, &restored, &[b"TTL", b"key"]),
+            b":100\r\n"
+        );
+        assert_eq!(
+            send(&mut session, &restored, &[b"GET", b"after"]),
+            b"$1\r\n1\r\n"
+        );
+    }
+
+    #[test]
+    fn test_config_set_appendonly_opens_and_closes_the_log() {
+        let path = temp_path("config-set", "aof");
+        let _ = fs::remove_file(&path);
+        let server = Server::default();
+        {
+            let mut config = server.config.write().unwrap();
+            config.dir = path.parent().unwrap().display().to_string();
+            config.appendfilename = path.file_name().unwrap().to_str().unwrap().to_string();
+        }
+        let mut session = Session::default();
+        send(&mut session, &server, &[b"SET", b"before", b"1"]);
+
+        assert_eq!(
+            send(
+                &mut session,
+                &server,
+                &[b"CONFIG", b"SET", b"appendonly", b"yes"]
+            ),
+            b"+OK\r\n"
+        );
+        assert!(server.aof.is_enabled());
+        while server.aof.rewrite_in_progress.load(Ordering::Acquire) {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        send(&mut session, &server, &[b"SET", b"during", b"2"]);
+        assert_eq!(
+            send(
+                &mut session,
+                &server,
+                &[b"CONFIG", b"SET", b"appendonly", b"no"]
+            ),
+            b"+OK\r\n"
+        );
+        assert!(!server.aof.is_enabled());
+        send(&mut session, &server, &[b"SET", b"after", b"3"]);
+
+        let restored = Server::default();
+        replay(&restored, &path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let mut session = Session::default();
+        assert_eq!(
+            send(
+                &mut session,
+                &restored,
+                &[b"MGET", b"before", b"during", b"after"]
+            ),
+            b"*3\r\n$1\r\n1\r\n$1\r\n2\r\n$-1\r\n"
+        );
+    }
+}
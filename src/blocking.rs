@@ -0,0 +1,106 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::oneshot;
+
+use crate::db::unpoisoned;
+
+/// Connections blocked in BLPOP/BRPOP, queued per key with the longest-waiting
+/// first, as Redis serves them.
+///
+/// A push wakes one waiter per element it added and leaves the popping to the
+/// woken connections, so each element goes to exactly one of them. Waiters
+/// register while holding the shard locks of every key they found empty, and
+/// pushes wake while holding the shard lock of theirs, so no push can land
+/// between a waiter's check and its registration.
+#[derive(Debug, Default)]
+pub struct Waiters {
+    queues: Mutex<HashMap<Vec<u8>, VecDeque<Arc<Wakeup>>>>,
+}
+
+/// A blocked connection's wakeup. It is queued under every key the connection
+/// waits on but fires at most once, sending the key that woke it.
+#[derive(Debug)]
+pub struct Wakeup(Mutex<Option<oneshot::Sender<Vec<u8>>>>);
+
+impl Wakeup {
+    pub fn new() -> (Arc<Self>, oneshot::Receiver<Vec<u8>>) {
+        let (sender, receiver) = oneshot::channel();
+        (Arc::new(Wakeup(Mutex::new(Some(sender)))), receiver)
+    }
+
+    /// False if it already fired, or its connection stopped listening.
+    fn fire(&self, key: &[u8]) -> bool {
+        match unpoisoned(self.0.lock()).take() {
+            Some(sender) => sender.send(key.to_vec()).is_ok(),
+            None => false,
+        }
+    }
+}
+
+impl Waiters {
+    pub fn register(&self, keys: &[Vec<u8>], wakeup: &Arc<Wakeup>) {
+        let mut queues = unpoisoned(self.queues.lock());
+        for key in keys {
+            queues
+                .entry(key.clone())
+                .or_default()
+                .push_back(wakeup.clone());
+        }
+    }
+
+    pub fn unregister(&self, keys: &[Vec<u8>], wakeup: &Arc<Wakeup>) {
+        let mut queues = unpoisoned(self.queues.lock());
+        for key in keys {
+            if let Some(queue) = queues.get_mut(key) {
+                queue.retain(|queued| !Arc::ptr_eq(queued, wakeup));
+                if queue.is_empty() {
+                    queues.remove(key);
+                }
+            }
+        }
+    }
+
+    /// Wakes up to `count` connections waiting on `key`, longest-waiting first.
+    /// Waiters already woken through another key are dropped on the way.
+    pub fn wake(&self, key: &[u8], mut count: usize) {
+        let mut queues = unpoisoned(self.queues.lock());
+        let Some(queue) = queues.get_mut(key) else {
+            return;
+        };
+        while count > 0 {
+            let Some(wakeup) = queue.pop_front() else {
+                break;
+            };
+            if wakeup.fire(key) {
+                count -= 1;
+            }
+        }
+        if queue.is_empty() {
+            queues.remove(key);
+        }
+    }
+
+    /// Exchanges everything queued here with `other`, for SWAPDB: a waiter
+    /// blocks on a database index, not on the keyspace behind it.
+    pub fn swap(&self, other: &Waiters) {
+        if std::ptr::eq(self, other) {
+            return;
+        }
+        let mut queues = unpoisoned(self.queues.lock());
+        std::mem::swap(&mut *queues, &mut *unpoisoned(other.queues.lock()));
+    }
+
+    /// Every key something is waiting on.
+    pub fn keys(&self) -> Vec<Vec<u8>> {
+        unpoisoned(self.queues.lock()).keys().cloned().collect()
+    }
+
+    /// How many wakeups are queued on `key`, counting ones that already fired.
+    #[cfg(test)]
+    pub fn queued(&self, key: &[u8]) -> usize {
+        unpoisoned(self.queues.lock())
+            .get(key)
+            .map_or(0, VecDeque::len)
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use tokio::sync::Notify;
+
+use crate::db::unpoisoned;
+
+/// Every open connection, for CLIENT LIST.
+#[derive(Debug, Default)]
+pub struct Clients {
+    next_id: AtomicU64,
+    /// Keyed by id, so CLIENT LIST shows clients in the order they connected.
+    connected: Mutex<BTreeMap<u64, Client>>,
+}
+
+#[derive(Debug)]
+struct Client {
+    addr: SocketAddr,
+    name: Option<Vec<u8>>,
+    connected_at: Instant,
+    /// Tells the connection's task to hang up.
+    kill: Arc<Notify>,
+}
+
+impl Clients {
+    /// Adds a connection from `addr` under a fresh id. Ids start at 1 and are
+    /// never reused. The entry stays until the returned registration drops.
+    pub fn register(&self, addr: SocketAddr) -> Registration<'_> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let kill = Arc::new(Notify::new());
+        unpoisoned(self.connected.lock()).insert(
+            id,
+            Client {
+                addr,
+                name: None,
+                connected_at: Instant::now(),
+                kill: kill.clone(),
+            },
+        );
+        Registration {
+            clients: self,
+            id,
+            kill,
+        }
+    }
+
+    /// Disconnects every client `target` picks, given its id and address,
+    /// returning how many. They leave the list right away, even though their
+    /// tasks notice a moment later.
+    pub fn kill(&self, target: impl Fn(u64, SocketAddr) -> bool) -> usize {
+        let mut connected = unpoisoned(self.connected.lock());
+        let ids: Vec<u64> = connected
+            .iter()
+            .filter(|(id, client)| target(**id, client.addr))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &ids {
+            // Stored as a permit if the task isn't waiting on it yet.
+            connected.remove(id).unwrap().kill.notify_one();
+        }
+        ids.len()
+    }
+
+    /// Records the name CLIENT SETNAME gave connection `id`, if it is registered.
+    pub fn set_name(&self, id: u64, name: Option<Vec<u8>>) {
+        if let Some(client) = unpoisoned(self.connected.lock()).get_mut(&id) {
+            client.name = name;
+        }
+    }
+
+    pub fn addr(&self, id: u64) -> Option<SocketAddr> {
+        unpoisoned(self.connected.lock())
+            .get(&id)
+            .map(|client| client.addr)
+    }
+
+    /// One line per connection, in the format of `CLIENT LIST`.
+    pub fn list(&self) -> String {
+        unpoisoned(self.connected.lock())
+            .iter()
+            .map(|(id, client)| {
+                format!(
+                    "id={} addr={} name={} age={}\n",
+                    id,
+                    client.addr,
+                    String::from_utf8_lossy(client.name.as_deref().unwrap_or_default()),
+                    client.connected_at.elapsed().as_secs()
+                )
+            })
+            .collect()
+    }
+}
+
+/// A connection's entry in `Clients`. Dropping it unregisters the connection,
+/// so it disappears however the connection's task ends, panics included.
+#[derive(Debug)]
+pub struct Registration<'a> {
+    clients: &'a Clients,
+    id: u64,
+    kill: Arc<Notify>,
+}
+
+impl Registration<'_> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Resolves once CLIENT KILL picks this connection.
+    pub async fn killed(&self) {
+        self.kill.notified().await
+    }
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        unpoisoned(self.clients.connected.lock()).remove(&self.id);
+    }
+}
@@ -0,0 +1,81 @@
+use std::fmt::Debug;
+#[cfg(test)]
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+use crate::db::unpoisoned;
+
+/// Where everything that decides expiry reads the time, so a test can stop it
+/// exactly at a deadline instead of sleeping past one.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Milliseconds since the unix epoch, at the same moment as `now`.
+    fn unix_millis(&self) -> u64;
+
+    /// Maps an absolute unix timestamp onto `now`'s clock. Past timestamps map
+    /// to now, which already counts as expired. `None` if the result overflows.
+    fn instant_from_unix_millis(&self, unix_millis: u64) -> Option<Instant> {
+        let remaining = unix_millis.saturating_sub(self.unix_millis());
+        self.now().checked_add(Duration::from_millis(remaining))
+    }
+
+    /// The inverse of `instant_from_unix_millis`: the unix time `instant` falls at.
+    fn unix_millis_from_instant(&self, instant: Instant) -> u64 {
+        let now = self.now();
+        let unix_now = self.unix_millis();
+        if instant >= now {
+            unix_now.saturating_add(instant.duration_since(now).as_millis() as u64)
+        } else {
+            unix_now.saturating_sub(now.duration_since(instant).as_millis() as u64)
+        }
+    }
+}
+
+/// The clock every server runs on outside of tests.
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn unix_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64
+    }
+}
+
+/// A clock that only moves when told to. Its unix time starts at the system's
+/// and moves with it.
+#[cfg(test)]
+#[derive(Debug)]
+pub struct ManualClock(Mutex<(Instant, u64)>);
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn new(now: Instant) -> Self {
+        ManualClock(Mutex::new((now, SystemClock.unix_millis())))
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let mut time = unpoisoned(self.0.lock());
+        time.0 += by;
+        time.1 += by.as_millis() as u64;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        unpoisoned(self.0.lock()).0
+    }
+
+    fn unix_millis(&self) -> u64 {
+        unpoisoned(self.0.lock()).1
+    }
+}
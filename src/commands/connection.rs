@@ -0,0 +1,179 @@
+use std::str;
+
+use super::{bulk_string, ArgParser, CommandError, CommandExt};
+use crate::db::Database;
+use crate::parser::RespValue;
+use crate::server::Server;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Ping {
+    msg: Option<Vec<u8>>,
+}
+
+impl CommandExt for Ping {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let msg = match parser.iter.next() {
+            Some(RespValue::BulkString(bs)) => Some(bs),
+            Some(_) => {
+                return Err(CommandError::InvalidArgument {
+                    reason: "PING argument must be a bulk string".to_string(),
+                })
+            }
+            None => None,
+        };
+        parser.finish()?;
+        Ok(Ping { msg })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, _db: &Database) -> RespValue {
+        match self.msg {
+            Some(msg) => bulk_string(&msg),
+            None => RespValue::SimpleString("PONG".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Echo {
+    msg: Vec<u8>,
+}
+
+impl CommandExt for Echo {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let msg = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Echo { msg })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, _db: &Database) -> RespValue {
+        bulk_string(&self.msg)
+    }
+}
+
+/// CLIENT subcommands. They act on the calling connection, so the session runs
+/// them rather than `CommandExt::execute`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientSubcommand {
+    SetName(Vec<u8>),
+    GetName,
+    Id,
+    List,
+    Kill(ClientKill),
+    /// Accepted for client libraries that send it; clients are never evicted.
+    NoEvict,
+}
+
+/// Which connections CLIENT KILL closes. Every filter given has to match.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientKill {
+    pub id: Option<u64>,
+    pub addr: Option<String>,
+    /// Whether the calling connection is spared. Yes unless SKIPME says otherwise.
+    pub skip_me: bool,
+    /// The old `CLIENT KILL ip:port` form, which replies OK or an error
+    /// rather than a count.
+    pub legacy: bool,
+}
+
+impl ClientKill {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let mut args = parser.remaining_bulk_strings()?.into_iter();
+        let mut kill = ClientKill {
+            id: None,
+            addr: None,
+            skip_me: true,
+            legacy: false,
+        };
+        match args.len() {
+            0 => return Err(CommandError::WrongArgCount),
+            1 => {
+                let addr = args.next().unwrap();
+                kill.addr = Some(String::from_utf8_lossy(&addr).into_owned());
+                kill.skip_me = false;
+                kill.legacy = true;
+                return Ok(kill);
+            }
+            len if len % 2 != 0 => return Err(CommandError::SyntaxError),
+            _ => {}
+        }
+        while let (Some(filter), Some(value)) = (args.next(), args.next()) {
+            match filter.to_ascii_uppercase().as_slice() {
+                b"ID" => {
+                    let id = str::from_utf8(&value)
+                        .ok()
+                        .and_then(|id| id.parse::<u64>().ok())
+                        .filter(|&id| id > 0)
+                        .ok_or_else(|| CommandError::InvalidArgument {
+                            reason: "client-id should be greater than 0".to_string(),
+                        })?;
+                    kill.id = Some(id);
+                }
+                b"ADDR" => kill.addr = Some(String::from_utf8_lossy(&value).into_owned()),
+                b"SKIPME" => {
+                    kill.skip_me = match value.to_ascii_lowercase().as_slice() {
+                        b"yes" => true,
+                        b"no" => false,
+                        _ => return Err(CommandError::SyntaxError),
+                    }
+                }
+                _ => return Err(CommandError::SyntaxError),
+            }
+        }
+        Ok(kill)
+    }
+}
+
+impl ClientSubcommand {
+    pub(super) fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let subcommand = parser.next_bulk_string()?;
+        let subcommand = match subcommand.to_ascii_uppercase().as_slice() {
+            b"SETNAME" => ClientSubcommand::SetName(parser.next_bulk_string()?),
+            b"GETNAME" => ClientSubcommand::GetName,
+            b"ID" => ClientSubcommand::Id,
+            b"LIST" => ClientSubcommand::List,
+            b"KILL" => return Ok(ClientSubcommand::Kill(ClientKill::parse(parser)?)),
+            b"NO-EVICT" => {
+                let value = parser.next_bulk_string()?;
+                if !value.eq_ignore_ascii_case(b"on") && !value.eq_ignore_ascii_case(b"off") {
+                    return Err(CommandError::SyntaxError);
+                }
+                ClientSubcommand::NoEvict
+            }
+            _ => {
+                return Err(CommandError::UnknownSubcommand(
+                    String::from_utf8_lossy(&subcommand).into_owned(),
+                ))
+            }
+        };
+        parser.finish()?;
+        Ok(subcommand)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Command;
+    use crate::parser::RespValue;
+    use crate::server::Server;
+    #[test]
+    fn test_ping_command() {
+        let server = Server::default();
+        let resp_value = RespValue::Array(vec![RespValue::BulkString(b"PING".to_vec())]);
+        let command = Command::from_resp(resp_value).unwrap();
+        let response = command.execute(&server, &server.db(0));
+        assert_eq!(response, RespValue::SimpleString("PONG".to_string()));
+    }
+
+    #[test]
+    fn test_ping_with_message() {
+        let server = Server::default();
+        let msg = b"hello";
+        let resp_value = RespValue::Array(vec![
+            RespValue::BulkString(b"PING".to_vec()),
+            RespValue::BulkString(msg.to_vec()),
+        ]);
+        let command = Command::from_resp(resp_value).unwrap();
+        let response = command.execute(&server, &server.db(0));
+        assert_eq!(response, RespValue::BulkString(msg.to_vec()));
+    }
+}
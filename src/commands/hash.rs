@@ -0,0 +1,576 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use super::keys::{scan_page, scan_reply, ScanOptions};
+use super::{
+    bulk_array, bulk_string, error_reply, parse_i64, parse_random_count, random_picks, unless_zero,
+    ArgParser, CommandError, CommandExt, ErrorCode, Propagate,
+};
+use crate::clock::Clock;
+use crate::db::{remove_if_empty, Database, DbData, DbValue};
+use crate::parser::RespValue;
+use crate::server::Server;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Hset {
+    key: Vec<u8>,
+    pairs: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl CommandExt for Hset {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let args = parser.remaining_bulk_strings()?;
+        if args.is_empty() || args.len() % 2 != 0 {
+            return Err(CommandError::WrongArgCount);
+        }
+
+        let mut args = args.into_iter();
+        let mut pairs = Vec::new();
+        while let (Some(field), Some(value)) = (args.next(), args.next()) {
+            pairs.push((field, value));
+        }
+        Ok(Hset { key, pairs })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        let mut db_lock = db.lock(&self.key);
+
+        if db_lock.get_live(&self.key).is_none() {
+            db_lock.insert(self.key.clone(), DbValue::new(DbData::Hash(HashMap::new())));
+        }
+        let mut entry = db_lock.get_mut(&self.key);
+        let Some(DbValue {
+            data: DbData::Hash(hash),
+            modified_at,
+            ..
+        }) = entry.as_deref_mut()
+        else {
+            return CommandError::WrongType.to_resp();
+        };
+        *modified_at = Instant::now();
+
+        let mut created = 0;
+        for (field, value) in self.pairs {
+            if hash.insert(field, value).is_none() {
+                created += 1;
+            }
+        }
+        RespValue::Integer(created)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Hget {
+    key: Vec<u8>,
+    field: Vec<u8>,
+}
+
+impl CommandExt for Hget {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let field = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Hget { key, field })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue {
+        db.read_live(&self.key, |db_value| match db_value {
+            Some(DbValue {
+                data: DbData::Hash(hash),
+                ..
+            }) => match hash.get(&self.field) {
+                Some(value) => {
+                    server.stats.record_lookup(true);
+                    bulk_string(value)
+                }
+                None => {
+                    server.stats.record_lookup(false);
+                    RespValue::Null
+                }
+            },
+            Some(_) => CommandError::WrongType.to_resp(),
+            None => {
+                server.stats.record_lookup(false);
+                RespValue::Null
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Hincrby {
+    key: Vec<u8>,
+    field: Vec<u8>,
+    delta: i64,
+}
+
+impl CommandExt for Hincrby {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let field = parser.next_bulk_string()?;
+        let delta = parser.next_integer()?;
+        parser.finish()?;
+        Ok(Hincrby { key, field, delta })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        let mut db_lock = db.lock(&self.key);
+
+        if db_lock.get_live(&self.key).is_none() {
+            db_lock.insert(self.key.clone(), DbValue::new(DbData::Hash(HashMap::new())));
+        }
+        let mut entry = db_lock.get_mut(&self.key);
+        let Some(DbValue {
+            data: DbData::Hash(hash),
+            modified_at,
+            ..
+        }) = entry.as_deref_mut()
+        else {
+            return CommandError::WrongType.to_resp();
+        };
+
+        // A missing field counts as 0.
+        let current = match hash.get(&self.field) {
+            Some(value) => match parse_i64(value) {
+                Some(n) => n,
+                None => return error_reply(ErrorCode::Err, "hash value is not an integer"),
+            },
+            None => 0,
+        };
+        let Some(new_value) = current.checked_add(self.delta) else {
+            return error_reply(ErrorCode::Err, "increment or decrement would overflow");
+        };
+        *modified_at = Instant::now();
+        hash.insert(self.field, new_value.to_string().into_bytes());
+        RespValue::Integer(new_value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Hdel {
+    key: Vec<u8>,
+    fields: Vec<Vec<u8>>,
+}
+
+impl CommandExt for Hdel {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let fields = parser.remaining_bulk_strings()?;
+        if fields.is_empty() {
+            return Err(CommandError::WrongArgCount);
+        }
+        Ok(Hdel { key, fields })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        let mut db_lock = db.lock(&self.key);
+
+        let mut entry = db_lock.get_live(&self.key);
+        let removed = match entry.as_deref_mut() {
+            Some(DbValue {
+                data: DbData::Hash(hash),
+                modified_at,
+                ..
+            }) => {
+                let removed = self
+                    .fields
+                    .iter()
+                    .filter(|field| hash.remove(*field).is_some())
+                    .count();
+                if removed > 0 {
+                    *modified_at = Instant::now();
+                }
+                removed
+            }
+            Some(_) => return CommandError::WrongType.to_resp(),
+            None => 0,
+        };
+        drop(entry);
+        remove_if_empty(&mut db_lock, &self.key);
+        RespValue::Integer(removed as i64)
+    }
+
+    fn propagate(&self, _clock: &dyn Clock) -> Propagate {
+        Box::new(unless_zero)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Hgetall {
+    key: Vec<u8>,
+}
+
+impl CommandExt for Hgetall {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Hgetall { key })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        db.read_live(&self.key, |db_value| match db_value {
+            Some(DbValue {
+                data: DbData::Hash(hash),
+                ..
+            }) => bulk_array(
+                hash.iter()
+                    .flat_map(|(field, value)| [field, value])
+                    .collect::<Vec<_>>(),
+            ),
+            Some(_) => CommandError::WrongType.to_resp(),
+            None => RespValue::Array(Vec::new()),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Hrandfield {
+    key: Vec<u8>,
+    count: Option<i64>,
+    with_values: bool,
+}
+
+impl CommandExt for Hrandfield {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let count = parse_random_count(parser)?;
+        let with_values = match parser.iter.peek() {
+            Some(_) => {
+                if !parser
+                    .next_bulk_string()?
+                    .eq_ignore_ascii_case(b"WITHVALUES")
+                {
+                    return Err(CommandError::SyntaxError);
+                }
+                true
+            }
+            None => false,
+        };
+        parser.finish()?;
+        Ok(Hrandfield {
+            key,
+            count,
+            with_values,
+        })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        db.read_live(&self.key, |db_value| {
+            let pairs = match db_value {
+                Some(DbValue {
+                    data: DbData::Hash(hash),
+                    ..
+                }) => hash.iter().collect(),
+                Some(_) => return CommandError::WrongType.to_resp(),
+                None => Vec::new(),
+            };
+            let Some(count) = self.count else {
+                return match random_picks(pairs, 1).first() {
+                    Some((field, _)) => bulk_string(field),
+                    None => RespValue::Null,
+                };
+            };
+            let picks = random_picks(pairs, count);
+            if self.with_values {
+                bulk_array(picks.into_iter().flat_map(|(field, value)| [field, value]))
+            } else {
+                bulk_array(picks.into_iter().map(|(field, _)| field))
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Hscan {
+    key: Vec<u8>,
+    cursor: u64,
+    options: ScanOptions,
+}
+
+impl CommandExt for Hscan {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let (cursor, options) = ScanOptions::parse(parser, true)?;
+        Ok(Hscan {
+            key,
+            cursor,
+            options,
+        })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        db.read_live(&self.key, |db_value| {
+            let hash = match db_value {
+                Some(DbValue {
+                    data: DbData::Hash(hash),
+                    ..
+                }) => hash,
+                Some(_) => return CommandError::WrongType.to_resp(),
+                None => return scan_reply(0, Vec::<&[u8]>::new()),
+            };
+
+            let (next_cursor, page) = scan_page(
+                hash.keys().map(Vec::as_slice),
+                self.cursor,
+                self.options.count,
+            );
+            let mut items = Vec::new();
+            for field in page.into_iter().filter(|field| self.options.matches(field)) {
+                items.push(field);
+                if !self.options.no_values {
+                    items.push(&hash[field]);
+                }
+            }
+            scan_reply(next_cursor, items)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{run, Command, CommandError};
+    use crate::parser::RespValue;
+    use crate::server::Server;
+
+    #[test]
+    fn test_hset_counts_only_new_fields() {
+        let server = Server::default();
+        assert_eq!(
+            run(&server, &[b"HSET", b"hash", b"a", b"1", b"b", b"2"]),
+            b":2\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"HSET", b"hash", b"a", b"10", b"c", b"3"]),
+            b":1\r\n"
+        );
+        assert_eq!(run(&server, &[b"HGET", b"hash", b"a"]), b"$2\r\n10\r\n");
+        assert_eq!(run(&server, &[b"HGET", b"hash", b"zzz"]), b"$-1\r\n");
+        assert_eq!(run(&server, &[b"HGET", b"missing", b"a"]), b"$-1\r\n");
+    }
+
+    #[test]
+    fn test_hgetall() {
+        let server = Server::default();
+        assert_eq!(run(&server, &[b"HGETALL", b"missing"]), b"*0\r\n");
+
+        run(&server, &[b"HSET", b"hash", b"field", b"value"]);
+        assert_eq!(
+            run(&server, &[b"HGETALL", b"hash"]),
+            b"*2\r\n$5\r\nfield\r\n$5\r\nvalue\r\n"
+        );
+    }
+
+    #[test]
+    fn test_hset_odd_arguments() {
+        let resp = RespValue::Array(vec![
+            RespValue::BulkString(b"HSET".to_vec()),
+            RespValue::BulkString(b"hash".to_vec()),
+            RespValue::BulkString(b"field".to_vec()),
+        ]);
+        assert!(Command::from_resp(resp).is_err());
+    }
+
+    #[test]
+    fn test_hincrby_starts_missing_fields_at_zero() {
+        let server = Server::default();
+        assert_eq!(run(&server, &[b"HINCRBY", b"hash", b"n", b"5"]), b":5\r\n");
+        assert_eq!(
+            run(&server, &[b"HINCRBY", b"hash", b"n", b"-7"]),
+            b":-2\r\n"
+        );
+        assert_eq!(run(&server, &[b"HGET", b"hash", b"n"]), b"$2\r\n-2\r\n");
+
+        run(
+            &server,
+            &[
+                b"HSET",
+                b"hash",
+                b"word",
+                b"abc",
+                b"big",
+                b"9223372036854775807",
+            ],
+        );
+        assert_eq!(
+            run(&server, &[b"HINCRBY", b"hash", b"word", b"1"]),
+            b"-ERR hash value is not an integer\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"HINCRBY", b"hash", b"big", b"1"]),
+            b"-ERR increment or decrement would overflow\r\n"
+        );
+        let resp = RespValue::Array(
+            [&b"HINCRBY"[..], b"hash", b"n", b"1.5"]
+                .iter()
+                .map(|arg| RespValue::BulkString(arg.to_vec()))
+                .collect(),
+        );
+        assert!(matches!(
+            Command::from_resp(resp),
+            Err(CommandError::NotAnInteger)
+        ));
+    }
+
+    #[test]
+    fn test_hdel_deletes_emptied_hash() {
+        let server = Server::default();
+        run(&server, &[b"HSET", b"hash", b"a", b"1", b"b", b"2"]);
+        assert_eq!(run(&server, &[b"HDEL", b"hash", b"a", b"zzz"]), b":1\r\n");
+        assert_eq!(run(&server, &[b"HDEL", b"hash", b"a"]), b":0\r\n");
+        assert_eq!(run(&server, &[b"HDEL", b"hash", b"b"]), b":1\r\n");
+        assert_eq!(run(&server, &[b"EXISTS", b"hash"]), b":0\r\n");
+        assert_eq!(run(&server, &[b"HDEL", b"missing", b"a"]), b":0\r\n");
+    }
+
+    #[test]
+    fn test_hash_commands_reject_other_types() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"string", b"v"]);
+        let wrong_type = b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        assert_eq!(run(&server, &[b"HSET", b"string", b"f", b"v"]), wrong_type);
+        assert_eq!(run(&server, &[b"HGET", b"string", b"f"]), wrong_type);
+        assert_eq!(run(&server, &[b"HGETALL", b"string"]), wrong_type);
+        assert_eq!(
+            run(&server, &[b"HINCRBY", b"string", b"f", b"1"]),
+            wrong_type
+        );
+        assert_eq!(run(&server, &[b"HDEL", b"string", b"f"]), wrong_type);
+    }
+
+    #[test]
+    fn test_hscan_novalues() {
+        let server = Server::default();
+        run(&server, &[b"HSET", b"hash", b"field", b"value"]);
+        assert_eq!(
+            run(&server, &[b"HSCAN", b"hash", b"0"]),
+            b"*2\r\n$1\r\n0\r\n*2\r\n$5\r\nfield\r\n$5\r\nvalue\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"HSCAN", b"hash", b"0", b"NOVALUES"]),
+            b"*2\r\n$1\r\n0\r\n*1\r\n$5\r\nfield\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"HSCAN", b"missing", b"0"]),
+            b"*2\r\n$1\r\n0\r\n*0\r\n"
+        );
+    }
+
+    /// The bulk strings of an array reply, in order.
+    fn strings(reply: &[u8]) -> Vec<Vec<u8>> {
+        let (RespValue::Array(items), _) = RespValue::parse(reply).unwrap() else {
+            panic!("expected an array reply");
+        };
+        items
+            .into_iter()
+            .map(|item| match item {
+                RespValue::BulkString(item) => item,
+                other => panic!("unexpected item {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_hrandfield_counts() {
+        let server = Server::default();
+        run(
+            &server,
+            &[b"HSET", b"hash", b"a", b"1", b"b", b"2", b"c", b"3"],
+        );
+
+        let mut distinct = strings(&run(&server, &[b"HRANDFIELD", b"hash", b"5"]));
+        distinct.sort();
+        assert_eq!(distinct, vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+
+        let repeated = strings(&run(&server, &[b"HRANDFIELD", b"hash", b"-7"]));
+        assert_eq!(repeated.len(), 7);
+        assert!(repeated.iter().all(|field| distinct.contains(field)));
+
+        assert_eq!(run(&server, &[b"HRANDFIELD", b"missing"]), b"$-1\r\n");
+        assert_eq!(run(&server, &[b"HRANDFIELD", b"missing", b"-2"]), b"*0\r\n");
+        assert!(matches!(
+            Command::from_resp(RespValue::Array(vec![
+                RespValue::BulkString(b"HRANDFIELD".to_vec()),
+                RespValue::BulkString(b"hash".to_vec()),
+                RespValue::BulkString(b"-100000000000".to_vec()),
+            ])),
+            Err(CommandError::OutOfRange)
+        ));
+        assert_eq!(
+            CommandError::OutOfRange.to_resp().encode(),
+            b"-ERR value is out of range\r\n"
+        );
+    }
+
+    #[test]
+    fn test_hrandfield_withvalues_interleaves() {
+        let server = Server::default();
+        run(&server, &[b"HSET", b"hash", b"a", b"1", b"b", b"2"]);
+        for count in [&b"2"[..], b"-4"] {
+            let reply = strings(&run(
+                &server,
+                &[b"HRANDFIELD", b"hash", count, b"WITHVALUES"],
+            ));
+            for pair in reply.chunks(2) {
+                match &pair[0][..] {
+                    b"a" => assert_eq!(pair[1], b"1"),
+                    b"b" => assert_eq!(pair[1], b"2"),
+                    other => panic!("unexpected field {:?}", other),
+                }
+            }
+        }
+        assert!(matches!(
+            Command::from_resp(RespValue::Array(vec![
+                RespValue::BulkString(b"HRANDFIELD".to_vec()),
+                RespValue::BulkString(b"hash".to_vec()),
+                RespValue::BulkString(b"WITHVALUES".to_vec()),
+            ])),
+            Err(CommandError::NotAnInteger)
+        ));
+    }
+
+    #[test]
+    fn test_hscan_full_iteration_recovers_every_pair() {
+        let server = Server::default();
+        let mut expected = Vec::new();
+        for i in 0..50 {
+            let (field, value) = (format!("field:{}", i), format!("value:{}", i));
+            run(
+                &server,
+                &[b"HSET", b"hash", field.as_bytes(), value.as_bytes()],
+            );
+            expected.push((field.into_bytes(), value.into_bytes()));
+        }
+
+        let mut seen = Vec::new();
+        let mut cursor = b"0".to_vec();
+        loop {
+            let reply = run(&server, &[b"HSCAN", b"hash", &cursor, b"COUNT", b"7"]);
+            let (RespValue::Array(mut reply), _) = RespValue::parse(&reply).unwrap() else {
+                panic!("expected an array reply");
+            };
+            let items = reply.pop().unwrap();
+            let Some(RespValue::BulkString(next)) = reply.pop() else {
+                panic!("expected a cursor");
+            };
+            let RespValue::Array(items) = items else {
+                panic!("expected a batch");
+            };
+            let mut items = items.into_iter().map(|item| match item {
+                RespValue::BulkString(item) => item,
+                other => panic!("unexpected item {:?}", other),
+            });
+            while let (Some(field), Some(value)) = (items.next(), items.next()) {
+                seen.push((field, value));
+            }
+            if next == b"0" {
+                break;
+            }
+            cursor = next;
+        }
+
+        seen.sort();
+        expected.sort();
+        assert_eq!(seen, expected);
+    }
+}
@@ -0,0 +1,2013 @@
+use std::str;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{
+    bulk_array, bulk_string, deadline_in, error_reply, ok, request_args, unless_zero, ArgParser,
+    CommandError, CommandExt, ErrorCode, Propagate,
+};
+use crate::clock::Clock;
+use crate::db::{unpoisoned, Database, DbData, DbValue, SHARD_COUNT};
+use crate::eviction;
+use crate::parser::RespValue;
+use crate::rdb;
+use crate::server::Server;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Exists {
+    keys: Vec<Vec<u8>>,
+}
+
+impl CommandExt for Exists {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let keys = parser.remaining_bulk_strings()?;
+        if keys.is_empty() {
+            return Err(CommandError::WrongArgCount);
+        }
+        Ok(Exists { keys })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue {
+        // Each key is read on its own, so the count isn't a point-in-time snapshot
+        // across shards; that matches what EXISTS has ever promised.
+        let count = self
+            .keys
+            .iter()
+            .filter(|key| {
+                let found = db.read_live(key, |db_value| db_value.is_some());
+                server.stats.record_lookup(found);
+                found
+            })
+            .count();
+
+        RespValue::Integer(count as i64)
+    }
+}
+
+/// `TOUCH key [key ...]`: marks keys as just used, for LRU eviction, without
+/// reading them. Counts the keys that exist, repeats included, like EXISTS.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Touch {
+    keys: Vec<Vec<u8>>,
+}
+
+impl CommandExt for Touch {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let keys = parser.remaining_bulk_strings()?;
+        if keys.is_empty() {
+            return Err(CommandError::WrongArgCount);
+        }
+        Ok(Touch { keys })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        // Looking a live key up is what updates its access time.
+        let count = self
+            .keys
+            .iter()
+            .filter(|key| db.read_live(key, |db_value| db_value.is_some()))
+            .count();
+        RespValue::Integer(count as i64)
+    }
+}
+
+/// Values with more elements than this are freed on another thread by UNLINK,
+/// as with Redis's `LAZYFREE_THRESHOLD`. Freeing fewer costs less than the handoff.
+const LAZYFREE_THRESHOLD: usize = 64;
+
+/// Shared body of DEL/UNLINK: removes the keys that exist and counts them. The
+/// values are freed after the locks are released, and with `lazy` the large
+/// ones are freed on a thread of their own, so the connection doesn't wait.
+fn delete_keys(db: &Database, keys: &[Vec<u8>], lazy: bool) -> RespValue {
+    let mut removed = Vec::new();
+    {
+        let mut shards = db.lock_keys(keys.iter().map(Vec::as_slice));
+        for key in keys {
+            // An expired key is dropped here too, but doesn't count.
+            let shard = shards.shard(key);
+            if shard.get_live(key).is_some() {
+                removed.extend(shard.remove(key));
+            }
+        }
+    }
+
+    let count = removed.len();
+    if lazy {
+        let large: Vec<DbValue> = removed
+            .extract_if(.., |db_value| {
+                db_value.data.element_count() > LAZYFREE_THRESHOLD
+            })
+            .collect();
+        if !large.is_empty() {
+            thread::spawn(move || drop(large));
+        }
+    }
+    RespValue::Integer(count as i64)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Del {
+    keys: Vec<Vec<u8>>,
+}
+
+impl CommandExt for Del {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let keys = parser.remaining_bulk_strings()?;
+        if keys.is_empty() {
+            return Err(CommandError::WrongArgCount);
+        }
+        Ok(Del { keys })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        delete_keys(db, &self.keys, false)
+    }
+
+    fn propagate(&self, _clock: &dyn Clock) -> Propagate {
+        Box::new(unless_zero)
+    }
+}
+
+/// DEL that leaves freeing large values to another thread.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Unlink {
+    keys: Vec<Vec<u8>>,
+}
+
+impl CommandExt for Unlink {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let keys = parser.remaining_bulk_strings()?;
+        if keys.is_empty() {
+            return Err(CommandError::WrongArgCount);
+        }
+        Ok(Unlink { keys })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        delete_keys(db, &self.keys, true)
+    }
+
+    fn propagate(&self, _clock: &dyn Clock) -> Propagate {
+        Box::new(unless_zero)
+    }
+}
+
+/// Shared body of TTL/PTTL: `-2` for a missing key, `-1` for a key without expiry.
+fn ttl_reply(db: &Database, key: &[u8], in_millis: bool) -> RespValue {
+    let ttl = db.read_live(key, |db_value| match db_value {
+        Some(db_value) => match db_value.expires_at {
+            Some(expires_at) => {
+                let remaining = expires_at.saturating_duration_since(db.now());
+                if in_millis {
+                    remaining.as_millis() as i64
+                } else {
+                    ((remaining.as_millis() + 500) / 1000) as i64
+                }
+            }
+            None => -1,
+        },
+        None => -2,
+    });
+
+    RespValue::Integer(ttl)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Ttl {
+    key: Vec<u8>,
+}
+
+impl CommandExt for Ttl {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Ttl { key })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        ttl_reply(db, &self.key, false)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Pttl {
+    key: Vec<u8>,
+}
+
+impl CommandExt for Pttl {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Pttl { key })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        ttl_reply(db, &self.key, true)
+    }
+}
+
+/// Shared body of EXPIRETIME/PEXPIRETIME, with TTL's -1 and -2 for no expiry and no key.
+fn expire_time_reply(db: &Database, key: &[u8], in_millis: bool, clock: &dyn Clock) -> RespValue {
+    let expire_time = db.read_live(key, |db_value| match db_value {
+        Some(db_value) => match db_value.expires_at {
+            Some(expires_at) => {
+                let unix_millis = clock.unix_millis_from_instant(expires_at) as i64;
+                if in_millis {
+                    unix_millis
+                } else {
+                    unix_millis / 1000
+                }
+            }
+            None => -1,
+        },
+        None => -2,
+    });
+
+    RespValue::Integer(expire_time)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Expiretime {
+    key: Vec<u8>,
+}
+
+impl CommandExt for Expiretime {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Expiretime { key })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue {
+        expire_time_reply(db, &self.key, false, &*server.clock)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Pexpiretime {
+    key: Vec<u8>,
+}
+
+impl CommandExt for Pexpiretime {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Pexpiretime { key })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue {
+        expire_time_reply(db, &self.key, true, &*server.clock)
+    }
+}
+
+/// The optional `NX`/`XX`/`GT`/`LT` flags of EXPIRE and friends. Compatible
+/// flags may be combined (`XX GT`), and then all of them have to hold.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct ExpireFlags {
+    nx: bool,
+    xx: bool,
+    gt: bool,
+    lt: bool,
+}
+
+impl ExpireFlags {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let mut flags = ExpireFlags::default();
+        for option in parser.remaining_bulk_strings()? {
+            match option.to_ascii_uppercase().as_slice() {
+                b"NX" => flags.nx = true,
+                b"XX" => flags.xx = true,
+                b"GT" => flags.gt = true,
+                b"LT" => flags.lt = true,
+                _ => return Err(CommandError::SyntaxError),
+            }
+        }
+
+        if flags.nx && (flags.xx || flags.gt || flags.lt) {
+            return Err(CommandError::InvalidArgument {
+                reason: "NX and XX, GT or LT options at the same time are not compatible"
+                    .to_string(),
+            });
+        }
+        if flags.gt && flags.lt {
+            return Err(CommandError::InvalidArgument {
+                reason: "GT and LT options at the same time are not compatible".to_string(),
+            });
+        }
+        Ok(flags)
+    }
+
+    /// Whether a key whose deadline is `current` may be given `new`. Like Redis,
+    /// GT and LT treat a key without an expiry as expiring infinitely far away.
+    fn allow(&self, current: Option<Instant>, new: Instant) -> bool {
+        match current {
+            Some(current) => {
+                let refused = self.nx || (self.gt && new <= current) || (self.lt && new >= current);
+                !refused
+            }
+            None => !self.xx && !self.gt,
+        }
+    }
+}
+
+/// Shared body of EXPIRE/PEXPIRE/EXPIREAT/PEXPIREAT. A non-positive timeout deletes the key
+/// right away, once `flags` allow it. Whether the key already expired and the
+/// new deadline both go by one reading of `clock`.
+fn expire_reply(
+    db: &Database,
+    key: &[u8],
+    millis: i64,
+    flags: &ExpireFlags,
+    clock: &dyn Clock,
+) -> RespValue {
+    let now = clock.now();
+    let mut db_lock = db.lock(key);
+
+    let current = match db_lock.get(key) {
+        Some(db_value) if db_value.is_expired_at(now) => {
+            db_lock.remove_expired(key);
+            return RespValue::Integer(0);
+        }
+        Some(db_value) => db_value.expires_at,
+        None => return RespValue::Integer(0),
+    };
+    let Some(expires_at) = now.checked_add(Duration::from_millis(millis.max(0) as u64)) else {
+        return error_reply(ErrorCode::Err, "invalid expire time");
+    };
+    if !flags.allow(current, expires_at) {
+        return RespValue::Integer(0);
+    }
+
+    if millis <= 0 {
+        db_lock.remove(key);
+    } else {
+        db_lock.set_expires_at(key, Some(expires_at));
+    }
+    RespValue::Integer(1)
+}
+
+/// What repeats an EXPIRE or PEXPIRE that took effect: a PEXPIREAT at the
+/// deadline it set, or a DEL if the timeout deleted the key. `millis` is
+/// `None` if the timeout overflowed, which set nothing.
+fn propagate_expire(millis: Option<i64>, clock: &dyn Clock) -> Propagate {
+    let unix_now = clock.unix_millis();
+    Box::new(move |request, result| {
+        if *result == RespValue::Integer(0) {
+            return None;
+        }
+        let millis = millis?;
+        let key = request_args(request).next()?;
+        if millis <= 0 {
+            return Some(vec![RespValue::Array(vec![bulk_string(b"DEL"), key])]);
+        }
+        let deadline = deadline_in(unix_now, millis as u64)?;
+        Some(vec![RespValue::Array(vec![
+            bulk_string(b"PEXPIREAT"),
+            key,
+            bulk_string(deadline.as_bytes()),
+        ])])
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Expire {
+    key: Vec<u8>,
+    seconds: i64,
+    flags: ExpireFlags,
+}
+
+impl CommandExt for Expire {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let seconds = parser.next_integer()?;
+        let flags = ExpireFlags::parse(parser)?;
+        Ok(Expire {
+            key,
+            seconds,
+            flags,
+        })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue {
+        match self.seconds.checked_mul(1000) {
+            Some(millis) => expire_reply(db, &self.key, millis, &self.flags, &*server.clock),
+            None => error_reply(ErrorCode::Err, "invalid expire time"),
+        }
+    }
+
+    fn propagate(&self, clock: &dyn Clock) -> Propagate {
+        propagate_expire(self.seconds.checked_mul(1000), clock)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Pexpire {
+    key: Vec<u8>,
+    millis: i64,
+    flags: ExpireFlags,
+}
+
+impl CommandExt for Pexpire {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let millis = parser.next_integer()?;
+        let flags = ExpireFlags::parse(parser)?;
+        Ok(Pexpire { key, millis, flags })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue {
+        expire_reply(db, &self.key, self.millis, &self.flags, &*server.clock)
+    }
+
+    fn propagate(&self, clock: &dyn Clock) -> Propagate {
+        propagate_expire(Some(self.millis), clock)
+    }
+}
+
+/// Takes an absolute deadline, which is how BGREWRITEAOF records expiries.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Pexpireat {
+    key: Vec<u8>,
+    unix_millis: i64,
+    flags: ExpireFlags,
+}
+
+impl CommandExt for Pexpireat {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let unix_millis = parser.next_integer()?;
+        let flags = ExpireFlags::parse(parser)?;
+        Ok(Pexpireat {
+            key,
+            unix_millis,
+            flags,
+        })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue {
+        let millis = self
+            .unix_millis
+            .saturating_sub(server.clock.unix_millis() as i64);
+        expire_reply(db, &self.key, millis, &self.flags, &*server.clock)
+    }
+
+    fn propagate(&self, _clock: &dyn Clock) -> Propagate {
+        Box::new(unless_zero)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Expireat {
+    key: Vec<u8>,
+    unix_seconds: i64,
+    flags: ExpireFlags,
+}
+
+impl CommandExt for Expireat {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let unix_seconds = parser.next_integer()?;
+        let flags = ExpireFlags::parse(parser)?;
+        Ok(Expireat {
+            key,
+            unix_seconds,
+            flags,
+        })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue {
+        let Some(unix_millis) = self.unix_seconds.checked_mul(1000) else {
+            return error_reply(ErrorCode::Err, "invalid expire time");
+        };
+        let millis = unix_millis.saturating_sub(server.clock.unix_millis() as i64);
+        expire_reply(db, &self.key, millis, &self.flags, &*server.clock)
+    }
+
+    fn propagate(&self, _clock: &dyn Clock) -> Propagate {
+        Box::new(unless_zero)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Persist {
+    key: Vec<u8>,
+}
+
+impl CommandExt for Persist {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Persist { key })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        let mut db_lock = db.lock(&self.key);
+
+        match db_lock.get(&self.key) {
+            Some(db_value) if db_value.is_expired_at(db.now()) => {
+                db_lock.remove_expired(&self.key);
+                RespValue::Integer(0)
+            }
+            Some(db_value) if db_value.expires_at.is_some() => {
+                db_lock.set_expires_at(&self.key, None);
+                RespValue::Integer(1)
+            }
+            _ => RespValue::Integer(0),
+        }
+    }
+
+    fn propagate(&self, _clock: &dyn Clock) -> Propagate {
+        Box::new(unless_zero)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Type {
+    key: Vec<u8>,
+}
+
+impl CommandExt for Type {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Type { key })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        let name = db.read_live(&self.key, |db_value| {
+            db_value.map_or("none", |db_value| db_value.data.type_name())
+        });
+        RespValue::SimpleString(name.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Rename {
+    source: Vec<u8>,
+    destination: Vec<u8>,
+}
+
+impl CommandExt for Rename {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let source = parser.next_bulk_string()?;
+        let destination = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Rename {
+            source,
+            destination,
+        })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        let mut shards = db.lock_keys([&self.source[..], &self.destination]);
+
+        if shards.shard(&self.source).get_live(&self.source).is_none() {
+            return CommandError::NoSuchKey.to_resp();
+        }
+        if self.source != self.destination {
+            // The value moves as-is, keeping its expiry.
+            let db_value = shards.shard(&self.source).remove(&self.source).unwrap();
+            shards
+                .shard(&self.destination)
+                .insert(self.destination, db_value);
+        }
+        ok()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Copy {
+    source: Vec<u8>,
+    destination: Vec<u8>,
+    replace: bool,
+}
+
+impl CommandExt for Copy {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let source = parser.next_bulk_string()?;
+        let destination = parser.next_bulk_string()?;
+        let mut replace = false;
+        for option in parser.remaining_bulk_strings()? {
+            match option.to_ascii_uppercase().as_slice() {
+                b"REPLACE" => replace = true,
+                _ => return Err(CommandError::SyntaxError),
+            }
+        }
+        Ok(Copy {
+            source,
+            destination,
+            replace,
+        })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        if self.source == self.destination {
+            return error_reply(
+                ErrorCode::Err,
+                "source and destination objects are the same",
+            );
+        }
+        let mut shards = db.lock_keys([&self.source[..], &self.destination]);
+
+        // A full clone, so the two keys never share list, hash or set storage.
+        let Some(mut db_value) = shards
+            .shard(&self.source)
+            .get_live(&self.source)
+            .map(|db_value| db_value.clone())
+        else {
+            return RespValue::Integer(0);
+        };
+        let destination = shards.shard(&self.destination);
+        if !self.replace && destination.get_live(&self.destination).is_some() {
+            return RespValue::Integer(0);
+        }
+        db_value.modified_at = Instant::now();
+        destination.insert(self.destination, db_value);
+        RespValue::Integer(1)
+    }
+
+    fn propagate(&self, _clock: &dyn Clock) -> Propagate {
+        Box::new(unless_zero)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Dump {
+    key: Vec<u8>,
+}
+
+impl CommandExt for Dump {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Dump { key })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        db.read_live(&self.key, |db_value| match db_value {
+            Some(db_value) => bulk_string(&rdb::dump_value(&db_value.data)),
+            None => RespValue::Null,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Restore {
+    key: Vec<u8>,
+    /// Milliseconds to live, 0 for no expiry.
+    ttl: i64,
+    payload: Vec<u8>,
+    replace: bool,
+    /// ABSTTL: `ttl` is a unix time in milliseconds instead.
+    absolute: bool,
+}
+
+impl CommandExt for Restore {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let ttl = parser.next_integer()?;
+        let payload = parser.next_bulk_string()?;
+        let mut replace = false;
+        let mut absolute = false;
+        for option in parser.remaining_bulk_strings()? {
+            match option.to_ascii_uppercase().as_slice() {
+                b"REPLACE" => replace = true,
+                b"ABSTTL" => absolute = true,
+                _ => return Err(CommandError::SyntaxError),
+            }
+        }
+        Ok(Restore {
+            key,
+            ttl,
+            payload,
+            replace,
+            absolute,
+        })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue {
+        let expires_at = match self.ttl {
+            0 => None,
+            ttl if ttl < 0 => {
+                return error_reply(ErrorCode::Err, "Invalid TTL value, must be >= 0");
+            }
+            ttl => {
+                let expires_at = if self.absolute {
+                    server.clock.instant_from_unix_millis(ttl as u64)
+                } else {
+                    server
+                        .clock
+                        .now()
+                        .checked_add(Duration::from_millis(ttl as u64))
+                };
+                match expires_at {
+                    Some(expires_at) => Some(expires_at),
+                    None => return error_reply(ErrorCode::Err, "invalid expire time"),
+                }
+            }
+        };
+        let Some(data) = rdb::restore_value(&self.payload) else {
+            return error_reply(ErrorCode::Err, "DUMP payload version or checksum are wrong");
+        };
+        let mut db_lock = db.lock(&self.key);
+
+        if !self.replace && db_lock.get_live(&self.key).is_some() {
+            return error_reply(ErrorCode::BusyKey, "Target key name already exists.");
+        }
+        let restored = match &data {
+            DbData::List(list) => list.len(),
+            _ => 0,
+        };
+        let mut db_value = DbValue::new(data);
+        db_value.expires_at = expires_at;
+        db_lock.insert(self.key.clone(), db_value);
+        // A restored list can answer a BLPOP, like a push onto a missing key.
+        db.waiters.wake(&self.key, restored);
+        ok()
+    }
+
+    /// A relative TTL becomes an ABSTTL deadline.
+    fn propagate(&self, clock: &dyn Clock) -> Propagate {
+        let (ttl, relative, replace) = (self.ttl, self.ttl != 0 && !self.absolute, self.replace);
+        let unix_now = clock.unix_millis();
+        Box::new(move |request, _| {
+            if !relative {
+                return Some(vec![request]);
+            }
+            let deadline = deadline_in(unix_now, ttl as u64)?;
+            let mut args = request_args(request);
+            let (key, payload) = (args.next()?, args.nth(1)?);
+            let mut rewritten = vec![
+                bulk_string(b"RESTORE"),
+                key,
+                bulk_string(deadline.as_bytes()),
+                payload,
+                bulk_string(b"ABSTTL"),
+            ];
+            if replace {
+                rewritten.push(bulk_string(b"REPLACE"));
+            }
+            Some(vec![RespValue::Array(rewritten)])
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ObjectSubcommand {
+    /// Whole seconds since the value was last written.
+    LastModified,
+    Encoding,
+    /// Whole seconds since the value was last read or written.
+    IdleTime,
+    /// The logarithmic access counter, only kept up under an LFU policy.
+    Freq,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Object {
+    subcommand: ObjectSubcommand,
+    key: Vec<u8>,
+}
+
+impl CommandExt for Object {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let subcommand = parser.next_bulk_string()?;
+        let subcommand = match subcommand.to_ascii_uppercase().as_slice() {
+            b"LASTMODIFIED" => ObjectSubcommand::LastModified,
+            b"ENCODING" => ObjectSubcommand::Encoding,
+            b"IDLETIME" => ObjectSubcommand::IdleTime,
+            b"FREQ" => ObjectSubcommand::Freq,
+            _ => {
+                return Err(CommandError::UnknownSubcommand(
+                    String::from_utf8_lossy(&subcommand).into_owned(),
+                ))
+            }
+        };
+        let key = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Object { subcommand, key })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue {
+        // Looking at a value isn't using it, so it mustn't reset IDLETIME.
+        db.peek_live(&self.key, |db_value| {
+            let Some(db_value) = db_value else {
+                return CommandError::NoSuchKey.to_resp();
+            };
+            match self.subcommand {
+                ObjectSubcommand::LastModified => {
+                    RespValue::Integer(db_value.modified_at.elapsed().as_secs() as i64)
+                }
+                ObjectSubcommand::Encoding => RespValue::SimpleString(db_value.encoding().to_string()),
+                ObjectSubcommand::IdleTime => {
+                    RespValue::Integer(db_value.accessed_at.idle().as_secs() as i64)
+                }
+                ObjectSubcommand::Freq => {
+                    if !eviction::is_lfu(&unpoisoned(server.config.read()).maxmemory_policy) {
+                        return error_reply(
+                            ErrorCode::Err,
+                            "An LFU maxmemory policy is not selected, access frequency not tracked. \
+                             Please note that when switching between policies at runtime LRU and LFU \
+                             data will take some time to adjust.",
+                        );
+                    }
+                    RespValue::Integer(db_value.accessed_at.frequency() as i64)
+                }
+            }
+        })
+    }
+}
+
+/// Matches `key` against a glob pattern supporting `*`, `?`, `[...]` classes
+/// (with `^` negation and `a-z` ranges) and `\\` escapes.
+pub(super) fn glob_match(pattern: &[u8], key: &[u8]) -> bool {
+    let (mut p, mut k) = (0, 0);
+    // Where to resume after the most recent `*` if the rest fails to match.
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while k < key.len() {
+        let step = match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, k));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => Some(p + 1),
+            Some(b'[') => match_class(pattern, p + 1, key[k]),
+            Some(b'\\') if p + 1 < pattern.len() => (pattern[p + 1] == key[k]).then_some(p + 2),
+            Some(&c) => (c == key[k]).then_some(p + 1),
+            None => None,
+        };
+
+        match (step, backtrack) {
+            (Some(next), _) => {
+                p = next;
+                k += 1;
+            }
+            (None, Some((star, star_k))) => {
+                backtrack = Some((star, star_k + 1));
+                p = star + 1;
+                k = star_k + 1;
+            }
+            (None, None) => return false,
+        }
+    }
+
+    pattern[p.min(pattern.len())..].iter().all(|&c| c == b'*')
+}
+
+/// Matches `c` against the class starting just after `[`, returning the
+/// pattern index following the closing `]`.
+fn match_class(pattern: &[u8], mut p: usize, c: u8) -> Option<usize> {
+    let negate = pattern.get(p) == Some(&b'^');
+    if negate {
+        p += 1;
+    }
+
+    let mut matched = false;
+    while p < pattern.len() && pattern[p] != b']' {
+        if pattern[p] == b'\\' && p + 1 < pattern.len() {
+            matched |= pattern[p + 1] == c;
+            p += 2;
+        } else if p + 2 < pattern.len() && pattern[p + 1] == b'-' && pattern[p + 2] != b']' {
+            let (low, high) = (
+                pattern[p].min(pattern[p + 2]),
+                pattern[p].max(pattern[p + 2]),
+            );
+            matched |= (low..=high).contains(&c);
+            p += 3;
+        } else {
+            matched |= pattern[p] == c;
+            p += 1;
+        }
+    }
+
+    // An unterminated class consumes the rest of the pattern.
+    (matched != negate).then_some((p + 1).min(pattern.len()))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Keys {
+    pattern: Vec<u8>,
+}
+
+impl CommandExt for Keys {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let pattern = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Keys { pattern })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        let mut keys: Vec<Vec<u8>> = Vec::new();
+        let now = db.now();
+        for index in 0..SHARD_COUNT {
+            db.read_shard_at(index, |keyspace| {
+                keys.extend(
+                    keyspace
+                        .iter()
+                        .filter(|(key, db_value)| {
+                            !db_value.is_expired_at(now) && glob_match(&self.pattern, key)
+                        })
+                        .map(|(key, _)| key.clone()),
+                );
+            });
+        }
+        bulk_array(keys)
+    }
+}
+
+/// Position of an element in SCAN order. Cursors are positions, so iteration
+/// order doesn't depend on HashMap layout or on keys added or removed mid-scan.
+fn scan_position(element: &[u8]) -> u64 {
+    // FNV-1a: cheap, and stable across runs unlike the HashMap hasher.
+    element.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Returns up to `count` elements at or after `cursor` in SCAN order, plus the
+/// cursor to resume from (`0` once the iteration is complete). Elements sharing
+/// a position are always returned together so none can be skipped.
+pub(super) fn scan_page<'a, I>(elements: I, cursor: u64, count: usize) -> (u64, Vec<&'a [u8]>)
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    scan_page_by(elements, cursor, count, scan_position)
+}
+
+/// `scan_page` with positions taken from `position` instead of `scan_position`.
+fn scan_page_by<'a, I>(
+    elements: I,
+    cursor: u64,
+    count: usize,
+    position: impl Fn(&[u8]) -> u64,
+) -> (u64, Vec<&'a [u8]>)
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    let mut pending: Vec<(u64, &[u8])> = elements
+        .into_iter()
+        .map(|element| (position(element), element))
+        .filter(|(position, _)| *position >= cursor)
+        .collect();
+
+    // Only the page is put in order: the rest is just split off behind it, so
+    // a call costs a pass over the collection rather than a sort of it.
+    let mut next_cursor = 0;
+    if pending.len() > count {
+        pending.select_nth_unstable(count - 1);
+        let last = pending[count - 1].0;
+        let mut end = count;
+        for index in count..pending.len() {
+            if pending[index].0 == last {
+                pending.swap(index, end);
+                end += 1;
+            }
+        }
+        if end < pending.len() {
+            next_cursor = last + 1;
+        }
+        pending.truncate(end);
+    }
+    pending.sort_unstable();
+    let page = pending.into_iter().map(|(_, element)| element).collect();
+    (next_cursor, page)
+}
+
+/// Options shared by the SCAN family.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct ScanOptions {
+    pub(super) pattern: Option<Vec<u8>>,
+    pub(super) count: usize,
+    /// HSCAN only: reply with field names alone.
+    pub(super) no_values: bool,
+}
+
+impl ScanOptions {
+    /// Parses `cursor [MATCH pattern] [COUNT n]`, plus `NOVALUES` when `allow_no_values` is set.
+    pub(super) fn parse(
+        parser: &mut ArgParser,
+        allow_no_values: bool,
+    ) -> Result<(u64, Self), CommandError> {
+        let cursor = parser.next_bulk_string()?;
+        let cursor = str::from_utf8(&cursor)
+            .ok()
+            .and_then(|cursor| cursor.parse::<u64>().ok())
+            .ok_or(CommandError::InvalidCursor)?;
+
+        let mut options = ScanOptions {
+            pattern: None,
+            count: 10,
+            no_values: false,
+        };
+        while parser.iter.peek().is_some() {
+            let option = parser.next_bulk_string()?.to_ascii_uppercase();
+            match option.as_slice() {
+                b"MATCH" => options.pattern = Some(parser.next_bulk_string()?),
+                b"COUNT" => {
+                    options.count = match parser.next_integer()? {
+                        count if count < 1 => return Err(CommandError::SyntaxError),
+                        count => count as usize,
+                    }
+                }
+                b"NOVALUES" if allow_no_values => options.no_values = true,
+                _ => return Err(CommandError::SyntaxError),
+            }
+        }
+        Ok((cursor, options))
+    }
+
+    pub(super) fn matches(&self, element: &[u8]) -> bool {
+        self.pattern
+            .as_ref()
+            .is_none_or(|pattern| glob_match(pattern, element))
+    }
+}
+
+/// Encodes a SCAN-family reply: the next cursor followed by the batch.
+pub(super) fn scan_reply<I>(cursor: u64, items: I) -> RespValue
+where
+    I: IntoIterator,
+    I::Item: AsRef<[u8]>,
+{
+    RespValue::Array(vec![
+        bulk_string(cursor.to_string().as_bytes()),
+        bulk_array(items),
+    ])
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Scan {
+    cursor: u64,
+    options: ScanOptions,
+}
+
+impl CommandExt for Scan {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let (cursor, options) = ScanOptions::parse(parser, false)?;
+        Ok(Scan { cursor, options })
+    }
+
+    /// The cursor holds the shard being scanned in its low part and the position
+    /// within that shard above it, so each call reads one shard under a shared
+    /// lock. A shard's positions are its keys' `scan_position`s scaled down to
+    /// leave room for the shard index.
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        let shards = SHARD_COUNT as u64;
+        let index = (self.cursor % shards) as usize;
+        let in_shard = self.cursor / shards;
+
+        let now = db.now();
+        let (next_in_shard, keys) = db.read_shard_at(index, |keyspace| {
+            let live_keys = keyspace
+                .iter()
+                .filter(|(_, db_value)| !db_value.is_expired_at(now))
+                .map(|(key, _)| key.as_slice());
+            let (next_in_shard, page) =
+                scan_page_by(live_keys, in_shard, self.options.count, |key| {
+                    scan_position(key) / (2 * shards)
+                });
+
+            // Like Redis, MATCH filters the batch after it is taken, so a page may be empty.
+            let keys: Vec<Vec<u8>> = page
+                .into_iter()
+                .filter(|key| self.options.matches(key))
+                .map(<[u8]>::to_vec)
+                .collect();
+            (next_in_shard, keys)
+        });
+
+        let next_cursor = match next_in_shard {
+            0 if index + 1 == SHARD_COUNT => 0,
+            0 => index as u64 + 1,
+            next => next * shards + index as u64,
+        };
+        scan_reply(next_cursor, keys)
+    }
+}
+
+/// `SORT key [LIMIT offset count] [ASC|DESC] [ALPHA]`. There is no BY, GET or
+/// STORE, so it never writes.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Sort {
+    key: Vec<u8>,
+    alpha: bool,
+    descending: bool,
+    /// Offset and count. A negative count takes everything after the offset.
+    limit: Option<(i64, i64)>,
+}
+
+impl CommandExt for Sort {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let mut sort = Sort {
+            key,
+            alpha: false,
+            descending: false,
+            limit: None,
+        };
+        while parser.iter.peek().is_some() {
+            match parser.next_bulk_string()?.to_ascii_uppercase().as_slice() {
+                b"ALPHA" => sort.alpha = true,
+                b"ASC" => sort.descending = false,
+                b"DESC" => sort.descending = true,
+                b"LIMIT" => sort.limit = Some((parser.next_integer()?, parser.next_integer()?)),
+                _ => return Err(CommandError::SyntaxError),
+            }
+        }
+        Ok(sort)
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        let elements = db.read_live(&self.key, |db_value| {
+            match db_value.map(|db_value| &db_value.data) {
+                Some(DbData::List(list)) => Ok(list.iter().cloned().collect()),
+                Some(DbData::Set(set)) => Ok(set.iter().cloned().collect()),
+                Some(_) => Err(CommandError::WrongType),
+                None => Ok(Vec::new()),
+            }
+        });
+        let mut elements: Vec<Vec<u8>> = match elements {
+            Ok(elements) => elements,
+            Err(e) => return e.to_resp(),
+        };
+
+        if self.alpha {
+            elements.sort_unstable();
+        } else {
+            let scores: Option<Vec<f64>> = elements
+                .iter()
+                .map(|element| {
+                    let score = str::from_utf8(element).ok()?.parse::<f64>().ok()?;
+                    (!score.is_nan()).then_some(score)
+                })
+                .collect();
+            let Some(scores) = scores else {
+                return error_reply(
+                    ErrorCode::Err,
+                    "One or more scores can't be converted into double",
+                );
+            };
+            let mut scored: Vec<(f64, Vec<u8>)> = scores.into_iter().zip(elements).collect();
+            // Equal scores fall back to the bytes, so the order is always the same.
+            scored.sort_unstable_by(|(a, a_bytes), (b, b_bytes)| {
+                a.total_cmp(b).then_with(|| a_bytes.cmp(b_bytes))
+            });
+            elements = scored.into_iter().map(|(_, element)| element).collect();
+        }
+        if self.descending {
+            elements.reverse();
+        }
+
+        let (offset, count) = match self.limit {
+            Some((offset, count)) => (
+                offset.max(0) as usize,
+                usize::try_from(count).unwrap_or(usize::MAX),
+            ),
+            None => (0, usize::MAX),
+        };
+        bulk_array(elements.iter().skip(offset).take(count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{run, Command, CommandError};
+    use super::{glob_match, scan_page, LAZYFREE_THRESHOLD};
+    use crate::clock::{Clock, ManualClock};
+    use crate::config::Config;
+    use crate::db::unix_millis_now;
+    use crate::parser::RespValue;
+    use crate::server::Server;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    #[test]
+    fn test_exists_counts_duplicates() {
+        let server = Server::default();
+        let set_resp = RespValue::Array(vec![
+            RespValue::BulkString(b"SET".to_vec()),
+            RespValue::BulkString(b"foo".to_vec()),
+            RespValue::BulkString(b"bar".to_vec()),
+        ]);
+        Command::from_resp(set_resp)
+            .unwrap()
+            .execute(&server, &server.db(0));
+
+        let exists_resp = RespValue::Array(vec![
+            RespValue::BulkString(b"EXISTS".to_vec()),
+            RespValue::BulkString(b"foo".to_vec()),
+            RespValue::BulkString(b"foo".to_vec()),
+            RespValue::BulkString(b"missing".to_vec()),
+        ]);
+        let command = Command::from_resp(exists_resp).unwrap();
+        assert_eq!(
+            command.execute(&server, &server.db(0)),
+            RespValue::Integer(2)
+        );
+    }
+
+    #[test]
+    fn test_exists_ignores_expired_keys() {
+        let server = Server::default();
+        let set_resp = RespValue::Array(vec![
+            RespValue::BulkString(b"SET".to_vec()),
+            RespValue::BulkString(b"foo".to_vec()),
+            RespValue::BulkString(b"bar".to_vec()),
+            RespValue::BulkString(b"PX".to_vec()),
+            RespValue::BulkString(b"10".to_vec()),
+        ]);
+        Command::from_resp(set_resp)
+            .unwrap()
+            .execute(&server, &server.db(0));
+
+        thread::sleep(Duration::from_millis(20));
+
+        let exists_resp = RespValue::Array(vec![
+            RespValue::BulkString(b"EXISTS".to_vec()),
+            RespValue::BulkString(b"foo".to_vec()),
+        ]);
+        let command = Command::from_resp(exists_resp).unwrap();
+        assert_eq!(
+            command.execute(&server, &server.db(0)),
+            RespValue::Integer(0)
+        );
+        assert!(server.db(0).lock_all().iter().next().is_none());
+    }
+
+    #[test]
+    fn test_ttl_and_pttl() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"forever", b"v"]);
+        run(&server, &[b"SET", b"expiring", b"v", b"PX", b"5000"]);
+
+        assert_eq!(run(&server, &[b"TTL", b"missing"]), b":-2\r\n");
+        assert_eq!(run(&server, &[b"PTTL", b"missing"]), b":-2\r\n");
+        assert_eq!(run(&server, &[b"TTL", b"forever"]), b":-1\r\n");
+        assert_eq!(run(&server, &[b"PTTL", b"forever"]), b":-1\r\n");
+        assert_eq!(run(&server, &[b"TTL", b"expiring"]), b":5\r\n");
+
+        let pttl = String::from_utf8(run(&server, &[b"PTTL", b"expiring"])).unwrap();
+        let pttl: i64 = pttl.trim_start_matches(':').trim_end().parse().unwrap();
+        assert!(pttl > 4000 && pttl <= 5000);
+    }
+
+    #[test]
+    fn test_expiretime_and_pexpiretime() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"forever", b"v"]);
+        let set_at = unix_millis_now() as i64;
+        run(&server, &[b"SET", b"expiring", b"v", b"PX", b"5000"]);
+
+        assert_eq!(run(&server, &[b"EXPIRETIME", b"missing"]), b":-2\r\n");
+        assert_eq!(run(&server, &[b"PEXPIRETIME", b"missing"]), b":-2\r\n");
+        assert_eq!(run(&server, &[b"EXPIRETIME", b"forever"]), b":-1\r\n");
+        assert_eq!(run(&server, &[b"PEXPIRETIME", b"forever"]), b":-1\r\n");
+
+        let at = |command: &[u8]| {
+            let reply = String::from_utf8(run(&server, &[command, b"expiring"])).unwrap();
+            reply
+                .trim_start_matches(':')
+                .trim_end()
+                .parse::<i64>()
+                .unwrap()
+        };
+        let pexpiretime = at(b"PEXPIRETIME");
+        assert!(
+            (pexpiretime - (set_at + 5000)).abs() < 100,
+            "{}",
+            pexpiretime
+        );
+        // Each conversion reads the clock again, so the second may just tick over.
+        assert!((at(b"EXPIRETIME") - pexpiretime / 1000).abs() <= 1);
+    }
+
+    #[test]
+    fn test_expire_and_pexpire() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"key", b"v"]);
+
+        assert_eq!(run(&server, &[b"EXPIRE", b"missing", b"10"]), b":0\r\n");
+        assert_eq!(run(&server, &[b"EXPIRE", b"key", b"10"]), b":1\r\n");
+        assert_eq!(run(&server, &[b"TTL", b"key"]), b":10\r\n");
+
+        assert_eq!(run(&server, &[b"PEXPIRE", b"key", b"20"]), b":1\r\n");
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(run(&server, &[b"GET", b"key"]), b"$-1\r\n");
+    }
+
+    #[test]
+    fn test_expireat_and_pexpireat() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"key", b"v"]);
+        let in_100s = (unix_millis_now() / 1000 + 100).to_string();
+        assert_eq!(
+            run(&server, &[b"EXPIREAT", b"key", in_100s.as_bytes()]),
+            b":1\r\n"
+        );
+        // The deadline is a whole second, so up to one has already passed.
+        let ttl = run(&server, &[b"TTL", b"key"]);
+        assert!(ttl == b":100\r\n" || ttl == b":99\r\n", "{:?}", ttl);
+        assert_eq!(
+            run(&server, &[b"EXPIREAT", b"missing", in_100s.as_bytes()]),
+            b":0\r\n"
+        );
+
+        let past = (unix_millis_now() - 1000).to_string();
+        assert_eq!(
+            run(&server, &[b"PEXPIREAT", b"key", past.as_bytes()]),
+            b":1\r\n"
+        );
+        assert_eq!(run(&server, &[b"EXISTS", b"key"]), b":0\r\n");
+        assert_eq!(
+            run(&server, &[b"EXPIREAT", b"key", b"9223372036854775807"]),
+            b"-ERR invalid expire time\r\n"
+        );
+    }
+
+    #[test]
+    fn test_expire_non_positive_deletes() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"key", b"v"]);
+        assert_eq!(run(&server, &[b"EXPIRE", b"key", b"-1"]), b":1\r\n");
+        assert!(server.db(0).lock_all().iter().next().is_none());
+
+        let resp = RespValue::Array(vec![
+            RespValue::BulkString(b"EXPIRE".to_vec()),
+            RespValue::BulkString(b"key".to_vec()),
+            RespValue::BulkString(b"soon".to_vec()),
+        ]);
+        let err = Command::from_resp(resp).err().unwrap();
+        assert_eq!(err.to_string(), "value is not an integer or out of range");
+    }
+
+    #[test]
+    fn test_expire_deadline_boundary() {
+        let clock = Arc::new(ManualClock::new(Instant::now()));
+        let server = Server::with_clock(Config::default(), clock.clone());
+        run(&server, &[b"SET", b"key", b"v"]);
+        assert_eq!(run(&server, &[b"PEXPIRE", b"key", b"100"]), b":1\r\n");
+        let deadline = clock.now() + Duration::from_millis(100);
+        assert_eq!(
+            server
+                .db(0)
+                .lock(b"key")
+                .get(&b"key"[..])
+                .unwrap()
+                .expires_at,
+            Some(deadline)
+        );
+
+        // Just short of the deadline the key is still there, and a new TTL
+        // that lands on the same deadline is taken.
+        clock.advance(Duration::from_millis(99));
+        assert_eq!(run(&server, &[b"GET", b"key"]), b"$1\r\nv\r\n");
+        assert_eq!(run(&server, &[b"PTTL", b"key"]), b":1\r\n");
+        assert_eq!(run(&server, &[b"PEXPIRE", b"key", b"1"]), b":1\r\n");
+        // At the deadline it is already gone to every command.
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(run(&server, &[b"TTL", b"key"]), b":-2\r\n");
+        assert_eq!(run(&server, &[b"GET", b"key"]), b"$-1\r\n");
+        assert_eq!(run(&server, &[b"PEXPIRE", b"key", b"100"]), b":0\r\n");
+        assert!(server.db(0).lock(b"key").get(&b"key"[..]).is_none());
+
+        // A zero TTL deletes an existing key at once, and a missing key is :0.
+        run(&server, &[b"SET", b"other", b"v"]);
+        assert_eq!(run(&server, &[b"EXPIRE", b"other", b"0"]), b":1\r\n");
+        assert_eq!(run(&server, &[b"EXISTS", b"other"]), b":0\r\n");
+        assert_eq!(run(&server, &[b"EXPIRE", b"missing", b"10"]), b":0\r\n");
+    }
+
+    #[test]
+    fn test_expire_gt_keeps_longer_ttl() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"key", b"v", b"EX", b"100"]);
+
+        assert_eq!(run(&server, &[b"EXPIRE", b"key", b"50", b"GT"]), b":0\r\n");
+        assert_eq!(run(&server, &[b"TTL", b"key"]), b":100\r\n");
+        assert_eq!(run(&server, &[b"EXPIRE", b"key", b"200", b"gt"]), b":1\r\n");
+        assert_eq!(run(&server, &[b"TTL", b"key"]), b":200\r\n");
+        assert_eq!(run(&server, &[b"EXPIRE", b"key", b"50", b"LT"]), b":1\r\n");
+        assert_eq!(run(&server, &[b"TTL", b"key"]), b":50\r\n");
+
+        // No expiry counts as infinite: never less than anything, never exceeded.
+        run(&server, &[b"SET", b"plain", b"v"]);
+        assert_eq!(
+            run(&server, &[b"EXPIRE", b"plain", b"50", b"GT"]),
+            b":0\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"EXPIRE", b"plain", b"-1", b"XX"]),
+            b":0\r\n"
+        );
+        assert_eq!(run(&server, &[b"EXISTS", b"plain"]), b":1\r\n");
+        assert_eq!(
+            run(&server, &[b"EXPIRE", b"plain", b"50", b"LT"]),
+            b":1\r\n"
+        );
+    }
+
+    #[test]
+    fn test_expire_nx_refuses_existing_ttl() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"key", b"v"]);
+
+        assert_eq!(run(&server, &[b"EXPIRE", b"key", b"100", b"NX"]), b":1\r\n");
+        assert_eq!(
+            run(&server, &[b"PEXPIRE", b"key", b"5000", b"NX"]),
+            b":0\r\n"
+        );
+        assert_eq!(run(&server, &[b"TTL", b"key"]), b":100\r\n");
+        assert_eq!(run(&server, &[b"EXPIRE", b"key", b"10", b"XX"]), b":1\r\n");
+        assert_eq!(run(&server, &[b"TTL", b"key"]), b":10\r\n");
+
+        for (flags, reason) in [
+            (
+                &[&b"NX"[..], b"GT"][..],
+                "NX and XX, GT or LT options at the same time are not compatible",
+            ),
+            (
+                &[&b"GT"[..], b"LT"][..],
+                "GT and LT options at the same time are not compatible",
+            ),
+        ] {
+            let mut args = vec![RespValue::BulkString(b"EXPIRE".to_vec())];
+            args.extend([&b"key"[..], b"10"].map(|arg| RespValue::BulkString(arg.to_vec())));
+            args.extend(
+                flags
+                    .iter()
+                    .map(|flag| RespValue::BulkString(flag.to_vec())),
+            );
+            let err = Command::from_resp(RespValue::Array(args)).err().unwrap();
+            assert_eq!(
+                err,
+                CommandError::InvalidArgument {
+                    reason: reason.to_string()
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_persist_removes_expiry() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"key", b"v", b"PX", b"20"]);
+        run(&server, &[b"SET", b"plain", b"v"]);
+
+        assert_eq!(run(&server, &[b"PERSIST", b"key"]), b":1\r\n");
+        assert_eq!(run(&server, &[b"PERSIST", b"key"]), b":0\r\n");
+        assert_eq!(run(&server, &[b"PERSIST", b"plain"]), b":0\r\n");
+        assert_eq!(run(&server, &[b"PERSIST", b"missing"]), b":0\r\n");
+
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(run(&server, &[b"GET", b"key"]), b"$1\r\nv\r\n");
+    }
+
+    #[test]
+    fn test_type_reports_value_kind() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"string", b"v"]);
+        run(&server, &[b"RPUSH", b"list", b"v"]);
+        run(&server, &[b"SET", b"expired", b"v", b"PX", b"1"]);
+        thread::sleep(Duration::from_millis(5));
+
+        assert_eq!(run(&server, &[b"TYPE", b"string"]), b"+string\r\n");
+        assert_eq!(run(&server, &[b"TYPE", b"list"]), b"+list\r\n");
+        assert_eq!(run(&server, &[b"TYPE", b"expired"]), b"+none\r\n");
+        assert_eq!(run(&server, &[b"TYPE", b"missing"]), b"+none\r\n");
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"*", b"anything"));
+        assert!(glob_match(b"user:*", b"user:42"));
+        assert!(!glob_match(b"user:*", b"session:42"));
+        assert!(glob_match(b"h?llo", b"hello"));
+        assert!(!glob_match(b"h?llo", b"hllo"));
+        assert!(glob_match(b"h[ab]llo", b"hallo"));
+        assert!(glob_match(b"h[ab]llo", b"hbllo"));
+        assert!(!glob_match(b"h[ab]llo", b"hcllo"));
+        assert!(glob_match(b"h[^e]llo", b"hallo"));
+        assert!(!glob_match(b"h[^e]llo", b"hello"));
+        assert!(glob_match(b"h[a-c]llo", b"hbllo"));
+        assert!(glob_match(b"a*b*c", b"aXXbYYc"));
+        assert!(!glob_match(b"a*b*c", b"aXXbYY"));
+        assert!(glob_match(b"a\\*", b"a*"));
+        assert!(!glob_match(b"a\\*", b"ab"));
+    }
+
+    #[test]
+    fn test_keys_filters_and_skips_expired() {
+        let server = Server::default();
+        assert_eq!(run(&server, &[b"KEYS", b"*"]), b"*0\r\n");
+
+        run(&server, &[b"SET", b"user:1", b"v"]);
+        run(&server, &[b"SET", b"session:1", b"v"]);
+        run(&server, &[b"SET", b"user:2", b"v", b"PX", b"1"]);
+        thread::sleep(Duration::from_millis(5));
+
+        assert_eq!(
+            run(&server, &[b"KEYS", b"user:*"]),
+            b"*1\r\n$6\r\nuser:1\r\n"
+        );
+        let RespValue::Array(all) = RespValue::parse(&run(&server, &[b"KEYS", b"*"])).unwrap().0
+        else {
+            panic!("expected an array reply");
+        };
+        assert_eq!(all.len(), 2);
+    }
+
+    fn scan(server: &Server, args: &[&[u8]]) -> (Vec<u8>, Vec<Vec<u8>>) {
+        let reply = run(server, args);
+        let (RespValue::Array(parts), _) = RespValue::parse(&reply).unwrap() else {
+            panic!("expected an array reply");
+        };
+        let [RespValue::BulkString(cursor), RespValue::Array(keys)] = &parts[..] else {
+            panic!("unexpected SCAN reply {:?}", parts);
+        };
+        let keys = keys
+            .iter()
+            .map(|key| match key {
+                RespValue::BulkString(key) => key.clone(),
+                other => panic!("unexpected key {:?}", other),
+            })
+            .collect();
+        (cursor.clone(), keys)
+    }
+
+    #[test]
+    fn test_scan_visits_every_key() {
+        let server = Server::default();
+        let expected: HashSet<Vec<u8>> = (0..100)
+            .map(|i| format!("key:{}", i).into_bytes())
+            .collect();
+        for key in &expected {
+            run(&server, &[b"SET", key, b"v"]);
+        }
+
+        let mut seen = HashSet::new();
+        let mut cursor = b"0".to_vec();
+        let mut calls = 0;
+        loop {
+            let (next, keys) = scan(&server, &[b"SCAN", &cursor, b"COUNT", b"7"]);
+            assert!(keys.len() <= 7);
+            seen.extend(keys);
+            calls += 1;
+            if next == b"0" {
+                break;
+            }
+            // Keys written mid-scan must not disturb the iteration.
+            run(
+                &server,
+                &[b"SET", format!("new:{}", calls).as_bytes(), b"v"],
+            );
+            cursor = next;
+        }
+        assert!(expected.is_subset(&seen));
+        assert!(calls >= 100 / 7);
+    }
+
+    #[test]
+    fn test_scan_match() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"user:1", b"v"]);
+        run(&server, &[b"SET", b"user:2", b"v"]);
+        run(&server, &[b"SET", b"session:1", b"v"]);
+
+        let mut keys = Vec::new();
+        let mut cursor = b"0".to_vec();
+        loop {
+            let (next, page) = scan(&server, &[b"SCAN", &cursor, b"MATCH", b"user:*"]);
+            keys.extend(page);
+            if next == b"0" {
+                break;
+            }
+            cursor = next;
+        }
+        keys.sort();
+        assert_eq!(keys, vec![b"user:1".to_vec(), b"user:2".to_vec()]);
+    }
+
+    #[test]
+    fn test_scan_page_always_terminates() {
+        // Small LCG so the sizes vary without pulling in a rand dependency.
+        let mut seed: u64 = 0x2545f4914f6cdd1d;
+        let mut next = || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            seed >> 33
+        };
+
+        for _ in 0..50 {
+            let size = (next() % 500) as usize;
+            let count = (next() % 20 + 1) as usize;
+            let elements: Vec<Vec<u8>> = (0..size).map(|i| i.to_string().into_bytes()).collect();
+
+            let mut cursor = 0;
+            let mut seen = 0;
+            let mut steps = 0;
+            loop {
+                let (next_cursor, page) =
+                    scan_page(elements.iter().map(Vec::as_slice), cursor, count);
+                seen += page.len();
+                steps += 1;
+                assert!(
+                    steps <= size / count + 1,
+                    "no progress at cursor {}",
+                    cursor
+                );
+                if next_cursor == 0 {
+                    break;
+                }
+                assert!(next_cursor > cursor);
+                cursor = next_cursor;
+            }
+            assert_eq!(seen, size);
+        }
+    }
+
+    #[test]
+    fn test_scan_page_keeps_equal_positions_together() {
+        let elements: Vec<&[u8]> = vec![b"a", b"a", b"a", b"b"];
+        let (cursor, page) = scan_page(elements.clone(), 0, 1);
+        assert!(page.len() == 3 || page == vec![&b"b"[..]]);
+        assert_ne!(cursor, 0);
+        let (cursor, rest) = scan_page(elements, cursor, 10);
+        assert_eq!(cursor, 0);
+        assert_eq!(page.len() + rest.len(), 4);
+    }
+
+    #[test]
+    fn test_object_lastmodified_tracks_writes_only() {
+        let server = Server::default();
+        assert_eq!(
+            run(&server, &[b"OBJECT", b"LASTMODIFIED", b"missing"]),
+            b"-ERR no such key\r\n"
+        );
+
+        run(&server, &[b"RPUSH", b"list", b"a"]);
+        let backdate = |server: &Server| {
+            let db = server.db(0);
+            let mut db_lock = db.lock(b"list");
+            let mut db_value = db_lock.get_mut(&b"list"[..]).unwrap();
+            db_value.modified_at -= Duration::from_secs(10);
+        };
+        backdate(&server);
+        run(&server, &[b"LRANGE", b"list", b"0", b"-1"]);
+        assert_eq!(
+            run(&server, &[b"OBJECT", b"lastmodified", b"list"]),
+            b":10\r\n"
+        );
+
+        run(&server, &[b"RPUSH", b"list", b"b"]);
+        assert_eq!(
+            run(&server, &[b"OBJECT", b"LASTMODIFIED", b"list"]),
+            b":0\r\n"
+        );
+    }
+
+    #[test]
+    fn test_object_encoding_of_strings() {
+        let server = Server::default();
+        assert_eq!(
+            run(&server, &[b"OBJECT", b"ENCODING", b"missing"]),
+            b"-ERR no such key\r\n"
+        );
+
+        run(&server, &[b"SET", b"number", b"-12345"]);
+        assert_eq!(
+            run(&server, &[b"OBJECT", b"ENCODING", b"number"]),
+            b"+int\r\n"
+        );
+        run(&server, &[b"SET", b"short", b"hello"]);
+        assert_eq!(
+            run(&server, &[b"OBJECT", b"encoding", b"short"]),
+            b"+embstr\r\n"
+        );
+        run(&server, &[b"SET", b"long", &[b'x'; 45]]);
+        assert_eq!(
+            run(&server, &[b"OBJECT", b"ENCODING", b"long"]),
+            b"+raw\r\n"
+        );
+
+        // Like Redis, a string appended to stays raw even if it looks like a number.
+        run(&server, &[b"APPEND", b"number", b"6"]);
+        assert_eq!(
+            run(&server, &[b"OBJECT", b"ENCODING", b"number"]),
+            b"+raw\r\n"
+        );
+        run(&server, &[b"SET", b"number", b"1"]);
+        assert_eq!(
+            run(&server, &[b"OBJECT", b"ENCODING", b"number"]),
+            b"+int\r\n"
+        );
+    }
+
+    #[test]
+    fn test_object_encoding_of_collections() {
+        let server = Server::default();
+        run(&server, &[b"RPUSH", b"list", b"a", b"b"]);
+        run(&server, &[b"HSET", b"hash", b"field", b"value"]);
+        run(&server, &[b"SADD", b"ints", b"1", b"2"]);
+        run(&server, &[b"SADD", b"words", b"one", b"two"]);
+        run(&server, &[b"ZADD", b"zset", b"1", b"one"]);
+        for (key, encoding) in [
+            (&b"list"[..], &b"+listpack\r\n"[..]),
+            (b"hash", b"+listpack\r\n"),
+            (b"ints", b"+intset\r\n"),
+            (b"words", b"+listpack\r\n"),
+            (b"zset", b"+listpack\r\n"),
+        ] {
+            assert_eq!(run(&server, &[b"OBJECT", b"ENCODING", key]), encoding);
+        }
+
+        run(&server, &[b"RPUSH", b"list", &[b'x'; 8192]]);
+        run(&server, &[b"HSET", b"hash", b"big", &[b'x'; 65]]);
+        run(&server, &[b"ZADD", b"zset", b"2", &[b'x'; 65]]);
+        assert_eq!(
+            run(&server, &[b"OBJECT", b"ENCODING", b"list"]),
+            b"+quicklist\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"OBJECT", b"ENCODING", b"hash"]),
+            b"+hashtable\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"OBJECT", b"ENCODING", b"zset"]),
+            b"+skiplist\r\n"
+        );
+    }
+
+    #[test]
+    fn test_rename_moves_value_and_ttl() {
+        let server = Server::default();
+        assert_eq!(
+            run(&server, &[b"RENAME", b"missing", b"dst"]),
+            b"-ERR no such key\r\n"
+        );
+
+        run(&server, &[b"SET", b"src", b"v", b"EX", b"100"]);
+        run(&server, &[b"SET", b"dst", b"old"]);
+        assert_eq!(run(&server, &[b"RENAME", b"src", b"dst"]), b"+OK\r\n");
+        assert_eq!(run(&server, &[b"EXISTS", b"src"]), b":0\r\n");
+        assert_eq!(run(&server, &[b"GET", b"dst"]), b"$1\r\nv\r\n");
+        assert_eq!(run(&server, &[b"TTL", b"dst"]), b":100\r\n");
+
+        assert_eq!(run(&server, &[b"RENAME", b"dst", b"dst"]), b"+OK\r\n");
+        assert_eq!(run(&server, &[b"GET", b"dst"]), b"$1\r\nv\r\n");
+    }
+
+    #[test]
+    fn test_sort_numeric_and_alpha() {
+        let server = Server::default();
+        run(
+            &server,
+            &[b"RPUSH", b"list", b"10", b"2", b"-1.5", b"2", b"3"],
+        );
+        assert_eq!(
+            run(&server, &[b"SORT", b"list"]),
+            b"*5\r\n$4\r\n-1.5\r\n$1\r\n2\r\n$1\r\n2\r\n$1\r\n3\r\n$2\r\n10\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"SORT", b"list", b"alpha"]),
+            b"*5\r\n$4\r\n-1.5\r\n$2\r\n10\r\n$1\r\n2\r\n$1\r\n2\r\n$1\r\n3\r\n"
+        );
+
+        run(&server, &[b"SADD", b"set", b"b", b"a", b"c"]);
+        assert_eq!(
+            run(&server, &[b"SORT", b"set"]),
+            b"-ERR One or more scores can't be converted into double\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"SORT", b"set", b"ALPHA", b"DESC"]),
+            b"*3\r\n$1\r\nc\r\n$1\r\nb\r\n$1\r\na\r\n"
+        );
+        assert_eq!(run(&server, &[b"SORT", b"missing"]), b"*0\r\n");
+
+        run(&server, &[b"SET", b"string", b"1"]);
+        assert!(run(&server, &[b"SORT", b"string"]).starts_with(b"-WRONGTYPE"));
+    }
+
+    #[test]
+    fn test_sort_limit_window() {
+        let server = Server::default();
+        run(&server, &[b"RPUSH", b"list", b"5", b"1", b"4", b"2", b"3"]);
+        assert_eq!(
+            run(&server, &[b"SORT", b"list", b"LIMIT", b"1", b"2"]),
+            b"*2\r\n$1\r\n2\r\n$1\r\n3\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"SORT", b"list", b"DESC", b"LIMIT", b"3", b"10"]),
+            b"*2\r\n$1\r\n2\r\n$1\r\n1\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"SORT", b"list", b"LIMIT", b"-5", b"-1"]),
+            b"*5\r\n$1\r\n1\r\n$1\r\n2\r\n$1\r\n3\r\n$1\r\n4\r\n$1\r\n5\r\n"
+        );
+        assert!(matches!(
+            Command::from_resp(RespValue::Array(
+                [&b"SORT"[..], b"list", b"LIMIT", b"1"]
+                    .iter()
+                    .map(|arg| RespValue::BulkString(arg.to_vec()))
+                    .collect()
+            )),
+            Err(CommandError::WrongArgCount)
+        ));
+    }
+
+    #[test]
+    fn test_object_idletime_counts_from_last_access() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"key", b"value"]);
+        assert_eq!(run(&server, &[b"OBJECT", b"IDLETIME", b"key"]), b":0\r\n");
+        thread::sleep(Duration::from_millis(1100));
+
+        // Asking doesn't count as an access.
+        run(&server, &[b"OBJECT", b"ENCODING", b"key"]);
+        assert_eq!(run(&server, &[b"OBJECT", b"IDLETIME", b"key"]), b":1\r\n");
+        run(&server, &[b"GET", b"key"]);
+        assert_eq!(run(&server, &[b"OBJECT", b"IDLETIME", b"key"]), b":0\r\n");
+        assert_eq!(
+            run(&server, &[b"OBJECT", b"IDLETIME", b"missing"]),
+            b"-ERR no such key\r\n"
+        );
+    }
+
+    #[test]
+    fn test_object_freq_needs_lfu_policy() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"key", b"value"]);
+        assert!(run(&server, &[b"OBJECT", b"FREQ", b"key"])
+            .starts_with(b"-ERR An LFU maxmemory policy is not selected"));
+
+        server
+            .config
+            .write()
+            .unwrap()
+            .set("maxmemory-policy", "allkeys-lfu")
+            .unwrap();
+        assert_eq!(run(&server, &[b"OBJECT", b"FREQ", b"key"]), b":5\r\n");
+        // The first access after the starting value always counts.
+        run(&server, &[b"GET", b"key"]);
+        assert_eq!(run(&server, &[b"OBJECT", b"FREQ", b"key"]), b":6\r\n");
+        assert_eq!(
+            run(&server, &[b"OBJECT", b"FREQ", b"missing"]),
+            b"-ERR no such key\r\n"
+        );
+    }
+
+    #[test]
+    fn test_dump_and_restore_round_trip() {
+        let server = Server::default();
+        run(&server, &[b"RPUSH", b"list", b"a", b"b", b"c"]);
+        let dumped = run(&server, &[b"DUMP", b"list"]);
+        let (RespValue::BulkString(payload), _) = RespValue::parse(&dumped).unwrap() else {
+            panic!("DUMP replied {:?}", dumped);
+        };
+        assert_eq!(run(&server, &[b"DUMP", b"missing"]), b"$-1\r\n");
+
+        assert_eq!(
+            run(&server, &[b"RESTORE", b"copy", b"0", &payload]),
+            b"+OK\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"LRANGE", b"copy", b"0", b"-1"]),
+            b"*3\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nc\r\n"
+        );
+        assert_eq!(run(&server, &[b"TTL", b"copy"]), b":-1\r\n");
+        assert_eq!(
+            run(&server, &[b"RESTORE", b"expiring", b"5000", &payload]),
+            b"+OK\r\n"
+        );
+        assert_eq!(run(&server, &[b"TTL", b"expiring"]), b":5\r\n");
+
+        let mut corrupt = payload.clone();
+        corrupt[6] ^= 1;
+        assert_eq!(
+            run(&server, &[b"RESTORE", b"other", b"0", &corrupt]),
+            b"-ERR DUMP payload version or checksum are wrong\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"RESTORE", b"other", b"0", b"junk"]),
+            b"-ERR DUMP payload version or checksum are wrong\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"RESTORE", b"other", b"-1", &payload]),
+            b"-ERR Invalid TTL value, must be >= 0\r\n"
+        );
+    }
+
+    #[test]
+    fn test_restore_onto_existing_key_needs_replace() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"string", b"value"]);
+        run(&server, &[b"SET", b"target", b"old"]);
+        let dumped = run(&server, &[b"DUMP", b"string"]);
+        let (RespValue::BulkString(payload), _) = RespValue::parse(&dumped).unwrap() else {
+            panic!("DUMP replied {:?}", dumped);
+        };
+
+        assert_eq!(
+            run(&server, &[b"RESTORE", b"target", b"0", &payload]),
+            b"-BUSYKEY Target key name already exists.\r\n"
+        );
+        assert_eq!(run(&server, &[b"GET", b"target"]), b"$3\r\nold\r\n");
+        assert_eq!(
+            run(
+                &server,
+                &[b"RESTORE", b"target", b"0", &payload, b"replace"]
+            ),
+            b"+OK\r\n"
+        );
+        assert_eq!(run(&server, &[b"GET", b"target"]), b"$5\r\nvalue\r\n");
+    }
+
+    #[test]
+    fn test_touch_counts_live_keys_and_refreshes_access() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"a", b"1"]);
+        run(&server, &[b"RPUSH", b"b", b"x"]);
+        run(&server, &[b"SET", b"gone", b"1", b"PX", b"1"]);
+        let accessed = |key: &[u8]| {
+            server
+                .db(0)
+                .lock(key)
+                .get(key)
+                .map(|db_value| db_value.accessed_at.millis())
+        };
+        let before = accessed(b"a").unwrap();
+        thread::sleep(Duration::from_millis(5));
+
+        assert_eq!(run(&server, &[b"TOUCH", b"a", b"b", b"missing"]), b":2\r\n");
+        assert!(accessed(b"a").unwrap() > before);
+        assert_eq!(run(&server, &[b"TOUCH", b"gone"]), b":0\r\n");
+        assert_eq!(run(&server, &[b"GET", b"a"]), b"$1\r\n1\r\n");
+    }
+
+    #[test]
+    fn test_del_and_unlink_count_removed_keys() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"a", b"1"]);
+        run(&server, &[b"HSET", b"b", b"f", b"v"]);
+        run(&server, &[b"SET", b"gone", b"1", b"PX", b"1"]);
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(
+            run(&server, &[b"DEL", b"a", b"b", b"gone", b"missing", b"a"]),
+            b":2\r\n"
+        );
+        assert_eq!(run(&server, &[b"EXISTS", b"a", b"b"]), b":0\r\n");
+
+        run(&server, &[b"SET", b"a", b"1"]);
+        let members: Vec<Vec<u8>> = (0..=LAZYFREE_THRESHOLD)
+            .map(|n| n.to_string().into_bytes())
+            .collect();
+        let mut sadd: Vec<&[u8]> = vec![b"SADD", b"big"];
+        sadd.extend(members.iter().map(Vec::as_slice));
+        run(&server, &sadd);
+        assert_eq!(
+            run(&server, &[b"UNLINK", b"a", b"big", b"missing"]),
+            b":2\r\n"
+        );
+        assert_eq!(run(&server, &[b"EXISTS", b"a", b"big"]), b":0\r\n");
+        assert_eq!(server.db(0).used_memory(), 0);
+    }
+
+    #[test]
+    fn test_copy_replace() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"src", b"new", b"EX", b"100"]);
+        run(&server, &[b"SET", b"dst", b"old"]);
+
+        assert_eq!(run(&server, &[b"COPY", b"src", b"dst"]), b":0\r\n");
+        assert_eq!(run(&server, &[b"GET", b"dst"]), b"$3\r\nold\r\n");
+        assert_eq!(
+            run(&server, &[b"COPY", b"src", b"dst", b"REPLACE"]),
+            b":1\r\n"
+        );
+        assert_eq!(run(&server, &[b"GET", b"dst"]), b"$3\r\nnew\r\n");
+        assert_eq!(run(&server, &[b"TTL", b"dst"]), b":100\r\n");
+        assert_eq!(run(&server, &[b"COPY", b"missing", b"dst"]), b":0\r\n");
+    }
+
+    #[test]
+    fn test_copy_is_independent() {
+        let server = Server::default();
+        run(&server, &[b"RPUSH", b"src", b"a", b"b"]);
+        assert_eq!(run(&server, &[b"COPY", b"src", b"dst"]), b":1\r\n");
+
+        run(&server, &[b"RPUSH", b"src", b"c"]);
+        run(&server, &[b"LPOP", b"dst"]);
+        assert_eq!(
+            run(&server, &[b"LRANGE", b"src", b"0", b"-1"]),
+            b"*3\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nc\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"LRANGE", b"dst", b"0", b"-1"]),
+            b"*1\r\n$1\r\nb\r\n"
+        );
+    }
+}
@@ -0,0 +1,806 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::{
+    bulk_array, bulk_string, error_reply, normalize_range, ok, parse_f64, unless_zero, ArgParser,
+    CommandError, CommandExt, ErrorCode, Propagate,
+};
+use crate::clock::Clock;
+use crate::db::{remove_if_empty, Database, DbData, DbValue};
+use crate::parser::RespValue;
+use crate::propagation;
+use crate::server::Server;
+
+/// Shared body of LPUSH/RPUSH, creating the list if the key is missing.
+fn push(db: &Database, key: Vec<u8>, values: Vec<Vec<u8>>, front: bool) -> RespValue {
+    let mut db_lock = db.lock(&key);
+
+    if db_lock.get_live(&key).is_none() {
+        db_lock.insert(key.clone(), DbValue::new(DbData::List(VecDeque::new())));
+    }
+    let mut entry = db_lock.get_mut(&key);
+    let Some(DbValue {
+        data: DbData::List(list),
+        modified_at,
+        ..
+    }) = entry.as_deref_mut()
+    else {
+        return CommandError::WrongType.to_resp();
+    };
+    *modified_at = Instant::now();
+
+    let pushed = values.len();
+    for value in values {
+        if front {
+            list.push_front(value);
+        } else {
+            list.push_back(value);
+        }
+    }
+    let reply = RespValue::Integer(list.len() as i64);
+    drop(entry);
+    // Still under the shard lock, so a BLPOP can't find the list empty and
+    // register after this.
+    db.waiters.wake(&key, pushed);
+    reply
+}
+
+fn parse_push(parser: &mut ArgParser) -> Result<(Vec<u8>, Vec<Vec<u8>>), CommandError> {
+    let key = parser.next_bulk_string()?;
+    let values = parser.remaining_bulk_strings()?;
+    if values.is_empty() {
+        return Err(CommandError::WrongArgCount);
+    }
+    Ok((key, values))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Lpush {
+    key: Vec<u8>,
+    values: Vec<Vec<u8>>,
+}
+
+impl CommandExt for Lpush {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let (key, values) = parse_push(parser)?;
+        Ok(Lpush { key, values })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        push(db, self.key, self.values, true)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Rpush {
+    key: Vec<u8>,
+    values: Vec<Vec<u8>>,
+}
+
+impl CommandExt for Rpush {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let (key, values) = parse_push(parser)?;
+        Ok(Rpush { key, values })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        push(db, self.key, self.values, false)
+    }
+}
+
+/// Shared body of LPOP/RPOP. The key is removed once the list is drained.
+fn pop(db: &Database, key: &[u8], count: Option<usize>, front: bool) -> RespValue {
+    let mut db_lock = db.lock(key);
+
+    let mut entry = db_lock.get_live(key);
+    let list = match entry.as_deref_mut() {
+        Some(DbValue {
+            data: DbData::List(list),
+            modified_at,
+            ..
+        }) => {
+            *modified_at = Instant::now();
+            list
+        }
+        Some(_) => return CommandError::WrongType.to_resp(),
+        None if count.is_some() => return RespValue::NullArray,
+        None => return RespValue::Null,
+    };
+
+    let take = count.unwrap_or(1).min(list.len());
+    let popped: Vec<Vec<u8>> = (0..take)
+        .filter_map(|_| {
+            if front {
+                list.pop_front()
+            } else {
+                list.pop_back()
+            }
+        })
+        .collect();
+    drop(entry);
+    remove_if_empty(&mut db_lock, key);
+
+    match count {
+        Some(_) => bulk_array(popped),
+        None => match popped.first() {
+            Some(value) => bulk_string(value),
+            None => RespValue::Null,
+        },
+    }
+}
+
+fn parse_pop(parser: &mut ArgParser) -> Result<(Vec<u8>, Option<usize>), CommandError> {
+    let key = parser.next_bulk_string()?;
+    let count = match parser.iter.peek() {
+        Some(_) => {
+            let count = parser.next_integer()?;
+            Some(
+                usize::try_from(count).map_err(|_| CommandError::InvalidArgument {
+                    reason: "value is out of range, must be positive".to_string(),
+                })?,
+            )
+        }
+        None => None,
+    };
+    parser.finish()?;
+    Ok((key, count))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Lpop {
+    key: Vec<u8>,
+    count: Option<usize>,
+}
+
+impl CommandExt for Lpop {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let (key, count) = parse_pop(parser)?;
+        Ok(Lpop { key, count })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        pop(db, &self.key, self.count, true)
+    }
+
+    fn propagate(&self, _clock: &dyn Clock) -> Propagate {
+        // A missing key has nothing to pop.
+        Box::new(|request, result| {
+            (!matches!(result, RespValue::Null | RespValue::NullArray)).then(|| vec![request])
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Rpop {
+    key: Vec<u8>,
+    count: Option<usize>,
+}
+
+impl CommandExt for Rpop {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let (key, count) = parse_pop(parser)?;
+        Ok(Rpop { key, count })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        pop(db, &self.key, self.count, false)
+    }
+
+    fn propagate(&self, _clock: &dyn Clock) -> Propagate {
+        // A missing key has nothing to pop.
+        Box::new(|request, result| {
+            (!matches!(result, RespValue::Null | RespValue::NullArray)).then(|| vec![request])
+        })
+    }
+}
+
+/// `BLPOP key [key ...] timeout` and BRPOP. Blocking is up to the connection,
+/// so the session runs these rather than `execute`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockingPop {
+    pub keys: Vec<Vec<u8>>,
+    /// `None` waits forever, as a timeout of 0 does.
+    pub timeout: Option<Duration>,
+    pub front: bool,
+}
+
+impl BlockingPop {
+    pub(super) fn parse(parser: &mut ArgParser, front: bool) -> Result<Self, CommandError> {
+        let mut keys = parser.remaining_bulk_strings()?;
+        let timeout = keys.pop().ok_or(CommandError::WrongArgCount)?;
+        if keys.is_empty() {
+            return Err(CommandError::WrongArgCount);
+        }
+        let seconds = parse_f64(&timeout).ok_or(CommandError::InvalidArgument {
+            reason: "timeout is not a float or out of range".to_string(),
+        })?;
+        let timeout =
+            Duration::try_from_secs_f64(seconds).map_err(|_| CommandError::InvalidArgument {
+                reason: "timeout is negative".to_string(),
+            })?;
+        Ok(BlockingPop {
+            keys,
+            timeout: (!timeout.is_zero()).then_some(timeout),
+            front,
+        })
+    }
+
+    /// Pops from the first key holding a list, replying `[key, value]`. The pop
+    /// is logged as the LPOP or RPOP it amounts to, so replaying the AOF never
+    /// blocks. If every key is empty, `on_empty` runs while their shards are
+    /// still locked and `None` is returned.
+    pub fn try_pop(
+        &self,
+        server: &Server,
+        db_index: usize,
+        on_empty: impl FnOnce(&Database),
+    ) -> Option<RespValue> {
+        let db = server.db(db_index);
+        loop {
+            let key = {
+                let mut shards = db.lock_keys(self.keys.iter().map(Vec::as_slice));
+                let mut found = None;
+                for key in &self.keys {
+                    match shards.shard(key).get_live(key).as_deref() {
+                        Some(DbValue {
+                            data: DbData::List(_),
+                            ..
+                        }) => {
+                            found = Some(key.clone());
+                            break;
+                        }
+                        Some(_) => return Some(CommandError::WrongType.to_resp()),
+                        None => {}
+                    }
+                }
+                match found {
+                    Some(key) => key,
+                    None => {
+                        on_empty(&db);
+                        return None;
+                    }
+                }
+            };
+
+            let name: &[u8] = if self.front { b"LPOP" } else { b"RPOP" };
+            let frame = bulk_array([name, &key]);
+            let popped = propagation::run_propagated(
+                server,
+                db_index,
+                || pop(&db, &key, None, self.front),
+                // Nothing was popped if another client drained the list first.
+                |popped| matches!(popped, RespValue::BulkString(_)).then(|| vec![frame]),
+            );
+            // Another client may have drained the list once the locks were released.
+            if let RespValue::BulkString(value) = popped {
+                return Some(RespValue::Array(vec![
+                    RespValue::BulkString(key),
+                    RespValue::BulkString(value),
+                ]));
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Lrange {
+    key: Vec<u8>,
+    start: i64,
+    stop: i64,
+}
+
+impl CommandExt for Lrange {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let start = parser.next_integer()?;
+        let stop = parser.next_integer()?;
+        parser.finish()?;
+        Ok(Lrange { key, start, stop })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        db.read_live(&self.key, |db_value| {
+            let list = match db_value {
+                Some(DbValue {
+                    data: DbData::List(list),
+                    ..
+                }) => list,
+                Some(_) => return CommandError::WrongType.to_resp(),
+                None => return RespValue::Array(Vec::new()),
+            };
+
+            match normalize_range(self.start, self.stop, list.len()) {
+                Some((start, stop)) => bulk_array(list.range(start..=stop)),
+                None => RespValue::Array(Vec::new()),
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Linsert {
+    key: Vec<u8>,
+    after: bool,
+    pivot: Vec<u8>,
+    value: Vec<u8>,
+}
+
+impl CommandExt for Linsert {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let after = match parser.next_bulk_string()?.to_ascii_uppercase().as_slice() {
+            b"BEFORE" => false,
+            b"AFTER" => true,
+            _ => return Err(CommandError::SyntaxError),
+        };
+        let pivot = parser.next_bulk_string()?;
+        let value = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Linsert {
+            key,
+            after,
+            pivot,
+            value,
+        })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        let mut db_lock = db.lock(&self.key);
+
+        let mut entry = db_lock.get_live(&self.key);
+        let Some(DbValue {
+            data: DbData::List(list),
+            modified_at,
+            ..
+        }) = entry.as_deref_mut()
+        else {
+            return match entry {
+                Some(_) => CommandError::WrongType.to_resp(),
+                None => RespValue::Integer(0),
+            };
+        };
+
+        let Some(index) = list.iter().position(|element| *element == self.pivot) else {
+            return RespValue::Integer(-1);
+        };
+        list.insert(index + self.after as usize, self.value);
+        *modified_at = Instant::now();
+        RespValue::Integer(list.len() as i64)
+    }
+
+    fn propagate(&self, _clock: &dyn Clock) -> Propagate {
+        // 0 for a missing list and -1 for a missing pivot insert nothing.
+        Box::new(|request, result| {
+            matches!(result, RespValue::Integer(len) if *len > 0).then(|| vec![request])
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Lset {
+    key: Vec<u8>,
+    index: i64,
+    value: Vec<u8>,
+}
+
+impl CommandExt for Lset {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let index = parser.next_integer()?;
+        let value = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Lset { key, index, value })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        let mut db_lock = db.lock(&self.key);
+
+        let mut entry = db_lock.get_live(&self.key);
+        let Some(DbValue {
+            data: DbData::List(list),
+            modified_at,
+            ..
+        }) = entry.as_deref_mut()
+        else {
+            return match entry {
+                Some(_) => CommandError::WrongType.to_resp(),
+                None => CommandError::NoSuchKey.to_resp(),
+            };
+        };
+
+        // A negative index counts from the end, -1 being the last element.
+        let len = list.len() as i64;
+        let index = if self.index < 0 {
+            len + self.index
+        } else {
+            self.index
+        };
+        let Some(element) = usize::try_from(index)
+            .ok()
+            .and_then(|index| list.get_mut(index))
+        else {
+            return error_reply(ErrorCode::Err, "index out of range");
+        };
+        *element = self.value;
+        *modified_at = Instant::now();
+        ok()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Lrem {
+    key: Vec<u8>,
+    count: i64,
+    value: Vec<u8>,
+}
+
+impl CommandExt for Lrem {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let count = parser.next_integer()?;
+        let value = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Lrem { key, count, value })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        let mut db_lock = db.lock(&self.key);
+
+        let mut entry = db_lock.get_live(&self.key);
+        let removed = match entry.as_deref_mut() {
+            Some(DbValue {
+                data: DbData::List(list),
+                modified_at,
+                ..
+            }) => {
+                // A positive count removes from the head, a negative one from
+                // the tail, and 0 removes every match.
+                let limit = match self.count {
+                    0 => usize::MAX,
+                    count => count.unsigned_abs().try_into().unwrap_or(usize::MAX),
+                };
+                let mut matches: Vec<usize> = list
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, element)| **element == self.value)
+                    .map(|(index, _)| index)
+                    .collect();
+                if self.count < 0 {
+                    matches.reverse();
+                }
+                matches.truncate(limit);
+                matches.sort_unstable();
+                // Removing from the back keeps the earlier indexes valid.
+                for &index in matches.iter().rev() {
+                    list.remove(index);
+                }
+                if !matches.is_empty() {
+                    *modified_at = Instant::now();
+                }
+                matches.len()
+            }
+            Some(_) => return CommandError::WrongType.to_resp(),
+            None => 0,
+        };
+        drop(entry);
+        remove_if_empty(&mut db_lock, &self.key);
+        RespValue::Integer(removed as i64)
+    }
+
+    fn propagate(&self, _clock: &dyn Clock) -> Propagate {
+        Box::new(unless_zero)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Lpos {
+    key: Vec<u8>,
+    element: Vec<u8>,
+    rank: i64,
+    count: Option<usize>,
+    max_len: usize,
+}
+
+impl CommandExt for Lpos {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let element = parser.next_bulk_string()?;
+        let mut lpos = Lpos {
+            key,
+            element,
+            rank: 1,
+            count: None,
+            max_len: 0,
+        };
+        let non_negative = |value: i64, option: &str| {
+            usize::try_from(value).map_err(|_| CommandError::InvalidArgument {
+                reason: format!("{} can't be negative", option),
+            })
+        };
+        while parser.iter.peek().is_some() {
+            match parser.next_bulk_string()?.to_ascii_uppercase().as_slice() {
+                b"RANK" => {
+                    lpos.rank = parser.next_integer()?;
+                    if lpos.rank == 0 {
+                        return Err(CommandError::InvalidArgument {
+                            reason: "RANK can't be zero: use 1 to start from the first match, \
+                                     2 from the second ... or use negative to start from the \
+                                     end of the list"
+                                .to_string(),
+                        });
+                    }
+                }
+                b"COUNT" => lpos.count = Some(non_negative(parser.next_integer()?, "COUNT")?),
+                b"MAXLEN" => lpos.max_len = non_negative(parser.next_integer()?, "MAXLEN")?,
+                _ => return Err(CommandError::SyntaxError),
+            }
+        }
+        Ok(lpos)
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        db.read_live(&self.key, |db_value| {
+            let list = match db_value {
+                Some(DbValue {
+                    data: DbData::List(list),
+                    ..
+                }) => list,
+                Some(_) => return CommandError::WrongType.to_resp(),
+                None if self.count.is_some() => return RespValue::Array(Vec::new()),
+                None => return RespValue::Null,
+            };
+
+            // MAXLEN and COUNT of 0 both mean no limit.
+            let compared = match self.max_len {
+                0 => list.len(),
+                max_len => max_len.min(list.len()),
+            };
+            let wanted = match self.count {
+                Some(0) => usize::MAX,
+                Some(count) => count,
+                None => 1,
+            };
+            let indexes: Box<dyn Iterator<Item = usize>> = if self.rank > 0 {
+                Box::new(0..compared)
+            } else {
+                Box::new((list.len() - compared..list.len()).rev())
+            };
+            let skipped = self.rank.unsigned_abs().saturating_sub(1);
+            let positions: Vec<_> = indexes
+                .filter(|&index| list[index] == self.element)
+                .skip(skipped.try_into().unwrap_or(usize::MAX))
+                .take(wanted)
+                .map(|index| RespValue::Integer(index as i64))
+                .collect();
+
+            match self.count {
+                Some(_) => RespValue::Array(positions),
+                None => positions.into_iter().next().unwrap_or(RespValue::Null),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{run, Command, CommandError};
+    use crate::parser::RespValue;
+    use crate::server::Server;
+
+    #[test]
+    fn test_push_onto_missing_key() {
+        let server = Server::default();
+        assert_eq!(run(&server, &[b"RPUSH", b"list", b"a", b"b"]), b":2\r\n");
+        assert_eq!(run(&server, &[b"LPUSH", b"list", b"y", b"z"]), b":4\r\n");
+        assert_eq!(
+            run(&server, &[b"LRANGE", b"list", b"0", b"-1"]),
+            b"*4\r\n$1\r\nz\r\n$1\r\ny\r\n$1\r\na\r\n$1\r\nb\r\n"
+        );
+    }
+
+    #[test]
+    fn test_lrange_negative_and_out_of_range_indices() {
+        let server = Server::default();
+        run(&server, &[b"RPUSH", b"list", b"a", b"b", b"c"]);
+
+        assert_eq!(
+            run(&server, &[b"LRANGE", b"list", b"-2", b"-1"]),
+            b"*2\r\n$1\r\nb\r\n$1\r\nc\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"LRANGE", b"list", b"-100", b"100"]),
+            b"*3\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nc\r\n"
+        );
+        assert_eq!(run(&server, &[b"LRANGE", b"list", b"5", b"10"]), b"*0\r\n");
+        assert_eq!(run(&server, &[b"LRANGE", b"list", b"2", b"1"]), b"*0\r\n");
+        assert_eq!(
+            run(&server, &[b"LRANGE", b"missing", b"0", b"-1"]),
+            b"*0\r\n"
+        );
+    }
+
+    #[test]
+    fn test_pop_single_and_count() {
+        let server = Server::default();
+        run(&server, &[b"RPUSH", b"list", b"a", b"b", b"c"]);
+
+        assert_eq!(run(&server, &[b"LPOP", b"list"]), b"$1\r\na\r\n");
+        assert_eq!(run(&server, &[b"RPOP", b"list"]), b"$1\r\nc\r\n");
+        assert_eq!(
+            run(&server, &[b"LPOP", b"list", b"10"]),
+            b"*1\r\n$1\r\nb\r\n"
+        );
+        assert_eq!(run(&server, &[b"LPOP", b"list"]), b"$-1\r\n");
+        assert_eq!(run(&server, &[b"LPOP", b"list", b"2"]), b"*-1\r\n");
+    }
+
+    #[test]
+    fn test_popping_last_element_removes_key() {
+        let server = Server::default();
+        run(&server, &[b"RPUSH", b"list", b"only"]);
+        assert_eq!(run(&server, &[b"RPOP", b"list"]), b"$4\r\nonly\r\n");
+        assert_eq!(run(&server, &[b"EXISTS", b"list"]), b":0\r\n");
+        assert!(server.db(0).lock_all().iter().next().is_none());
+    }
+
+    #[test]
+    fn test_linsert_around_pivot() {
+        let server = Server::default();
+        run(&server, &[b"RPUSH", b"list", b"a", b"c", b"a"]);
+        assert_eq!(
+            run(&server, &[b"LINSERT", b"list", b"before", b"c", b"b"]),
+            b":4\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"LINSERT", b"list", b"AFTER", b"a", b"x"]),
+            b":5\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"LINSERT", b"list", b"AFTER", b"nope", b"x"]),
+            b":-1\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"LINSERT", b"missing", b"AFTER", b"a", b"x"]),
+            b":0\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"LRANGE", b"list", b"0", b"-1"]),
+            b"*5\r\n$1\r\na\r\n$1\r\nx\r\n$1\r\nb\r\n$1\r\nc\r\n$1\r\na\r\n"
+        );
+    }
+
+    #[test]
+    fn test_lset_negative_index() {
+        let server = Server::default();
+        run(&server, &[b"RPUSH", b"list", b"a", b"b", b"c"]);
+        assert_eq!(run(&server, &[b"LSET", b"list", b"-1", b"z"]), b"+OK\r\n");
+        assert_eq!(run(&server, &[b"LSET", b"list", b"0", b"y"]), b"+OK\r\n");
+        assert_eq!(
+            run(&server, &[b"LRANGE", b"list", b"0", b"-1"]),
+            b"*3\r\n$1\r\ny\r\n$1\r\nb\r\n$1\r\nz\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"LSET", b"list", b"-4", b"z"]),
+            b"-ERR index out of range\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"LSET", b"list", b"3", b"z"]),
+            b"-ERR index out of range\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"LSET", b"missing", b"0", b"z"]),
+            b"-ERR no such key\r\n"
+        );
+    }
+
+    #[test]
+    fn test_lrem_direction_and_emptying() {
+        let server = Server::default();
+        run(&server, &[b"RPUSH", b"list", b"x", b"a", b"x", b"b", b"x"]);
+        assert_eq!(run(&server, &[b"LREM", b"list", b"-2", b"x"]), b":2\r\n");
+        assert_eq!(
+            run(&server, &[b"LRANGE", b"list", b"0", b"-1"]),
+            b"*3\r\n$1\r\nx\r\n$1\r\na\r\n$1\r\nb\r\n"
+        );
+        assert_eq!(run(&server, &[b"LREM", b"list", b"1", b"a"]), b":1\r\n");
+        assert_eq!(run(&server, &[b"LREM", b"list", b"0", b"x"]), b":1\r\n");
+        assert_eq!(run(&server, &[b"LREM", b"list", b"0", b"b"]), b":1\r\n");
+        assert_eq!(run(&server, &[b"EXISTS", b"list"]), b":0\r\n");
+        assert_eq!(run(&server, &[b"LREM", b"list", b"0", b"b"]), b":0\r\n");
+    }
+
+    #[test]
+    fn test_list_commands_reject_strings() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"string", b"v"]);
+        let wrong_type = b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        assert_eq!(run(&server, &[b"LPUSH", b"string", b"a"]), wrong_type);
+        assert_eq!(run(&server, &[b"LPOP", b"string"]), wrong_type);
+        assert_eq!(
+            run(&server, &[b"LRANGE", b"string", b"0", b"-1"]),
+            wrong_type
+        );
+        assert_eq!(
+            run(&server, &[b"LINSERT", b"string", b"BEFORE", b"a", b"b"]),
+            wrong_type
+        );
+        assert_eq!(run(&server, &[b"LSET", b"string", b"0", b"a"]), wrong_type);
+        assert_eq!(run(&server, &[b"LREM", b"string", b"0", b"a"]), wrong_type);
+        assert_eq!(run(&server, &[b"LPOS", b"string", b"a"]), wrong_type);
+    }
+
+    #[test]
+    fn test_lpos_first_match() {
+        let server = Server::default();
+        run(&server, &[b"RPUSH", b"list", b"a", b"b", b"c", b"b"]);
+        assert_eq!(run(&server, &[b"LPOS", b"list", b"b"]), b":1\r\n");
+        assert_eq!(run(&server, &[b"LPOS", b"list", b"z"]), b"$-1\r\n");
+        assert_eq!(run(&server, &[b"LPOS", b"missing", b"b"]), b"$-1\r\n");
+        assert_eq!(
+            run(&server, &[b"LPOS", b"list", b"c", b"MAXLEN", b"2"]),
+            b"$-1\r\n"
+        );
+        let rank_zero = ["LPOS", "list", "b", "RANK", "0"]
+            .map(|arg| RespValue::BulkString(arg.as_bytes().to_vec()));
+        assert!(matches!(
+            Command::from_resp(RespValue::Array(rank_zero.to_vec())),
+            Err(CommandError::InvalidArgument { .. })
+        ));
+    }
+
+    #[test]
+    fn test_lpos_count_returns_every_index() {
+        let server = Server::default();
+        run(&server, &[b"RPUSH", b"list", b"x", b"a", b"x", b"b", b"x"]);
+        assert_eq!(
+            run(&server, &[b"LPOS", b"list", b"x", b"COUNT", b"2"]),
+            b"*2\r\n:0\r\n:2\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"LPOS", b"list", b"x", b"COUNT", b"0"]),
+            b"*3\r\n:0\r\n:2\r\n:4\r\n"
+        );
+        assert_eq!(
+            run(
+                &server,
+                &[b"LPOS", b"list", b"x", b"RANK", b"2", b"COUNT", b"0"]
+            ),
+            b"*2\r\n:2\r\n:4\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"LPOS", b"missing", b"x", b"COUNT", b"1"]),
+            b"*0\r\n"
+        );
+    }
+
+    #[test]
+    fn test_lpos_negative_rank_searches_from_the_tail() {
+        let server = Server::default();
+        run(&server, &[b"RPUSH", b"list", b"x", b"a", b"x", b"b", b"x"]);
+        assert_eq!(
+            run(&server, &[b"LPOS", b"list", b"x", b"RANK", b"-1"]),
+            b":4\r\n"
+        );
+        assert_eq!(
+            run(
+                &server,
+                &[b"LPOS", b"list", b"x", b"RANK", b"-2", b"COUNT", b"0"]
+            ),
+            b"*2\r\n:2\r\n:0\r\n"
+        );
+        assert_eq!(
+            run(
+                &server,
+                &[b"LPOS", b"list", b"x", b"RANK", b"-1", b"MAXLEN", b"2"]
+            ),
+            b":4\r\n"
+        );
+        assert_eq!(
+            run(
+                &server,
+                &[b"LPOS", b"list", b"a", b"RANK", b"-1", b"MAXLEN", b"2"]
+            ),
+            b"$-1\r\n"
+        );
+    }
+}
@@ -0,0 +1,677 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::iter::Peekable;
+use std::str;
+use std::time::Duration;
+use std::vec;
+
+use crate::clock::Clock;
+use crate::db::Database;
+use crate::parser::RespValue;
+use crate::propagation;
+use crate::server::Server;
+
+mod connection;
+mod hash;
+mod keys;
+mod list;
+mod pubsub;
+mod server;
+mod set;
+mod sorted_set;
+mod string;
+mod table;
+
+pub use connection::ClientSubcommand;
+pub use list::BlockingPop;
+
+#[derive(Debug, PartialEq)]
+pub enum CommandError {
+    NotAnArray,
+    EmptyCommand,
+    CommandNotBulkString,
+    UnknownCommand(String),
+    UnknownSubcommand(String),
+    WrongArgCount,
+    NotAnInteger,
+    NotAFloat,
+    WrongType,
+    NoSuchKey,
+    InvalidCursor,
+    SyntaxError,
+    OutOfRange,
+    InvalidArgument { reason: String },
+}
+
+/// The first word of an error reply. Clients branch on it, so it must match Redis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCode {
+    Err,
+    WrongType,
+    NoAuth,
+    WrongPass,
+    BusyKey,
+    ExecAbort,
+    Oom,
+    NoProto,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::Err => "ERR",
+            ErrorCode::WrongType => "WRONGTYPE",
+            ErrorCode::NoAuth => "NOAUTH",
+            ErrorCode::WrongPass => "WRONGPASS",
+            ErrorCode::BusyKey => "BUSYKEY",
+            ErrorCode::ExecAbort => "EXECABORT",
+            ErrorCode::Oom => "OOM",
+            ErrorCode::NoProto => "NOPROTO",
+        }
+    }
+}
+
+pub fn error_reply(code: ErrorCode, message: &str) -> RespValue {
+    RespValue::Error(format!("{} {}", code.as_str(), message))
+}
+
+impl CommandError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            CommandError::WrongType => ErrorCode::WrongType,
+            _ => ErrorCode::Err,
+        }
+    }
+
+    pub fn to_resp(&self) -> RespValue {
+        error_reply(self.code(), &self.to_string())
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::NotAnArray => write!(f, "Command must be an array of bulk strings"),
+            CommandError::EmptyCommand => write!(f, "Empty command"),
+            CommandError::CommandNotBulkString => write!(f, "Command name must be a bulk string"),
+            CommandError::UnknownCommand(cmd) => write!(f, "unknown command `{}`", cmd),
+            CommandError::UnknownSubcommand(sub) => write!(f, "unknown subcommand '{}'", sub),
+            CommandError::WrongArgCount => write!(f, "wrong number of arguments"),
+            CommandError::NotAnInteger => write!(f, "value is not an integer or out of range"),
+            CommandError::NotAFloat => write!(f, "value is not a valid float"),
+            CommandError::WrongType => {
+                write!(f, "Operation against a key holding the wrong kind of value")
+            }
+            CommandError::NoSuchKey => write!(f, "no such key"),
+            CommandError::InvalidCursor => write!(f, "invalid cursor"),
+            CommandError::SyntaxError => write!(f, "syntax error"),
+            CommandError::OutOfRange => write!(f, "value is out of range"),
+            CommandError::InvalidArgument { reason } => write!(f, "invalid argument: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+fn bulk_array<I>(items: I) -> RespValue
+where
+    I: IntoIterator,
+    I::Item: AsRef<[u8]>,
+{
+    RespValue::Array(
+        items
+            .into_iter()
+            .map(|item| bulk_string(item.as_ref()))
+            .collect(),
+    )
+}
+
+fn bulk_string(value: &[u8]) -> RespValue {
+    RespValue::BulkString(value.to_vec())
+}
+
+pub fn ok() -> RespValue {
+    RespValue::SimpleString("OK".to_string())
+}
+
+/// Hands out a command's arguments by move, so no argument is copied on its way
+/// into a command struct.
+struct ArgParser {
+    iter: Peekable<vec::IntoIter<RespValue>>,
+}
+
+impl ArgParser {
+    fn new(args: vec::IntoIter<RespValue>) -> Self {
+        Self {
+            iter: args.peekable(),
+        }
+    }
+
+    fn next_bulk_string(&mut self) -> Result<Vec<u8>, CommandError> {
+        match self.iter.next() {
+            Some(RespValue::BulkString(bs)) => Ok(bs),
+            Some(_) => Err(CommandError::InvalidArgument {
+                reason: "argument must be a bulk string".to_string(),
+            }),
+            None => Err(CommandError::WrongArgCount),
+        }
+    }
+
+    fn next_integer(&mut self) -> Result<i64, CommandError> {
+        let bytes = self.next_bulk_string()?;
+        parse_i64(&bytes).ok_or(CommandError::NotAnInteger)
+    }
+
+    fn next_float(&mut self) -> Result<f64, CommandError> {
+        let bytes = self.next_bulk_string()?;
+        parse_f64(&bytes).ok_or(CommandError::NotAFloat)
+    }
+
+    fn remaining_bulk_strings(&mut self) -> Result<Vec<Vec<u8>>, CommandError> {
+        let mut values = Vec::new();
+        while self.iter.peek().is_some() {
+            values.push(self.next_bulk_string()?);
+        }
+        Ok(values)
+    }
+
+    fn finish(&mut self) -> Result<(), CommandError> {
+        if self.iter.peek().is_some() {
+            Err(CommandError::WrongArgCount)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+trait CommandExt {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError>
+    where
+        Self: Sized;
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue;
+
+    /// How this write repeats on a replica or in the AOF. Asked before
+    /// `execute` consumes the command, so it keeps only the few fields it
+    /// needs; the arguments come from the request as sent. A relative expiry
+    /// is resolved against `clock`, the one the command runs by.
+    fn propagate(&self, _clock: &dyn Clock) -> Propagate {
+        Box::new(|request, _| Some(vec![request]))
+    }
+}
+
+/// Given the request as sent and the reply it got, the commands that repeat a
+/// write: `request` itself, unless it holds something relative such as an
+/// expiry that replaying it wouldn't reproduce. `None` when the write changed
+/// nothing. Only called for writes that didn't fail.
+type Propagate = Box<dyn FnOnce(RespValue, &RespValue) -> Option<Vec<RespValue>>>;
+
+/// The arguments `request` was sent with, after the command name, for
+/// rewriting it without copying them.
+fn request_args(request: RespValue) -> impl Iterator<Item = RespValue> {
+    let args = match request {
+        RespValue::Array(args) => args,
+        _ => Vec::new(),
+    };
+    args.into_iter().skip(1)
+}
+
+/// The unix time `millis` after `unix_now`, as the argument a PXAT or
+/// PEXPIREAT propagates a relative expiry with.
+fn deadline_in(unix_now: u64, millis: u64) -> Option<String> {
+    unix_now
+        .checked_add(millis)
+        .map(|deadline| deadline.to_string())
+}
+
+/// `request` unless the write it made changed nothing, which `result`, the
+/// number of keys or elements it touched, says.
+fn unless_zero(request: RespValue, result: &RespValue) -> Option<Vec<RespValue>> {
+    (*result != RespValue::Integer(0)).then(|| vec![request])
+}
+
+fn parse_i64(bytes: &[u8]) -> Option<i64> {
+    str::from_utf8(bytes).ok()?.parse::<i64>().ok()
+}
+
+/// Parses a finite float. Rust also accepts `inf` and `nan`, which Redis doesn't.
+fn parse_f64(bytes: &[u8]) -> Option<f64> {
+    let value = str::from_utf8(bytes).ok()?.parse::<f64>().ok()?;
+    value.is_finite().then_some(value)
+}
+
+/// Resolves an inclusive Redis-style `start..=stop` range (negative indices count
+/// from the end) against a collection of `len` elements. `None` means empty.
+fn normalize_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (start + len).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        stop + len
+    } else {
+        stop.min(len - 1)
+    };
+    if start > stop || start >= len {
+        return None;
+    }
+    Some((start as usize, stop as usize))
+}
+
+/// The most elements an SRANDMEMBER-style count may ask for. A negative count
+/// repeats elements, so without a cap one request could ask for more replies
+/// than the server has memory to build.
+const MAX_RANDOM_COUNT: u64 = 1 << 24;
+
+/// Reads the optional count of an SRANDMEMBER-style command.
+fn parse_random_count(parser: &mut ArgParser) -> Result<Option<i64>, CommandError> {
+    if parser.iter.peek().is_none() {
+        return Ok(None);
+    }
+    let count = parser.next_integer()?;
+    if count.unsigned_abs() > MAX_RANDOM_COUNT {
+        return Err(CommandError::OutOfRange);
+    }
+    Ok(Some(count))
+}
+
+/// Picks elements for SRANDMEMBER-style commands. A positive `count` picks up to
+/// that many distinct elements; a negative one picks exactly `-count`, possibly
+/// repeating some.
+fn random_picks<T: Clone>(mut items: Vec<T>, count: i64) -> Vec<T> {
+    let random = RandomState::new();
+    if items.is_empty() {
+        return Vec::new();
+    }
+    if count < 0 {
+        // Grown as it fills rather than sized from the count up front.
+        let mut picks = Vec::new();
+        for draw in 0..count.unsigned_abs() {
+            picks.push(items[random.hash_one(draw) as usize % items.len()].clone());
+        }
+        return picks;
+    }
+    // A partial Fisher-Yates shuffle of the first `count` slots.
+    let count = items.len().min(count as usize);
+    for slot in 0..count {
+        let pick = slot + random.hash_one(slot) as usize % (items.len() - slot);
+        items.swap(slot, pick);
+    }
+    items.truncate(count);
+    items
+}
+
+/// Commands that change the dataset, which the append-only file records.
+const WRITE_COMMANDS: &[&[u8]] = &[
+    b"SET",
+    b"DEL",
+    b"UNLINK",
+    b"INCR",
+    b"DECR",
+    b"INCRBY",
+    b"DECRBY",
+    b"INCRBYFLOAT",
+    b"APPEND",
+    b"GETDEL",
+    b"GETEX",
+    b"GETSET",
+    b"MSET",
+    b"SETRANGE",
+    b"SETBIT",
+    b"FLUSHDB",
+    b"FLUSHALL",
+    b"SWAPDB",
+    b"EXPIRE",
+    b"PEXPIRE",
+    b"EXPIREAT",
+    b"PEXPIREAT",
+    b"PERSIST",
+    b"RENAME",
+    b"COPY",
+    b"RESTORE",
+    b"LPUSH",
+    b"RPUSH",
+    b"LINSERT",
+    b"LSET",
+    b"LREM",
+    b"LPOP",
+    b"RPOP",
+    b"HSET",
+    b"HINCRBY",
+    b"HDEL",
+    b"SADD",
+    b"SMOVE",
+    b"ZADD",
+];
+
+/// The name a request frame starts with, if it is shaped like a command.
+pub fn command_name(frame: &RespValue) -> Option<&[u8]> {
+    match frame {
+        RespValue::Array(elements) => match elements.first() {
+            Some(RespValue::BulkString(name)) => Some(name),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Whether `name` is a command the server implements, in any case.
+pub fn is_known_command(name: &[u8]) -> bool {
+    table::lookup(name).is_some()
+}
+
+pub fn is_write_command(frame: &RespValue) -> bool {
+    command_name(frame).is_some_and(|name| {
+        WRITE_COMMANDS
+            .iter()
+            .any(|write| name.eq_ignore_ascii_case(write))
+    })
+}
+
+/// Writes that can grow the dataset, which are refused with OOM when nothing
+/// can be evicted. Writes that only shrink it or change expiries always run.
+const GROWING_COMMANDS: &[&[u8]] = &[
+    b"SET",
+    b"GETSET",
+    b"INCR",
+    b"DECR",
+    b"INCRBY",
+    b"DECRBY",
+    b"INCRBYFLOAT",
+    b"APPEND",
+    b"MSET",
+    b"SETRANGE",
+    b"SETBIT",
+    b"COPY",
+    b"RESTORE",
+    b"LPUSH",
+    b"RPUSH",
+    b"LINSERT",
+    b"LSET",
+    b"HSET",
+    b"HINCRBY",
+    b"SADD",
+    b"ZADD",
+];
+
+pub fn may_grow_dataset(frame: &RespValue) -> bool {
+    command_name(frame).is_some_and(|name| {
+        GROWING_COMMANDS
+            .iter()
+            .any(|growing| name.eq_ignore_ascii_case(growing))
+    })
+}
+
+/// A parsed command that runs against the server on its own.
+pub struct KeyspaceCommand(Box<dyn CommandExt + Send>);
+
+pub enum Command {
+    Keyspace(KeyspaceCommand),
+    /// Transaction control and subscriptions, which only mean something to a `Session`.
+    Multi,
+    Exec,
+    Discard,
+    Subscribe(Vec<Vec<u8>>),
+    Unsubscribe(Vec<Vec<u8>>),
+    Reset,
+    /// `HELLO [protover]`, kept unvalidated so the session can answer NOPROTO.
+    Hello(Option<i64>),
+    Select(i64),
+    /// `AUTH [username] password`. Only the `default` user exists.
+    Auth {
+        username: Option<Vec<u8>>,
+        password: Vec<u8>,
+    },
+    Client(ClientSubcommand),
+    /// `DEBUG SLEEP`, which pauses only the connection that sent it.
+    Sleep(Duration),
+    /// BLPOP and BRPOP, which may leave the connection waiting for a push.
+    BlockingPop(BlockingPop),
+    Monitor,
+}
+
+impl Command {
+    pub fn from_resp(resp: RespValue) -> Result<Self, CommandError> {
+        let RespValue::Array(elements) = resp else {
+            return Err(CommandError::NotAnArray);
+        };
+        let mut elements = elements.into_iter();
+        let command_bytes = match elements.next() {
+            Some(RespValue::BulkString(command_bytes)) => command_bytes,
+            Some(_) => return Err(CommandError::CommandNotBulkString),
+            None => return Err(CommandError::EmptyCommand),
+        };
+
+        let Some(spec) = table::lookup(&command_bytes) else {
+            let cmd_name = String::from_utf8_lossy(&command_bytes).to_ascii_uppercase();
+            return Err(CommandError::UnknownCommand(cmd_name));
+        };
+        if !spec.accepts(elements.len() + 1) {
+            return Err(CommandError::WrongArgCount);
+        }
+        (spec.parse)(&mut ArgParser::new(elements))
+    }
+
+    /// Runs a write parsed from `request` against database `db_index`, and
+    /// hands what repeats it to the AOF and the followers.
+    pub fn execute_propagated(
+        self,
+        server: &Server,
+        db_index: usize,
+        request: RespValue,
+    ) -> RespValue {
+        let db = server.db(db_index);
+        match self {
+            Command::Keyspace(KeyspaceCommand(command)) => {
+                let propagate = command.propagate(&*server.clock);
+                propagation::run_propagated(
+                    server,
+                    db_index,
+                    || command.execute(server, &db),
+                    |result| propagate(request, result),
+                )
+            }
+            command => propagation::run_propagated(
+                server,
+                db_index,
+                || command.execute(server, &db),
+                |_| Some(vec![request]),
+            ),
+        }
+    }
+
+    pub fn execute(self, server: &Server, db: &Database) -> RespValue {
+        match self {
+            Command::Keyspace(KeyspaceCommand(command)) => command.execute(server, db),
+            Command::Multi
+            | Command::Exec
+            | Command::Discard
+            | Command::Subscribe(_)
+            | Command::Unsubscribe(_)
+            | Command::Reset
+            | Command::Hello(_)
+            | Command::Select(_)
+            | Command::Auth { .. }
+            | Command::Client(_)
+            | Command::Sleep(_)
+            | Command::BlockingPop(_)
+            | Command::Monitor => error_reply(
+                ErrorCode::Err,
+                "transactions and subscriptions are only available on a connection",
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn run(server: &Server, args: &[&[u8]]) -> Vec<u8> {
+    let resp = RespValue::Array(
+        args.iter()
+            .map(|arg| RespValue::BulkString(arg.to_vec()))
+            .collect(),
+    );
+    Command::from_resp(resp)
+        .unwrap()
+        .execute(server, &server.db(0))
+        .encode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{remove_if_empty, DbData, DbValue};
+    #[test]
+    fn test_only_names_and_options_are_case_folded() {
+        let server = Server::default();
+        assert_eq!(
+            run(&server, &[b"set", b"K", b"MixedValue", b"px", b"10000"]),
+            b"+OK\r\n"
+        );
+        assert_eq!(run(&server, &[b"Get", b"K"]), b"$10\r\nMixedValue\r\n");
+        assert_eq!(run(&server, &[b"get", b"k"]), b"$-1\r\n");
+        assert_eq!(run(&server, &[b"pttl", b"K"]).first(), Some(&b':'));
+        assert_eq!(run(&server, &[b"exists", b"K", b"k"]), b":1\r\n");
+        assert_eq!(run(&server, &[b"echo", b"CaSe"]), b"$4\r\nCaSe\r\n");
+        assert!(server.db(0).lock(b"K").contains_key(b"K".as_slice()));
+    }
+
+    #[test]
+    fn test_error_replies_carry_their_code() {
+        assert_eq!(
+            CommandError::WrongType.to_resp().encode(),
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+        assert_eq!(
+            error_reply(ErrorCode::NoAuth, "Authentication required.").encode(),
+            b"-NOAUTH Authentication required.\r\n"
+        );
+        assert!(CommandError::WrongArgCount
+            .to_resp()
+            .encode()
+            .starts_with(b"-ERR "));
+    }
+
+    #[test]
+    fn test_extra_arguments_error() {
+        let resp = RespValue::Array(vec![
+            RespValue::BulkString(b"GET".to_vec()),
+            RespValue::BulkString(b"key".to_vec()),
+            RespValue::BulkString(b"extra".to_vec()),
+        ]);
+        assert!(matches!(
+            Command::from_resp(resp),
+            Err(CommandError::WrongArgCount)
+        ));
+    }
+
+    #[test]
+    fn test_drained_collections_leave_no_key() {
+        let server = Server::default();
+        run(&server, &[b"RPUSH", b"left", b"a", b"b"]);
+        run(&server, &[b"LPOP", b"left"]);
+        run(&server, &[b"LPOP", b"left"]);
+        run(&server, &[b"RPUSH", b"right", b"a", b"b", b"c"]);
+        run(&server, &[b"RPOP", b"right", b"5"]);
+        for key in [&b"left"[..], b"right"] {
+            assert_eq!(run(&server, &[b"TYPE", key]), b"+none\r\n");
+            assert_eq!(run(&server, &[b"EXISTS", key]), b":0\r\n");
+        }
+
+        let empty = [
+            (&b"hash"[..], DbData::Hash(Default::default())),
+            (b"set", DbData::Set(Default::default())),
+            (b"string", DbData::String(Vec::new())),
+        ];
+        for (key, data) in empty {
+            let db = server.db(0);
+            let mut db_lock = db.lock(key);
+            db_lock.insert(key.to_vec(), DbValue::new(data));
+            remove_if_empty(&mut db_lock, key);
+        }
+        let keys: Vec<_> = server
+            .db(0)
+            .lock_all()
+            .iter()
+            .map(|(key, _)| key.clone())
+            .collect();
+        assert_eq!(keys, vec![b"string".to_vec()]);
+    }
+
+    #[test]
+    fn test_arguments_are_moved_not_copied() {
+        let values: Vec<Vec<u8>> = (0..1000).map(|i| vec![b'v'; i % 64 + 1]).collect();
+        let pointers: Vec<*const u8> = values.iter().map(|value| value.as_ptr()).collect();
+
+        let args: Vec<RespValue> = values.into_iter().map(RespValue::BulkString).collect();
+        let mut parser = ArgParser::new(args.into_iter());
+        let parsed = parser.remaining_bulk_strings().unwrap();
+
+        let parsed_pointers: Vec<*const u8> = parsed.iter().map(|value| value.as_ptr()).collect();
+        assert_eq!(parsed_pointers, pointers);
+    }
+
+    #[test]
+    fn test_wrongtype_golden_output() {
+        // Byte-for-byte what Redis sends; client libraries match on it.
+        const GOLDEN: &[u8] =
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+
+        let server = Server::default();
+        run(&server, &[b"SET", b"string", b"v"]);
+        run(&server, &[b"RPUSH", b"list", b"v"]);
+        let commands: &[&[&[u8]]] = &[
+            &[b"GET", b"list"],
+            &[b"SET", b"list", b"v", b"GET"],
+            &[b"INCR", b"list"],
+            &[b"DECR", b"list"],
+            &[b"INCRBY", b"list", b"2"],
+            &[b"DECRBY", b"list", b"2"],
+            &[b"INCRBYFLOAT", b"list", b"1.5"],
+            &[b"APPEND", b"list", b"v"],
+            &[b"STRLEN", b"list"],
+            &[b"GETDEL", b"list"],
+            &[b"GETEX", b"list", b"PERSIST"],
+            &[b"GETSET", b"list", b"v"],
+            &[b"GETRANGE", b"list", b"0", b"-1"],
+            &[b"SETRANGE", b"list", b"0", b"v"],
+            &[b"SETBIT", b"list", b"0", b"1"],
+            &[b"GETBIT", b"list", b"0"],
+            &[b"BITCOUNT", b"list"],
+            &[b"LPUSH", b"string", b"v"],
+            &[b"RPUSH", b"string", b"v"],
+            &[b"LPOP", b"string"],
+            &[b"RPOP", b"string"],
+            &[b"LRANGE", b"string", b"0", b"-1"],
+            &[b"LINSERT", b"string", b"BEFORE", b"a", b"b"],
+            &[b"LSET", b"string", b"0", b"a"],
+            &[b"LREM", b"string", b"0", b"a"],
+            &[b"LPOS", b"string", b"a"],
+            &[b"SORT", b"string"],
+            &[b"HSET", b"string", b"f", b"v"],
+            &[b"HGET", b"string", b"f"],
+            &[b"HGETALL", b"string"],
+            &[b"HRANDFIELD", b"string"],
+            &[b"HSCAN", b"string", b"0"],
+            &[b"HINCRBY", b"string", b"f", b"1"],
+            &[b"HDEL", b"string", b"f"],
+            &[b"SADD", b"string", b"m"],
+            &[b"SMEMBERS", b"string"],
+            &[b"SISMEMBER", b"string", b"m"],
+            &[b"SRANDMEMBER", b"string"],
+            &[b"SMOVE", b"string", b"other", b"m"],
+            &[b"SINTER", b"string"],
+            &[b"SUNION", b"string"],
+            &[b"SDIFF", b"string"],
+            &[b"SSCAN", b"string", b"0"],
+            &[b"ZADD", b"string", b"1", b"m"],
+            &[b"ZSCORE", b"string", b"m"],
+            &[b"ZRANGE", b"string", b"0", b"-1"],
+            &[b"ZSCAN", b"string", b"0"],
+        ];
+        for args in commands {
+            assert_eq!(run(&server, args), GOLDEN, "{:?}", args);
+        }
+    }
+}
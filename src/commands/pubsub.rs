@@ -0,0 +1,61 @@
+use super::keys::glob_match;
+use super::{bulk_array, ArgParser, CommandError, CommandExt};
+use crate::db::Database;
+use crate::parser::RespValue;
+use crate::server::Server;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Publish {
+    channel: Vec<u8>,
+    message: Vec<u8>,
+}
+
+impl CommandExt for Publish {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let channel = parser.next_bulk_string()?;
+        let message = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Publish { channel, message })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, _db: &Database) -> RespValue {
+        RespValue::Integer(server.pubsub.publish(&self.channel, &self.message) as i64)
+    }
+}
+
+/// `PUBSUB CHANNELS [pattern]`, the only introspection subcommand so far.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Pubsub {
+    pattern: Option<Vec<u8>>,
+}
+
+impl CommandExt for Pubsub {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let subcommand = parser.next_bulk_string()?;
+        if !subcommand.eq_ignore_ascii_case(b"CHANNELS") {
+            return Err(CommandError::UnknownSubcommand(
+                String::from_utf8_lossy(&subcommand).into_owned(),
+            ));
+        }
+        let pattern = match parser.iter.peek() {
+            Some(_) => Some(parser.next_bulk_string()?),
+            None => None,
+        };
+        parser.finish()?;
+        Ok(Pubsub { pattern })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, _db: &Database) -> RespValue {
+        let channels: Vec<Vec<u8>> = server
+            .pubsub
+            .channels()
+            .into_iter()
+            .filter(|channel| {
+                self.pattern
+                    .as_ref()
+                    .is_none_or(|pattern| glob_match(pattern, channel))
+            })
+            .collect();
+        bulk_array(channels)
+    }
+}
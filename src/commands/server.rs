@@ -0,0 +1,917 @@
+use super::keys::glob_match;
+use super::table::{lookup, CommandSpec, COMMAND_TABLE};
+use super::{
+    bulk_array, bulk_string, error_reply, ok, ArgParser, CommandError, CommandExt, ErrorCode,
+};
+use crate::config::PARAMETERS;
+use crate::db::{unpoisoned, Database};
+use crate::logging::{log, Level};
+use crate::parser::RespValue;
+use crate::server::{Server, REDIS_VERSION};
+use crate::stats::Stats;
+use crate::{aof, rdb};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Info {
+    section: Option<String>,
+}
+
+impl Info {
+    fn wants(&self, section: &str) -> bool {
+        match &self.section {
+            None => true,
+            Some(requested) => {
+                requested == "all"
+                    || requested == "everything"
+                    || requested == "default"
+                    || requested == section
+            }
+        }
+    }
+}
+
+impl CommandExt for Info {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let section = match parser.iter.peek() {
+            Some(_) => {
+                let section = parser.next_bulk_string()?;
+                Some(String::from_utf8_lossy(&section).to_ascii_lowercase())
+            }
+            None => None,
+        };
+        parser.finish()?;
+        Ok(Info { section })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, _db: &Database) -> RespValue {
+        let mut info = String::new();
+
+        if self.wants("server") {
+            info.push_str("# Server\r\n");
+            info.push_str(&format!("redis_version:{}\r\n", REDIS_VERSION));
+            info.push_str(&format!(
+                "uptime_in_seconds:{}\r\n",
+                server.started_at.elapsed().as_secs()
+            ));
+        }
+
+        if self.wants("clients") {
+            info.push_str("# Clients\r\n");
+            info.push_str(&format!(
+                "connected_clients:{}\r\n",
+                Stats::get(&server.stats.connected_clients)
+            ));
+        }
+
+        if self.wants("memory") {
+            let used_memory: usize = (0..server.database_count())
+                .map(|index| server.db(index).used_memory())
+                .sum();
+            let config = unpoisoned(server.config.read());
+            info.push_str("# Memory\r\n");
+            info.push_str(&format!("used_memory:{}\r\n", used_memory));
+            info.push_str(&format!("maxmemory:{}\r\n", config.maxmemory));
+            info.push_str(&format!("maxmemory_policy:{}\r\n", config.maxmemory_policy));
+        }
+
+        if self.wants("persistence") {
+            let snapshot = &server.snapshot;
+            info.push_str("# Persistence\r\n");
+            info.push_str("loading:0\r\n");
+            info.push_str(&format!(
+                "rdb_bgsave_in_progress:{}\r\n",
+                snapshot.bgsave_in_progress.load(Ordering::Relaxed) as u8
+            ));
+            info.push_str(&format!(
+                "rdb_last_save_time:{}\r\n",
+                snapshot.last_save_time.load(Ordering::Relaxed)
+            ));
+            let status = if snapshot.last_bgsave_failed.load(Ordering::Relaxed) {
+                "err"
+            } else {
+                "ok"
+            };
+            info.push_str(&format!("rdb_last_bgsave_status:{}\r\n", status));
+            info.push_str(&format!(
+                "aof_enabled:{}\r\n",
+                server.aof.is_enabled() as u8
+            ));
+            info.push_str(&format!(
+                "aof_rewrite_in_progress:{}\r\n",
+                server.aof.rewrite_in_progress.load(Ordering::Relaxed) as u8
+            ));
+        }
+
+        if self.wants("stats") {
+            info.push_str("# Stats\r\n");
+            info.push_str(&format!(
+                "total_commands_processed:{}\r\n",
+                Stats::get(&server.stats.total_commands_processed)
+            ));
+            info.push_str(&format!(
+                "rejected_connections:{}\r\n",
+                Stats::get(&server.stats.rejected_connections)
+            ));
+            info.push_str(&format!(
+                "keyspace_hits:{}\r\n",
+                Stats::get(&server.stats.keyspace_hits)
+            ));
+            info.push_str(&format!(
+                "keyspace_misses:{}\r\n",
+                Stats::get(&server.stats.keyspace_misses)
+            ));
+            let expired_keys: u64 = (0..server.database_count())
+                .map(|index| server.db(index).expired_keys())
+                .sum();
+            info.push_str(&format!("expired_keys:{}\r\n", expired_keys));
+            info.push_str(&format!(
+                "evicted_keys:{}\r\n",
+                Stats::get(&server.stats.evicted_keys)
+            ));
+        }
+
+        // Like Redis, only asked-for or full reports carry the per-command lines.
+        if self
+            .section
+            .as_deref()
+            .is_some_and(|section| matches!(section, "commandstats" | "all" | "everything"))
+        {
+            info.push_str("# Commandstats\r\n");
+            for (name, stat) in server.stats.command_stats() {
+                info.push_str(&format!(
+                    "cmdstat_{}:calls={},usec={},usec_per_call={:.2}\r\n",
+                    String::from_utf8_lossy(&name),
+                    stat.calls,
+                    stat.usec,
+                    stat.usec as f64 / stat.calls as f64
+                ));
+            }
+        }
+
+        if self.wants("keyspace") {
+            info.push_str("# Keyspace\r\n");
+            for index in 0..server.database_count() {
+                let db = server.db(index);
+                let now = db.now();
+                let shards = db.read_all();
+                let (keys, expires) = shards
+                    .iter()
+                    .filter(|(_, db_value)| !db_value.is_expired_at(now))
+                    .fold((0, 0), |(keys, expires), (_, db_value)| {
+                        (keys + 1, expires + db_value.expires_at.is_some() as u64)
+                    });
+                // Like Redis, an empty database gets no line at all.
+                if keys > 0 {
+                    info.push_str(&format!(
+                        "db{}:keys={},expires={},avg_ttl=0\r\n",
+                        index, keys, expires
+                    ));
+                }
+            }
+        }
+
+        bulk_string(info.as_bytes())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Flushdb;
+
+/// ASYNC and SYNC are accepted for compatibility; flushing is always synchronous.
+fn parse_flush_mode(parser: &mut ArgParser) -> Result<(), CommandError> {
+    if let Some(RespValue::BulkString(mode)) = parser.iter.peek() {
+        if mode.eq_ignore_ascii_case(b"ASYNC") || mode.eq_ignore_ascii_case(b"SYNC") {
+            parser.iter.next();
+        }
+    }
+    parser.finish()
+}
+
+fn flush(db: &Database) {
+    for shard in db.lock_all().shards_mut() {
+        shard.clear();
+    }
+}
+
+impl CommandExt for Flushdb {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        parse_flush_mode(parser)?;
+        Ok(Flushdb)
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        flush(db);
+        ok()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Flushall;
+
+impl CommandExt for Flushall {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        parse_flush_mode(parser)?;
+        Ok(Flushall)
+    }
+
+    fn execute(self: Box<Self>, server: &Server, _db: &Database) -> RespValue {
+        // Each database is cleared under its own lock, one after another.
+        for index in 0..server.database_count() {
+            flush(&server.db(index));
+        }
+        ok()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Dbsize;
+
+impl CommandExt for Dbsize {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        parser.finish()?;
+        Ok(Dbsize)
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        let now = db.now();
+        let shards = db.read_all();
+
+        // Expired keys may not have been reaped yet, but GET can't see them either.
+        let live = shards
+            .iter()
+            .filter(|(_, db_value)| !db_value.is_expired_at(now))
+            .count();
+        RespValue::Integer(live as i64)
+    }
+}
+
+/// `WAIT numreplicas timeout`. There are no replicas to wait for, so it
+/// answers at once that none acknowledged, whatever the timeout.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Wait;
+
+impl CommandExt for Wait {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        parser.next_integer()?;
+        if parser.next_integer()? < 0 {
+            return Err(CommandError::InvalidArgument {
+                reason: "timeout is negative".to_string(),
+            });
+        }
+        parser.finish()?;
+        Ok(Wait)
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, _db: &Database) -> RespValue {
+        RespValue::Integer(0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Swapdb {
+    first: i64,
+    second: i64,
+}
+
+impl CommandExt for Swapdb {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let first = parser.next_integer()?;
+        let second = parser.next_integer()?;
+        parser.finish()?;
+        Ok(Swapdb { first, second })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, _db: &Database) -> RespValue {
+        let count = server.database_count();
+        let index = |index: i64| usize::try_from(index).ok().filter(|&index| index < count);
+        match (index(self.first), index(self.second)) {
+            (Some(first), Some(second)) => {
+                server.swap_databases(first, second);
+                ok()
+            }
+            _ => error_reply(ErrorCode::Err, "DB index is out of range"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Save;
+
+impl CommandExt for Save {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        parser.finish()?;
+        Ok(Save)
+    }
+
+    fn execute(self: Box<Self>, server: &Server, _db: &Database) -> RespValue {
+        if server.snapshot.bgsave_in_progress.load(Ordering::Acquire) {
+            return error_reply(ErrorCode::Err, "Background save already in progress");
+        }
+        let path = unpoisoned(server.config.read()).snapshot_path();
+        match rdb::save(server, &path) {
+            Ok(()) => ok(),
+            Err(e) => {
+                log!(
+                    server.log_level,
+                    Level::Warning,
+                    "Saving to {} failed: {e}",
+                    path.display()
+                );
+                error_reply(ErrorCode::Err, &format!("saving the snapshot failed: {e}"))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Bgsave;
+
+impl CommandExt for Bgsave {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        // SCHEDULE is accepted for compatibility; a running save is never queued behind.
+        if let Some(RespValue::BulkString(option)) = parser.iter.peek() {
+            if option.eq_ignore_ascii_case(b"SCHEDULE") {
+                parser.iter.next();
+            }
+        }
+        parser.finish()?;
+        Ok(Bgsave)
+    }
+
+    fn execute(self: Box<Self>, server: &Server, _db: &Database) -> RespValue {
+        let path = unpoisoned(server.config.read()).snapshot_path();
+        if rdb::background_save(server, &path) {
+            RespValue::SimpleString("Background saving started".to_string())
+        } else {
+            error_reply(ErrorCode::Err, "Background save already in progress")
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Bgrewriteaof;
+
+impl CommandExt for Bgrewriteaof {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        parser.finish()?;
+        Ok(Bgrewriteaof)
+    }
+
+    fn execute(self: Box<Self>, server: &Server, _db: &Database) -> RespValue {
+        let path = unpoisoned(server.config.read()).aof_path();
+        if aof::background_rewrite(server, &path) {
+            RespValue::SimpleString("Background append only file rewriting started".to_string())
+        } else {
+            error_reply(
+                ErrorCode::Err,
+                "Background append only file rewriting already in progress",
+            )
+        }
+    }
+}
+
+/// Largest `lru` DEBUG OBJECT reports; like Redis' clock, it wraps past 24 bits.
+const LRU_CLOCK_MAX: u64 = (1 << 24) - 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum DebugSubcommand {
+    /// Pauses the calling connection, so it is turned into `Command::Sleep`
+    /// for the session to wait out without holding up anyone else.
+    Sleep(Duration),
+    /// Turns the background expiry of unread keys on or off.
+    SetActiveExpire(bool),
+    /// Describes how a key's value is stored.
+    Object(Vec<u8>),
+    Other(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Debug {
+    pub(super) subcommand: DebugSubcommand,
+}
+
+impl CommandExt for Debug {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let name = String::from_utf8_lossy(&parser.next_bulk_string()?).to_ascii_uppercase();
+        let subcommand = match name.as_str() {
+            "SLEEP" => {
+                let seconds = parser.next_float()?;
+                parser.finish()?;
+                let duration = Duration::try_from_secs_f64(seconds).map_err(|_| {
+                    CommandError::InvalidArgument {
+                        reason: "sleep time must be a non-negative number of seconds".to_string(),
+                    }
+                })?;
+                DebugSubcommand::Sleep(duration)
+            }
+            "SET-ACTIVE-EXPIRE" => {
+                let enabled = match parser.next_integer()? {
+                    0 => false,
+                    1 => true,
+                    _ => return Err(CommandError::SyntaxError),
+                };
+                parser.finish()?;
+                DebugSubcommand::SetActiveExpire(enabled)
+            }
+            "OBJECT" => {
+                let key = parser.next_bulk_string()?;
+                parser.finish()?;
+                DebugSubcommand::Object(key)
+            }
+            _ => {
+                // Arguments are only meaningful to subcommands we implement.
+                parser.remaining_bulk_strings()?;
+                DebugSubcommand::Other(name)
+            }
+        };
+        Ok(Debug { subcommand })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue {
+        match self.subcommand {
+            DebugSubcommand::Object(key) => {
+                db.peek_live(&key, |db_value| {
+                    let Some(db_value) = db_value else {
+                        return CommandError::NoSuchKey.to_resp();
+                    };
+                    // The fields Redis prints, with what it reports for a value
+                    // nothing else shares. Where the value lives in memory means
+                    // nothing to a client and changes from run to run, so `at` is
+                    // a placeholder. `lru` is the last access in seconds.
+                    let mut line = format!(
+                        "Value at:0x0 refcount:1 encoding:{} serializedlength:{} lru:{} lru_seconds_idle:{}",
+                        db_value.encoding(),
+                        rdb::serialized_len(&db_value.data),
+                        (db_value.accessed_at.millis() / 1000) & LRU_CLOCK_MAX,
+                        db_value.accessed_at.idle().as_secs()
+                    );
+                    if let Some(nodes) = db_value.quicklist_nodes() {
+                        line.push_str(&format!(" ql_nodes:{}", nodes));
+                    }
+                    RespValue::SimpleString(line)
+                })
+            }
+            DebugSubcommand::SetActiveExpire(enabled) => {
+                server.active_expire.store(enabled, Ordering::Relaxed);
+                ok()
+            }
+            // Ones the config lists as safe to acknowledge without doing anything.
+            DebugSubcommand::Other(name)
+                if server.debug_compat
+                    && unpoisoned(server.config.read())
+                        .debug_compat_noops
+                        .contains(&name) =>
+            {
+                ok()
+            }
+            DebugSubcommand::Other(name) => CommandError::UnknownSubcommand(name).to_resp(),
+            DebugSubcommand::Sleep(_) => unreachable!("DEBUG SLEEP runs in the session"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ConfigSubcommand {
+    Get(Vec<u8>),
+    Set(String, String),
+    ResetStat,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Config {
+    subcommand: ConfigSubcommand,
+}
+
+impl CommandExt for Config {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let subcommand = parser.next_bulk_string()?;
+        let subcommand = match subcommand.to_ascii_uppercase().as_slice() {
+            b"GET" => ConfigSubcommand::Get(parser.next_bulk_string()?.to_ascii_lowercase()),
+            b"SET" => {
+                let name = parser.next_bulk_string()?;
+                let value = parser.next_bulk_string()?;
+                ConfigSubcommand::Set(
+                    String::from_utf8_lossy(&name).to_ascii_lowercase(),
+                    String::from_utf8_lossy(&value).into_owned(),
+                )
+            }
+            b"RESETSTAT" => ConfigSubcommand::ResetStat,
+            _ => {
+                return Err(CommandError::UnknownSubcommand(
+                    String::from_utf8_lossy(&subcommand).into_owned(),
+                ))
+            }
+        };
+        parser.finish()?;
+        Ok(Config { subcommand })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, _db: &Database) -> RespValue {
+        match self.subcommand {
+            ConfigSubcommand::Get(pattern) => {
+                let config = unpoisoned(server.config.read());
+                let pairs = PARAMETERS
+                    .iter()
+                    .filter(|name| glob_match(&pattern, name.as_bytes()))
+                    .flat_map(|&name| [name.to_string(), config.get(name).unwrap_or_default()]);
+                bulk_array(pairs)
+            }
+            ConfigSubcommand::Set(name, value) => {
+                let mut config = unpoisoned(server.config.write());
+                if config.get(&name).is_none() {
+                    let message = format!(
+                        "Unknown option or number of arguments for CONFIG SET - '{}'",
+                        name
+                    );
+                    return error_reply(ErrorCode::Err, &message);
+                }
+                let was_appending = config.appendonly;
+                let set = config.set(&name, &value);
+                let (appending, aof_path) = (config.appendonly, config.aof_path());
+                // Logged writes may read the config while holding the log's lock.
+                drop(config);
+                let set = set.and_then(|()| match (was_appending, appending) {
+                    (false, true) => aof::enable(server, &aof_path).map_err(|e| {
+                        unpoisoned(server.config.write()).appendonly = false;
+                        e.to_string()
+                    }),
+                    (true, false) => {
+                        server.aof.close();
+                        Ok(())
+                    }
+                    _ => Ok(()),
+                });
+                match set {
+                    Ok(()) => ok(),
+                    Err(reason) => {
+                        let message = format!(
+                            "CONFIG SET failed (possibly related to argument '{}') - {}",
+                            name, reason
+                        );
+                        error_reply(ErrorCode::Err, &message)
+                    }
+                }
+            }
+            ConfigSubcommand::ResetStat => {
+                server.stats.reset();
+                for index in 0..server.database_count() {
+                    server.db(index).reset_expired_keys();
+                }
+                ok()
+            }
+        }
+    }
+}
+
+/// What COMMAND reports about one command: name, arity, flags and key positions.
+fn command_entry(spec: &CommandSpec) -> RespValue {
+    let flags = spec.flags().into_iter().map(str::to_string);
+    RespValue::Array(vec![
+        bulk_string(spec.name.to_ascii_lowercase().as_bytes()),
+        RespValue::Integer(spec.arity),
+        RespValue::Array(flags.map(RespValue::SimpleString).collect()),
+        RespValue::Integer(spec.first_key),
+        RespValue::Integer(spec.last_key),
+        RespValue::Integer(spec.step),
+    ])
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum CommandInfoSubcommand {
+    List,
+    Count,
+    /// The entries `List` has for the named commands, null for unknown ones.
+    Info(Vec<Vec<u8>>),
+    /// Docs for the named commands, or every command if none are named.
+    Docs(Vec<Vec<u8>>),
+}
+
+/// `COMMAND`, which describes the commands this server supports.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct CommandInfo {
+    subcommand: CommandInfoSubcommand,
+}
+
+impl CommandExt for CommandInfo {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let subcommand = match parser.iter.peek() {
+            None => CommandInfoSubcommand::List,
+            Some(_) => {
+                let name = parser.next_bulk_string()?;
+                match name.to_ascii_uppercase().as_slice() {
+                    b"COUNT" => CommandInfoSubcommand::Count,
+                    b"INFO" => CommandInfoSubcommand::Info(parser.remaining_bulk_strings()?),
+                    b"DOCS" => CommandInfoSubcommand::Docs(parser.remaining_bulk_strings()?),
+                    _ => {
+                        return Err(CommandError::UnknownSubcommand(
+                            String::from_utf8_lossy(&name).into_owned(),
+                        ))
+                    }
+                }
+            }
+        };
+        parser.finish()?;
+        Ok(CommandInfo { subcommand })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, _db: &Database) -> RespValue {
+        match self.subcommand {
+            CommandInfoSubcommand::List => {
+                RespValue::Array(COMMAND_TABLE.iter().map(command_entry).collect())
+            }
+            CommandInfoSubcommand::Info(names) => RespValue::Array(
+                names
+                    .iter()
+                    .map(|name| lookup(name).map_or(RespValue::Null, command_entry))
+                    .collect(),
+            ),
+            CommandInfoSubcommand::Count => RespValue::Integer(COMMAND_TABLE.len() as i64),
+            // A map of name to docs, sent as the flat array RESP2 has for one:
+            // commands don't know which protocol their connection speaks.
+            // There are no docs to give, so each command gets an empty one.
+            CommandInfoSubcommand::Docs(names) => {
+                let specs: Vec<_> = if names.is_empty() {
+                    COMMAND_TABLE.iter().collect()
+                } else {
+                    names.iter().filter_map(|name| lookup(name)).collect()
+                };
+                RespValue::Array(
+                    specs
+                        .into_iter()
+                        .flat_map(|spec| {
+                            [
+                                bulk_string(spec.name.to_ascii_lowercase().as_bytes()),
+                                RespValue::Array(Vec::new()),
+                            ]
+                        })
+                        .collect(),
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{run, Command};
+    use crate::db::DbData;
+    use crate::parser::RespValue;
+    use crate::server::Server;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_flushdb_clears_keyspace() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"a", b"1"]);
+        run(&server, &[b"RPUSH", b"b", b"1"]);
+        run(&server, &[b"HSET", b"c", b"f", b"1"]);
+
+        assert_eq!(run(&server, &[b"FLUSHDB"]), b"+OK\r\n");
+        assert_eq!(run(&server, &[b"DBSIZE"]), b":0\r\n");
+        assert_eq!(run(&server, &[b"FLUSHDB", b"async"]), b"+OK\r\n");
+    }
+
+    #[test]
+    fn test_dbsize_skips_expired_keys() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"a", b"1"]);
+        run(&server, &[b"SET", b"b", b"1"]);
+        run(&server, &[b"SET", b"c", b"1", b"PX", b"1"]);
+        thread::sleep(Duration::from_millis(5));
+
+        assert_eq!(run(&server, &[b"DBSIZE"]), b":2\r\n");
+    }
+
+    #[test]
+    fn test_flushdb_rejects_arguments() {
+        let resp = RespValue::Array(vec![
+            RespValue::BulkString(b"FLUSHDB".to_vec()),
+            RespValue::BulkString(b"everything".to_vec()),
+        ]);
+        assert!(Command::from_resp(resp).is_err());
+    }
+
+    #[test]
+    fn test_wait_acknowledges_no_replicas() {
+        let server = Server::default();
+        assert_eq!(run(&server, &[b"WAIT", b"1", b"0"]), b":0\r\n");
+        assert_eq!(run(&server, &[b"WAIT", b"0", b"100"]), b":0\r\n");
+
+        for args in [
+            [b"WAIT".as_slice(), b"one", b"0"],
+            [b"WAIT", b"1", b"soon"],
+            [b"WAIT", b"1", b"-1"],
+        ] {
+            let resp = RespValue::Array(
+                args.iter()
+                    .map(|arg| RespValue::BulkString(arg.to_vec()))
+                    .collect(),
+            );
+            assert!(Command::from_resp(resp).is_err(), "accepted {:?}", args);
+        }
+    }
+
+    /// DEBUG OBJECT's reply for `key` as its `field:value` pairs, in order.
+    fn debug_object(server: &Server, key: &[u8]) -> Vec<(String, String)> {
+        let reply = String::from_utf8(run(server, &[b"DEBUG", b"OBJECT", key])).unwrap();
+        let fields = reply
+            .strip_prefix("+Value ")
+            .and_then(|fields| fields.strip_suffix("\r\n"))
+            .unwrap_or_else(|| panic!("{reply:?}"));
+        fields
+            .split(' ')
+            .map(|field| {
+                let (name, value) = field.split_once(':').unwrap();
+                (name.to_string(), value.to_string())
+            })
+            .collect()
+    }
+
+    /// The `lru` DEBUG OBJECT should report for `key`.
+    fn lru(server: &Server, key: &[u8]) -> String {
+        server.db(0).peek_live(key, |db_value| {
+            let accessed_at = &db_value.unwrap().accessed_at;
+            ((accessed_at.millis() / 1000) & super::LRU_CLOCK_MAX).to_string()
+        })
+    }
+
+    #[test]
+    fn test_debug_object_describes_the_value() {
+        let server = Server::default();
+        run(&server, &[b"RPUSH", b"list", b"a", b"b"]);
+        let expected = [
+            ("at", "0x0".to_string()),
+            ("refcount", "1".to_string()),
+            ("encoding", "listpack".to_string()),
+            // A u32 count, then a u32 length and the byte for each element.
+            ("serializedlength", "14".to_string()),
+            ("lru", lru(&server, b"list")),
+            ("lru_seconds_idle", "0".to_string()),
+            ("ql_nodes", "1".to_string()),
+        ]
+        .map(|(name, value)| (name.to_string(), value));
+        assert_eq!(debug_object(&server, b"list"), expected);
+
+        run(&server, &[b"SET", b"string", b"12"]);
+        let expected = [
+            ("at", "0x0".to_string()),
+            ("refcount", "1".to_string()),
+            ("encoding", "int".to_string()),
+            ("serializedlength", "6".to_string()),
+            ("lru", lru(&server, b"string")),
+            ("lru_seconds_idle", "0".to_string()),
+        ]
+        .map(|(name, value)| (name.to_string(), value));
+        assert_eq!(debug_object(&server, b"string"), expected);
+        assert_eq!(
+            run(&server, &[b"DEBUG", b"OBJECT", b"missing"]),
+            b"-ERR no such key\r\n"
+        );
+    }
+
+    #[test]
+    fn test_debug_compat_noops() {
+        let strict = Server::default();
+        assert_eq!(
+            run(&strict, &[b"DEBUG", b"jmap"]),
+            b"-ERR unknown subcommand 'JMAP'\r\n"
+        );
+
+        let compat = Server {
+            debug_compat: true,
+            ..Server::default()
+        };
+        assert_eq!(run(&compat, &[b"DEBUG", b"jmap"]), b"+OK\r\n");
+        assert_eq!(
+            run(&compat, &[b"DEBUG", b"QUICKLIST-PACKED-THRESHOLD", b"1K"]),
+            b"+OK\r\n"
+        );
+        assert_eq!(
+            run(&compat, &[b"DEBUG", b"SEGFAULT"]),
+            b"-ERR unknown subcommand 'SEGFAULT'\r\n"
+        );
+
+        // The allowlist is configurable.
+        run(
+            &compat,
+            &[
+                b"CONFIG",
+                b"SET",
+                b"debug-compat-noops",
+                b"change-repl-id jmap",
+            ],
+        );
+        assert_eq!(run(&compat, &[b"DEBUG", b"CHANGE-REPL-ID"]), b"+OK\r\n");
+        assert_eq!(
+            run(&compat, &[b"DEBUG", b"QUICKLIST-PACKED-THRESHOLD", b"1K"]),
+            b"-ERR unknown subcommand 'QUICKLIST-PACKED-THRESHOLD'\r\n"
+        );
+        assert_eq!(
+            run(&compat, &[b"CONFIG", b"GET", b"debug-compat-noops"]),
+            b"*2\r\n$18\r\ndebug-compat-noops\r\n$19\r\nCHANGE-REPL-ID JMAP\r\n"
+        );
+    }
+
+    #[test]
+    fn test_config_set_then_get() {
+        let server = Server::default();
+        assert_eq!(
+            run(&server, &[b"CONFIG", b"SET", b"maxmemory", b"100mb"]),
+            b"+OK\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"CONFIG", b"GET", b"maxmemory"]),
+            b"*2\r\n$9\r\nmaxmemory\r\n$9\r\n104857600\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"CONFIG", b"GET", b"MAXMEMORY*"]),
+            b"*4\r\n$9\r\nmaxmemory\r\n$9\r\n104857600\r\n\
+              $16\r\nmaxmemory-policy\r\n$10\r\nnoeviction\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"CONFIG", b"SET", b"bogus", b"1"]),
+            b"-ERR Unknown option or number of arguments for CONFIG SET - 'bogus'\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"CONFIG", b"SET", b"maxmemory", b"lots"]),
+            b"-ERR CONFIG SET failed (possibly related to argument 'maxmemory') - \
+              argument must be a memory value\r\n"
+        );
+    }
+
+    #[test]
+    fn test_config_resetstat_zeroes_info_stats() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"a", b"1"]);
+        run(&server, &[b"GET", b"a"]);
+        run(&server, &[b"GET", b"missing"]);
+        let info = String::from_utf8(run(&server, &[b"INFO", b"stats"])).unwrap();
+        assert!(info.contains("keyspace_hits:1\r\n") && info.contains("keyspace_misses:1\r\n"));
+
+        assert_eq!(run(&server, &[b"CONFIG", b"RESETSTAT"]), b"+OK\r\n");
+        let info = String::from_utf8(run(&server, &[b"INFO", b"stats"])).unwrap();
+        assert!(info.contains("keyspace_hits:0\r\n") && info.contains("keyspace_misses:0\r\n"));
+        assert_eq!(run(&server, &[b"GET", b"a"]), b"$1\r\n1\r\n");
+    }
+
+    #[test]
+    fn test_info_sections() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"a", b"1"]);
+        run(&server, &[b"SET", b"b", b"1", b"EX", b"100"]);
+
+        let info = String::from_utf8(run(&server, &[b"INFO"])).unwrap();
+        assert!(info.contains("redis_version:"));
+        assert!(info.contains("connected_clients:0\r\n"));
+        assert!(info.contains("db0:keys=2,expires=1,avg_ttl=0\r\n"));
+
+        let info = String::from_utf8(run(&server, &[b"INFO", b"Keyspace"])).unwrap();
+        assert!(info.contains("db0:keys=") && !info.contains("redis_version:"));
+    }
+
+    #[test]
+    fn test_swapdb_during_commands_applies_each_once() {
+        let server = Server::default();
+        let incr = || {
+            let resp = RespValue::Array(vec![
+                RespValue::BulkString(b"INCR".to_vec()),
+                RespValue::BulkString(b"counter".to_vec()),
+            ]);
+            Command::from_resp(resp).unwrap()
+        };
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                for _ in 0..500 {
+                    run(&server, &[b"SWAPDB", b"0", b"1"]);
+                }
+            });
+            for _ in 0..500 {
+                // Resolved per command, like a session on db0 would.
+                incr().execute(&server, &server.db(0));
+            }
+        });
+
+        // Each INCR landed in exactly one of the two keyspaces.
+        let total: i64 = (0..2)
+            .filter_map(|index| {
+                let db = server.db(index);
+                let db_lock = db.lock(b"counter");
+                match db_lock.get(&b"counter"[..]).map(|db_value| &db_value.data) {
+                    Some(DbData::String(value)) => {
+                        Some(std::str::from_utf8(value).unwrap().parse::<i64>().unwrap())
+                    }
+                    _ => None,
+                }
+            })
+            .sum();
+        assert_eq!(total, 500);
+        assert_eq!(
+            run(&server, &[b"SWAPDB", b"0", b"16"]),
+            b"-ERR DB index is out of range\r\n"
+        );
+    }
+}
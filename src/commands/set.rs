@@ -0,0 +1,588 @@
+use std::collections::HashSet;
+use std::time::Instant;
+
+use super::keys::{scan_page, scan_reply, ScanOptions};
+use super::{
+    bulk_array, bulk_string, parse_random_count, random_picks, unless_zero, ArgParser,
+    CommandError, CommandExt, Propagate,
+};
+use crate::clock::Clock;
+use crate::db::{remove_if_empty, Database, DbData, DbValue};
+use crate::parser::RespValue;
+use crate::server::Server;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Sadd {
+    key: Vec<u8>,
+    members: Vec<Vec<u8>>,
+}
+
+impl CommandExt for Sadd {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let members = parser.remaining_bulk_strings()?;
+        if members.is_empty() {
+            return Err(CommandError::WrongArgCount);
+        }
+        Ok(Sadd { key, members })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        let mut db_lock = db.lock(&self.key);
+
+        if db_lock.get_live(&self.key).is_none() {
+            db_lock.insert(self.key.clone(), DbValue::new(DbData::Set(HashSet::new())));
+        }
+        let mut entry = db_lock.get_mut(&self.key);
+        let Some(DbValue {
+            data: DbData::Set(set),
+            modified_at,
+            ..
+        }) = entry.as_deref_mut()
+        else {
+            return CommandError::WrongType.to_resp();
+        };
+        *modified_at = Instant::now();
+
+        let added = self
+            .members
+            .into_iter()
+            .map(|member| set.insert(member))
+            .filter(|&added| added)
+            .count();
+        RespValue::Integer(added as i64)
+    }
+
+    fn propagate(&self, _clock: &dyn Clock) -> Propagate {
+        Box::new(unless_zero)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Smembers {
+    key: Vec<u8>,
+}
+
+impl CommandExt for Smembers {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Smembers { key })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        db.read_live(&self.key, |db_value| match db_value {
+            Some(DbValue {
+                data: DbData::Set(set),
+                ..
+            }) => bulk_array(set.iter()),
+            Some(_) => CommandError::WrongType.to_resp(),
+            None => RespValue::Array(Vec::new()),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Sismember {
+    key: Vec<u8>,
+    member: Vec<u8>,
+}
+
+impl CommandExt for Sismember {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let member = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Sismember { key, member })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        db.read_live(&self.key, |db_value| match db_value {
+            Some(DbValue {
+                data: DbData::Set(set),
+                ..
+            }) => RespValue::Integer(set.contains(&self.member) as i64),
+            Some(_) => CommandError::WrongType.to_resp(),
+            None => RespValue::Integer(0),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Srandmember {
+    key: Vec<u8>,
+    count: Option<i64>,
+}
+
+impl CommandExt for Srandmember {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let count = parse_random_count(parser)?;
+        parser.finish()?;
+        Ok(Srandmember { key, count })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        db.read_live(&self.key, |db_value| {
+            let members = match db_value {
+                Some(DbValue {
+                    data: DbData::Set(set),
+                    ..
+                }) => set.iter().collect(),
+                Some(_) => return CommandError::WrongType.to_resp(),
+                None => Vec::new(),
+            };
+            match self.count {
+                Some(count) => bulk_array(random_picks(members, count)),
+                None => match random_picks(members, 1).first() {
+                    Some(member) => bulk_string(member),
+                    None => RespValue::Null,
+                },
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Sscan {
+    key: Vec<u8>,
+    cursor: u64,
+    options: ScanOptions,
+}
+
+impl CommandExt for Sscan {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let (cursor, options) = ScanOptions::parse(parser, false)?;
+        Ok(Sscan {
+            key,
+            cursor,
+            options,
+        })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        db.read_live(&self.key, |db_value| {
+            let set = match db_value {
+                Some(DbValue {
+                    data: DbData::Set(set),
+                    ..
+                }) => set,
+                Some(_) => return CommandError::WrongType.to_resp(),
+                None => return scan_reply(0, Vec::<&[u8]>::new()),
+            };
+
+            let (next_cursor, page) = scan_page(
+                set.iter().map(Vec::as_slice),
+                self.cursor,
+                self.options.count,
+            );
+            scan_reply(
+                next_cursor,
+                page.into_iter()
+                    .filter(|member| self.options.matches(member)),
+            )
+        })
+    }
+}
+
+/// Read-locks `keys` and hands `combine` the set at each, `None` for a missing key.
+/// Replies WRONGTYPE instead if any of them holds something else.
+fn with_sets(
+    db: &Database,
+    keys: &[Vec<u8>],
+    combine: impl FnOnce(Vec<Option<&HashSet<Vec<u8>>>>) -> RespValue,
+) -> RespValue {
+    let shards = db.read_keys(keys.iter().map(Vec::as_slice));
+
+    let mut sets = Vec::with_capacity(keys.len());
+    for key in keys {
+        match shards.read_live(key) {
+            Some(DbValue {
+                data: DbData::Set(set),
+                ..
+            }) => sets.push(Some(set)),
+            Some(_) => return CommandError::WrongType.to_resp(),
+            None => sets.push(None),
+        }
+    }
+    combine(sets)
+}
+
+fn parse_keys(parser: &mut ArgParser) -> Result<Vec<Vec<u8>>, CommandError> {
+    let keys = parser.remaining_bulk_strings()?;
+    if keys.is_empty() {
+        return Err(CommandError::WrongArgCount);
+    }
+    Ok(keys)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Sinter {
+    keys: Vec<Vec<u8>>,
+}
+
+impl CommandExt for Sinter {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        Ok(Sinter {
+            keys: parse_keys(parser)?,
+        })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        with_sets(db, &self.keys, |sets| {
+            // A missing key is an empty set, so the intersection is empty too.
+            let Some(mut sets) = sets.into_iter().collect::<Option<Vec<_>>>() else {
+                return RespValue::Array(Vec::new());
+            };
+            // Probing the others for each member of the smallest is the least work.
+            sets.sort_by_key(|set| set.len());
+            let (smallest, others) = sets.split_first().unwrap();
+            bulk_array(
+                smallest
+                    .iter()
+                    .filter(|member| others.iter().all(|set| set.contains(*member))),
+            )
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Sunion {
+    keys: Vec<Vec<u8>>,
+}
+
+impl CommandExt for Sunion {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        Ok(Sunion {
+            keys: parse_keys(parser)?,
+        })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        with_sets(db, &self.keys, |sets| {
+            let union: HashSet<&Vec<u8>> = sets.into_iter().flatten().flatten().collect();
+            bulk_array(union)
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Sdiff {
+    keys: Vec<Vec<u8>>,
+}
+
+impl CommandExt for Sdiff {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        Ok(Sdiff {
+            keys: parse_keys(parser)?,
+        })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        with_sets(db, &self.keys, |sets| {
+            let (first, others) = sets.split_first().unwrap();
+            let Some(first) = first else {
+                return RespValue::Array(Vec::new());
+            };
+            bulk_array(
+                first
+                    .iter()
+                    .filter(|member| !others.iter().flatten().any(|set| set.contains(*member))),
+            )
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Smove {
+    source: Vec<u8>,
+    destination: Vec<u8>,
+    member: Vec<u8>,
+}
+
+impl CommandExt for Smove {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let source = parser.next_bulk_string()?;
+        let destination = parser.next_bulk_string()?;
+        let member = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Smove {
+            source,
+            destination,
+            member,
+        })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        let mut shards = db.lock_keys([&self.source[..], &self.destination]);
+
+        // Both keys are checked before anything moves.
+        for key in [&self.source, &self.destination] {
+            let entry = shards.shard(key).get_live(key);
+            if entry
+                .as_deref()
+                .is_some_and(|db_value| !matches!(db_value.data, DbData::Set(_)))
+            {
+                return CommandError::WrongType.to_resp();
+            }
+        }
+
+        let source = shards.shard(&self.source);
+        let mut entry = source.get_mut(&self.source);
+        let Some(DbValue {
+            data: DbData::Set(set),
+            modified_at,
+            ..
+        }) = entry.as_deref_mut()
+        else {
+            return RespValue::Integer(0);
+        };
+        if self.source == self.destination {
+            return RespValue::Integer(set.contains(&self.member) as i64);
+        }
+        if !set.remove(&self.member) {
+            return RespValue::Integer(0);
+        }
+        *modified_at = Instant::now();
+        drop(entry);
+        remove_if_empty(source, &self.source);
+
+        let destination = shards.shard(&self.destination);
+        if destination.get_live(&self.destination).is_none() {
+            destination.insert(
+                self.destination.clone(),
+                DbValue::new(DbData::Set(HashSet::new())),
+            );
+        }
+        let mut entry = destination.get_mut(&self.destination);
+        if let Some(DbValue {
+            data: DbData::Set(set),
+            modified_at,
+            ..
+        }) = entry.as_deref_mut()
+        {
+            *modified_at = Instant::now();
+            set.insert(self.member);
+        }
+        RespValue::Integer(1)
+    }
+
+    fn propagate(&self, _clock: &dyn Clock) -> Propagate {
+        Box::new(unless_zero)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{run, Command, CommandError};
+    use crate::parser::RespValue;
+    use crate::server::Server;
+
+    #[test]
+    fn test_sadd_counts_only_new_members() {
+        let server = Server::default();
+        assert_eq!(
+            run(&server, &[b"SADD", b"set", b"a", b"b", b"a"]),
+            b":2\r\n"
+        );
+        assert_eq!(run(&server, &[b"SADD", b"set", b"b", b"c"]), b":1\r\n");
+        assert_eq!(run(&server, &[b"SISMEMBER", b"set", b"c"]), b":1\r\n");
+        assert_eq!(run(&server, &[b"SISMEMBER", b"set", b"z"]), b":0\r\n");
+        assert_eq!(run(&server, &[b"SISMEMBER", b"missing", b"a"]), b":0\r\n");
+    }
+
+    #[test]
+    fn test_smembers_any_order() {
+        let server = Server::default();
+        assert_eq!(run(&server, &[b"SMEMBERS", b"missing"]), b"*0\r\n");
+
+        run(&server, &[b"SADD", b"set", b"one", b"two", b"three"]);
+        assert_eq!(
+            members(&run(&server, &[b"SMEMBERS", b"set"])),
+            vec![b"one".to_vec(), b"three".to_vec(), b"two".to_vec()]
+        );
+    }
+
+    /// The members of a set reply, sorted.
+    fn members(reply: &[u8]) -> Vec<Vec<u8>> {
+        let (RespValue::Array(items), _) = RespValue::parse(reply).unwrap() else {
+            panic!("expected an array reply");
+        };
+        let mut members: Vec<_> = items
+            .into_iter()
+            .map(|item| match item {
+                RespValue::BulkString(member) => member,
+                other => panic!("unexpected member {:?}", other),
+            })
+            .collect();
+        members.sort();
+        members
+    }
+
+    #[test]
+    fn test_set_algebra() {
+        let server = Server::default();
+        run(&server, &[b"SADD", b"a", b"1", b"2", b"3", b"4"]);
+        run(&server, &[b"SADD", b"b", b"2", b"3", b"5"]);
+        run(&server, &[b"SADD", b"c", b"3", b"6"]);
+
+        assert_eq!(
+            members(&run(&server, &[b"SINTER", b"a", b"b", b"c"])),
+            [b"3"]
+        );
+        assert_eq!(
+            members(&run(&server, &[b"SUNION", b"b", b"c"])),
+            [b"2", b"3", b"5", b"6"]
+        );
+        assert_eq!(
+            members(&run(&server, &[b"SDIFF", b"a", b"b", b"c"])),
+            [b"1", b"4"]
+        );
+        assert_eq!(members(&run(&server, &[b"SINTER", b"a"])).len(), 4);
+    }
+
+    #[test]
+    fn test_set_algebra_treats_missing_keys_as_empty() {
+        let server = Server::default();
+        run(&server, &[b"SADD", b"a", b"1", b"2"]);
+
+        assert_eq!(run(&server, &[b"SINTER", b"a", b"missing"]), b"*0\r\n");
+        assert_eq!(
+            members(&run(&server, &[b"SUNION", b"missing", b"a"])),
+            [b"1", b"2"]
+        );
+        assert_eq!(
+            members(&run(&server, &[b"SDIFF", b"a", b"missing"])),
+            [b"1", b"2"]
+        );
+        assert_eq!(run(&server, &[b"SDIFF", b"missing", b"a"]), b"*0\r\n");
+
+        run(&server, &[b"SET", b"string", b"v"]);
+        for command in [&b"SINTER"[..], b"SUNION", b"SDIFF"] {
+            assert!(run(&server, &[command, b"missing", b"string"]).starts_with(b"-WRONGTYPE"));
+        }
+    }
+
+    #[test]
+    fn test_smove_creates_destination() {
+        let server = Server::default();
+        run(&server, &[b"SADD", b"src", b"a", b"b"]);
+        assert_eq!(run(&server, &[b"SMOVE", b"src", b"dst", b"a"]), b":1\r\n");
+        assert_eq!(run(&server, &[b"SMOVE", b"src", b"dst", b"a"]), b":0\r\n");
+        assert_eq!(run(&server, &[b"SMOVE", b"nope", b"dst", b"a"]), b":0\r\n");
+        assert_eq!(run(&server, &[b"SMEMBERS", b"dst"]), b"*1\r\n$1\r\na\r\n");
+        assert_eq!(run(&server, &[b"SMEMBERS", b"src"]), b"*1\r\n$1\r\nb\r\n");
+        assert_eq!(run(&server, &[b"SMOVE", b"src", b"src", b"b"]), b":1\r\n");
+    }
+
+    #[test]
+    fn test_smove_deletes_emptied_source() {
+        let server = Server::default();
+        run(&server, &[b"SADD", b"src", b"only"]);
+        run(&server, &[b"SADD", b"dst", b"only"]);
+        assert_eq!(
+            run(&server, &[b"SMOVE", b"src", b"dst", b"only"]),
+            b":1\r\n"
+        );
+        assert_eq!(run(&server, &[b"EXISTS", b"src"]), b":0\r\n");
+        assert_eq!(
+            run(&server, &[b"SMEMBERS", b"dst"]),
+            b"*1\r\n$4\r\nonly\r\n"
+        );
+
+        // A wrong-typed destination leaves the source alone.
+        run(&server, &[b"SET", b"string", b"v"]);
+        assert!(run(&server, &[b"SMOVE", b"dst", b"string", b"only"]).starts_with(b"-WRONGTYPE"));
+        assert_eq!(run(&server, &[b"SISMEMBER", b"dst", b"only"]), b":1\r\n");
+    }
+
+    #[test]
+    fn test_set_commands_reject_other_types() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"string", b"v"]);
+        run(&server, &[b"RPUSH", b"list", b"v"]);
+        run(&server, &[b"HSET", b"hash", b"f", b"v"]);
+        let wrong_type = b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        for key in [&b"string"[..], b"list", b"hash"] {
+            assert_eq!(run(&server, &[b"SADD", key, b"m"]), wrong_type);
+            assert_eq!(run(&server, &[b"SMEMBERS", key]), wrong_type);
+            assert_eq!(run(&server, &[b"SISMEMBER", key, b"m"]), wrong_type);
+        }
+    }
+
+    #[test]
+    fn test_srandmember_positive_count_is_distinct() {
+        let server = Server::default();
+        run(&server, &[b"SADD", b"set", b"a", b"b", b"c"]);
+        let reply = run(&server, &[b"SRANDMEMBER", b"set"]);
+        assert!(
+            [&b"$1\r\na\r\n"[..], b"$1\r\nb\r\n", b"$1\r\nc\r\n"].contains(&&reply[..]),
+            "{:?}",
+            reply
+        );
+
+        let picked = members(&run(&server, &[b"SRANDMEMBER", b"set", b"2"]));
+        assert_eq!(picked.len(), 2);
+        assert_ne!(picked[0], picked[1]);
+        assert_eq!(
+            members(&run(&server, &[b"SRANDMEMBER", b"set", b"10"])),
+            vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]
+        );
+        assert_eq!(run(&server, &[b"SRANDMEMBER", b"set", b"0"]), b"*0\r\n");
+        assert_eq!(run(&server, &[b"SRANDMEMBER", b"missing"]), b"$-1\r\n");
+        assert_eq!(run(&server, &[b"SRANDMEMBER", b"missing", b"3"]), b"*0\r\n");
+    }
+
+    #[test]
+    fn test_srandmember_negative_count_repeats() {
+        let server = Server::default();
+        run(&server, &[b"SADD", b"set", b"only"]);
+        assert_eq!(
+            members(&run(&server, &[b"SRANDMEMBER", b"set", b"-3"])),
+            vec![b"only".to_vec(); 3]
+        );
+
+        run(&server, &[b"SADD", b"set", b"other"]);
+        let picked = members(&run(&server, &[b"SRANDMEMBER", b"set", b"-20"]));
+        assert_eq!(picked.len(), 20);
+        assert!(picked
+            .iter()
+            .all(|member| member == b"only" || member == b"other"));
+        assert_eq!(
+            run(&server, &[b"SRANDMEMBER", b"missing", b"-3"]),
+            b"*0\r\n"
+        );
+
+        // Too many repeats to ever build a reply for.
+        assert!(matches!(
+            Command::from_resp(RespValue::Array(vec![
+                RespValue::BulkString(b"SRANDMEMBER".to_vec()),
+                RespValue::BulkString(b"set".to_vec()),
+                RespValue::BulkString(b"-100000000000".to_vec()),
+            ])),
+            Err(CommandError::OutOfRange)
+        ));
+    }
+
+    #[test]
+    fn test_sscan() {
+        let server = Server::default();
+        run(&server, &[b"SADD", b"set", b"apple", b"avocado", b"banana"]);
+        let reply = run(&server, &[b"SSCAN", b"set", b"0", b"MATCH", b"a*"]);
+        assert!(reply.starts_with(b"*2\r\n$1\r\n0\r\n"), "{:?}", reply);
+        assert_eq!(
+            members(&reply[b"*2\r\n$1\r\n0\r\n".len()..]),
+            vec![b"apple".to_vec(), b"avocado".to_vec()]
+        );
+        assert_eq!(
+            run(&server, &[b"SSCAN", b"missing", b"0"]),
+            b"*2\r\n$1\r\n0\r\n*0\r\n"
+        );
+        run(&server, &[b"SET", b"string", b"v"]);
+        assert_eq!(
+            run(&server, &[b"SSCAN", b"string", b"0"]),
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+    }
+}
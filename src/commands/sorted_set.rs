@@ -0,0 +1,351 @@
+use std::str;
+use std::time::Instant;
+
+use super::keys::{scan_page, scan_reply, ScanOptions};
+use super::{bulk_string, normalize_range, parse_f64, ArgParser, CommandError, CommandExt};
+use crate::db::{Database, DbData, DbValue};
+use crate::parser::RespValue;
+use crate::server::Server;
+use crate::sorted_set::SortedSet;
+
+/// Parses a score, which unlike other floats may be infinite.
+fn parse_score(bytes: &[u8]) -> Result<f64, CommandError> {
+    match str::from_utf8(bytes)
+        .map(str::to_ascii_lowercase)
+        .as_deref()
+    {
+        Ok("inf" | "+inf") => Ok(f64::INFINITY),
+        Ok("-inf") => Ok(f64::NEG_INFINITY),
+        _ => parse_f64(bytes).ok_or(CommandError::NotAFloat),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Zadd {
+    key: Vec<u8>,
+    members: Vec<(f64, Vec<u8>)>,
+}
+
+impl CommandExt for Zadd {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let args = parser.remaining_bulk_strings()?;
+        if args.is_empty() {
+            return Err(CommandError::WrongArgCount);
+        }
+        if args.len() % 2 != 0 {
+            return Err(CommandError::SyntaxError);
+        }
+        let mut args = args.into_iter();
+        let mut members = Vec::new();
+        while let (Some(score), Some(member)) = (args.next(), args.next()) {
+            members.push((parse_score(&score)?, member));
+        }
+        Ok(Zadd { key, members })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        let mut db_lock = db.lock(&self.key);
+
+        if db_lock.get_live(&self.key).is_none() {
+            db_lock.insert(
+                self.key.clone(),
+                DbValue::new(DbData::SortedSet(SortedSet::default())),
+            );
+        }
+        let mut entry = db_lock.get_mut(&self.key);
+        let Some(DbValue {
+            data: DbData::SortedSet(set),
+            modified_at,
+            ..
+        }) = entry.as_deref_mut()
+        else {
+            return CommandError::WrongType.to_resp();
+        };
+        *modified_at = Instant::now();
+
+        let added = self
+            .members
+            .into_iter()
+            .map(|(score, member)| set.insert(member, score))
+            .filter(|&added| added)
+            .count();
+        RespValue::Integer(added as i64)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Zscore {
+    key: Vec<u8>,
+    member: Vec<u8>,
+}
+
+impl CommandExt for Zscore {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let member = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Zscore { key, member })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        db.read_live(&self.key, |db_value| match db_value {
+            Some(DbValue {
+                data: DbData::SortedSet(set),
+                ..
+            }) => match set.score(&self.member) {
+                Some(score) => bulk_string(score.to_string().as_bytes()),
+                None => RespValue::Null,
+            },
+            Some(_) => CommandError::WrongType.to_resp(),
+            None => RespValue::Null,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Zrange {
+    key: Vec<u8>,
+    start: i64,
+    stop: i64,
+    with_scores: bool,
+}
+
+impl CommandExt for Zrange {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let start = parser.next_integer()?;
+        let stop = parser.next_integer()?;
+        let with_scores = match parser.iter.peek() {
+            Some(_) => {
+                if !parser
+                    .next_bulk_string()?
+                    .eq_ignore_ascii_case(b"WITHSCORES")
+                {
+                    return Err(CommandError::SyntaxError);
+                }
+                true
+            }
+            None => false,
+        };
+        parser.finish()?;
+        Ok(Zrange {
+            key,
+            start,
+            stop,
+            with_scores,
+        })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        db.read_live(&self.key, |db_value| {
+            let set = match db_value {
+                Some(DbValue {
+                    data: DbData::SortedSet(set),
+                    ..
+                }) => set,
+                Some(_) => return CommandError::WrongType.to_resp(),
+                None => return RespValue::Array(Vec::new()),
+            };
+
+            let Some((start, stop)) = normalize_range(self.start, self.stop, set.len()) else {
+                return RespValue::Array(Vec::new());
+            };
+            let mut reply = Vec::new();
+            for (member, score) in set.iter().skip(start).take(stop - start + 1) {
+                reply.push(bulk_string(member));
+                if self.with_scores {
+                    reply.push(bulk_string(score.to_string().as_bytes()));
+                }
+            }
+            RespValue::Array(reply)
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Zscan {
+    key: Vec<u8>,
+    cursor: u64,
+    options: ScanOptions,
+}
+
+impl CommandExt for Zscan {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let (cursor, options) = ScanOptions::parse(parser, false)?;
+        Ok(Zscan {
+            key,
+            cursor,
+            options,
+        })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        db.read_live(&self.key, |db_value| {
+            let set = match db_value {
+                Some(DbValue {
+                    data: DbData::SortedSet(set),
+                    ..
+                }) => set,
+                Some(_) => return CommandError::WrongType.to_resp(),
+                None => return scan_reply(0, Vec::<&[u8]>::new()),
+            };
+
+            let (next_cursor, page) = scan_page(
+                set.iter().map(|(member, _)| member.as_slice()),
+                self.cursor,
+                self.options.count,
+            );
+            let mut items = Vec::new();
+            for member in page
+                .into_iter()
+                .filter(|member| self.options.matches(member))
+            {
+                items.push(member.to_vec());
+                if let Some(score) = set.score(member) {
+                    items.push(score.to_string().into_bytes());
+                }
+            }
+            scan_reply(next_cursor, items)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{run, Command};
+    use crate::parser::RespValue;
+    use crate::server::Server;
+
+    /// The error a request is rejected with before it runs.
+    fn parse_error(args: &[&[u8]]) -> Vec<u8> {
+        let frame = RespValue::Array(
+            args.iter()
+                .map(|arg| RespValue::BulkString(arg.to_vec()))
+                .collect(),
+        );
+        match Command::from_resp(frame) {
+            Err(err) => err.to_resp().encode(),
+            Ok(_) => panic!("accepted {:?}", args),
+        }
+    }
+
+    #[test]
+    fn test_zadd_updates_existing_scores() {
+        let server = Server::default();
+        assert_eq!(
+            run(&server, &[b"ZADD", b"zset", b"1", b"a", b"2", b"b"]),
+            b":2\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"ZADD", b"zset", b"3.5", b"a", b"-inf", b"c"]),
+            b":1\r\n"
+        );
+        assert_eq!(run(&server, &[b"ZSCORE", b"zset", b"a"]), b"$3\r\n3.5\r\n");
+        assert_eq!(run(&server, &[b"ZSCORE", b"zset", b"c"]), b"$4\r\n-inf\r\n");
+        assert_eq!(run(&server, &[b"ZSCORE", b"zset", b"nope"]), b"$-1\r\n");
+        assert_eq!(
+            run(&server, &[b"ZRANGE", b"zset", b"0", b"-1"]),
+            b"*3\r\n$1\r\nc\r\n$1\r\nb\r\n$1\r\na\r\n"
+        );
+        assert_eq!(
+            parse_error(&[b"ZADD", b"zset", b"1", b"a", b"2"]),
+            b"-ERR syntax error\r\n"
+        );
+        assert_eq!(
+            parse_error(&[b"ZADD", b"zset", b"high", b"a"]),
+            b"-ERR value is not a valid float\r\n"
+        );
+    }
+
+    #[test]
+    fn test_zrange_negative_indices_and_ties() {
+        let server = Server::default();
+        run(
+            &server,
+            &[
+                b"ZADD", b"zset", b"1", b"b", b"1", b"a", b"0", b"z", b"5", b"c",
+            ],
+        );
+        assert_eq!(
+            run(&server, &[b"ZRANGE", b"zset", b"-3", b"-2"]),
+            b"*2\r\n$1\r\na\r\n$1\r\nb\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"ZRANGE", b"zset", b"-100", b"0"]),
+            b"*1\r\n$1\r\nz\r\n"
+        );
+        assert_eq!(run(&server, &[b"ZRANGE", b"zset", b"3", b"1"]), b"*0\r\n");
+        assert_eq!(
+            run(&server, &[b"ZRANGE", b"missing", b"0", b"-1"]),
+            b"*0\r\n"
+        );
+    }
+
+    #[test]
+    fn test_zrange_withscores_interleaves() {
+        let server = Server::default();
+        run(&server, &[b"ZADD", b"zset", b"1.5", b"a", b"10", b"b"]);
+        assert_eq!(
+            run(&server, &[b"ZRANGE", b"zset", b"0", b"-1", b"withscores"]),
+            b"*4\r\n$1\r\na\r\n$3\r\n1.5\r\n$1\r\nb\r\n$2\r\n10\r\n"
+        );
+        assert_eq!(
+            parse_error(&[b"ZRANGE", b"zset", b"0", b"-1", b"REV"]),
+            b"-ERR syntax error\r\n"
+        );
+    }
+
+    #[test]
+    fn test_zset_answers_the_same_after_leaving_listpack() {
+        let server = Server::default();
+        for i in 0..128 {
+            let score = (i % 5).to_string();
+            let member = format!("member:{i}");
+            run(
+                &server,
+                &[b"ZADD", b"zset", score.as_bytes(), member.as_bytes()],
+            );
+        }
+        assert_eq!(
+            run(&server, &[b"OBJECT", b"ENCODING", b"zset"]),
+            b"+listpack\r\n"
+        );
+        let range = run(&server, &[b"ZRANGE", b"zset", b"0", b"-1", b"WITHSCORES"]);
+        let score = run(&server, &[b"ZSCORE", b"zset", b"member:42"]);
+
+        run(&server, &[b"ZADD", b"zset", b"100", b"last"]);
+        assert_eq!(
+            run(&server, &[b"OBJECT", b"ENCODING", b"zset"]),
+            b"+skiplist\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"ZRANGE", b"zset", b"0", b"127", b"WITHSCORES"]),
+            range
+        );
+        assert_eq!(
+            run(&server, &[b"ZRANGE", b"zset", b"-1", b"-1"]),
+            b"*1\r\n$4\r\nlast\r\n"
+        );
+        assert_eq!(run(&server, &[b"ZSCORE", b"zset", b"member:42"]), score);
+    }
+
+    #[test]
+    fn test_zscan_interleaves_scores() {
+        let server = Server::default();
+        run(&server, &[b"ZADD", b"zset", b"1.5", b"a"]);
+        assert_eq!(
+            run(&server, &[b"ZSCAN", b"zset", b"0"]),
+            b"*2\r\n$1\r\n0\r\n*2\r\n$1\r\na\r\n$3\r\n1.5\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"ZSCAN", b"missing", b"0"]),
+            b"*2\r\n$1\r\n0\r\n*0\r\n"
+        );
+        assert_eq!(
+            parse_error(&[b"ZSCAN", b"zset", b"0", b"NOVALUES"]),
+            b"-ERR syntax error\r\n"
+        );
+    }
+}
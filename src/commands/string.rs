@@ -0,0 +1,1744 @@
+use std::str;
+use std::time::{Duration, Instant};
+
+use super::{
+    bulk_string, deadline_in, error_reply, ok, parse_f64, parse_i64, request_args, ArgParser,
+    CommandError, CommandExt, ErrorCode, Propagate,
+};
+use crate::clock::Clock;
+use crate::db::{Database, DbData, DbValue};
+use crate::notify::{self, Flags};
+use crate::parser::{RespValue, MAX_BULK_LEN};
+use crate::server::Server;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Get {
+    key: Vec<u8>,
+}
+
+impl CommandExt for Get {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Get { key })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue {
+        db.read_live(&self.key, |db_value| {
+            server.stats.record_lookup(db_value.is_some());
+            match db_value {
+                Some(db_value) => match db_value.as_string() {
+                    Some(value) => bulk_string(value),
+                    None => CommandError::WrongType.to_resp(),
+                },
+                None => RespValue::Null,
+            }
+        })
+    }
+}
+
+/// Expiry option shared by SET and friends, kept as given and resolved at execute time.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Expiry {
+    Ex(u64),
+    Px(u64),
+    ExAt(u64),
+    PxAt(u64),
+}
+
+impl Expiry {
+    /// Parses the value following one of the `EX`/`PX`/`EXAT`/`PXAT` tokens.
+    fn parse(option: &[u8], parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let value_bytes = parser.next_bulk_string()?;
+        let value = str::from_utf8(&value_bytes)
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .ok_or_else(|| CommandError::InvalidArgument {
+                reason: format!(
+                    "{} value must be a positive integer",
+                    String::from_utf8_lossy(option)
+                ),
+            })?;
+
+        Ok(match option {
+            b"EX" => Expiry::Ex(value),
+            b"PX" => Expiry::Px(value),
+            b"EXAT" => Expiry::ExAt(value),
+            _ => Expiry::PxAt(value),
+        })
+    }
+
+    /// `None` for a time Redis rejects: zero, or more milliseconds than fit an
+    /// `i64`, counting from now for EX and PX. A deadline already passed is fine.
+    fn to_instant(self, clock: &dyn Clock) -> Option<Instant> {
+        let millis = match self {
+            Expiry::Ex(secs) | Expiry::ExAt(secs) => secs.checked_mul(1000)?,
+            Expiry::Px(ms) | Expiry::PxAt(ms) => ms,
+        };
+        let relative = matches!(self, Expiry::Ex(_) | Expiry::Px(_));
+        let base = if relative { clock.unix_millis() } else { 0 };
+        if millis == 0 || millis.checked_add(base)? > i64::MAX as u64 {
+            return None;
+        }
+        if relative {
+            clock.now().checked_add(Duration::from_millis(millis))
+        } else {
+            clock.instant_from_unix_millis(millis)
+        }
+    }
+
+    /// How many milliseconds from now a relative EX or PX expires, which
+    /// propagating turns into a deadline. `None` for EXAT and PXAT, and
+    /// `Some(None)` for an EX too large to count in milliseconds.
+    fn relative_millis(self) -> Option<Option<u64>> {
+        match self {
+            Expiry::Ex(secs) => Some(secs.checked_mul(1000)),
+            Expiry::Px(millis) => Some(Some(millis)),
+            Expiry::ExAt(_) | Expiry::PxAt(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Set {
+    key: Vec<u8>,
+    value: Vec<u8>,
+    expiry: Option<Expiry>,
+    keep_ttl: bool,
+    nx: bool,
+    xx: bool,
+    get: bool,
+}
+
+impl CommandExt for Set {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let value = parser.next_bulk_string()?;
+
+        // Collect every option first, then check the exclusivity rules in one place.
+        let mut expiries = Vec::new();
+        let mut keep_ttl = false;
+        let mut get = false;
+        let mut nx = false;
+        let mut xx = false;
+        while let Some(peeked_arg) = parser.iter.peek() {
+            let RespValue::BulkString(option_bytes) = peeked_arg else {
+                return Err(CommandError::SyntaxError);
+            };
+            let option = option_bytes.to_ascii_uppercase();
+            parser.iter.next(); // Consume the option name
+
+            match option.as_slice() {
+                b"EX" | b"PX" | b"EXAT" | b"PXAT" => expiries.push(Expiry::parse(&option, parser)?),
+                b"KEEPTTL" => keep_ttl = true,
+                b"GET" => get = true,
+                b"NX" => nx = true,
+                b"XX" => xx = true,
+                _ => return Err(CommandError::SyntaxError),
+            }
+        }
+
+        let conflicts = [
+            expiries.len() > 1,
+            !expiries.is_empty() && keep_ttl,
+            nx && xx,
+        ];
+        if conflicts.contains(&true) {
+            return Err(CommandError::SyntaxError);
+        }
+        let expiry = expiries.pop();
+
+        Ok(Set {
+            key,
+            value,
+            expiry,
+            keep_ttl,
+            nx,
+            xx,
+            get,
+        })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue {
+        let mut db_lock = db.lock(&self.key);
+
+        let existing = db_lock
+            .get(&self.key)
+            .filter(|db_value| !db_value.is_expired_at(db.now()));
+        // Checked first so a bad time is an error even when NX or XX refuses.
+        let expires_at = match self.expiry.map(|expiry| expiry.to_instant(&*server.clock)) {
+            Some(None) => {
+                return error_reply(ErrorCode::Err, "invalid expire time in 'set' command")
+            }
+            Some(expires_at) => expires_at,
+            None if self.keep_ttl => existing.and_then(|db_value| db_value.expires_at),
+            None => None,
+        };
+        let old_value = match existing {
+            Some(db_value) if self.get => match db_value.as_string() {
+                Some(value) => bulk_string(value),
+                None => return CommandError::WrongType.to_resp(),
+            },
+            _ => RespValue::Null,
+        };
+        if (self.nx && existing.is_some()) || (self.xx && existing.is_none()) {
+            return old_value;
+        }
+
+        db_lock.insert(self.key, DbValue::string(self.value, expires_at));
+        if self.get {
+            old_value
+        } else {
+            ok()
+        }
+    }
+
+    /// A relative EX or PX becomes PXAT, so a replay expires the key when the
+    /// original did. NX, XX and GET are dropped once the write has happened,
+    /// and a write they refused propagates nothing.
+    fn propagate(&self, clock: &dyn Clock) -> Propagate {
+        let (get, nx, xx) = (self.get, self.nx, self.xx);
+        let relative = self.expiry.and_then(Expiry::relative_millis);
+        let unix_now = clock.unix_millis();
+        Box::new(move |request, result| {
+            // With GET the reply is the old value, so it says whether the key existed.
+            let refused = if get {
+                let existed = *result != RespValue::Null;
+                (nx && existed) || (xx && !existed)
+            } else {
+                *result == RespValue::Null
+            };
+            if refused {
+                return None;
+            }
+            let Some(millis) = relative else {
+                return Some(vec![request]);
+            };
+            let deadline = deadline_in(unix_now, millis?)?;
+            let mut args = request_args(request);
+            let (key, value) = (args.next()?, args.next()?);
+            Some(vec![RespValue::Array(vec![
+                bulk_string(b"SET"),
+                key,
+                value,
+                bulk_string(b"PXAT"),
+                bulk_string(deadline.as_bytes()),
+            ])])
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Getdel {
+    key: Vec<u8>,
+}
+
+impl CommandExt for Getdel {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Getdel { key })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue {
+        let mut db_lock = db.lock(&self.key);
+        let is_string = db_lock
+            .get_live(&self.key)
+            .map(|db_value| db_value.as_string().is_some());
+        server.stats.record_lookup(is_string.is_some());
+        match is_string {
+            Some(false) => CommandError::WrongType.to_resp(),
+            Some(true) => match db_lock.remove(&self.key).map(|db_value| db_value.data) {
+                Some(DbData::String(value)) => {
+                    drop(db_lock);
+                    notify::keyspace_event(server, db, Flags::GENERIC, "del", &self.key);
+                    RespValue::BulkString(value)
+                }
+                _ => unreachable!("checked to be a string"),
+            },
+            None => RespValue::Null,
+        }
+    }
+
+    fn propagate(&self, _clock: &dyn Clock) -> Propagate {
+        Box::new(|request, result| (*result != RespValue::Null).then(|| vec![request]))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Getset {
+    key: Vec<u8>,
+    value: Vec<u8>,
+}
+
+impl CommandExt for Getset {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let value = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Getset { key, value })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue {
+        let mut db_lock = db.lock(&self.key);
+        let entry = db_lock.get_live(&self.key);
+        server.stats.record_lookup(entry.is_some());
+        let old = match entry.as_deref().map(DbValue::as_string) {
+            Some(Some(value)) => bulk_string(value),
+            Some(None) => return CommandError::WrongType.to_resp(),
+            None => RespValue::Null,
+        };
+        drop(entry);
+
+        // Like SET, and unlike APPEND or INCR, this drops the key's expiry.
+        db_lock.insert(self.key, DbValue::string(self.value, None));
+        old
+    }
+}
+
+/// What GETEX does to the key's expiry.
+#[derive(Debug, Clone, PartialEq)]
+enum GetexExpiry {
+    Keep,
+    Set(Expiry),
+    Persist,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Getex {
+    key: Vec<u8>,
+    expiry: GetexExpiry,
+}
+
+impl CommandExt for Getex {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let expiry = match parser.iter.next() {
+            None => GetexExpiry::Keep,
+            Some(RespValue::BulkString(option)) => {
+                let option = option.to_ascii_uppercase();
+                match option.as_slice() {
+                    b"EX" | b"PX" | b"EXAT" | b"PXAT" => {
+                        GetexExpiry::Set(Expiry::parse(&option, parser)?)
+                    }
+                    b"PERSIST" => GetexExpiry::Persist,
+                    _ => return Err(CommandError::SyntaxError),
+                }
+            }
+            Some(_) => return Err(CommandError::SyntaxError),
+        };
+        // Only one option is allowed.
+        if parser.iter.peek().is_some() {
+            return Err(CommandError::SyntaxError);
+        }
+        Ok(Getex { key, expiry })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue {
+        let expires_at = match self.expiry {
+            GetexExpiry::Set(expiry) => match expiry.to_instant(&*server.clock) {
+                Some(expires_at) => Some(Some(expires_at)),
+                None => {
+                    return error_reply(ErrorCode::Err, "invalid expire time in 'getex' command")
+                }
+            },
+            GetexExpiry::Persist => Some(None),
+            GetexExpiry::Keep => None,
+        };
+
+        let mut db_lock = db.lock(&self.key);
+        let (reply, had_expiry) = {
+            let db_value = db_lock.get_live(&self.key);
+            server.stats.record_lookup(db_value.is_some());
+            let Some(db_value) = db_value else {
+                return RespValue::Null;
+            };
+            let Some(value) = db_value.as_string() else {
+                return CommandError::WrongType.to_resp();
+            };
+            (bulk_string(value), db_value.expires_at.is_some())
+        };
+        if let Some(expires_at) = expires_at {
+            db_lock.set_expires_at(&self.key, expires_at);
+        }
+        drop(db_lock);
+        // PERSIST on a key that had no TTL left it as it was.
+        let event = match expires_at {
+            Some(Some(_)) => Some("expire"),
+            Some(None) if had_expiry => Some("persist"),
+            _ => None,
+        };
+        if let Some(event) = event {
+            notify::keyspace_event(server, db, Flags::GENERIC, event, &self.key);
+        }
+        reply
+    }
+
+    /// A relative EX or PX becomes a PEXPIREAT. Without an option, or on a
+    /// missing key, nothing changed.
+    fn propagate(&self, clock: &dyn Clock) -> Propagate {
+        let unix_now = clock.unix_millis();
+        let relative = match self.expiry {
+            GetexExpiry::Keep => return Box::new(|_, _| None),
+            GetexExpiry::Set(expiry) => expiry.relative_millis(),
+            GetexExpiry::Persist => None,
+        };
+        Box::new(move |request, result| {
+            if *result == RespValue::Null {
+                return None;
+            }
+            let Some(millis) = relative else {
+                return Some(vec![request]);
+            };
+            let deadline = deadline_in(unix_now, millis?)?;
+            let key = request_args(request).next()?;
+            Some(vec![RespValue::Array(vec![
+                bulk_string(b"PEXPIREAT"),
+                key,
+                bulk_string(deadline.as_bytes()),
+            ])])
+        })
+    }
+}
+
+/// Changes the string at `key` under its lock, keeping the key's expiry.
+/// `update` gets the string, empty if the key is missing, and whether the key
+/// existed. It returns the reply, or an error reply after leaving the string
+/// as it was; a missing key is only created if it succeeds. `in_place` says
+/// whether the change edits an existing string rather than replacing it,
+/// which decides what OBJECT ENCODING reports (see `DbValue::raw`). A newly
+/// created string is never an edit.
+fn with_string_mut(
+    db: &Database,
+    key: &[u8],
+    in_place: bool,
+    update: impl FnOnce(&mut Vec<u8>, bool) -> Result<RespValue, RespValue>,
+) -> RespValue {
+    let mut db_lock = db.lock(key);
+
+    if let Some(mut db_value) = db_lock.get_live(key) {
+        let DbValue {
+            data: DbData::String(value),
+            modified_at,
+            raw,
+            ..
+        } = &mut *db_value
+        else {
+            return CommandError::WrongType.to_resp();
+        };
+        return match update(value, true) {
+            Ok(reply) => {
+                *modified_at = Instant::now();
+                *raw = in_place;
+                reply
+            }
+            Err(reply) => reply,
+        };
+    }
+
+    let mut value = Vec::new();
+    match update(&mut value, false) {
+        Ok(reply) => {
+            db_lock.insert(key.to_vec(), DbValue::string(value, None));
+            reply
+        }
+        Err(reply) => reply,
+    }
+}
+
+/// Shared body of INCR/DECR: missing keys count as 0 and the existing expiry is kept.
+/// `event` is the keyspace notification a successful change publishes.
+fn incr_by(server: &Server, db: &Database, key: &[u8], delta: i64, event: &str) -> RespValue {
+    let reply = with_string_mut(db, key, false, |value, existed| {
+        let current = if existed {
+            parse_i64(value).ok_or(CommandError::NotAnInteger.to_resp())?
+        } else {
+            0
+        };
+        let new_value = current
+            .checked_add(delta)
+            .ok_or(CommandError::NotAnInteger.to_resp())?;
+        *value = new_value.to_string().into_bytes();
+        Ok(RespValue::Integer(new_value))
+    });
+    if !matches!(reply, RespValue::Error(_)) {
+        notify::keyspace_event(server, db, Flags::STRING, event, key);
+    }
+    reply
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Incr {
+    key: Vec<u8>,
+}
+
+impl CommandExt for Incr {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Incr { key })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue {
+        incr_by(server, db, &self.key, 1, "incrby")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Decr {
+    key: Vec<u8>,
+}
+
+impl CommandExt for Decr {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Decr { key })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue {
+        incr_by(server, db, &self.key, -1, "decrby")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Incrby {
+    key: Vec<u8>,
+    delta: i64,
+}
+
+impl CommandExt for Incrby {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let delta = parser.next_integer()?;
+        parser.finish()?;
+        Ok(Incrby { key, delta })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue {
+        incr_by(server, db, &self.key, self.delta, "incrby")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Decrby {
+    key: Vec<u8>,
+    delta: i64,
+}
+
+impl CommandExt for Decrby {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let delta = parser.next_integer()?;
+        parser.finish()?;
+        Ok(Decrby { key, delta })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue {
+        // -i64::MIN doesn't fit, so that delta is an overflow like any other.
+        match self.delta.checked_neg() {
+            Some(delta) => incr_by(server, db, &self.key, delta, "decrby"),
+            None => CommandError::NotAnInteger.to_resp(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Incrbyfloat {
+    key: Vec<u8>,
+    delta: f64,
+}
+
+impl CommandExt for Incrbyfloat {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let delta = parser.next_float()?;
+        parser.finish()?;
+        Ok(Incrbyfloat { key, delta })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue {
+        let reply = with_string_mut(db, &self.key, false, |value, existed| {
+            let current = if existed {
+                parse_f64(value).ok_or(CommandError::NotAFloat.to_resp())?
+            } else {
+                0.0
+            };
+            let new_value = current + self.delta;
+            if !new_value.is_finite() {
+                return Err(error_reply(
+                    ErrorCode::Err,
+                    "increment would produce NaN or Infinity",
+                ));
+            }
+            // `Display` drops a zero fraction, so 5.0 is stored as "5" like Redis does.
+            *value = new_value.to_string().into_bytes();
+            Ok(bulk_string(value))
+        });
+        if !matches!(reply, RespValue::Error(_)) {
+            notify::keyspace_event(server, db, Flags::STRING, "incrbyfloat", &self.key);
+        }
+        reply
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Mset {
+    pairs: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl CommandExt for Mset {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let args = parser.remaining_bulk_strings()?;
+        if args.is_empty() || args.len() % 2 != 0 {
+            return Err(CommandError::WrongArgCount);
+        }
+        let mut args = args.into_iter();
+        let mut pairs = Vec::with_capacity(args.len() / 2);
+        while let (Some(key), Some(value)) = (args.next(), args.next()) {
+            pairs.push((key, value));
+        }
+        Ok(Mset { pairs })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        let mut shards = db.lock_keys(self.pairs.iter().map(|(key, _)| &key[..]));
+        for (key, value) in self.pairs {
+            shards.shard(&key).insert(key, DbValue::string(value, None));
+        }
+        ok()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Mget {
+    keys: Vec<Vec<u8>>,
+}
+
+impl CommandExt for Mget {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let keys = parser.remaining_bulk_strings()?;
+        if keys.is_empty() {
+            return Err(CommandError::WrongArgCount);
+        }
+        Ok(Mget { keys })
+    }
+
+    fn execute(self: Box<Self>, server: &Server, db: &Database) -> RespValue {
+        let shards = db.read_keys(self.keys.iter().map(|key| &key[..]));
+        let values = self
+            .keys
+            .iter()
+            .map(|key| {
+                let db_value = shards.read_live(key);
+                server.stats.record_lookup(db_value.is_some());
+                // Unlike GET, a key of another type is just reported as missing.
+                match db_value.and_then(DbValue::as_string) {
+                    Some(value) => bulk_string(value),
+                    None => RespValue::Null,
+                }
+            })
+            .collect();
+        RespValue::Array(values)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Append {
+    key: Vec<u8>,
+    value: Vec<u8>,
+}
+
+impl CommandExt for Append {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let value = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Append { key, value })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        with_string_mut(db, &self.key, true, |value, _| {
+            value.extend_from_slice(&self.value);
+            Ok(RespValue::Integer(value.len() as i64))
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Strlen {
+    key: Vec<u8>,
+}
+
+impl CommandExt for Strlen {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Strlen { key })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        db.read_live(&self.key, |db_value| match db_value {
+            Some(db_value) => match db_value.as_string() {
+                Some(value) => RespValue::Integer(value.len() as i64),
+                None => CommandError::WrongType.to_resp(),
+            },
+            None => RespValue::Integer(0),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Getrange {
+    key: Vec<u8>,
+    start: i64,
+    end: i64,
+}
+
+impl CommandExt for Getrange {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let start = parser.next_integer()?;
+        let end = parser.next_integer()?;
+        parser.finish()?;
+        Ok(Getrange { key, start, end })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        db.read_live(&self.key, |db_value| {
+            let value = match db_value.map(DbValue::as_string) {
+                Some(Some(value)) => value.as_slice(),
+                Some(None) => return CommandError::WrongType.to_resp(),
+                None => &[],
+            };
+            let len = value.len() as i64;
+            let resolve = |index: i64| {
+                if index < 0 {
+                    (len + index).max(0)
+                } else {
+                    index
+                }
+            };
+            let (start, end) = (resolve(self.start), resolve(self.end).min(len - 1));
+            if start > end {
+                return bulk_string(&[]);
+            }
+            bulk_string(&value[start as usize..=end as usize])
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Setrange {
+    key: Vec<u8>,
+    offset: usize,
+    value: Vec<u8>,
+}
+
+impl CommandExt for Setrange {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let offset =
+            usize::try_from(parser.next_integer()?).map_err(|_| CommandError::InvalidArgument {
+                reason: "offset is out of range".to_string(),
+            })?;
+        let value = parser.next_bulk_string()?;
+        parser.finish()?;
+        Ok(Setrange { key, offset, value })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        let mut db_lock = db.lock(&self.key);
+        let len = match db_lock.get_live(&self.key) {
+            Some(db_value) => match db_value.as_string() {
+                Some(value) => Some(value.len()),
+                None => return CommandError::WrongType.to_resp(),
+            },
+            None => None,
+        };
+        // Writing nothing changes nothing: no padding, and a missing key isn't created.
+        if self.value.is_empty() {
+            return RespValue::Integer(len.unwrap_or(0) as i64);
+        }
+
+        let end = self.offset.saturating_add(self.value.len());
+        if end > MAX_BULK_LEN as usize {
+            return error_reply(
+                ErrorCode::Err,
+                "string exceeds maximum allowed size (proto-max-bulk-len)",
+            );
+        }
+        if len.is_none() {
+            db_lock.insert(self.key.clone(), DbValue::string(Vec::new(), None));
+        }
+        let mut entry = db_lock.get_mut(&self.key);
+        let Some(DbValue {
+            data: DbData::String(value),
+            modified_at,
+            raw,
+            ..
+        }) = entry.as_deref_mut()
+        else {
+            unreachable!("checked or created as a string");
+        };
+
+        if value.len() < end {
+            value.resize(end, 0);
+        }
+        value[self.offset..end].copy_from_slice(&self.value);
+        *modified_at = Instant::now();
+        *raw = true;
+        RespValue::Integer(value.len() as i64)
+    }
+
+    fn propagate(&self, _clock: &dyn Clock) -> Propagate {
+        let changes = !self.value.is_empty();
+        Box::new(move |request, _| changes.then(|| vec![request]))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Setbit {
+    key: Vec<u8>,
+    offset: usize,
+    bit: bool,
+}
+
+impl CommandExt for Setbit {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let offset = usize::try_from(parser.next_integer()?)
+            .ok()
+            .filter(|offset| offset / 8 < MAX_BULK_LEN as usize)
+            .ok_or(CommandError::InvalidArgument {
+                reason: "bit offset is not an integer or out of range".to_string(),
+            })?;
+        let bit = match parser.next_bulk_string()?.as_slice() {
+            b"0" => false,
+            b"1" => true,
+            _ => {
+                return Err(CommandError::InvalidArgument {
+                    reason: "bit is not an integer or out of range".to_string(),
+                })
+            }
+        };
+        parser.finish()?;
+        Ok(Setbit { key, offset, bit })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        let mut db_lock = db.lock(&self.key);
+        let is_string = db_lock
+            .get_live(&self.key)
+            .map(|db_value| db_value.as_string().is_some());
+        match is_string {
+            Some(true) => {}
+            Some(false) => return CommandError::WrongType.to_resp(),
+            // Unlike SETRANGE, clearing a bit still creates the key.
+            None => {
+                db_lock.insert(self.key.clone(), DbValue::string(Vec::new(), None));
+            }
+        }
+        let mut entry = db_lock.get_mut(&self.key);
+        let Some(DbValue {
+            data: DbData::String(value),
+            modified_at,
+            raw,
+            ..
+        }) = entry.as_deref_mut()
+        else {
+            unreachable!("checked or created as a string");
+        };
+
+        let (byte, mask) = bit_position(self.offset);
+        if value.len() <= byte {
+            value.resize(byte + 1, 0);
+        }
+        let previous = value[byte] & mask != 0;
+        if self.bit {
+            value[byte] |= mask;
+        } else {
+            value[byte] &= !mask;
+        }
+        *modified_at = Instant::now();
+        *raw = true;
+        RespValue::Integer(previous as i64)
+    }
+}
+
+/// The byte holding bit `offset` and the mask selecting it. Bit 0 is the most
+/// significant bit of the first byte, as in Redis.
+fn bit_position(offset: usize) -> (usize, u8) {
+    (offset / 8, 0x80 >> (offset % 8))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Getbit {
+    key: Vec<u8>,
+    offset: usize,
+}
+
+impl CommandExt for Getbit {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let offset =
+            usize::try_from(parser.next_integer()?).map_err(|_| CommandError::InvalidArgument {
+                reason: "bit offset is not an integer or out of range".to_string(),
+            })?;
+        parser.finish()?;
+        Ok(Getbit { key, offset })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        db.read_live(&self.key, |db_value| {
+            let value = match db_value.map(DbValue::as_string) {
+                Some(Some(value)) => value.as_slice(),
+                Some(None) => return CommandError::WrongType.to_resp(),
+                None => &[],
+            };
+            let (byte, mask) = bit_position(self.offset);
+            let bit = value.get(byte).is_some_and(|byte| byte & mask != 0);
+            RespValue::Integer(bit as i64)
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Bitcount {
+    key: Vec<u8>,
+    /// Inclusive byte range, resolved like GETRANGE's. `None` counts the whole string.
+    range: Option<(i64, i64)>,
+}
+
+impl CommandExt for Bitcount {
+    fn parse(parser: &mut ArgParser) -> Result<Self, CommandError> {
+        let key = parser.next_bulk_string()?;
+        let range = match parser.iter.peek() {
+            Some(_) => {
+                let start = parser.next_integer()?;
+                let end = match parser.iter.peek() {
+                    Some(_) => parser.next_integer()?,
+                    None => return Err(CommandError::SyntaxError),
+                };
+                Some((start, end))
+            }
+            None => None,
+        };
+        parser.finish()?;
+        Ok(Bitcount { key, range })
+    }
+
+    fn execute(self: Box<Self>, _server: &Server, db: &Database) -> RespValue {
+        db.read_live(&self.key, |db_value| {
+            let value = match db_value.map(DbValue::as_string) {
+                Some(Some(value)) => value.as_slice(),
+                Some(None) => return CommandError::WrongType.to_resp(),
+                None => &[],
+            };
+            let bytes = match self.range {
+                None => value,
+                Some((start, end)) => {
+                    let len = value.len() as i64;
+                    let resolve = |index: i64| {
+                        if index < 0 {
+                            (len + index).max(0)
+                        } else {
+                            index
+                        }
+                    };
+                    let (start, end) = (resolve(start), resolve(end).min(len - 1));
+                    if start > end {
+                        &[]
+                    } else {
+                        &value[start as usize..=end as usize]
+                    }
+                }
+            };
+            let count: u32 = bytes.iter().map(|byte| byte.count_ones()).sum();
+            RespValue::Integer(count as i64)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{run, Command, CommandError};
+    use crate::db::{unix_millis_now, DbData, DbValue};
+    use crate::parser::RespValue;
+    use crate::server::Server;
+    use crate::stats::Stats;
+    use std::thread;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    /// Turns keyspace notifications on and listens on `__keyevent@0__:<event>`.
+    fn listen(server: &Server, event: &str) -> mpsc::UnboundedReceiver<RespValue> {
+        let mut config = server.config.write().unwrap();
+        config.set("notify-keyspace-events", "KEA").unwrap();
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let channel = format!("__keyevent@0__:{event}");
+        let id = server.pubsub.next_subscriber_id();
+        server.pubsub.subscribe(channel.as_bytes(), id, &sender);
+        receiver
+    }
+
+    /// The keys notified so far.
+    fn notified(receiver: &mut mpsc::UnboundedReceiver<RespValue>) -> Vec<Vec<u8>> {
+        let mut keys = Vec::new();
+        while let Ok(RespValue::Array(message)) = receiver.try_recv() {
+            match message.as_slice() {
+                [_, _, RespValue::BulkString(key)] => keys.push(key.clone()),
+                other => panic!("unexpected message {:?}", other),
+            }
+        }
+        keys
+    }
+
+    #[test]
+    fn test_set_get() {
+        let value = b"hello world value";
+        let server = Server::default();
+        let resp_value = RespValue::Array(vec![
+            RespValue::BulkString(b"SET".to_vec()),
+            RespValue::BulkString(b"key".to_vec()),
+            RespValue::BulkString(value.to_vec()),
+        ]);
+
+        let command = Command::from_resp(resp_value).unwrap();
+        let response = command.execute(&server, &server.db(0));
+        assert_eq!(response, RespValue::SimpleString("OK".to_string()));
+
+        let resp_value = RespValue::Array(vec![
+            RespValue::BulkString(b"GET".to_vec()),
+            RespValue::BulkString(b"key".to_vec()),
+        ]);
+        let command = Command::from_resp(resp_value).unwrap();
+        let response = command.execute(&server, &server.db(0));
+        assert_eq!(response, RespValue::BulkString(value.to_vec()));
+    }
+
+    #[test]
+    fn test_set_with_px_and_expiration() {
+        let server = Server::default();
+        let key = b"key";
+        let value = b"value";
+        let px_ms = 100u64;
+        let set_resp = RespValue::Array(vec![
+            RespValue::BulkString(b"SET".to_vec()),
+            RespValue::BulkString(key.to_vec()),
+            RespValue::BulkString(value.to_vec()),
+            RespValue::BulkString(b"PX".to_vec()),
+            RespValue::BulkString(px_ms.to_string().into_bytes()),
+        ]);
+
+        let command = Command::from_resp(set_resp).unwrap();
+        assert_eq!(
+            command.execute(&server, &server.db(0)),
+            RespValue::SimpleString("OK".to_string())
+        );
+
+        let get_resp = RespValue::Array(vec![
+            RespValue::BulkString(b"GET".to_vec()),
+            RespValue::BulkString(key.to_vec()),
+        ]);
+        let get_command = Command::from_resp(get_resp).unwrap();
+        assert_eq!(
+            get_command.execute(&server, &server.db(0)),
+            RespValue::BulkString(value.to_vec())
+        );
+
+        thread::sleep(Duration::from_millis(px_ms + 10));
+
+        let get_resp_after = RespValue::Array(vec![
+            RespValue::BulkString(b"GET".to_vec()),
+            RespValue::BulkString(key.to_vec()),
+        ]);
+        let get_command_after = Command::from_resp(get_resp_after).unwrap();
+        assert_eq!(
+            get_command_after.execute(&server, &server.db(0)),
+            RespValue::Null
+        );
+    }
+
+    #[test]
+    fn test_incr_decr() {
+        let server = Server::default();
+        let incr = || {
+            RespValue::Array(vec![
+                RespValue::BulkString(b"INCR".to_vec()),
+                RespValue::BulkString(b"counter".to_vec()),
+            ])
+        };
+        assert_eq!(
+            Command::from_resp(incr())
+                .unwrap()
+                .execute(&server, &server.db(0)),
+            RespValue::Integer(1)
+        );
+        assert_eq!(
+            Command::from_resp(incr())
+                .unwrap()
+                .execute(&server, &server.db(0)),
+            RespValue::Integer(2)
+        );
+
+        let decr_resp = RespValue::Array(vec![
+            RespValue::BulkString(b"DECR".to_vec()),
+            RespValue::BulkString(b"counter".to_vec()),
+        ]);
+        assert_eq!(
+            Command::from_resp(decr_resp)
+                .unwrap()
+                .execute(&server, &server.db(0)),
+            RespValue::Integer(1)
+        );
+    }
+
+    #[test]
+    fn test_incr_errors() {
+        let server = Server::default();
+        let set_resp = RespValue::Array(vec![
+            RespValue::BulkString(b"SET".to_vec()),
+            RespValue::BulkString(b"text".to_vec()),
+            RespValue::BulkString(b"abc".to_vec()),
+        ]);
+        Command::from_resp(set_resp)
+            .unwrap()
+            .execute(&server, &server.db(0));
+        let set_resp = RespValue::Array(vec![
+            RespValue::BulkString(b"SET".to_vec()),
+            RespValue::BulkString(b"max".to_vec()),
+            RespValue::BulkString(i64::MAX.to_string().into_bytes()),
+        ]);
+        Command::from_resp(set_resp)
+            .unwrap()
+            .execute(&server, &server.db(0));
+
+        for key in [b"text".as_slice(), b"max".as_slice()] {
+            let incr_resp = RespValue::Array(vec![
+                RespValue::BulkString(b"INCR".to_vec()),
+                RespValue::BulkString(key.to_vec()),
+            ]);
+            assert_eq!(
+                Command::from_resp(incr_resp)
+                    .unwrap()
+                    .execute(&server, &server.db(0)),
+                CommandError::NotAnInteger.to_resp()
+            );
+        }
+    }
+
+    #[test]
+    fn test_keys_are_case_sensitive() {
+        let server = Server::default();
+        for (key, value) in [(b"abc", b"1"), (b"ABC", b"2")] {
+            let set_resp = RespValue::Array(vec![
+                RespValue::BulkString(b"SET".to_vec()),
+                RespValue::BulkString(key.to_vec()),
+                RespValue::BulkString(value.to_vec()),
+            ]);
+            Command::from_resp(set_resp)
+                .unwrap()
+                .execute(&server, &server.db(0));
+        }
+        assert_eq!(server.db(0).lock_all().iter().count(), 2);
+
+        let get_resp = RespValue::Array(vec![
+            RespValue::BulkString(b"GET".to_vec()),
+            RespValue::BulkString(b"abc".to_vec()),
+        ]);
+        let command = Command::from_resp(get_resp).unwrap();
+        assert_eq!(
+            command.execute(&server, &server.db(0)),
+            RespValue::BulkString(b"1".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_get_counts_keyspace_hits_and_misses() {
+        let server = Server::default();
+        let set_resp = RespValue::Array(vec![
+            RespValue::BulkString(b"SET".to_vec()),
+            RespValue::BulkString(b"present".to_vec()),
+            RespValue::BulkString(b"value".to_vec()),
+        ]);
+        Command::from_resp(set_resp)
+            .unwrap()
+            .execute(&server, &server.db(0));
+        let set_resp = RespValue::Array(vec![
+            RespValue::BulkString(b"SET".to_vec()),
+            RespValue::BulkString(b"expiring".to_vec()),
+            RespValue::BulkString(b"value".to_vec()),
+            RespValue::BulkString(b"PX".to_vec()),
+            RespValue::BulkString(b"1".to_vec()),
+        ]);
+        Command::from_resp(set_resp)
+            .unwrap()
+            .execute(&server, &server.db(0));
+        thread::sleep(Duration::from_millis(5));
+
+        for key in [b"present".as_slice(), b"missing", b"expiring"] {
+            let get_resp = RespValue::Array(vec![
+                RespValue::BulkString(b"GET".to_vec()),
+                RespValue::BulkString(key.to_vec()),
+            ]);
+            Command::from_resp(get_resp)
+                .unwrap()
+                .execute(&server, &server.db(0));
+        }
+
+        assert_eq!(Stats::get(&server.stats.keyspace_hits), 1);
+        assert_eq!(Stats::get(&server.stats.keyspace_misses), 2);
+
+        let info_resp = RespValue::Array(vec![
+            RespValue::BulkString(b"INFO".to_vec()),
+            RespValue::BulkString(b"stats".to_vec()),
+        ]);
+        let RespValue::BulkString(info) = Command::from_resp(info_resp)
+            .unwrap()
+            .execute(&server, &server.db(0))
+        else {
+            panic!("INFO should reply with a bulk string");
+        };
+        let info = String::from_utf8(info).unwrap();
+        assert!(info.contains("keyspace_hits:1\r\n"));
+        assert!(info.contains("keyspace_misses:2\r\n"));
+    }
+
+    #[test]
+    fn test_binary_safe_keys() {
+        let server = Server::default();
+        let key = vec![0xFF, 0xFE, b'k'];
+        let set_resp = RespValue::Array(vec![
+            RespValue::BulkString(b"SET".to_vec()),
+            RespValue::BulkString(key.clone()),
+            RespValue::BulkString(vec![0xFF, 0xFE]),
+        ]);
+        Command::from_resp(set_resp)
+            .unwrap()
+            .execute(&server, &server.db(0));
+        assert!(server.db(0).lock(&key).contains_key(&key));
+
+        let get_resp = RespValue::Array(vec![
+            RespValue::BulkString(b"GET".to_vec()),
+            RespValue::BulkString(key),
+        ]);
+        let command = Command::from_resp(get_resp).unwrap();
+        assert_eq!(
+            command.execute(&server, &server.db(0)),
+            RespValue::BulkString(vec![0xFF, 0xFE])
+        );
+    }
+
+    #[test]
+    fn test_set_nx_xx() {
+        let server = Server::default();
+        assert_eq!(run(&server, &[b"SET", b"key", b"v1", b"XX"]), b"$-1\r\n");
+        assert_eq!(run(&server, &[b"GET", b"key"]), b"$-1\r\n");
+
+        assert_eq!(run(&server, &[b"SET", b"key", b"v1", b"NX"]), b"+OK\r\n");
+        assert_eq!(run(&server, &[b"SET", b"key", b"v2", b"nx"]), b"$-1\r\n");
+        assert_eq!(run(&server, &[b"GET", b"key"]), b"$2\r\nv1\r\n");
+
+        assert_eq!(run(&server, &[b"SET", b"key", b"v3", b"XX"]), b"+OK\r\n");
+        assert_eq!(run(&server, &[b"GET", b"key"]), b"$2\r\nv3\r\n");
+
+        let resp = RespValue::Array(vec![
+            RespValue::BulkString(b"SET".to_vec()),
+            RespValue::BulkString(b"key".to_vec()),
+            RespValue::BulkString(b"v".to_vec()),
+            RespValue::BulkString(b"NX".to_vec()),
+            RespValue::BulkString(b"XX".to_vec()),
+        ]);
+        assert!(Command::from_resp(resp).is_err());
+    }
+
+    #[test]
+    fn test_set_ex_matches_px() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"ex", b"v", b"EX", b"10"]);
+        run(&server, &[b"SET", b"px", b"v", b"PX", b"10000"]);
+        assert_eq!(run(&server, &[b"TTL", b"ex"]), b":10\r\n");
+        assert_eq!(run(&server, &[b"TTL", b"px"]), b":10\r\n");
+    }
+
+    #[test]
+    fn test_set_absolute_expiry() {
+        let server = Server::default();
+        let past = (unix_millis_now() / 1000 - 10).to_string();
+        run(&server, &[b"SET", b"past", b"v", b"EXAT", past.as_bytes()]);
+        assert_eq!(run(&server, &[b"GET", b"past"]), b"$-1\r\n");
+
+        let future = (unix_millis_now() + 10_000).to_string();
+        run(
+            &server,
+            &[b"SET", b"future", b"v", b"PXAT", future.as_bytes()],
+        );
+        assert_eq!(run(&server, &[b"TTL", b"future"]), b":10\r\n");
+
+        let resp = RespValue::Array(vec![
+            RespValue::BulkString(b"SET".to_vec()),
+            RespValue::BulkString(b"key".to_vec()),
+            RespValue::BulkString(b"v".to_vec()),
+            RespValue::BulkString(b"EX".to_vec()),
+            RespValue::BulkString(b"10".to_vec()),
+            RespValue::BulkString(b"PXAT".to_vec()),
+            RespValue::BulkString(future.into_bytes()),
+        ]);
+        assert!(Command::from_resp(resp).is_err());
+    }
+
+    #[test]
+    fn test_set_future_exat_and_past_pxat() {
+        let server = Server::default();
+        let future = (unix_millis_now() / 1000 + 100).to_string();
+        assert_eq!(
+            run(
+                &server,
+                &[b"SET", b"alive", b"v", b"EXAT", future.as_bytes()]
+            ),
+            b"+OK\r\n"
+        );
+        assert_eq!(run(&server, &[b"GET", b"alive"]), b"$1\r\nv\r\n");
+
+        let past = (unix_millis_now() - 1000).to_string();
+        assert_eq!(
+            run(&server, &[b"SET", b"gone", b"v", b"PXAT", past.as_bytes()]),
+            b"+OK\r\n"
+        );
+        assert_eq!(run(&server, &[b"GET", b"gone"]), b"$-1\r\n");
+        assert_eq!(run(&server, &[b"EXISTS", b"gone"]), b":0\r\n");
+    }
+
+    #[test]
+    fn test_set_rejects_zero_and_overflowing_expiry() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"key", b"v"]);
+        for option in [
+            &[&b"EX"[..], b"0"][..],
+            &[b"PX", b"0"],
+            &[b"EXAT", b"0"],
+            &[b"PXAT", b"0"],
+            &[b"EX", b"9223372036854775"],
+            &[b"PX", b"9223372036854775807"],
+            &[b"EXAT", b"9223372036854776"],
+            &[b"EX", b"0", b"NX"],
+        ] {
+            let args: Vec<&[u8]> = [&b"SET"[..], b"key", b"new"]
+                .into_iter()
+                .chain(option.iter().copied())
+                .collect();
+            assert_eq!(
+                run(&server, &args),
+                b"-ERR invalid expire time in 'set' command\r\n",
+                "{:?}",
+                option
+            );
+        }
+        assert_eq!(run(&server, &[b"GET", b"key"]), b"$1\r\nv\r\n");
+        assert_eq!(
+            run(&server, &[b"GETEX", b"key", b"PX", b"0"]),
+            b"-ERR invalid expire time in 'getex' command\r\n"
+        );
+    }
+
+    #[test]
+    fn test_set_conflicting_options() {
+        let illegal: &[&[&[u8]]] = &[
+            &[b"EX", b"10", b"PX", b"100"],
+            &[b"EX", b"10", b"EXAT", b"9999999999"],
+            &[b"PX", b"100", b"PXAT", b"9999999999999"],
+            &[b"EX", b"10", b"EX", b"10"],
+            &[b"EX", b"10", b"KEEPTTL"],
+            &[b"KEEPTTL", b"PX", b"100"],
+            &[b"EXAT", b"9999999999", b"KEEPTTL"],
+            &[b"KEEPTTL", b"PXAT", b"9999999999999"],
+            &[b"NX", b"XX"],
+            &[b"XX", b"GET", b"NX"],
+        ];
+        for options in illegal {
+            let mut args = vec![RespValue::BulkString(b"SET".to_vec())];
+            args.extend(
+                [&b"key"[..], b"v"]
+                    .iter()
+                    .chain(options.iter())
+                    .map(|arg| RespValue::BulkString(arg.to_vec())),
+            );
+            let Err(err) = Command::from_resp(RespValue::Array(args)) else {
+                panic!("accepted {:?}", options);
+            };
+            assert_eq!(
+                err.to_resp().encode(),
+                b"-ERR syntax error\r\n",
+                "{:?}",
+                options
+            );
+        }
+    }
+
+    #[test]
+    fn test_set_keepttl() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"key", b"old", b"PX", b"30"]);
+        assert_eq!(
+            run(&server, &[b"SET", b"key", b"new", b"KEEPTTL"]),
+            b"+OK\r\n"
+        );
+        assert_eq!(run(&server, &[b"GET", b"key"]), b"$3\r\nnew\r\n");
+
+        thread::sleep(Duration::from_millis(40));
+        assert_eq!(run(&server, &[b"GET", b"key"]), b"$-1\r\n");
+
+        run(&server, &[b"SET", b"fresh", b"v", b"KEEPTTL"]);
+        assert_eq!(run(&server, &[b"TTL", b"fresh"]), b":-1\r\n");
+    }
+
+    #[test]
+    fn test_set_get_option() {
+        let server = Server::default();
+        assert_eq!(run(&server, &[b"SET", b"key", b"v1", b"GET"]), b"$-1\r\n");
+        assert_eq!(
+            run(&server, &[b"SET", b"key", b"v2", b"GET"]),
+            b"$2\r\nv1\r\n"
+        );
+        assert_eq!(run(&server, &[b"GET", b"key"]), b"$2\r\nv2\r\n");
+
+        assert_eq!(
+            run(&server, &[b"SET", b"key", b"v3", b"NX", b"GET"]),
+            b"$2\r\nv2\r\n"
+        );
+        assert_eq!(run(&server, &[b"GET", b"key"]), b"$2\r\nv2\r\n");
+    }
+
+    #[test]
+    fn test_string_commands_reject_other_types() {
+        let server = Server::default();
+        server.db(0).lock(b"list").insert(
+            b"list".to_vec(),
+            DbValue::new(DbData::List(vec![b"a".to_vec()].into())),
+        );
+
+        let wrong_type = b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        assert_eq!(run(&server, &[b"GET", b"list"]), wrong_type);
+        assert_eq!(run(&server, &[b"INCR", b"list"]), wrong_type);
+        assert_eq!(run(&server, &[b"SET", b"list", b"v", b"GET"]), wrong_type);
+
+        assert_eq!(run(&server, &[b"SET", b"list", b"v"]), b"+OK\r\n");
+        assert_eq!(run(&server, &[b"GET", b"list"]), b"$1\r\nv\r\n");
+    }
+
+    #[test]
+    fn test_append_and_strlen() {
+        let server = Server::default();
+        assert_eq!(run(&server, &[b"STRLEN", b"key"]), b":0\r\n");
+        assert_eq!(run(&server, &[b"APPEND", b"key", b"Hello"]), b":5\r\n");
+        assert_eq!(run(&server, &[b"APPEND", b"key", b" World"]), b":11\r\n");
+        assert_eq!(run(&server, &[b"GET", b"key"]), b"$11\r\nHello World\r\n");
+        assert_eq!(run(&server, &[b"STRLEN", b"key"]), b":11\r\n");
+
+        run(&server, &[b"RPUSH", b"list", b"a"]);
+        let wrongtype = CommandError::WrongType.to_resp().encode();
+        assert_eq!(run(&server, &[b"STRLEN", b"list"]), wrongtype);
+        assert_eq!(run(&server, &[b"APPEND", b"list", b"a"]), wrongtype);
+    }
+
+    #[test]
+    fn test_append_keeps_expiry() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"key", b"a", b"EX", b"100"]);
+        run(&server, &[b"APPEND", b"key", b"b"]);
+        assert_eq!(run(&server, &[b"TTL", b"key"]), b":100\r\n");
+    }
+
+    #[test]
+    fn test_getset_returns_old_value_and_clears_ttl() {
+        let server = Server::default();
+        assert_eq!(run(&server, &[b"GETSET", b"key", b"one"]), b"$-1\r\n");
+        run(&server, &[b"EXPIRE", b"key", b"100"]);
+        assert_eq!(run(&server, &[b"GETSET", b"key", b"two"]), b"$3\r\none\r\n");
+        assert_eq!(run(&server, &[b"GET", b"key"]), b"$3\r\ntwo\r\n");
+        assert_eq!(run(&server, &[b"TTL", b"key"]), b":-1\r\n");
+
+        run(&server, &[b"RPUSH", b"list", b"a"]);
+        assert_eq!(
+            run(&server, &[b"GETSET", b"list", b"v"]),
+            CommandError::WrongType.to_resp().encode()
+        );
+        assert_eq!(
+            run(&server, &[b"LRANGE", b"list", b"0", b"-1"]),
+            b"*1\r\n$1\r\na\r\n"
+        );
+    }
+
+    #[test]
+    fn test_incr_keeps_expiry() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"counter", b"41", b"EX", b"100"]);
+        assert_eq!(run(&server, &[b"INCR", b"counter"]), b":42\r\n");
+        assert_eq!(
+            run(&server, &[b"INCRBYFLOAT", b"counter", b"0.5"]),
+            b"$4\r\n42.5\r\n"
+        );
+        assert_eq!(run(&server, &[b"TTL", b"counter"]), b":100\r\n");
+
+        // Rewriting the whole counter makes it compact again after an edit in place.
+        run(&server, &[b"APPEND", b"counter", b"0"]);
+        run(&server, &[b"INCRBYFLOAT", b"counter", b"-0.5"]);
+        assert_eq!(
+            run(&server, &[b"OBJECT", b"ENCODING", b"counter"]),
+            b"+int\r\n"
+        );
+        assert_eq!(run(&server, &[b"TTL", b"counter"]), b":100\r\n");
+    }
+
+    #[test]
+    fn test_getrange_indices() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"key", b"This is a string"]);
+
+        let getrange = |start: &[u8], end: &[u8]| run(&server, &[b"GETRANGE", b"key", start, end]);
+        assert_eq!(getrange(b"0", b"3"), b"$4\r\nThis\r\n");
+        assert_eq!(getrange(b"-3", b"-1"), b"$3\r\ning\r\n");
+        assert_eq!(getrange(b"0", b"-1"), b"$16\r\nThis is a string\r\n");
+        assert_eq!(getrange(b"10", b"100"), b"$6\r\nstring\r\n");
+        assert_eq!(getrange(b"-100", b"1"), b"$2\r\nTh\r\n");
+        assert_eq!(getrange(b"5", b"2"), b"$0\r\n\r\n");
+        assert_eq!(
+            run(&server, &[b"GETRANGE", b"missing", b"0", b"-1"]),
+            b"$0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_setrange_pads_with_zeros() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"key", b"ab"]);
+        assert_eq!(run(&server, &[b"SETRANGE", b"key", b"4", b"cd"]), b":6\r\n");
+        assert_eq!(run(&server, &[b"GET", b"key"]), b"$6\r\nab\0\0cd\r\n");
+        assert_eq!(run(&server, &[b"SETRANGE", b"key", b"1", b"X"]), b":6\r\n");
+        assert_eq!(run(&server, &[b"GET", b"key"]), b"$6\r\naX\0\0cd\r\n");
+
+        assert_eq!(run(&server, &[b"SETRANGE", b"new", b"2", b"z"]), b":3\r\n");
+        assert_eq!(run(&server, &[b"GET", b"new"]), b"$3\r\n\0\0z\r\n");
+        assert_eq!(run(&server, &[b"SETRANGE", b"empty", b"5", b""]), b":0\r\n");
+        assert_eq!(run(&server, &[b"EXISTS", b"empty"]), b":0\r\n");
+        assert_eq!(
+            run(&server, &[b"SETRANGE", b"key", b"536870912", b""]),
+            b":6\r\n"
+        );
+        assert_eq!(run(&server, &[b"SETRANGE", b"key", b"20", b""]), b":6\r\n");
+        assert_eq!(run(&server, &[b"GET", b"key"]), b"$6\r\naX\0\0cd\r\n");
+        assert_eq!(
+            run(&server, &[b"SETRANGE", b"key", b"536870912", b"x"]),
+            b"-ERR string exceeds maximum allowed size (proto-max-bulk-len)\r\n"
+        );
+    }
+
+    #[test]
+    fn test_incrbyfloat_formats_like_redis() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"key", b"4.5"]);
+        assert_eq!(
+            run(&server, &[b"INCRBYFLOAT", b"key", b"0.5"]),
+            b"$1\r\n5\r\n"
+        );
+        assert_eq!(run(&server, &[b"GET", b"key"]), b"$1\r\n5\r\n");
+        assert_eq!(
+            run(&server, &[b"INCRBYFLOAT", b"key", b"-0.25"]),
+            b"$4\r\n4.75\r\n"
+        );
+        run(&server, &[b"SET", b"nan", b"nan"]);
+        assert_eq!(
+            run(&server, &[b"INCRBYFLOAT", b"nan", b"1"]),
+            b"-ERR value is not a valid float\r\n"
+        );
+        run(&server, &[b"SET", b"big", b"1e308"]);
+        assert_eq!(
+            run(&server, &[b"INCRBYFLOAT", b"big", b"1e308"]),
+            b"-ERR increment would produce NaN or Infinity\r\n"
+        );
+    }
+
+    #[test]
+    fn test_incrby_overflow() {
+        let server = Server::default();
+        assert_eq!(run(&server, &[b"INCRBY", b"key", b"10"]), b":10\r\n");
+        assert_eq!(run(&server, &[b"DECRBY", b"key", b"15"]), b":-5\r\n");
+
+        run(&server, &[b"SET", b"key", b"9223372036854775800"]);
+        assert_eq!(
+            run(&server, &[b"INCRBY", b"key", b"100"]),
+            b"-ERR value is not an integer or out of range\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"GET", b"key"]),
+            b"$19\r\n9223372036854775800\r\n"
+        );
+        assert_eq!(
+            run(&server, &[b"DECRBY", b"key", b"-9223372036854775808"]),
+            b"-ERR value is not an integer or out of range\r\n"
+        );
+    }
+
+    #[test]
+    fn test_mget_mixed_keys() {
+        let server = Server::default();
+        assert_eq!(run(&server, &[b"MSET", b"a", b"1", b"b", b"2"]), b"+OK\r\n");
+        run(&server, &[b"LPUSH", b"list", b"x"]);
+
+        assert_eq!(
+            run(&server, &[b"MGET", b"a", b"missing", b"list", b"b"]),
+            b"*4\r\n$1\r\n1\r\n$-1\r\n$-1\r\n$1\r\n2\r\n"
+        );
+        let odd = RespValue::Array(
+            [&b"MSET"[..], b"a", b"1", b"b"]
+                .iter()
+                .map(|arg| RespValue::BulkString(arg.to_vec()))
+                .collect(),
+        );
+        assert_eq!(
+            Command::from_resp(odd).err(),
+            Some(CommandError::WrongArgCount)
+        );
+    }
+
+    #[test]
+    fn test_getdel_removes_key() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"key", b"value"]);
+        assert_eq!(run(&server, &[b"GETDEL", b"key"]), b"$5\r\nvalue\r\n");
+        assert_eq!(run(&server, &[b"EXISTS", b"key"]), b":0\r\n");
+        assert_eq!(run(&server, &[b"GETDEL", b"key"]), b"$-1\r\n");
+
+        run(&server, &[b"LPUSH", b"list", b"x"]);
+        assert_eq!(
+            run(&server, &[b"GETDEL", b"list"]),
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+        assert_eq!(run(&server, &[b"EXISTS", b"list"]), b":1\r\n");
+    }
+
+    #[test]
+    fn test_getex_persist_clears_ttl() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"key", b"value", b"EX", b"100"]);
+        assert_eq!(run(&server, &[b"GETEX", b"key"]), b"$5\r\nvalue\r\n");
+        assert_eq!(run(&server, &[b"TTL", b"key"]), b":100\r\n");
+
+        assert_eq!(
+            run(&server, &[b"GETEX", b"key", b"PERSIST"]),
+            b"$5\r\nvalue\r\n"
+        );
+        assert_eq!(run(&server, &[b"TTL", b"key"]), b":-1\r\n");
+
+        assert_eq!(
+            run(&server, &[b"GETEX", b"key", b"EX", b"50"]),
+            b"$5\r\nvalue\r\n"
+        );
+        assert_eq!(run(&server, &[b"TTL", b"key"]), b":50\r\n");
+    }
+
+    #[test]
+    fn test_getdel_notifies_once() {
+        let server = Server::default();
+        let mut dels = listen(&server, "del");
+        run(&server, &[b"SET", b"key", b"value"]);
+        run(&server, &[b"LPUSH", b"list", b"x"]);
+        run(&server, &[b"GETDEL", b"key"]);
+        run(&server, &[b"GETDEL", b"key"]);
+        run(&server, &[b"GETDEL", b"list"]);
+        assert_eq!(notified(&mut dels), vec![b"key".to_vec()]);
+    }
+
+    #[test]
+    fn test_incr_notifies_on_success() {
+        let server = Server::default();
+        let mut incrs = listen(&server, "incrby");
+        let mut decrs = listen(&server, "decrby");
+        let mut floats = listen(&server, "incrbyfloat");
+        run(&server, &[b"INCR", b"counter"]);
+        run(&server, &[b"INCRBY", b"counter", b"5"]);
+        run(&server, &[b"DECR", b"counter"]);
+        run(&server, &[b"DECRBY", b"counter", b"2"]);
+        run(&server, &[b"INCRBYFLOAT", b"counter", b"0.5"]);
+        assert_eq!(notified(&mut incrs), vec![b"counter".to_vec(); 2]);
+        assert_eq!(notified(&mut decrs), vec![b"counter".to_vec(); 2]);
+        assert_eq!(notified(&mut floats), vec![b"counter".to_vec()]);
+    }
+
+    #[test]
+    fn test_failed_incr_does_not_notify() {
+        let server = Server::default();
+        let mut incrs = listen(&server, "incrby");
+        run(&server, &[b"LPUSH", b"list", b"x"]);
+        assert_eq!(
+            run(&server, &[b"INCR", b"list"]),
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+        run(&server, &[b"SET", b"text", b"abc"]);
+        run(&server, &[b"INCR", b"text"]);
+        assert!(notified(&mut incrs).is_empty());
+    }
+
+    #[test]
+    fn test_getex_notifies_only_ttl_changes() {
+        let server = Server::default();
+        let mut expires = listen(&server, "expire");
+        let mut persists = listen(&server, "persist");
+        run(&server, &[b"SET", b"key", b"value"]);
+        run(&server, &[b"GETEX", b"key", b"PERSIST"]);
+        run(&server, &[b"GETEX", b"key"]);
+        run(&server, &[b"GETEX", b"missing", b"EX", b"10"]);
+        assert!(notified(&mut expires).is_empty());
+        assert!(notified(&mut persists).is_empty());
+
+        run(&server, &[b"GETEX", b"key", b"EX", b"10"]);
+        assert_eq!(notified(&mut expires), vec![b"key".to_vec()]);
+        run(&server, &[b"GETEX", b"key", b"PERSIST"]);
+        run(&server, &[b"GETEX", b"key", b"PERSIST"]);
+        assert_eq!(notified(&mut persists), vec![b"key".to_vec()]);
+    }
+
+    #[test]
+    fn test_setbit_grows_string_with_zero_bytes() {
+        let server = Server::default();
+        assert_eq!(run(&server, &[b"SETBIT", b"bits", b"100", b"1"]), b":0\r\n");
+        assert_eq!(run(&server, &[b"STRLEN", b"bits"]), b":13\r\n");
+        assert_eq!(run(&server, &[b"SETBIT", b"bits", b"100", b"0"]), b":1\r\n");
+        assert_eq!(run(&server, &[b"GETBIT", b"bits", b"100"]), b":0\r\n");
+        assert_eq!(run(&server, &[b"GETBIT", b"bits", b"100000"]), b":0\r\n");
+
+        // Bit 0 is the high bit of the first byte: "@" is 0b0100_0000.
+        run(&server, &[b"SETBIT", b"char", b"1", b"1"]);
+        assert_eq!(run(&server, &[b"GET", b"char"]), b"$1\r\n@\r\n");
+        assert_eq!(run(&server, &[b"GETBIT", b"char", b"1"]), b":1\r\n");
+
+        for args in [
+            &[&b"SETBIT"[..], b"bits", b"-1", b"1"][..],
+            &[b"SETBIT", b"bits", b"0", b"2"],
+        ] {
+            let frame = RespValue::Array(
+                args.iter()
+                    .map(|arg| RespValue::BulkString(arg.to_vec()))
+                    .collect(),
+            );
+            assert!(Command::from_resp(frame).is_err(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn test_bitcount_over_byte_range() {
+        let server = Server::default();
+        // "foobar" holds 4, 6, 6, 3, 3 and 4 set bits.
+        run(&server, &[b"SET", b"key", b"foobar"]);
+        assert_eq!(run(&server, &[b"BITCOUNT", b"key"]), b":26\r\n");
+        assert_eq!(run(&server, &[b"BITCOUNT", b"key", b"0", b"0"]), b":4\r\n");
+        assert_eq!(run(&server, &[b"BITCOUNT", b"key", b"1", b"1"]), b":6\r\n");
+        assert_eq!(
+            run(&server, &[b"BITCOUNT", b"key", b"-2", b"-1"]),
+            b":7\r\n"
+        );
+        assert_eq!(run(&server, &[b"BITCOUNT", b"key", b"4", b"2"]), b":0\r\n");
+        assert_eq!(run(&server, &[b"BITCOUNT", b"missing"]), b":0\r\n");
+    }
+}
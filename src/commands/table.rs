@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use super::connection::{Echo, Ping};
+use super::hash::{Hdel, Hget, Hgetall, Hincrby, Hrandfield, Hscan, Hset};
+use super::keys::{
+    Copy, Del, Dump, Exists, Expire, Expireat, Expiretime, Keys, Object, Persist, Pexpire,
+    Pexpireat, Pexpiretime, Pttl, Rename, Restore, Scan, Sort, Touch, Ttl, Type, Unlink,
+};
+use super::list::{Linsert, Lpop, Lpos, Lpush, Lrange, Lrem, Lset, Rpop, Rpush};
+use super::pubsub::{Publish, Pubsub};
+use super::server::{
+    Bgrewriteaof, Bgsave, CommandInfo, Config, Dbsize, Debug, DebugSubcommand, Flushall, Flushdb,
+    Info, Save, Swapdb, Wait,
+};
+use super::set::{Sadd, Sdiff, Sinter, Sismember, Smembers, Smove, Srandmember, Sscan, Sunion};
+use super::sorted_set::{Zadd, Zrange, Zscan, Zscore};
+use super::string::{
+    Append, Bitcount, Decr, Decrby, Get, Getbit, Getdel, Getex, Getrange, Getset, Incr, Incrby,
+    Incrbyfloat, Mget, Mset, Set, Setbit, Setrange, Strlen,
+};
+use super::{
+    ArgParser, BlockingPop, ClientSubcommand, Command, CommandError, CommandExt, KeyspaceCommand,
+    GROWING_COMMANDS, WRITE_COMMANDS,
+};
+
+/// Builds a command from the arguments after its name.
+type Parse = fn(&mut ArgParser) -> Result<Command, CommandError>;
+
+/// One command: how to parse it, and what COMMAND reports about it, in
+/// Redis's conventions. A negative arity is a minimum, and the count includes
+/// the command name. Key positions are argument indexes, all 0 for a command
+/// without keys, and a negative `last_key` counts back from the end.
+pub(super) struct CommandSpec {
+    pub(super) name: &'static str,
+    pub(super) arity: i64,
+    pub(super) first_key: i64,
+    pub(super) last_key: i64,
+    pub(super) step: i64,
+    pub(super) parse: Parse,
+    /// A write that isn't in `WRITE_COMMANDS` because it propagates what it
+    /// did as other commands, as BLPOP does with the LPOP it stands for.
+    pub(super) writes_as_others: bool,
+}
+
+impl CommandSpec {
+    /// Whether `len` arguments, counting the name, fit the arity.
+    pub(super) fn accepts(&self, len: usize) -> bool {
+        let len = len as i64;
+        if self.arity < 0 {
+            len >= -self.arity
+        } else {
+            len == self.arity
+        }
+    }
+
+    /// Flags derived from the lists the AOF and maxmemory already go by, so
+    /// they can't disagree with how the command is actually treated.
+    pub(super) fn flags(&self) -> Vec<&'static str> {
+        let listed = |list: &[&[u8]]| list.contains(&self.name.as_bytes());
+        let mut flags = Vec::new();
+        if self.writes_as_others || listed(WRITE_COMMANDS) {
+            flags.push("write");
+        } else if self.first_key > 0 {
+            flags.push("readonly");
+        }
+        if listed(GROWING_COMMANDS) {
+            flags.push("denyoom");
+        }
+        flags
+    }
+}
+
+const fn spec(
+    name: &'static str,
+    arity: i64,
+    first_key: i64,
+    last_key: i64,
+    step: i64,
+    parse: Parse,
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        first_key,
+        last_key,
+        step,
+        parse,
+        writes_as_others: false,
+    }
+}
+
+/// A `spec` for a write that propagates itself as other commands.
+const fn spec_writing_as_others(
+    name: &'static str,
+    arity: i64,
+    first_key: i64,
+    last_key: i64,
+    step: i64,
+    parse: Parse,
+) -> CommandSpec {
+    CommandSpec {
+        writes_as_others: true,
+        ..spec(name, arity, first_key, last_key, step, parse)
+    }
+}
+
+fn keyspace<C: CommandExt + Send + 'static>(
+    parser: &mut ArgParser,
+) -> Result<Command, CommandError> {
+    Ok(Command::Keyspace(KeyspaceCommand(Box::new(C::parse(
+        parser,
+    )?))))
+}
+
+fn debug(parser: &mut ArgParser) -> Result<Command, CommandError> {
+    Ok(match Debug::parse(parser)? {
+        Debug {
+            subcommand: DebugSubcommand::Sleep(duration),
+        } => Command::Sleep(duration),
+        debug => Command::Keyspace(KeyspaceCommand(Box::new(debug))),
+    })
+}
+
+fn blpop(parser: &mut ArgParser) -> Result<Command, CommandError> {
+    Ok(Command::BlockingPop(BlockingPop::parse(parser, true)?))
+}
+
+fn brpop(parser: &mut ArgParser) -> Result<Command, CommandError> {
+    Ok(Command::BlockingPop(BlockingPop::parse(parser, false)?))
+}
+
+fn subscribe(parser: &mut ArgParser) -> Result<Command, CommandError> {
+    let channels = parser.remaining_bulk_strings()?;
+    if channels.is_empty() {
+        return Err(CommandError::WrongArgCount);
+    }
+    Ok(Command::Subscribe(channels))
+}
+
+fn unsubscribe(parser: &mut ArgParser) -> Result<Command, CommandError> {
+    Ok(Command::Unsubscribe(parser.remaining_bulk_strings()?))
+}
+
+fn select(parser: &mut ArgParser) -> Result<Command, CommandError> {
+    let index = parser.next_integer()?;
+    parser.finish()?;
+    Ok(Command::Select(index))
+}
+
+fn hello(parser: &mut ArgParser) -> Result<Command, CommandError> {
+    let protover = match parser.iter.peek() {
+        Some(_) => Some(parser.next_integer()?),
+        None => None,
+    };
+    parser.finish()?;
+    Ok(Command::Hello(protover))
+}
+
+fn auth(parser: &mut ArgParser) -> Result<Command, CommandError> {
+    let first = parser.next_bulk_string()?;
+    let (username, password) = match parser.iter.peek() {
+        Some(_) => (Some(first), parser.next_bulk_string()?),
+        None => (None, first),
+    };
+    parser.finish()?;
+    Ok(Command::Auth { username, password })
+}
+
+fn client(parser: &mut ArgParser) -> Result<Command, CommandError> {
+    Ok(Command::Client(ClientSubcommand::parse(parser)?))
+}
+
+fn monitor(parser: &mut ArgParser) -> Result<Command, CommandError> {
+    parser.finish()?;
+    Ok(Command::Monitor)
+}
+
+fn multi(parser: &mut ArgParser) -> Result<Command, CommandError> {
+    parser.finish()?;
+    Ok(Command::Multi)
+}
+
+fn exec(parser: &mut ArgParser) -> Result<Command, CommandError> {
+    parser.finish()?;
+    Ok(Command::Exec)
+}
+
+fn discard(parser: &mut ArgParser) -> Result<Command, CommandError> {
+    parser.finish()?;
+    Ok(Command::Discard)
+}
+
+fn reset(parser: &mut ArgParser) -> Result<Command, CommandError> {
+    parser.finish()?;
+    Ok(Command::Reset)
+}
+
+/// Every command the server knows, in the order COMMAND lists them. A command
+/// is added by adding its entry here.
+pub(super) static COMMAND_TABLE: &[CommandSpec] = &[
+    spec("PING", -1, 0, 0, 0, keyspace::<Ping>),
+    spec("ECHO", 2, 0, 0, 0, keyspace::<Echo>),
+    spec("GET", 2, 1, 1, 1, keyspace::<Get>),
+    spec("SET", -3, 1, 1, 1, keyspace::<Set>),
+    spec("EXISTS", -2, 1, -1, 1, keyspace::<Exists>),
+    spec("DEL", -2, 1, -1, 1, keyspace::<Del>),
+    spec("UNLINK", -2, 1, -1, 1, keyspace::<Unlink>),
+    spec("INCR", 2, 1, 1, 1, keyspace::<Incr>),
+    spec("DECR", 2, 1, 1, 1, keyspace::<Decr>),
+    spec("INCRBY", 3, 1, 1, 1, keyspace::<Incrby>),
+    spec("DECRBY", 3, 1, 1, 1, keyspace::<Decrby>),
+    spec("INCRBYFLOAT", 3, 1, 1, 1, keyspace::<Incrbyfloat>),
+    spec("APPEND", 3, 1, 1, 1, keyspace::<Append>),
+    spec("STRLEN", 2, 1, 1, 1, keyspace::<Strlen>),
+    spec("GETDEL", 2, 1, 1, 1, keyspace::<Getdel>),
+    spec("GETEX", -2, 1, 1, 1, keyspace::<Getex>),
+    spec("GETSET", 3, 1, 1, 1, keyspace::<Getset>),
+    spec("MSET", -3, 1, -1, 2, keyspace::<Mset>),
+    spec("MGET", -2, 1, -1, 1, keyspace::<Mget>),
+    spec("GETRANGE", 4, 1, 1, 1, keyspace::<Getrange>),
+    spec("SETRANGE", 4, 1, 1, 1, keyspace::<Setrange>),
+    spec("SETBIT", 4, 1, 1, 1, keyspace::<Setbit>),
+    spec("GETBIT", 3, 1, 1, 1, keyspace::<Getbit>),
+    spec("BITCOUNT", -2, 1, 1, 1, keyspace::<Bitcount>),
+    spec("INFO", -1, 0, 0, 0, keyspace::<Info>),
+    spec("FLUSHDB", -1, 0, 0, 0, keyspace::<Flushdb>),
+    spec("FLUSHALL", -1, 0, 0, 0, keyspace::<Flushall>),
+    spec("DBSIZE", 1, 0, 0, 0, keyspace::<Dbsize>),
+    spec("SWAPDB", 3, 0, 0, 0, keyspace::<Swapdb>),
+    spec("SAVE", 1, 0, 0, 0, keyspace::<Save>),
+    spec("BGSAVE", -1, 0, 0, 0, keyspace::<Bgsave>),
+    spec("BGREWRITEAOF", 1, 0, 0, 0, keyspace::<Bgrewriteaof>),
+    spec("DEBUG", -2, 0, 0, 0, debug),
+    spec("WAIT", 3, 0, 0, 0, keyspace::<Wait>),
+    spec("CONFIG", -2, 0, 0, 0, keyspace::<Config>),
+    spec("COMMAND", -1, 0, 0, 0, keyspace::<CommandInfo>),
+    spec("TTL", 2, 1, 1, 1, keyspace::<Ttl>),
+    spec("PTTL", 2, 1, 1, 1, keyspace::<Pttl>),
+    spec("EXPIRETIME", 2, 1, 1, 1, keyspace::<Expiretime>),
+    spec("PEXPIRETIME", 2, 1, 1, 1, keyspace::<Pexpiretime>),
+    spec("EXPIRE", -3, 1, 1, 1, keyspace::<Expire>),
+    spec("PEXPIRE", -3, 1, 1, 1, keyspace::<Pexpire>),
+    spec("EXPIREAT", -3, 1, 1, 1, keyspace::<Expireat>),
+    spec("PEXPIREAT", -3, 1, 1, 1, keyspace::<Pexpireat>),
+    spec("PERSIST", 2, 1, 1, 1, keyspace::<Persist>),
+    spec("TYPE", 2, 1, 1, 1, keyspace::<Type>),
+    spec("TOUCH", -2, 1, -1, 1, keyspace::<Touch>),
+    spec("KEYS", 2, 0, 0, 0, keyspace::<Keys>),
+    spec("SCAN", -2, 0, 0, 0, keyspace::<Scan>),
+    spec("OBJECT", -2, 2, 2, 1, keyspace::<Object>),
+    spec("RENAME", 3, 1, 2, 1, keyspace::<Rename>),
+    spec("DUMP", 2, 1, 1, 1, keyspace::<Dump>),
+    spec("RESTORE", -4, 1, 1, 1, keyspace::<Restore>),
+    spec("COPY", -3, 1, 2, 1, keyspace::<Copy>),
+    spec("SORT", -2, 1, 1, 1, keyspace::<Sort>),
+    spec("LPUSH", -3, 1, 1, 1, keyspace::<Lpush>),
+    spec("RPUSH", -3, 1, 1, 1, keyspace::<Rpush>),
+    spec("LRANGE", 4, 1, 1, 1, keyspace::<Lrange>),
+    spec("LINSERT", 5, 1, 1, 1, keyspace::<Linsert>),
+    spec("LSET", 4, 1, 1, 1, keyspace::<Lset>),
+    spec("LREM", 4, 1, 1, 1, keyspace::<Lrem>),
+    spec("LPOS", -3, 1, 1, 1, keyspace::<Lpos>),
+    spec("LPOP", -2, 1, 1, 1, keyspace::<Lpop>),
+    spec("RPOP", -2, 1, 1, 1, keyspace::<Rpop>),
+    spec_writing_as_others("BLPOP", -3, 1, -2, 1, blpop),
+    spec_writing_as_others("BRPOP", -3, 1, -2, 1, brpop),
+    spec("HSET", -4, 1, 1, 1, keyspace::<Hset>),
+    spec("HGET", 3, 1, 1, 1, keyspace::<Hget>),
+    spec("HGETALL", 2, 1, 1, 1, keyspace::<Hgetall>),
+    spec("HRANDFIELD", -2, 1, 1, 1, keyspace::<Hrandfield>),
+    spec("HINCRBY", 4, 1, 1, 1, keyspace::<Hincrby>),
+    spec("HDEL", -3, 1, 1, 1, keyspace::<Hdel>),
+    spec("HSCAN", -3, 1, 1, 1, keyspace::<Hscan>),
+    spec("SADD", -3, 1, 1, 1, keyspace::<Sadd>),
+    spec("SMEMBERS", 2, 1, 1, 1, keyspace::<Smembers>),
+    spec("SISMEMBER", 3, 1, 1, 1, keyspace::<Sismember>),
+    spec("SRANDMEMBER", -2, 1, 1, 1, keyspace::<Srandmember>),
+    spec("SMOVE", 4, 1, 2, 1, keyspace::<Smove>),
+    spec("SINTER", -2, 1, -1, 1, keyspace::<Sinter>),
+    spec("SUNION", -2, 1, -1, 1, keyspace::<Sunion>),
+    spec("SDIFF", -2, 1, -1, 1, keyspace::<Sdiff>),
+    spec("SSCAN", -3, 1, 1, 1, keyspace::<Sscan>),
+    spec("ZADD", -4, 1, 1, 1, keyspace::<Zadd>),
+    spec("ZSCORE", 3, 1, 1, 1, keyspace::<Zscore>),
+    spec("ZRANGE", -4, 1, 1, 1, keyspace::<Zrange>),
+    spec("ZSCAN", -3, 1, 1, 1, keyspace::<Zscan>),
+    spec("PUBLISH", 3, 0, 0, 0, keyspace::<Publish>),
+    spec("SUBSCRIBE", -2, 0, 0, 0, subscribe),
+    spec("UNSUBSCRIBE", -1, 0, 0, 0, unsubscribe),
+    spec("PUBSUB", -2, 0, 0, 0, keyspace::<Pubsub>),
+    spec("SELECT", 2, 0, 0, 0, select),
+    spec("HELLO", -1, 0, 0, 0, hello),
+    spec("AUTH", -2, 0, 0, 0, auth),
+    spec("CLIENT", -2, 0, 0, 0, client),
+    spec("MONITOR", 1, 0, 0, 0, monitor),
+    spec("MULTI", 1, 0, 0, 0, multi),
+    spec("EXEC", 1, 0, 0, 0, exec),
+    spec("DISCARD", 1, 0, 0, 0, discard),
+    spec("RESET", 1, 0, 0, 0, reset),
+];
+
+/// Finds a command by name, in any case. The index is built on first use.
+pub(super) fn lookup(name: &[u8]) -> Option<&'static CommandSpec> {
+    static BY_NAME: OnceLock<HashMap<&'static [u8], &'static CommandSpec>> = OnceLock::new();
+    let by_name = BY_NAME.get_or_init(|| {
+        COMMAND_TABLE
+            .iter()
+            .map(|spec| (spec.name.as_bytes(), spec))
+            .collect()
+    });
+    by_name.get(name.to_ascii_uppercase().as_slice()).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{run, Command, CommandError};
+    use super::*;
+    use crate::parser::RespValue;
+    use crate::server::Server;
+
+    fn parse(args: &[&[u8]]) -> Result<Command, CommandError> {
+        Command::from_resp(RespValue::Array(
+            args.iter()
+                .map(|arg| RespValue::BulkString(arg.to_vec()))
+                .collect(),
+        ))
+    }
+
+    #[test]
+    fn test_names_are_unique_and_upper_case() {
+        for spec in COMMAND_TABLE {
+            assert_eq!(spec.name, spec.name.to_ascii_uppercase());
+            assert!(std::ptr::eq(lookup(spec.name.as_bytes()).unwrap(), spec));
+        }
+        assert!(lookup(b"zAdD").is_some());
+    }
+
+    #[test]
+    fn test_unknown_command_and_arity() {
+        assert!(matches!(
+            parse(&[b"nosuch", b"arg"]),
+            Err(CommandError::UnknownCommand(name)) if name == "NOSUCH"
+        ));
+        assert!(matches!(parse(&[b"GET"]), Err(CommandError::WrongArgCount)));
+        assert!(matches!(
+            parse(&[b"ZADD", b"zset", b"1"]),
+            Err(CommandError::WrongArgCount)
+        ));
+        assert!(matches!(
+            parse(&[b"MULTI", b"now"]),
+            Err(CommandError::WrongArgCount)
+        ));
+        assert!(matches!(parse(&[b"multi"]), Ok(Command::Multi)));
+    }
+
+    #[test]
+    fn test_command_count_and_info() {
+        let server = Server::default();
+        assert_eq!(
+            run(&server, &[b"COMMAND", b"COUNT"]),
+            format!(":{}\r\n", COMMAND_TABLE.len()).into_bytes()
+        );
+
+        let RespValue::Array(commands) =
+            Command::from_resp(RespValue::Array(vec![RespValue::BulkString(
+                b"COMMAND".to_vec(),
+            )]))
+            .unwrap()
+            .execute(&server, &server.db(0))
+        else {
+            panic!("COMMAND did not reply with an array");
+        };
+        assert_eq!(commands.len(), COMMAND_TABLE.len());
+        let set = commands
+            .iter()
+            .find(|command| {
+                matches!(command, RespValue::Array(fields)
+                    if fields[0] == RespValue::BulkString(b"set".to_vec()))
+            })
+            .unwrap();
+        assert_eq!(
+            set.encode(),
+            b"*6\r\n$3\r\nset\r\n:-3\r\n*2\r\n+write\r\n+denyoom\r\n:1\r\n:1\r\n:1\r\n"
+        );
+        // BLPOP writes, though it propagates as the LPOP it performed.
+        assert_eq!(
+            run(&server, &[b"COMMAND", b"INFO", b"blpop", b"nosuch"]),
+            b"*2\r\n*6\r\n$5\r\nblpop\r\n:-3\r\n*1\r\n+write\r\n:1\r\n:-2\r\n:1\r\n$-1\r\n"
+        );
+
+        assert_eq!(
+            run(&server, &[b"COMMAND", b"DOCS", b"get", b"nosuch"]),
+            b"*2\r\n$3\r\nget\r\n*0\r\n"
+        );
+    }
+}
@@ -0,0 +1,331 @@
+use std::path::PathBuf;
+
+use crate::notify::Flags;
+use crate::parser::MAX_BULK_LEN;
+
+/// Runtime parameters exposed through CONFIG GET/SET.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// Memory limit in bytes; 0 means no limit.
+    pub maxmemory: u64,
+    pub maxmemory_policy: String,
+    /// Snapshot schedule as `seconds changes` pairs, empty to disable.
+    pub save: String,
+    /// Whether writes go to the append-only file. Setting it at runtime opens
+    /// or closes the log.
+    pub appendonly: bool,
+    /// The append-only file's name in `dir`. Fixed at startup.
+    pub appendfilename: String,
+    /// Number of databases. Fixed at startup.
+    pub databases: usize,
+    /// Password clients must AUTH with, empty for none.
+    pub requirepass: String,
+    /// Directory the snapshot file is written to.
+    pub dir: String,
+    pub dbfilename: String,
+    /// Largest bulk string a client may send. A connection whose unparsed
+    /// input grows past it (plus framing) is dropped.
+    pub proto_max_bulk_len: u64,
+    /// Seconds a client may send nothing before it is disconnected; 0 never.
+    pub timeout: u64,
+    /// Connections accepted per second once a burst has used up `accept_burst`.
+    pub accept_rate: u32,
+    /// Connections accepted at once before `accept_rate` applies.
+    pub accept_burst: u32,
+    /// Seconds a connection is quiet before TCP probes whether its peer is
+    /// still there; 0 turns the probes off. Applies to new connections.
+    pub tcp_keepalive: u64,
+    /// Which keyspace notifications writes publish; none by default.
+    pub notify_keyspace_events: Flags,
+    /// DEBUG subcommands answered with `+OK` under `--debug-compat`, in upper
+    /// case. Set as a space-separated list.
+    pub debug_compat_noops: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            maxmemory: 0,
+            maxmemory_policy: "noeviction".to_string(),
+            save: "3600 1 300 100 60 10000".to_string(),
+            appendonly: false,
+            appendfilename: "appendonly.aof".to_string(),
+            databases: 16,
+            requirepass: String::new(),
+            dir: ".".to_string(),
+            dbfilename: "dump.rdb".to_string(),
+            proto_max_bulk_len: MAX_BULK_LEN as u64,
+            timeout: 0,
+            accept_rate: 1000,
+            accept_burst: 200,
+            tcp_keepalive: 300,
+            notify_keyspace_events: Flags::default(),
+            debug_compat_noops: vec!["JMAP".to_string(), "QUICKLIST-PACKED-THRESHOLD".to_string()],
+        }
+    }
+}
+
+/// Every parameter, in the order CONFIG GET lists them.
+pub const PARAMETERS: &[&str] = &[
+    "maxmemory",
+    "maxmemory-policy",
+    "save",
+    "appendonly",
+    "appendfilename",
+    "databases",
+    "requirepass",
+    "dir",
+    "dbfilename",
+    "proto-max-bulk-len",
+    "timeout",
+    "accept-rate",
+    "accept-burst",
+    "tcp-keepalive",
+    "notify-keyspace-events",
+    "debug-compat-noops",
+];
+
+/// Redis refuses to go lower, so ordinary values always fit.
+const MIN_PROTO_MAX_BULK_LEN: u64 = 1024 * 1024;
+
+const MAXMEMORY_POLICIES: &[&str] = &[
+    "noeviction",
+    "allkeys-lru",
+    "volatile-lru",
+    "allkeys-lfu",
+    "volatile-lfu",
+    "allkeys-random",
+    "volatile-random",
+    "volatile-ttl",
+];
+
+impl Config {
+    /// The current value of `name` as CONFIG GET shows it.
+    pub fn get(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "maxmemory" => self.maxmemory.to_string(),
+            "maxmemory-policy" => self.maxmemory_policy.clone(),
+            "save" => self.save.clone(),
+            "appendonly" => yes_no(self.appendonly).to_string(),
+            "appendfilename" => self.appendfilename.clone(),
+            "databases" => self.databases.to_string(),
+            "requirepass" => self.requirepass.clone(),
+            "dir" => self.dir.clone(),
+            "dbfilename" => self.dbfilename.clone(),
+            "proto-max-bulk-len" => self.proto_max_bulk_len.to_string(),
+            "timeout" => self.timeout.to_string(),
+            "accept-rate" => self.accept_rate.to_string(),
+            "accept-burst" => self.accept_burst.to_string(),
+            "tcp-keepalive" => self.tcp_keepalive.to_string(),
+            "notify-keyspace-events" => self.notify_keyspace_events.to_string(),
+            "debug-compat-noops" => self.debug_compat_noops.join(" "),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    pub fn snapshot_path(&self) -> PathBuf {
+        PathBuf::from(&self.dir).join(&self.dbfilename)
+    }
+
+    pub fn aof_path(&self) -> PathBuf {
+        PathBuf::from(&self.dir).join(&self.appendfilename)
+    }
+
+    /// Sets `name` from its CONFIG SET form. Errors carry the reason to show the client.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "maxmemory" => {
+                self.maxmemory = parse_memory(value).ok_or("argument must be a memory value")?;
+            }
+            "maxmemory-policy" => {
+                let policy = value.to_ascii_lowercase();
+                if !MAXMEMORY_POLICIES.contains(&policy.as_str()) {
+                    return Err("argument(s) must be one of the following: ".to_string()
+                        + &MAXMEMORY_POLICIES.join(", "));
+                }
+                self.maxmemory_policy = policy;
+            }
+            "save" => {
+                let numbers: Option<Vec<u64>> =
+                    value.split_whitespace().map(|n| n.parse().ok()).collect();
+                if numbers.is_none_or(|numbers| numbers.len() % 2 != 0) {
+                    return Err("Invalid save parameters".to_string());
+                }
+                self.save = value.split_whitespace().collect::<Vec<_>>().join(" ");
+            }
+            "appendonly" => {
+                self.appendonly = match value.to_ascii_lowercase().as_str() {
+                    "yes" => true,
+                    "no" => false,
+                    _ => return Err("argument must be 'yes' or 'no'".to_string()),
+                };
+            }
+            "databases" | "appendfilename" => return Err("can't set immutable config".to_string()),
+            "requirepass" => self.requirepass = value.to_string(),
+            "dir" => {
+                if !std::path::Path::new(value).is_dir() {
+                    return Err(format!("No such directory '{}'", value));
+                }
+                self.dir = value.to_string();
+            }
+            "dbfilename" => {
+                // Like Redis, the file has to live directly in `dir`.
+                if value.is_empty() || value.contains('/') {
+                    return Err("dbfilename can't be a path, just a filename".to_string());
+                }
+                self.dbfilename = value.to_string();
+            }
+            "proto-max-bulk-len" => {
+                let len = parse_memory(value).ok_or("argument must be a memory value")?;
+                if len < MIN_PROTO_MAX_BULK_LEN {
+                    return Err("proto-max-bulk-len must be 1mb or greater".to_string());
+                }
+                self.proto_max_bulk_len = len;
+            }
+            "timeout" => {
+                self.timeout = value
+                    .parse()
+                    .map_err(|_| "argument couldn't be parsed into an integer")?;
+            }
+            "accept-rate" => self.accept_rate = parse_positive(value)?,
+            "accept-burst" => self.accept_burst = parse_positive(value)?,
+            "tcp-keepalive" => {
+                self.tcp_keepalive = value
+                    .parse()
+                    .map_err(|_| "argument couldn't be parsed into an integer")?;
+            }
+            "notify-keyspace-events" => {
+                self.notify_keyspace_events = Flags::parse(value)
+                    .ok_or("Invalid event class character. Use 'Ag$lshzxeKEtmn'.")?;
+            }
+            "debug-compat-noops" => {
+                self.debug_compat_noops = value
+                    .split_whitespace()
+                    .map(str::to_ascii_uppercase)
+                    .collect();
+            }
+            _ => return Err(format!("Unknown option '{}'", name)),
+        }
+        Ok(())
+    }
+
+    /// Sets `name` from a startup flag, which may also set the parameters that
+    /// can't change while the server runs.
+    pub fn set_at_startup(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "appendfilename" => {
+                if value.is_empty() || value.contains('/') {
+                    return Err("appendfilename can't be a path, just a filename".to_string());
+                }
+                self.appendfilename = value.to_string();
+            }
+            "databases" => {
+                self.databases = value
+                    .parse()
+                    .ok()
+                    .filter(|&databases| databases > 0)
+                    .ok_or("argument must be a positive number of databases")?;
+            }
+            _ => self.set(name, value)?,
+        }
+        Ok(())
+    }
+}
+
+fn parse_positive(value: &str) -> Result<u32, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|&n| n > 0)
+        .ok_or("argument must be a positive integer".to_string())
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+/// Parses a size the way redis.conf does: a plain byte count or a number with
+/// a `k`/`m`/`g` (powers of 1000) or `kb`/`mb`/`gb` (powers of 1024) suffix.
+pub fn parse_memory(value: &str) -> Option<u64> {
+    let value = value.to_ascii_lowercase();
+    let digits_end = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(digits_end);
+    let multiplier: u64 = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_memory_units() {
+        assert_eq!(parse_memory("0"), Some(0));
+        assert_eq!(parse_memory("100"), Some(100));
+        assert_eq!(parse_memory("1k"), Some(1000));
+        assert_eq!(parse_memory("1K"), Some(1000));
+        assert_eq!(parse_memory("1kb"), Some(1024));
+        assert_eq!(parse_memory("2mb"), Some(2 * 1024 * 1024));
+        assert_eq!(parse_memory("1g"), Some(1_000_000_000));
+        assert_eq!(parse_memory(""), None);
+        assert_eq!(parse_memory("1tb"), None);
+        assert_eq!(parse_memory("-1"), None);
+        assert_eq!(parse_memory("99999999999gb"), None);
+    }
+
+    #[test]
+    fn test_set_validates_values() {
+        let mut config = Config::default();
+        assert!(config.set("maxmemory", "lots").is_err());
+        assert!(config.set("maxmemory-policy", "sometimes").is_err());
+        assert!(config.set("save", "60").is_err());
+        assert!(config.set("appendonly", "maybe").is_err());
+        assert!(config.set("databases", "4").is_err());
+        assert!(config.set("proto-max-bulk-len", "1000kb").is_err());
+        assert!(config.set("timeout", "-1").is_err());
+        assert!(config.set("tcp-keepalive", "often").is_err());
+        assert!(config.set("accept-rate", "0").is_err());
+        assert!(config.set("accept-burst", "-5").is_err());
+        assert!(config.set("notify-keyspace-events", "KEq").is_err());
+        assert_eq!(config, Config::default());
+
+        config.set("save", "").unwrap();
+        assert_eq!(config.get("save").as_deref(), Some(""));
+        config.set("notify-keyspace-events", "E$g").unwrap();
+        assert_eq!(config.get("notify-keyspace-events").as_deref(), Some("g$E"));
+    }
+
+    #[test]
+    fn test_startup_sets_immutable_parameters() {
+        let mut config = Config::default();
+        assert!(config.set("appendfilename", "log.aof").is_err());
+        config.set_at_startup("appendfilename", "log.aof").unwrap();
+        config.set_at_startup("databases", "4").unwrap();
+        config.set_at_startup("appendonly", "yes").unwrap();
+        assert_eq!(config.aof_path(), PathBuf::from("./log.aof"));
+        assert_eq!(config.databases, 4);
+        assert!(config.appendonly);
+
+        assert!(config
+            .set_at_startup("appendfilename", "logs/log.aof")
+            .is_err());
+        assert!(config.set_at_startup("databases", "0").is_err());
+        assert!(config.set_at_startup("appendonly", "maybe").is_err());
+    }
+}
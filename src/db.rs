@@ -1,11 +1,990 @@
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::ops::{Deref, DerefMut};
+use std::str;
+use std::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, LockResult, OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
+
+use crate::blocking::Waiters;
+use crate::clock::{Clock, SystemClock};
+use crate::logging::{self, log, Level};
+use crate::sorted_set::SortedSet;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DbData {
+    String(Vec<u8>),
+    List(VecDeque<Vec<u8>>),
+    Hash(HashMap<Vec<u8>, Vec<u8>>),
+    Set(HashSet<Vec<u8>>),
+    SortedSet(SortedSet),
+}
+
+impl DbData {
+    /// True for a list, hash, set or sorted set with no elements left. Strings may be empty.
+    pub fn is_empty_collection(&self) -> bool {
+        match self {
+            DbData::String(_) => false,
+            DbData::List(list) => list.is_empty(),
+            DbData::Hash(hash) => hash.is_empty(),
+            DbData::Set(set) => set.is_empty(),
+            DbData::SortedSet(set) => set.is_empty(),
+        }
+    }
+
+    /// How many elements a collection holds; a string counts as one.
+    pub fn element_count(&self) -> usize {
+        match self {
+            DbData::String(_) => 1,
+            DbData::List(list) => list.len(),
+            DbData::Hash(hash) => hash.len(),
+            DbData::Set(set) => set.len(),
+            DbData::SortedSet(set) => set.len(),
+        }
+    }
+
+    /// Bytes held by the value's contents, which is what maxmemory counts.
+    pub fn size(&self) -> usize {
+        match self {
+            DbData::String(value) => value.len(),
+            DbData::List(list) => list.iter().map(Vec::len).sum(),
+            DbData::Hash(hash) => hash
+                .iter()
+                .map(|(field, value)| field.len() + value.len())
+                .sum(),
+            DbData::Set(set) => set.iter().map(Vec::len).sum(),
+            DbData::SortedSet(set) => set
+                .iter()
+                .map(|(member, _)| member.len() + size_of::<f64>())
+                .sum(),
+        }
+    }
+
+    /// The name TYPE reports for this value.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            DbData::String(_) => "string",
+            DbData::List(_) => "list",
+            DbData::Hash(_) => "hash",
+            DbData::Set(_) => "set",
+            DbData::SortedSet(_) => "zset",
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct DbValue {
-    pub value: Vec<u8>,
+    pub data: DbData,
     pub expires_at: Option<Instant>,
+    /// When the value was last written. Reads and expiry changes leave it alone.
+    pub modified_at: Instant,
+    /// When the value was last read or written and how often, for LRU and
+    /// LFU eviction.
+    pub accessed_at: AccessTime,
+    /// Set when a string is modified in place. Redis then stops using the
+    /// compact encodings for it, so OBJECT ENCODING reports `raw` from then on.
+    pub raw: bool,
+    /// Where the key sits in its shard's `Keyspace::order`. Only `Keyspace` sets it.
+    slot: usize,
+}
+
+/// Longest string Redis stores as `embstr`.
+const EMBSTR_MAX_LEN: usize = 44;
+/// Largest list (in bytes) that fits a single listpack node, as with Redis's
+/// default `list-max-listpack-size -2`.
+const LIST_MAX_LISTPACK_BYTES: usize = 8 * 1024;
+/// Redis's `hash-max-listpack-entries` and `hash-max-listpack-value`.
+const HASH_MAX_LISTPACK_ENTRIES: usize = 128;
+const HASH_MAX_LISTPACK_VALUE: usize = 64;
+/// Redis's `set-max-intset-entries`, `set-max-listpack-entries` and `set-max-listpack-value`.
+const SET_MAX_INTSET_ENTRIES: usize = 512;
+const SET_MAX_LISTPACK_ENTRIES: usize = 128;
+const SET_MAX_LISTPACK_VALUE: usize = 64;
+
+/// What a new value's access frequency starts at, so it isn't evicted before
+/// it has had a chance to be read.
+const LFU_INIT_VAL: u8 = 5;
+/// Redis's default `lfu-log-factor`: the frequency reaches its maximum of 255
+/// after about a million accesses.
+const LFU_LOG_FACTOR: f64 = 10.0;
+/// Redis's default `lfu-decay-time`: the frequency drops by one for every
+/// minute without an access.
+const LFU_DECAY_MILLIS: u64 = 60 * 1000;
+
+/// A last-access time and access frequency that reads can update through a
+/// shared reference.
+#[derive(Debug)]
+pub struct AccessTime {
+    millis: AtomicU64,
+    /// Redis's logarithmic LFU counter, as of the last access.
+    frequency: AtomicU8,
+}
+
+impl AccessTime {
+    fn now() -> Self {
+        AccessTime {
+            millis: AtomicU64::new(clock_millis()),
+            frequency: AtomicU8::new(LFU_INIT_VAL),
+        }
+    }
+
+    pub fn touch(&self) {
+        let now = clock_millis();
+        let frequency = self.frequency_at(now);
+        // Each access is less likely to count the more there have been.
+        let odds = 1.0 / (frequency.saturating_sub(LFU_INIT_VAL) as f64 * LFU_LOG_FACTOR + 1.0);
+        let roll = RandomState::new().hash_one(now) as f64 / u64::MAX as f64;
+        let frequency = if roll < odds {
+            frequency.saturating_add(1)
+        } else {
+            frequency
+        };
+        self.frequency.store(frequency, Ordering::Relaxed);
+        self.millis.store(now, Ordering::Relaxed);
+    }
+
+    /// Milliseconds on a clock that only ever moves forward; only the order matters.
+    pub fn millis(&self) -> u64 {
+        self.millis.load(Ordering::Relaxed)
+    }
+
+    /// How long since the last access.
+    pub fn idle(&self) -> Duration {
+        Duration::from_millis(clock_millis().saturating_sub(self.millis()))
+    }
+
+    /// The access frequency, decayed for the time since the last access.
+    pub fn frequency(&self) -> u8 {
+        self.frequency_at(clock_millis())
+    }
+
+    fn frequency_at(&self, now: u64) -> u8 {
+        let idle_minutes = now.saturating_sub(self.millis()) / LFU_DECAY_MILLIS;
+        self.frequency
+            .load(Ordering::Relaxed)
+            .saturating_sub(idle_minutes.min(u8::MAX as u64) as u8)
+    }
+}
+
+impl Clone for AccessTime {
+    fn clone(&self) -> Self {
+        AccessTime {
+            millis: AtomicU64::new(self.millis()),
+            frequency: AtomicU8::new(self.frequency.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+fn clock_millis() -> u64 {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    EPOCH.get_or_init(Instant::now).elapsed().as_millis() as u64
+}
+
+impl DbValue {
+    pub fn new(data: DbData) -> Self {
+        DbValue {
+            data,
+            expires_at: None,
+            modified_at: Instant::now(),
+            accessed_at: AccessTime::now(),
+            raw: false,
+            slot: 0,
+        }
+    }
+
+    pub fn string(value: Vec<u8>, expires_at: Option<Instant>) -> Self {
+        DbValue {
+            data: DbData::String(value),
+            expires_at,
+            modified_at: Instant::now(),
+            accessed_at: AccessTime::now(),
+            raw: false,
+            slot: 0,
+        }
+    }
+
+    pub fn as_string(&self) -> Option<&Vec<u8>> {
+        match &self.data {
+            DbData::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The encoding OBJECT ENCODING reports: the one Redis would pick for this
+    /// value with its default thresholds. Values here are always stored the
+    /// same way, so this is only what clients expect to see.
+    pub fn encoding(&self) -> &'static str {
+        let is_integer = |bytes: &Vec<u8>| {
+            bytes.len() <= 20 && str::from_utf8(bytes).is_ok_and(|s| s.parse::<i64>().is_ok())
+        };
+        match &self.data {
+            DbData::String(_) if self.raw => "raw",
+            DbData::String(value) if is_integer(value) => "int",
+            DbData::String(value) if value.len() <= EMBSTR_MAX_LEN => "embstr",
+            DbData::String(_) => "raw",
+            DbData::List(_) if self.data.size() <= LIST_MAX_LISTPACK_BYTES => "listpack",
+            DbData::List(_) => "quicklist",
+            DbData::Hash(hash)
+                if hash.len() <= HASH_MAX_LISTPACK_ENTRIES
+                    && hash.iter().all(|(field, value)| {
+                        field.len() <= HASH_MAX_LISTPACK_VALUE
+                            && value.len() <= HASH_MAX_LISTPACK_VALUE
+                    }) =>
+            {
+                "listpack"
+            }
+            DbData::Hash(_) => "hashtable",
+            DbData::Set(set)
+                if set.len() <= SET_MAX_INTSET_ENTRIES && set.iter().all(is_integer) =>
+            {
+                "intset"
+            }
+            DbData::Set(set)
+                if set.len() <= SET_MAX_LISTPACK_ENTRIES
+                    && set
+                        .iter()
+                        .all(|member| member.len() <= SET_MAX_LISTPACK_VALUE) =>
+            {
+                "listpack"
+            }
+            DbData::Set(_) => "hashtable",
+            DbData::SortedSet(set) => set.encoding(),
+        }
+    }
+
+    /// How many listpack nodes Redis would split a list into, or `None` for
+    /// other types.
+    pub fn quicklist_nodes(&self) -> Option<usize> {
+        match &self.data {
+            DbData::List(_) => Some(self.data.size().div_ceil(LIST_MAX_LISTPACK_BYTES).max(1)),
+            _ => None,
+        }
+    }
+
+    /// What the key and value count against maxmemory.
+    pub fn size(&self, key: &[u8]) -> usize {
+        key.len() + self.data.size()
+    }
+
+    /// A key is expired from its deadline onwards, not just after it.
+    pub fn is_expired_at(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+}
+
+/// The system's unix time, for the snapshot and AOF files, which record
+/// deadlines as real timestamps whatever clock the server runs on.
+pub fn unix_millis_now() -> u64 {
+    SystemClock.unix_millis()
+}
+
+/// `Clock::instant_from_unix_millis` on the system clock, for loading files.
+pub fn instant_from_unix_millis(unix_millis: u64) -> Option<Instant> {
+    SystemClock.instant_from_unix_millis(unix_millis)
+}
+
+/// `Clock::unix_millis_from_instant` on the system clock, for writing files.
+pub fn unix_millis_from_instant(instant: Instant) -> u64 {
+    SystemClock.unix_millis_from_instant(instant)
+}
+
+/// One shard's keys. Reads go straight to the map; every change goes through
+/// the methods here so the database's memory count stays exact.
+#[derive(Debug)]
+pub struct Keyspace {
+    entries: HashMap<Vec<u8>, DbValue>,
+    /// Every key once, in no particular order, so sampling can start anywhere
+    /// without walking the map. A key's `DbValue::slot` is its index here.
+    order: Vec<Vec<u8>>,
+    /// Shared by every shard of the database.
+    used_memory: Arc<AtomicUsize>,
+    /// Keys removed for having expired, shared like `used_memory`.
+    expired_keys: Arc<AtomicU64>,
+    /// What decides whether a key has expired, shared like `used_memory`.
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for Keyspace {
+    fn default() -> Self {
+        Keyspace {
+            entries: HashMap::new(),
+            order: Vec::new(),
+            used_memory: Arc::default(),
+            expired_keys: Arc::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl Deref for Keyspace {
+    type Target = HashMap<Vec<u8>, DbValue>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries
+    }
+}
+
+impl Keyspace {
+    /// The time on the database's clock, which expiry is judged by.
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    pub fn insert(&mut self, key: Vec<u8>, mut value: DbValue) -> Option<DbValue> {
+        self.used_memory
+            .fetch_add(value.size(&key), Ordering::Relaxed);
+        value.slot = match self.entries.get(&key) {
+            Some(old) => old.slot,
+            None => {
+                self.order.push(key.clone());
+                self.order.len() - 1
+            }
+        };
+        let old = self.entries.insert(key, value);
+        if let Some(old) = &old {
+            // The key itself was counted by both and is only stored once.
+            self.used_memory
+                .fetch_sub(old.data.size(), Ordering::Relaxed);
+        }
+        old
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<DbValue> {
+        let removed = self.entries.remove(key);
+        if let Some(removed) = &removed {
+            self.used_memory
+                .fetch_sub(removed.size(key), Ordering::Relaxed);
+            self.order.swap_remove(removed.slot);
+            if let Some(moved) = self.order.get(removed.slot) {
+                let moved = self.entries.get_mut(moved).expect("ordered key is missing");
+                moved.slot = removed.slot;
+            }
+        }
+        removed
+    }
+
+    pub fn clear(&mut self) {
+        let size: usize = self
+            .entries
+            .iter()
+            .map(|(key, value)| value.size(key))
+            .sum();
+        self.used_memory.fetch_sub(size, Ordering::Relaxed);
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    /// Up to `count` distinct entries, starting `offset` into the shard and
+    /// wrapping around. Costs O(count) however large the shard is.
+    fn sample(&self, offset: usize, count: usize) -> impl Iterator<Item = (&Vec<u8>, &DbValue)> {
+        let len = self.order.len();
+        (0..count.min(len)).map(move |i| {
+            let key = &self.order[(offset + i) % len];
+            (key, &self.entries[key])
+        })
+    }
+
+    /// Removes `key`, which has expired, counting it for INFO's `expired_keys`.
+    pub fn remove_expired(&mut self, key: &[u8]) {
+        if self.remove(key).is_some() {
+            self.expired_keys.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The value at `key`. Changes made through it are counted against
+    /// maxmemory when it is dropped.
+    pub fn get_mut(&mut self, key: &[u8]) -> Option<ValueMut<'_>> {
+        let value = self.entries.get_mut(key)?;
+        Some(ValueMut {
+            value,
+            size_before: None,
+            used_memory: &self.used_memory,
+        })
+    }
+
+    /// Sets or clears the deadline of `key`, returning false if it doesn't exist.
+    /// Unlike going through `get_mut`, this never measures the value.
+    pub fn set_expires_at(&mut self, key: &[u8], expires_at: Option<Instant>) -> bool {
+        match self.entries.get_mut(key) {
+            Some(db_value) => {
+                db_value.expires_at = expires_at;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Looks up a key, lazily removing it and returning `None` if it has expired.
+    pub fn get_live(&mut self, key: &[u8]) -> Option<ValueMut<'_>> {
+        let now = self.now();
+        if self
+            .entries
+            .get(key)
+            .is_some_and(|db_value| db_value.is_expired_at(now))
+        {
+            self.remove_expired(key);
+            return None;
+        }
+        let value = self.get_mut(key)?;
+        value.accessed_at.touch();
+        Some(value)
+    }
+}
+
+/// Mutable access to one value. Dropping it adds any growth or shrinkage to the
+/// database's memory count. The value is only measured if it was borrowed
+/// mutably, so looking at a large collection costs nothing extra.
+pub struct ValueMut<'a> {
+    value: &'a mut DbValue,
+    size_before: Option<usize>,
+    used_memory: &'a AtomicUsize,
+}
+
+impl Deref for ValueMut<'_> {
+    type Target = DbValue;
+
+    fn deref(&self) -> &DbValue {
+        self.value
+    }
+}
+
+impl DerefMut for ValueMut<'_> {
+    fn deref_mut(&mut self) -> &mut DbValue {
+        self.size_before
+            .get_or_insert_with(|| self.value.data.size());
+        self.value
+    }
+}
+
+impl Drop for ValueMut<'_> {
+    fn drop(&mut self) {
+        let Some(size_before) = self.size_before else {
+            return;
+        };
+        let size_after = self.value.data.size();
+        if size_after >= size_before {
+            self.used_memory
+                .fetch_add(size_after - size_before, Ordering::Relaxed);
+        } else {
+            self.used_memory
+                .fetch_sub(size_before - size_after, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Number of independently locked shards the keyspace is split into.
+pub const SHARD_COUNT: usize = 16;
+
+/// The keyspace, split into shards so commands on unrelated keys don't contend
+/// for one lock. Anything that locks more than one shard takes them in index
+/// order, which rules out lock-order deadlocks.
+///
+/// Shards are `RwLock`s: read-only commands go through `read_live`, or
+/// `read_keys` and `read_all` for several keys, and share a shard. Lazy expiry
+/// is the one write on the read path; an expired key reads as missing and is
+/// then removed under a brief write lock, so the common case never takes the
+/// exclusive lock.
+#[derive(Debug)]
+pub struct Database {
+    shards: Vec<RwLock<Keyspace>>,
+    /// Sum of `DbValue::size` over every key, expired or not.
+    used_memory: Arc<AtomicUsize>,
+    /// How many keys have been removed for having expired.
+    expired_keys: Arc<AtomicU64>,
+    /// Connections blocked in BLPOP/BRPOP on this database's keys.
+    pub waiters: Waiters,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Database::new(Arc::new(SystemClock))
+    }
 }
 
-pub type Database = Arc<Mutex<HashMap<String, DbValue>>>;
+impl Database {
+    /// An empty database whose keys expire by `clock`.
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        let used_memory = Arc::<AtomicUsize>::default();
+        let expired_keys = Arc::<AtomicU64>::default();
+        let shard = || {
+            RwLock::new(Keyspace {
+                entries: HashMap::new(),
+                order: Vec::new(),
+                used_memory: used_memory.clone(),
+                expired_keys: expired_keys.clone(),
+                clock: clock.clone(),
+            })
+        };
+        Database {
+            shards: (0..SHARD_COUNT).map(|_| shard()).collect(),
+            used_memory: used_memory.clone(),
+            expired_keys: expired_keys.clone(),
+            waiters: Waiters::default(),
+            clock: clock.clone(),
+        }
+    }
+
+    /// The time on the clock expiry is judged by.
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    fn shard_index(&self, key: &[u8]) -> usize {
+        // DefaultHasher::new() uses fixed keys, so routing is stable for the process.
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    pub fn used_memory(&self) -> usize {
+        self.used_memory.load(Ordering::Relaxed)
+    }
+
+    pub fn expired_keys(&self) -> u64 {
+        self.expired_keys.load(Ordering::Relaxed)
+    }
+
+    /// Zeroes `expired_keys`, for CONFIG RESETSTAT.
+    pub fn reset_expired_keys(&self) {
+        self.expired_keys.store(0, Ordering::Relaxed);
+    }
+
+    /// Locks the shard holding `key` for writing.
+    pub fn lock(&self, key: &[u8]) -> RwLockWriteGuard<'_, Keyspace> {
+        self.write_shard(self.shard_index(key))
+    }
+
+    /// Runs `read` on the live value at `key` under a shared lock.
+    pub fn read_live<T>(&self, key: &[u8], read: impl FnOnce(Option<&DbValue>) -> T) -> T {
+        self.read_at(key, true, read)
+    }
+
+    /// Like `read_live`, but leaves the access time alone, for looking at a
+    /// value without counting as a use of it.
+    pub fn peek_live<T>(&self, key: &[u8], read: impl FnOnce(Option<&DbValue>) -> T) -> T {
+        self.read_at(key, false, read)
+    }
+
+    fn read_at<T>(&self, key: &[u8], touch: bool, read: impl FnOnce(Option<&DbValue>) -> T) -> T {
+        let index = self.shard_index(key);
+        let now = self.now();
+        {
+            let keyspace = self.read_shard(index);
+            match keyspace.get(key) {
+                Some(db_value) if db_value.is_expired_at(now) => {}
+                db_value => {
+                    if let Some(db_value) = db_value.filter(|_| touch) {
+                        db_value.accessed_at.touch();
+                    }
+                    return read(db_value);
+                }
+            }
+        }
+
+        // Another writer may have replaced the key since the read lock was dropped.
+        let mut keyspace = self.write_shard(index);
+        if keyspace
+            .get(key)
+            .is_some_and(|db_value| db_value.is_expired_at(now))
+        {
+            keyspace.remove_expired(key);
+        }
+        read(None)
+    }
+
+    /// Locks every shard holding one of `keys`.
+    pub fn lock_keys<'k>(&self, keys: impl IntoIterator<Item = &'k [u8]>) -> ShardGuards<'_> {
+        let mut indexes: Vec<usize> = keys.into_iter().map(|key| self.shard_index(key)).collect();
+        indexes.sort_unstable();
+        indexes.dedup();
+        self.lock_indexes(indexes)
+    }
+
+    /// Locks the whole keyspace.
+    pub fn lock_all(&self) -> ShardGuards<'_> {
+        self.lock_indexes((0..self.shards.len()).collect())
+    }
+
+    /// Like `lock_keys`, but shared, for commands that only read several keys.
+    pub fn read_keys<'k>(&self, keys: impl IntoIterator<Item = &'k [u8]>) -> ReadGuards<'_> {
+        let mut indexes: Vec<usize> = keys.into_iter().map(|key| self.shard_index(key)).collect();
+        indexes.sort_unstable();
+        indexes.dedup();
+        self.read_indexes(indexes)
+    }
+
+    /// Like `lock_all`, but shared, for commands that only count or read keys.
+    pub fn read_all(&self) -> ReadGuards<'_> {
+        self.read_indexes((0..self.shards.len()).collect())
+    }
+
+    /// Runs `read` on shard `index` under a shared lock, for commands that walk
+    /// the keyspace a shard at a time instead of locking all of it.
+    pub fn read_shard_at<T>(&self, index: usize, read: impl FnOnce(&Keyspace) -> T) -> T {
+        read(&self.read_shard(index))
+    }
+
+    /// Removes expired keys among `sample` keys of each shard, starting `offset`
+    /// entries in. Shards are locked one at a time, so callers that advance
+    /// `offset` between calls eventually visit every key without ever holding
+    /// more than one shard. Returns how many keys were removed.
+    pub fn reap_expired(&self, offset: usize, sample: usize) -> usize {
+        let now = self.now();
+        let mut removed = 0;
+
+        for index in 0..self.shards.len() {
+            let mut keyspace = self.write_shard(index);
+            if keyspace.is_empty() {
+                continue;
+            }
+            let expired: Vec<Vec<u8>> = keyspace
+                .sample(offset, sample)
+                .filter(|(_, db_value)| db_value.is_expired_at(now))
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in expired {
+                keyspace.remove_expired(&key);
+                removed += 1;
+            }
+        }
+
+        removed
+    }
+
+    /// Scores `sample` keys of each shard, starting `offset` entries in, and
+    /// returns the lowest-scoring one. Keys `score` maps to `None` are skipped.
+    /// Like `reap_expired`, only one shard is locked at a time.
+    pub fn lowest_sampled(
+        &self,
+        offset: usize,
+        sample: usize,
+        score: impl Fn(&[u8], &DbValue) -> Option<u64>,
+    ) -> Option<(u64, Vec<u8>)> {
+        let mut best: Option<(u64, Vec<u8>)> = None;
+
+        for index in 0..self.shards.len() {
+            let keyspace = self.read_shard(index);
+            if keyspace.is_empty() {
+                continue;
+            }
+            let candidate = keyspace
+                .sample(offset, sample)
+                .filter_map(|(key, db_value)| Some((score(key, db_value)?, key)))
+                .min_by_key(|(score, _)| *score);
+            if let Some((score, key)) = candidate {
+                if best.as_ref().is_none_or(|(best, _)| score < *best) {
+                    best = Some((score, key.clone()));
+                }
+            }
+        }
+
+        best
+    }
+
+    fn lock_indexes(&self, indexes: Vec<usize>) -> ShardGuards<'_> {
+        let guards = indexes
+            .into_iter()
+            .map(|index| (index, self.write_shard(index)))
+            .collect();
+        ShardGuards { db: self, guards }
+    }
+
+    fn read_indexes(&self, indexes: Vec<usize>) -> ReadGuards<'_> {
+        let guards = indexes
+            .into_iter()
+            .map(|index| (index, self.read_shard(index)))
+            .collect();
+        ShardGuards { db: self, guards }
+    }
+
+    fn write_shard(&self, index: usize) -> RwLockWriteGuard<'_, Keyspace> {
+        unpoisoned(self.shards[index].write())
+    }
+
+    fn read_shard(&self, index: usize) -> RwLockReadGuard<'_, Keyspace> {
+        unpoisoned(self.shards[index].read())
+    }
+}
+
+/// Takes a lock even if a thread panicked while holding it. Every shard lock
+/// goes through here, so one panicking command costs its own connection
+/// rather than poisoning the keyspace for every command after it. Whatever
+/// that command left half-done stays as it was, which at worst is one key
+/// partly written; memory accounting still holds, since `ValueMut` applies
+/// its delta while unwinding. Recovering here keeps std's locks, where a
+/// non-poisoning lock such as parking_lot's would need another dependency.
+pub fn unpoisoned<G>(result: LockResult<G>) -> G {
+    result.unwrap_or_else(|poisoned| {
+        // Locks are taken where no server is at hand, so use the level it set.
+        log!(
+            logging::process_level(),
+            Level::Warning,
+            "Recovering a lock poisoned by a panicking command"
+        );
+        poisoned.into_inner()
+    })
+}
+
+/// A set of locked shards, from `Database::lock_keys` or `Database::lock_all`,
+/// or with `ReadGuards` from `read_keys` or `read_all`.
+pub struct ShardGuards<'a, G = RwLockWriteGuard<'a, Keyspace>> {
+    db: &'a Database,
+    guards: Vec<(usize, G)>,
+}
+
+/// Shards locked for reading. An expired key reads as missing but is left for
+/// the next write or the reaper to remove, as nothing here may change a shard.
+pub type ReadGuards<'a> = ShardGuards<'a, RwLockReadGuard<'a, Keyspace>>;
+
+impl<'a> ShardGuards<'a> {
+    /// The locked shard holding `key`. Panics if that shard wasn't locked.
+    pub fn shard(&mut self, key: &[u8]) -> &mut Keyspace {
+        let position = self.position(key);
+        &mut self.guards[position].1
+    }
+
+    pub fn shards_mut(&mut self) -> impl Iterator<Item = &mut Keyspace> + use<'_, 'a> {
+        self.guards.iter_mut().map(|(_, guard)| &mut **guard)
+    }
+}
+
+impl<G: Deref<Target = Keyspace>> ShardGuards<'_, G> {
+    /// The value at `key`, expired or not, for reading several keys at once.
+    /// Panics if its shard wasn't locked.
+    pub fn get(&self, key: &[u8]) -> Option<&DbValue> {
+        self.guards[self.position(key)].1.get(key)
+    }
+
+    /// The live value at `key`, counting as a use of it. Panics if its shard
+    /// wasn't locked.
+    pub fn read_live(&self, key: &[u8]) -> Option<&DbValue> {
+        let now = self.db.now();
+        let db_value = self
+            .get(key)
+            .filter(|db_value| !db_value.is_expired_at(now))?;
+        db_value.accessed_at.touch();
+        Some(db_value)
+    }
+
+    fn position(&self, key: &[u8]) -> usize {
+        let index = self.db.shard_index(key);
+        self.guards
+            .binary_search_by_key(&index, |(index, _)| *index)
+            .expect("key's shard is not locked")
+    }
+
+    /// Every entry in the locked shards, expired or not.
+    pub fn iter(&self) -> impl Iterator<Item = (&Vec<u8>, &DbValue)> {
+        self.guards.iter().flat_map(|(_, guard)| guard.iter())
+    }
+}
+
+/// Deletes `key` if it holds an empty collection. Every command that removes
+/// elements calls this, so collection keys never exist empty.
+pub fn remove_if_empty(keyspace: &mut Keyspace, key: &[u8]) {
+    if keyspace
+        .get(key)
+        .is_some_and(|db_value| db_value.data.is_empty_collection())
+    {
+        keyspace.remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::run;
+    use crate::server::Server;
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// Two keys that route to different shards.
+    fn keys_on_different_shards(db: &Database) -> (Vec<u8>, Vec<u8>) {
+        let first = b"key:0".to_vec();
+        let second = (1..)
+            .map(|i| format!("key:{}", i).into_bytes())
+            .find(|key| db.shard_index(key) != db.shard_index(&first))
+            .unwrap();
+        (first, second)
+    }
+
+    #[test]
+    fn test_different_shards_do_not_block() {
+        let db = Database::default();
+        let (first, second) = keys_on_different_shards(&db);
+
+        let _held = db.lock(&first);
+        let (done_tx, done_rx) = mpsc::channel();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                db.lock(&second)
+                    .insert(second.clone(), DbValue::string(b"v".to_vec(), None));
+                done_tx.send(()).unwrap();
+            });
+            // The other shard stays available while this one is held.
+            done_rx
+                .recv_timeout(Duration::from_secs(5))
+                .expect("locking an unrelated shard blocked");
+        });
+    }
+
+    #[test]
+    fn test_concurrent_reads_share_a_shard() {
+        let db = Database::default();
+        db.lock(b"key")
+            .insert(b"key".to_vec(), DbValue::string(b"v".to_vec(), None));
+
+        // With an exclusive lock none of the readers could finish while this is held.
+        let _reader = db.shards[db.shard_index(b"key")].read().unwrap();
+        let (done_tx, done_rx) = mpsc::channel();
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                let done_tx = done_tx.clone();
+                let db = &db;
+                scope.spawn(move || {
+                    let value = db.read_live(b"key", |db_value| db_value.cloned());
+                    done_tx.send(value.is_some()).unwrap();
+                });
+            }
+            for _ in 0..8 {
+                let found = done_rx
+                    .recv_timeout(Duration::from_secs(5))
+                    .expect("readers blocked each other");
+                assert!(found);
+            }
+        });
+    }
+
+    #[test]
+    fn test_read_only_commands_share_shards() {
+        let server = Server::default();
+        run(&server, &[b"HSET", b"hash", b"field", b"v"]);
+        run(&server, &[b"SADD", b"set", b"member"]);
+        run(&server, &[b"SET", b"string", b"v"]);
+
+        // With exclusive locks none of these could run while this is held.
+        let db = server.db(0);
+        let _readers = db.read_all();
+        let reads: [&[&[u8]]; 5] = [
+            &[b"HGET", b"hash", b"field"],
+            &[b"SMEMBERS", b"set"],
+            &[b"SINTER", b"set", b"set"],
+            &[b"MGET", b"string", b"hash"],
+            &[b"DBSIZE"],
+        ];
+        let (done_tx, done_rx) = mpsc::channel();
+        thread::scope(|scope| {
+            for read in reads {
+                let done_tx = done_tx.clone();
+                let server = &server;
+                scope.spawn(move || done_tx.send(run(server, read)).unwrap());
+            }
+            for _ in reads {
+                done_rx
+                    .recv_timeout(Duration::from_secs(5))
+                    .expect("a read-only command took an exclusive lock");
+            }
+        });
+    }
+
+    #[test]
+    fn test_read_live_reaps_expired_keys() {
+        let db = Database::default();
+        db.lock(b"key").insert(
+            b"key".to_vec(),
+            DbValue::string(b"v".to_vec(), Some(Instant::now())),
+        );
+
+        assert!(db.read_live(b"key", |db_value| db_value.is_none()));
+        assert!(!db.lock(b"key").contains_key(&b"key"[..]));
+    }
+
+    #[test]
+    fn test_lock_keys_routes_each_key() {
+        let db = Database::default();
+        let (first, second) = keys_on_different_shards(&db);
+
+        let mut shards = db.lock_keys([&first[..], &second, &first]);
+        assert_eq!(shards.guards.len(), 2);
+        shards
+            .shard(&first)
+            .insert(first.clone(), DbValue::string(b"1".to_vec(), None));
+        shards
+            .shard(&second)
+            .insert(second.clone(), DbValue::string(b"2".to_vec(), None));
+        drop(shards);
+
+        assert!(db.lock(&first).contains_key(&first));
+        assert!(db.lock(&second).contains_key(&second));
+        assert_eq!(db.lock_all().iter().count(), 2);
+    }
+
+    #[test]
+    fn test_sampling_survives_removals() {
+        let mut keyspace = Keyspace::default();
+        for i in 0..20 {
+            let key = format!("key:{i}").into_bytes();
+            let expires_at = (i % 2 == 0).then(Instant::now);
+            keyspace.insert(key, DbValue::string(b"v".to_vec(), expires_at));
+        }
+        keyspace.insert(b"key:3".to_vec(), DbValue::string(b"w".to_vec(), None));
+        for i in (0..20).step_by(2) {
+            keyspace.remove(format!("key:{i}").as_bytes());
+        }
+
+        let mut sampled: Vec<&Vec<u8>> = keyspace.sample(7, 100).map(|(key, _)| key).collect();
+        sampled.sort();
+        let mut live: Vec<&Vec<u8>> = keyspace.keys().collect();
+        live.sort();
+        assert_eq!(sampled, live);
+        assert_eq!(keyspace.sample(3, 4).count(), 4);
+    }
+
+    #[test]
+    fn test_panic_under_lock_does_not_poison_keyspace() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"key", b"before"]);
+
+        let db = server.db(0);
+        let panicked = thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let _keyspace = db.lock(b"key");
+                    panic!("command failed while holding the lock");
+                })
+                .join()
+                .is_err()
+        });
+        assert!(panicked);
+        assert!(db.shards[db.shard_index(b"key")].is_poisoned());
+
+        assert_eq!(run(&server, &[b"GET", b"key"]), b"$6\r\nbefore\r\n");
+        assert_eq!(run(&server, &[b"SET", b"key", b"after"]), b"+OK\r\n");
+        assert_eq!(run(&server, &[b"DBSIZE"]), b":1\r\n");
+    }
+
+    #[test]
+    fn test_panic_under_shared_state_lock_is_recovered() {
+        let server = Server::default();
+        let panicked = thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let _config = server.config.write();
+                    panic!("command failed while holding the config");
+                })
+                .join()
+                .is_err()
+        });
+        assert!(panicked);
+        assert!(server.config.is_poisoned());
+
+        // SET reads the config for keyspace notifications and eviction.
+        assert_eq!(run(&server, &[b"SET", b"key", b"value"]), b"+OK\r\n");
+        assert_eq!(
+            run(&server, &[b"CONFIG", b"GET", b"timeout"]),
+            b"*2\r\n$7\r\ntimeout\r\n$1\r\n0\r\n"
+        );
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use crate::db::{unpoisoned, DbValue};
+use crate::parser::RespValue;
+use crate::propagation;
+use crate::server::Server;
+use crate::stats::Stats;
+
+/// Keys looked at per shard when picking a victim. Like Redis, eviction
+/// compares a sample rather than keeping every key in access order, so it is
+/// approximate: the key evicted is the oldest of those sampled.
+const SAMPLES_PER_SHARD: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Policy {
+    NoEviction,
+    Lru { volatile: bool },
+    Lfu { volatile: bool },
+    Random { volatile: bool },
+    VolatileTtl,
+}
+
+impl Policy {
+    fn from_name(name: &str) -> Self {
+        match name {
+            "allkeys-lru" => Policy::Lru { volatile: false },
+            "volatile-lru" => Policy::Lru { volatile: true },
+            "allkeys-lfu" => Policy::Lfu { volatile: false },
+            "volatile-lfu" => Policy::Lfu { volatile: true },
+            "allkeys-random" => Policy::Random { volatile: false },
+            "volatile-random" => Policy::Random { volatile: true },
+            "volatile-ttl" => Policy::VolatileTtl,
+            _ => Policy::NoEviction,
+        }
+    }
+}
+
+/// Whether `policy` evicts by access frequency, which OBJECT FREQ reports.
+pub fn is_lfu(policy: &str) -> bool {
+    matches!(Policy::from_name(policy), Policy::Lfu { .. })
+}
+
+/// Evicts keys until the dataset fits in `maxmemory` again, before a command
+/// that may grow it runs. Returns false if it still doesn't fit and the
+/// command should be refused.
+pub fn make_room(server: &Server) -> bool {
+    let (limit, policy) = {
+        let config = unpoisoned(server.config.read());
+        (
+            config.maxmemory,
+            Policy::from_name(&config.maxmemory_policy),
+        )
+    };
+    if limit == 0 {
+        return true;
+    }
+
+    loop {
+        let used: usize = (0..server.database_count())
+            .map(|index| server.db(index).used_memory())
+            .sum();
+        if used as u64 <= limit {
+            return true;
+        }
+        if policy == Policy::NoEviction || !evict_one(server, policy) {
+            return false;
+        }
+    }
+}
+
+/// Removes the best candidate `policy` finds across every database. Returns
+/// false if no key qualifies.
+fn evict_one(server: &Server, policy: Policy) -> bool {
+    let random = RandomState::new();
+    let offset = random.hash_one(0u8) as usize;
+    let now = server.clock.now();
+    let score = |key: &[u8], db_value: &DbValue| {
+        let volatile = match policy {
+            Policy::Lru { volatile } | Policy::Lfu { volatile } | Policy::Random { volatile } => {
+                volatile
+            }
+            Policy::VolatileTtl | Policy::NoEviction => true,
+        };
+        if volatile && db_value.expires_at.is_none() {
+            return None;
+        }
+        Some(match policy {
+            Policy::Lru { .. } => db_value.accessed_at.millis(),
+            Policy::Lfu { .. } => db_value.accessed_at.frequency() as u64,
+            Policy::Random { .. } => random.hash_one(key),
+            Policy::VolatileTtl | Policy::NoEviction => {
+                db_value.expires_at.map_or(0, |expires_at| {
+                    expires_at.saturating_duration_since(now).as_millis() as u64
+                })
+            }
+        })
+    };
+
+    let Some((index, key)) = (0..server.database_count())
+        .filter_map(|index| {
+            let (score, key) = server
+                .db(index)
+                .lowest_sampled(offset, SAMPLES_PER_SHARD, score)?;
+            Some((score, index, key))
+        })
+        .min_by_key(|(score, _, _)| *score)
+        .map(|(_, index, key)| (index, key))
+    else {
+        return false;
+    };
+
+    let db = server.db(index);
+    let remove = || {
+        // A writer may have removed it since it was sampled; the caller just
+        // measures again and picks another.
+        db.lock(&key).remove(&key);
+        RespValue::Integer(1)
+    };
+    // Propagated as a deadline in the past, which removes a key of any type,
+    // so a replay doesn't bring it back.
+    let frame = RespValue::Array(vec![
+        RespValue::BulkString(b"PEXPIREAT".to_vec()),
+        RespValue::BulkString(key.clone()),
+        RespValue::BulkString(b"0".to_vec()),
+    ]);
+    propagation::run_propagated(server, index, remove, |_| Some(vec![frame]));
+    Stats::incr(&server.stats.evicted_keys);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::session::Session;
+    use crate::test_support::send;
+
+    #[test]
+    fn test_lru_evicts_least_recently_used() {
+        let server = Server::default();
+        let mut session = Session::default();
+        send(
+            &mut session,
+            &server,
+            &[b"CONFIG", b"SET", b"maxmemory", b"100"],
+        );
+        send(
+            &mut session,
+            &server,
+            &[b"CONFIG", b"SET", b"maxmemory-policy", b"allkeys-lru"],
+        );
+
+        // Each key counts 2 + 18 bytes, so five fit.
+        let value = [b'v'; 18];
+        for key in [b"k0", b"k1", b"k2", b"k3", b"k4"] {
+            assert_eq!(
+                send(&mut session, &server, &[b"SET", key, &value]),
+                b"+OK\r\n"
+            );
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        // Reading k0 makes k1 the oldest.
+        send(&mut session, &server, &[b"GET", b"k0"]);
+        std::thread::sleep(Duration::from_millis(2));
+
+        // k5 only reaches the limit; each write after it finds the dataset
+        // over and evicts the oldest key first.
+        send(&mut session, &server, &[b"SET", b"k5", &value]);
+        send(&mut session, &server, &[b"SET", b"k6", &value]);
+        send(&mut session, &server, &[b"SET", b"k7", &value]);
+        for key in [b"k1", b"k2"] {
+            assert_eq!(send(&mut session, &server, &[b"EXISTS", key]), b":0\r\n");
+        }
+        for key in [b"k0", b"k3", b"k4", b"k5", b"k6", b"k7"] {
+            assert_eq!(send(&mut session, &server, &[b"EXISTS", key]), b":1\r\n");
+        }
+        assert_eq!(Stats::get(&server.stats.evicted_keys), 2);
+    }
+
+    #[test]
+    fn test_lfu_evicts_least_frequently_used() {
+        let server = Server::default();
+        let mut session = Session::default();
+        send(
+            &mut session,
+            &server,
+            &[b"CONFIG", b"SET", b"maxmemory", b"100"],
+        );
+        send(
+            &mut session,
+            &server,
+            &[b"CONFIG", b"SET", b"maxmemory-policy", b"allkeys-lfu"],
+        );
+
+        let value = [b'v'; 18];
+        for key in [b"k0", b"k1", b"k2", b"k3", b"k4"] {
+            send(&mut session, &server, &[b"SET", key, &value]);
+        }
+        // The first read always counts, so k2 is left the least used.
+        for key in [b"k0", b"k1", b"k3", b"k4"] {
+            send(&mut session, &server, &[b"GET", key]);
+        }
+
+        send(&mut session, &server, &[b"SET", b"k5", &value]);
+        send(&mut session, &server, &[b"GET", b"k5"]);
+        send(&mut session, &server, &[b"SET", b"k6", &value]);
+        assert_eq!(send(&mut session, &server, &[b"EXISTS", b"k2"]), b":0\r\n");
+        for key in [b"k0", b"k1", b"k3", b"k4", b"k5", b"k6"] {
+            assert_eq!(send(&mut session, &server, &[b"EXISTS", key]), b":1\r\n");
+        }
+    }
+
+    #[test]
+    fn test_noeviction_refuses_writes() {
+        let server = Server::default();
+        let mut session = Session::default();
+        send(
+            &mut session,
+            &server,
+            &[b"CONFIG", b"SET", b"maxmemory", b"10"],
+        );
+        send(
+            &mut session,
+            &server,
+            &[b"SET", b"key", b"a long enough value"],
+        );
+
+        assert_eq!(
+            send(&mut session, &server, &[b"SET", b"other", b"v"]),
+            b"-OOM command not allowed when used memory > 'maxmemory'.\r\n"
+        );
+        // Reads and removals still work, and make room again.
+        assert_eq!(send(&mut session, &server, &[b"EXISTS", b"key"]), b":1\r\n");
+        assert_eq!(
+            send(&mut session, &server, &[b"GETDEL", b"key"]),
+            b"$19\r\na long enough value\r\n"
+        );
+        assert_eq!(
+            send(&mut session, &server, &[b"SET", b"other", b"v"]),
+            b"+OK\r\n"
+        );
+    }
+}
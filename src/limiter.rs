@@ -0,0 +1,107 @@
+use std::time::Instant;
+
+use crate::logging::{log, Level};
+
+/// Token bucket guarding `accept`. Bursts up to `burst` connections go straight
+/// through, after which connections are admitted at `per_second`.
+#[derive(Debug)]
+pub struct AcceptLimiter {
+    per_second: f64,
+    burst: f64,
+    tokens: f64,
+    last_refill: Instant,
+    limiting: bool,
+    log_level: Level,
+}
+
+impl AcceptLimiter {
+    pub fn new(per_second: u32, burst: u32, log_level: Level) -> Self {
+        AcceptLimiter {
+            per_second: per_second as f64,
+            burst: burst as f64,
+            tokens: burst as f64,
+            last_refill: Instant::now(),
+            limiting: false,
+            log_level,
+        }
+    }
+
+    /// Changes the rate and burst, keeping the tokens already saved up as far
+    /// as the new burst allows.
+    pub fn set_limits(&mut self, per_second: u32, burst: u32) {
+        self.per_second = per_second as f64;
+        self.burst = burst as f64;
+        self.tokens = self.tokens.min(self.burst);
+    }
+
+    /// Takes a token for a connection accepted at `now`, returning false if
+    /// the connection should be rejected.
+    pub fn try_acquire(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.per_second).min(self.burst);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            if self.limiting {
+                log!(
+                    self.log_level,
+                    Level::Notice,
+                    "Accept rate back under limit"
+                );
+                self.limiting = false;
+            }
+            true
+        } else {
+            if !self.limiting {
+                log!(
+                    self.log_level,
+                    Level::Warning,
+                    "Accept rate limit of {}/s engaged, rejecting connections",
+                    self.per_second
+                );
+                self.limiting = true;
+            }
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_burst_beyond_rate_is_rejected() {
+        let mut limiter = AcceptLimiter::new(10, 5, Level::Warning);
+        let now = Instant::now();
+
+        let accepted = (0..20).filter(|_| limiter.try_acquire(now)).count();
+        assert_eq!(accepted, 5);
+    }
+
+    #[test]
+    fn test_steady_rate_is_accepted() {
+        let mut limiter = AcceptLimiter::new(10, 1, Level::Warning);
+        let start = Instant::now();
+
+        for i in 0..50 {
+            assert!(limiter.try_acquire(start + Duration::from_millis(100 * i)));
+        }
+        assert!(!limiter.try_acquire(start + Duration::from_millis(4910)));
+    }
+
+    #[test]
+    fn test_new_limits_apply_at_once() {
+        let mut limiter = AcceptLimiter::new(10, 5, Level::Warning);
+        let now = Instant::now();
+
+        limiter.set_limits(10, 2);
+        let accepted = (0..20).filter(|_| limiter.try_acquire(now)).count();
+        assert_eq!(accepted, 2);
+
+        limiter.set_limits(1000, 2);
+        assert!(limiter.try_acquire(now + Duration::from_millis(1)));
+    }
+}
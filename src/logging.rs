@@ -0,0 +1,58 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How much the server prints, quietest first. The names are Redis's `loglevel` values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Warning,
+    #[default]
+    Notice,
+    Verbose,
+    Debug,
+}
+
+impl Level {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "warning" => Some(Level::Warning),
+            "notice" => Some(Level::Notice),
+            "verbose" => Some(Level::Verbose),
+            "debug" => Some(Level::Debug),
+            _ => None,
+        }
+    }
+}
+
+/// The server's level, for messages logged where no `Server` is at hand.
+static PROCESS_LEVEL: AtomicU8 = AtomicU8::new(Level::Notice as u8);
+
+/// Records the level `process_level` reports, once the server's is known.
+pub fn set_process_level(level: Level) {
+    PROCESS_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn process_level() -> Level {
+    match PROCESS_LEVEL.load(Ordering::Relaxed) {
+        0 => Level::Warning,
+        1 => Level::Notice,
+        2 => Level::Verbose,
+        _ => Level::Debug,
+    }
+}
+
+/// `log!(server.log_level, Level::Debug, "...", args)` prints the message if
+/// the server logs at that level. The arguments are only formatted when it
+/// does. Warnings go to stderr, everything else to stdout.
+macro_rules! log {
+    ($current:expr, $level:expr, $($arg:tt)*) => {{
+        let level: $crate::logging::Level = $level;
+        if level <= $current {
+            if level == $crate::logging::Level::Warning {
+                eprintln!($($arg)*);
+            } else {
+                println!($($arg)*);
+            }
+        }
+    }};
+}
+
+pub(crate) use log;
@@ -1,95 +1,1412 @@
-#![allow(unused_imports)]
 use bytes::{Buf, BytesMut};
-use commands::Command;
-use db::Database;
+use config::{Config, PARAMETERS};
+use db::unpoisoned;
+use limiter::AcceptLimiter;
+use logging::{log, Level};
 use parser::{ParserError, RespValue};
+use server::{Server, SharedServer};
+use session::Session;
+use stats::Stats;
 use std::{
-    borrow::BorrowMut,
-    collections::HashMap,
-    io::{BufRead, BufReader, Read, Result, Write},
-    str,
-    sync::{Arc, Mutex},
-    thread,
+    io::Result,
+    net::{IpAddr, SocketAddr},
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
 };
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 
+mod aof;
+mod blocking;
+mod clients;
+mod clock;
 mod commands;
+mod config;
 mod db;
+mod eviction;
+mod limiter;
+mod logging;
+mod monitor;
+mod notify;
 mod parser;
+mod propagation;
+mod pubsub;
+mod rdb;
+mod server;
+mod session;
+mod sorted_set;
+mod stats;
+#[cfg(test)]
+mod test_support;
 
-async fn handle_connection(mut socket: TcpStream, db: Database) {
+const DEFAULT_PORT: u16 = 6379;
+const EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
+const EXPIRE_SAMPLE_SIZE: usize = 20;
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
+/// Room for a request's headers on top of its largest bulk string, when
+/// capping how much unparsed input a connection may buffer.
+const REQUEST_FRAMING_ALLOWANCE: usize = 64 * 1024;
+/// How many bytes of replies to a pipeline are held back before writing them
+/// out, so one large pipeline doesn't buffer all its replies at once.
+const REPLY_BATCH_SIZE: usize = 64 * 1024;
+/// How much of the unparsed input a debug log line shows.
+const LOGGED_INPUT_PREFIX: usize = 64;
+
+/// Removes expired keys nobody reads again, a few per shard every tick.
+async fn expire_loop(server: SharedServer) {
+    let mut interval = tokio::time::interval(EXPIRE_INTERVAL);
+    let mut offset = 0;
+
+    loop {
+        interval.tick().await;
+        if !server.active_expire.load(Ordering::Relaxed) {
+            continue;
+        }
+        for index in 0..server.database_count() {
+            server.db(index).reap_expired(offset, EXPIRE_SAMPLE_SIZE);
+        }
+        offset = offset.wrapping_add(EXPIRE_SAMPLE_SIZE);
+    }
+}
+
+/// Serves one client until it disconnects.
+///
+/// Pipelined frames are executed in order, and the replies to everything that
+/// arrived in one read are written together in a single write. If that write
+/// fails, nothing after it is run, but the commands in the batch have already
+/// been applied: the client may not see the replies to at most one batch of
+/// applied writes, so one that retries its unacknowledged commands on
+/// reconnect executes at most that batch twice.
+async fn handle_connection<S>(mut socket: S, server: SharedServer, peer: SocketAddr)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let registration = server.clients.register(peer);
+    let mut session = Session::new(registration.id());
+    tokio::select! {
+        _ = serve(&mut socket, &server, &mut session) => {}
+        _ = registration.killed() => {
+            log!(server.log_level, Level::Verbose, "Client killed");
+        }
+    }
+    // However the connection ended, it must not stay registered anywhere.
+    session.close(&server);
+    drop(registration);
+}
+
+async fn serve<S>(socket: &mut S, server: &Server, session: &mut Session)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
     let mut buffer = BytesMut::with_capacity(4096);
 
     loop {
+        log!(
+            server.log_level,
+            Level::Debug,
+            "Current buffer: {} bytes, starting \"{}\"",
+            buffer.len(),
+            buffer[..buffer.len().min(LOGGED_INPUT_PREFIX)].escape_ascii()
+        );
+        // Every complete request in the buffer runs before the next read. They
+        // are parsed a batch at a time, and requests that arrive while a BLPOP
+        // blocks make up the next batch. Only a partial request at the end is
+        // parsed again once more arrives.
+        let mut consumed = 0;
+        let mut replies = Vec::new();
+        let mut malformed = None;
         loop {
-            println!("Current buffer: {buffer:#?}");
-            let response = match RespValue::parse(&buffer) {
-                Ok((value, consumed)) => {
-                    let command_result = Command::from_resp(value);
-
-                    let response = match command_result {
-                        Ok(command) => command.execute(&db),
-                        Err(e) => format!("-ERR {}\r\n", e).into_bytes(),
-                    };
-                    buffer.advance(consumed);
-                    response
+            let (frames, len, stopped_at) = RespValue::parse_many(&buffer[consumed..]);
+            if let Some(ParserError::InvalidFormat(e)) = stopped_at {
+                malformed = Some(e);
+            }
+            if frames.is_empty() {
+                break;
+            }
+            consumed += len;
+            for frame in frames {
+                let responses = session.execute(frame, server);
+                if let Some(pause) = session.take_pause() {
+                    // The replies before DEBUG SLEEP's aren't held up by it.
+                    if !flush(socket, &mut replies, server).await {
+                        return;
+                    }
+                    tokio::time::sleep(pause).await;
                 }
-                Err(ParserError::Incomplete) => break,
-                Err(ParserError::InvalidFormat(e)) => {
-                    let _ = socket.write_all(format!("-ERR {}\r\n", e).as_bytes()).await;
-                    // NOTE: Do you want to close connection here?
+                for response in responses {
+                    response.encode_into(&mut replies);
+                }
+                if (replies.len() >= REPLY_BATCH_SIZE || session.is_blocked())
+                    && !flush(socket, &mut replies, server).await
+                {
                     return;
                 }
-            };
 
-            if let Err(e) = socket.write_all(&response).await {
-                eprintln!("failed to write response: {:?}", e);
-                return;
+                // A blocked BLPOP holds up the rest of the pipeline. Reading on
+                // meanwhile only buffers requests, but notices a disconnect.
+                while session.is_blocked() {
+                    tokio::select! {
+                        reply = session.unblocked(server) => {
+                            let Some(reply) = reply else { continue };
+                            if let Err(e) = socket.write_all(&reply.encode()).await {
+                                log!(server.log_level, Level::Warning, "failed to write response: {:?}", e);
+                                return;
+                            }
+                        }
+                        read = socket.read_buf(&mut buffer) => match read {
+                            Ok(0) | Err(_) => return,
+                            Ok(_) => {
+                                if oversized(buffer.len() - consumed, server) {
+                                    let _ = socket.write_all(&too_big_reply().encode()).await;
+                                    return;
+                                }
+                            }
+                        },
+                    }
+                }
+            }
+            // Nothing read later can mend the malformed request.
+            if malformed.is_some() {
+                break;
             }
         }
+        if let Some(e) = malformed {
+            // The requests before it still get their replies.
+            RespValue::Error(format!("ERR {}", e)).encode_into(&mut replies);
+            let _ = socket.write_all(&replies).await;
+            // There's no telling where the next request starts, so like Redis
+            // hang up after the error.
+            return;
+        }
+        if !flush(socket, &mut replies, server).await {
+            return;
+        }
+        buffer.advance(consumed);
+
+        if oversized(buffer.len(), server) {
+            let _ = socket.write_all(&too_big_reply().encode()).await;
+            return;
+        }
+
+        // The idle clock restarts after every batch, so time spent blocked in
+        // BLPOP doesn't count against the client.
+        let idle_timeout = match unpoisoned(server.config.read()).timeout {
+            0 => None,
+            seconds => Some(Duration::from_secs(seconds)),
+        }
+        .filter(|_| session.may_idle_out());
+
+        // Published messages are written between requests, never inside a reply.
+        let read = tokio::select! {
+            read = async {
+                match idle_timeout {
+                    Some(limit) => tokio::time::timeout(limit, socket.read_buf(&mut buffer)).await,
+                    None => Ok(socket.read_buf(&mut buffer).await),
+                }
+            } => match read {
+                Ok(read) => read,
+                Err(_) => {
+                    log!(server.log_level, Level::Verbose, "Closing idle client");
+                    return;
+                }
+            },
+            message = session.next_message() => {
+                if let Err(e) = socket.write_all(&message.encode()).await {
+                    log!(server.log_level, Level::Warning, "failed to write message: {:?}", e);
+                    return;
+                }
+                continue;
+            }
+        };
 
-        match socket.read_buf(&mut buffer).await {
+        match read {
             Ok(0) => {
-                println!("Client closed connection");
+                if buffer.is_empty() {
+                    log!(server.log_level, Level::Verbose, "Client closed connection");
+                } else {
+                    // Never reply to a partial command, just account for it.
+                    log!(
+                        server.log_level,
+                        Level::Verbose,
+                        "Client closed connection mid-frame, dropping {} buffered bytes",
+                        buffer.len()
+                    );
+                    Stats::incr(&server.stats.incomplete_on_close);
+                }
                 return;
             }
             Ok(n) => {
-                println!("Read {} bytes from socket", n);
+                log!(
+                    server.log_level,
+                    Level::Debug,
+                    "Read {} bytes from socket",
+                    n
+                );
             }
             Err(e) => {
-                eprintln!("failed to read from socket; err = {:?}", e);
+                log!(
+                    server.log_level,
+                    Level::Warning,
+                    "failed to read from socket; err = {:?}",
+                    e
+                );
                 return;
             }
         }
     }
 }
 
-async fn server_loop() {
-    let db: Database = Arc::new(Mutex::new(HashMap::new()));
-    let listener = match TcpListener::bind("127.0.0.1:6379").await {
+/// Whether `unparsed` bytes of input are more than any request could need. A
+/// partial request may hold one bulk string of up to proto-max-bulk-len; beyond
+/// that the client is never going to finish it, so stop buffering.
+fn oversized(unparsed: usize, server: &Server) -> bool {
+    let proto_max_bulk_len = unpoisoned(server.config.read()).proto_max_bulk_len as usize;
+    unparsed > proto_max_bulk_len.saturating_add(REQUEST_FRAMING_ALLOWANCE)
+}
+
+fn too_big_reply() -> RespValue {
+    RespValue::Error("ERR Protocol error: too big inline/bulk request".into())
+}
+
+/// Writes out and clears the batched `replies`, returning false if the
+/// connection is gone.
+async fn flush<S>(socket: &mut S, replies: &mut Vec<u8>, server: &Server) -> bool
+where
+    S: AsyncWrite + Unpin,
+{
+    if replies.is_empty() {
+        return true;
+    }
+    let written = socket.write_all(replies).await;
+    replies.clear();
+    if let Err(e) = written {
+        log!(
+            server.log_level,
+            Level::Warning,
+            "failed to write response: {:?}",
+            e
+        );
+        return false;
+    }
+    true
+}
+
+/// Resolves once the process is asked to stop with Ctrl-C or SIGTERM.
+async fn shutdown_signal(log_level: Level) {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log!(
+                log_level,
+                Level::Warning,
+                "Unable to listen for Ctrl-C: {e}"
+            );
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                log!(
+                    log_level,
+                    Level::Warning,
+                    "Unable to listen for SIGTERM: {e}"
+                );
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
+
+async fn server_loop(server: Server, address: SocketAddr) {
+    let server: SharedServer = Arc::new(server);
+    let listener = match TcpListener::bind(address).await {
         Ok(s) => s,
         Err(e) => {
-            println!("Error unable to start the server: {e}");
+            log!(
+                server.log_level,
+                Level::Warning,
+                "Error unable to start the server: {e}"
+            );
             return;
         }
     };
 
+    tokio::spawn(expire_loop(server.clone()));
+    tokio::spawn(propagation::discard(server.propagation.placeholder()));
+    let shutdown = shutdown_signal(server.log_level);
+    accept_loop(listener, server, shutdown).await;
+}
+
+/// Accepts clients until `shutdown` resolves, then gives the open connections
+/// `SHUTDOWN_GRACE_PERIOD` to finish before dropping them.
+async fn accept_loop(
+    listener: TcpListener,
+    server: SharedServer,
+    shutdown: impl std::future::Future<Output = ()>,
+) {
+    let (rate, burst) = {
+        let config = unpoisoned(server.config.read());
+        (config.accept_rate, config.accept_burst)
+    };
+    let mut limiter = AcceptLimiter::new(rate, burst, server.log_level);
+    let mut connections = tokio::task::JoinSet::new();
+    tokio::pin!(shutdown);
+
     loop {
-        match listener.accept().await {
-            Ok((socket, _)) => {
-                let db_clone = db.clone();
-                tokio::spawn(async move {
-                    handle_connection(socket, db_clone).await;
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = &mut shutdown => break,
+        };
+        // Forget connections that already finished.
+        while connections.try_join_next().is_some() {}
+
+        match accepted {
+            Ok((socket, peer)) => {
+                let (rate, burst, keepalive) = {
+                    let config = unpoisoned(server.config.read());
+                    (
+                        config.accept_rate,
+                        config.accept_burst,
+                        config.tcp_keepalive,
+                    )
+                };
+                // CONFIG SET may have changed the limits since the last accept.
+                limiter.set_limits(rate, burst);
+                if !limiter.try_acquire(Instant::now()) {
+                    Stats::incr(&server.stats.rejected_connections);
+                    drop(socket);
+                    continue;
+                }
+                if let Err(e) = set_keepalive(&socket, keepalive) {
+                    log!(
+                        server.log_level,
+                        Level::Warning,
+                        "Unable to set TCP keepalive: {:?}",
+                        e
+                    );
+                }
+                let server_clone = server.clone();
+                Stats::incr(&server.stats.connected_clients);
+                connections.spawn(async move {
+                    handle_connection(socket, server_clone.clone(), peer).await;
+                    Stats::decr(&server_clone.stats.connected_clients);
                 });
             }
-            Err(e) => eprintln!("Failed to establish connectin: {:?}", e),
+            Err(e) => log!(
+                server.log_level,
+                Level::Warning,
+                "Failed to establish connectin: {:?}",
+                e
+            ),
         };
     }
+
+    log!(
+        server.log_level,
+        Level::Notice,
+        "Shutting down, waiting for {} connections",
+        connections.len()
+    );
+    drop(listener);
+    let drained = tokio::time::timeout(SHUTDOWN_GRACE_PERIOD, async {
+        while connections.join_next().await.is_some() {}
+    })
+    .await;
+    if drained.is_err() {
+        log!(
+            server.log_level,
+            Level::Notice,
+            "Dropping {} connections still open",
+            connections.len()
+        );
+    }
+}
+
+/// Has TCP probe a connection after `seconds` of quiet, the way Redis applies
+/// `tcp-keepalive`, so a peer that vanished without closing is noticed. The
+/// read then fails, which ends the connection and cleans up after it like any
+/// other disconnect. 0 leaves the probes off.
+fn set_keepalive(socket: &TcpStream, seconds: u64) -> Result<()> {
+    let socket = socket2::SockRef::from(socket);
+    if seconds == 0 {
+        return socket.set_keepalive(false);
+    }
+    let keepalive = socket2::TcpKeepalive::new().with_time(Duration::from_secs(seconds));
+    // Redis gives up after about twice the idle time: three probes a third apart.
+    #[cfg(target_os = "linux")]
+    let keepalive = keepalive
+        .with_interval(Duration::from_secs((seconds / 3).max(1)))
+        .with_retries(3);
+    socket.set_tcp_keepalive(&keepalive)
+}
+
+/// Reads `--bind <ip>` and `--port <port>` from `args`, defaulting to
+/// 127.0.0.1:6379. Other arguments are left for their own flags.
+fn parse_address(
+    args: impl IntoIterator<Item = String>,
+) -> std::result::Result<SocketAddr, String> {
+    let mut ip = IpAddr::from([127, 0, 0, 1]);
+    let mut port = DEFAULT_PORT;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bind" => {
+                let value = args.next().ok_or("--bind needs an address")?;
+                ip = value
+                    .parse()
+                    .map_err(|_| format!("invalid bind address '{value}'"))?;
+            }
+            "--port" => {
+                let value = args.next().ok_or("--port needs a number")?;
+                port = value
+                    .parse()
+                    .map_err(|_| format!("invalid port '{value}'"))?;
+            }
+            _ => {}
+        }
+    }
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// Reads `--loglevel <level>` from `args`, with `--verbose` short for
+/// `--loglevel verbose`. Defaults to notice, which prints nothing per request.
+fn parse_log_level(args: impl IntoIterator<Item = String>) -> std::result::Result<Level, String> {
+    let mut level = Level::default();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--verbose" => level = Level::Verbose,
+            "--loglevel" => {
+                let value = args.next().ok_or("--loglevel needs a level")?;
+                level = Level::from_name(&value).ok_or(format!("invalid log level '{value}'"))?;
+            }
+            _ => {}
+        }
+    }
+    Ok(level)
+}
+
+/// Reads `--<parameter> <value>` for any CONFIG parameter from `args`, the
+/// way `redis-server --appendonly yes` takes them, including the ones CONFIG
+/// SET can't change such as `appendfilename`.
+fn parse_config(args: impl IntoIterator<Item = String>) -> std::result::Result<Config, String> {
+    let mut config = Config::default();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let Some(name) = arg
+            .strip_prefix("--")
+            .filter(|name| PARAMETERS.contains(name))
+        else {
+            continue;
+        };
+        let value = args.next().ok_or(format!("{arg} needs a value"))?;
+        config
+            .set_at_startup(name, &value)
+            .map_err(|e| format!("invalid {arg} '{value}': {e}"))?;
+    }
+    Ok(config)
+}
+
+/// Loads the dataset saved by a previous run. The append-only file is the more
+/// complete record, so when it is enabled the snapshot is not read.
+fn restore(server: &Server) -> std::result::Result<(), String> {
+    let config = unpoisoned(server.config.read()).clone();
+    if config.appendonly {
+        let path = config.aof_path();
+        let replayed =
+            aof::replay(server, &path).map_err(|e| format!("replaying {}: {e}", path.display()))?;
+        log!(
+            server.log_level,
+            Level::Notice,
+            "Replayed {} commands from {}",
+            replayed,
+            path.display()
+        );
+        server
+            .aof
+            .open(&path)
+            .map_err(|e| format!("opening {}: {e}", path.display()))?;
+    } else {
+        let path = config.snapshot_path();
+        let loaded =
+            rdb::load(server, &path).map_err(|e| format!("loading {}: {e}", path.display()))?;
+        log!(
+            server.log_level,
+            Level::Notice,
+            "Loaded {} keys from {}",
+            loaded,
+            path.display()
+        );
+    }
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
-    server_loop().await;
+    let address = match parse_address(std::env::args().skip(1)) {
+        Ok(address) => address,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
+    let log_level = match parse_log_level(std::env::args().skip(1)) {
+        Ok(level) => level,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
+    let config = match parse_config(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
+    let server = Server {
+        debug_compat: std::env::args().any(|arg| arg == "--debug-compat"),
+        log_level,
+        ..Server::new(config)
+    };
+    logging::set_process_level(server.log_level);
+
+    if let Err(e) = restore(&server) {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+    server_loop(server, address).await;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Where connections served over an in-memory stream claim to come from.
+    fn peer() -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 50000))
+    }
+
+    #[tokio::test]
+    async fn test_partial_frame_then_eof() {
+        let server: SharedServer = Arc::new(Server::default());
+        let (mut client, server_stream) = tokio::io::duplex(4096);
+
+        let handle = tokio::spawn(handle_connection(server_stream, server.clone(), peer()));
+        client.write_all(b"*1\r\n$4\r\nPI").await.unwrap();
+        client.shutdown().await.unwrap();
+        handle.await.unwrap();
+
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).await.unwrap();
+        assert!(reply.is_empty());
+        assert_eq!(Stats::get(&server.stats.incomplete_on_close), 1);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_request_is_answered_then_closes() {
+        let server: SharedServer = Arc::new(Server::default());
+        let (mut client, server_stream) = tokio::io::duplex(4096);
+
+        let handle = tokio::spawn(handle_connection(server_stream, server.clone(), peer()));
+        client
+            .write_all(b"SET key v\r\nSET \"open\r\nSET other v\r\n")
+            .await
+            .unwrap();
+        handle.await.unwrap();
+
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).await.unwrap();
+        assert_eq!(
+            reply,
+            b"+OK\r\n-ERR Protocol error: unbalanced quotes in request\r\n"
+        );
+        assert!(server.db(0).peek_live(b"other", |value| value.is_none()));
+    }
+
+    #[tokio::test]
+    async fn test_clean_close_is_not_counted() {
+        let server: SharedServer = Arc::new(Server::default());
+        let (mut client, server_stream) = tokio::io::duplex(4096);
+
+        let handle = tokio::spawn(handle_connection(server_stream, server.clone(), peer()));
+        client.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+        client.shutdown().await.unwrap();
+        handle.await.unwrap();
+
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).await.unwrap();
+        assert_eq!(reply, b"+PONG\r\n");
+        assert_eq!(Stats::get(&server.stats.incomplete_on_close), 0);
+    }
+
+    #[tokio::test]
+    async fn test_expire_loop_reaps_unread_keys() {
+        let server: SharedServer = Arc::new(Server::default());
+        tokio::spawn(expire_loop(server.clone()));
+        let (mut client, server_stream) = tokio::io::duplex(4096);
+        tokio::spawn(handle_connection(server_stream, server.clone(), peer()));
+
+        client
+            .write_all(b"*5\r\n$3\r\nSET\r\n$3\r\nkey\r\n$1\r\nv\r\n$2\r\nPX\r\n$2\r\n10\r\n")
+            .await
+            .unwrap();
+        let mut reply = [0; 5];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"+OK\r\n");
+
+        // DBSIZE already hides expired keys, so look at what is actually stored.
+        tokio::time::sleep(EXPIRE_INTERVAL * 3).await;
+        assert!(server.db(0).lock_all().iter().next().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_active_expire_can_be_paused() {
+        let server: SharedServer = Arc::new(Server::default());
+        tokio::spawn(expire_loop(server.clone()));
+        assert_eq!(
+            commands::run(&server, &[b"DEBUG", b"SET-ACTIVE-EXPIRE", b"0"]),
+            b"+OK\r\n"
+        );
+        commands::run(&server, &[b"SET", b"key", b"v", b"PX", b"10"]);
+
+        tokio::time::sleep(EXPIRE_INTERVAL * 3).await;
+        assert_eq!(server.db(0).lock(b"key").len(), 1);
+
+        commands::run(&server, &[b"DEBUG", b"SET-ACTIVE-EXPIRE", b"1"]);
+        tokio::time::sleep(EXPIRE_INTERVAL * 3).await;
+        assert_eq!(server.db(0).lock(b"key").len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_debug_sleep_only_pauses_its_connection() {
+        let server: SharedServer = Arc::new(Server::default());
+        let (mut sleeper, sleeper_stream) = tokio::io::duplex(4096);
+        let (mut other, other_stream) = tokio::io::duplex(4096);
+        tokio::spawn(handle_connection(sleeper_stream, server.clone(), peer()));
+        tokio::spawn(handle_connection(other_stream, server.clone(), peer()));
+
+        let started = Instant::now();
+        sleeper.write_all(b"DEBUG SLEEP 0.3\r\n").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        other.write_all(b"PING\r\n").await.unwrap();
+        let mut reply = [0; 7];
+        other.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"+PONG\r\n");
+        assert!(started.elapsed() < Duration::from_millis(200));
+
+        let mut reply = [0; 5];
+        sleeper.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"+OK\r\n");
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn test_monitor_sees_commands_from_other_connections() {
+        let server: SharedServer = Arc::new(Server::default());
+        let (mut monitor, monitor_stream) = tokio::io::duplex(4096);
+        let (mut client, client_stream) = tokio::io::duplex(4096);
+        tokio::spawn(handle_connection(monitor_stream, server.clone(), peer()));
+        tokio::spawn(handle_connection(client_stream, server.clone(), peer()));
+
+        monitor.write_all(b"MONITOR\r\n").await.unwrap();
+        let mut reply = [0; 5];
+        monitor.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"+OK\r\n");
+        // The monitor's own commands stay out of its feed.
+        monitor.write_all(b"PING\r\n").await.unwrap();
+        let mut reply = [0; 7];
+        monitor.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"+PONG\r\n");
+
+        // Requests refused before running never reach the feed.
+        client.write_all(b"NOPE\r\nSET key\r\n").await.unwrap();
+        let refused = b"-ERR unknown command `NOPE`\r\n-ERR wrong number of arguments\r\n";
+        let mut reply = vec![0; refused.len()];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply, refused);
+        client
+            .write_all(b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nva lu\r\n")
+            .await
+            .unwrap();
+        let mut reply = [0; 5];
+        client.read_exact(&mut reply).await.unwrap();
+
+        let mut line = Vec::new();
+        while !line.ends_with(b"\r\n") {
+            line.push(monitor.read_u8().await.unwrap());
+        }
+        let line = String::from_utf8(line).unwrap();
+        assert!(line.starts_with('+'), "{line:?}");
+        assert!(
+            line.ends_with(" [0 127.0.0.1:50000] \"SET\" \"key\" \"va lu\"\r\n"),
+            "{line:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout_drops_only_quiet_clients() {
+        let server: SharedServer = Arc::new(Server::default());
+        server.config.write().unwrap().set("timeout", "1").unwrap();
+        let (idle, idle_stream) = tokio::io::duplex(4096);
+        let (mut active, active_stream) = tokio::io::duplex(4096);
+        let (mut blocked, blocked_stream) = tokio::io::duplex(4096);
+        let idle_handle = tokio::spawn(handle_connection(idle_stream, server.clone(), peer()));
+        let active_handle = tokio::spawn(handle_connection(active_stream, server.clone(), peer()));
+        tokio::spawn(handle_connection(blocked_stream, server.clone(), peer()));
+
+        blocked.write_all(b"BLPOP list 1.5\r\n").await.unwrap();
+        for _ in 0..6 {
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            active.write_all(b"PING\r\n").await.unwrap();
+            let mut reply = [0; 7];
+            active.read_exact(&mut reply).await.unwrap();
+        }
+        tokio::time::timeout(Duration::from_millis(500), idle_handle)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!active_handle.is_finished());
+        drop(idle);
+
+        // Waiting in BLPOP past the timeout is not idling.
+        let mut reply = [0; 5];
+        blocked.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"*-1\r\n");
+        blocked.write_all(b"PING\r\n").await.unwrap();
+        let mut reply = [0; 7];
+        blocked.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"+PONG\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_client_kill_closes_the_other_connection() {
+        let server: SharedServer = Arc::new(Server::default());
+        let (mut killer, killer_stream) = tokio::io::duplex(4096);
+        let (mut victim, victim_stream) = tokio::io::duplex(4096);
+        tokio::spawn(handle_connection(killer_stream, server.clone(), peer()));
+        let handle = tokio::spawn(handle_connection(victim_stream, server.clone(), peer()));
+
+        victim.write_all(b"CLIENT ID\r\n").await.unwrap();
+        let mut reply = [0; 4];
+        victim.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b":2\r\n");
+
+        killer.write_all(b"CLIENT KILL ID 2\r\n").await.unwrap();
+        killer.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b":1\r\n");
+        tokio::time::timeout(Duration::from_secs(1), handle)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(victim.read(&mut [0; 16]).await.unwrap(), 0);
+        assert_eq!(server.clients.list().lines().count(), 1);
+
+        killer.write_all(b"CLIENT KILL ID 2\r\n").await.unwrap();
+        killer.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b":0\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_push_wakes_blpop_on_another_connection() {
+        let server: SharedServer = Arc::new(Server::default());
+        let (mut blocked, blocked_stream) = tokio::io::duplex(4096);
+        let (mut pusher, pusher_stream) = tokio::io::duplex(4096);
+        tokio::spawn(handle_connection(blocked_stream, server.clone(), peer()));
+        tokio::spawn(handle_connection(pusher_stream, server.clone(), peer()));
+
+        blocked
+            .write_all(b"BLPOP list 0\r\nPING\r\n")
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        pusher.write_all(b"RPUSH list a\r\n").await.unwrap();
+        let mut reply = [0; 4];
+        pusher.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b":1\r\n");
+
+        // The PING pipelined behind the BLPOP is answered after it.
+        let mut reply = [0; 28];
+        blocked.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"*2\r\n$4\r\nlist\r\n$1\r\na\r\n+PONG\r\n");
+        assert_eq!(server.db(0).lock(b"list").len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_blpop_follows_its_index_through_swapdb() {
+        let server: SharedServer = Arc::new(Server::default());
+        let (mut blocked, blocked_stream) = tokio::io::duplex(4096);
+        let (mut other, other_stream) = tokio::io::duplex(4096);
+        tokio::spawn(handle_connection(blocked_stream, server.clone(), peer()));
+        tokio::spawn(handle_connection(other_stream, server.clone(), peer()));
+
+        // A push to the database swapped into index 0 wakes a BLPOP on 0.
+        blocked.write_all(b"BLPOP list 0\r\n").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        other
+            .write_all(b"SWAPDB 0 1\r\nRPUSH list a\r\n")
+            .await
+            .unwrap();
+        let mut reply = [0; 9];
+        other.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"+OK\r\n:1\r\n");
+        let mut reply = [0; 21];
+        blocked.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"*2\r\n$4\r\nlist\r\n$1\r\na\r\n");
+        assert_eq!(server.db(1).waiters.queued(b"list"), 0);
+
+        // Swapping in a database that already has the list wakes it too.
+        blocked.write_all(b"BLPOP list 0\r\n").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        other
+            .write_all(b"SELECT 1\r\nRPUSH list b\r\nSWAPDB 0 1\r\n")
+            .await
+            .unwrap();
+        let mut reply = [0; 14];
+        other.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"+OK\r\n:1\r\n+OK\r\n");
+        let mut reply = [0; 21];
+        blocked.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"*2\r\n$4\r\nlist\r\n$1\r\nb\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_one_element_unblocks_the_longest_waiting_blpop() {
+        let server: SharedServer = Arc::new(Server::default());
+        let (mut first, first_stream) = tokio::io::duplex(4096);
+        let (mut second, second_stream) = tokio::io::duplex(4096);
+        let (mut pusher, pusher_stream) = tokio::io::duplex(4096);
+        tokio::spawn(handle_connection(first_stream, server.clone(), peer()));
+        tokio::spawn(handle_connection(second_stream, server.clone(), peer()));
+        tokio::spawn(handle_connection(pusher_stream, server.clone(), peer()));
+
+        first.write_all(b"BLPOP list 0.3\r\n").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        second.write_all(b"BLPOP list 0.3\r\n").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        pusher.write_all(b"LPUSH list only\r\n").await.unwrap();
+
+        let mut reply = [0; 24];
+        first.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"*2\r\n$4\r\nlist\r\n$4\r\nonly\r\n");
+        let started = Instant::now();
+        let mut reply = [0; 5];
+        second.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"*-1\r\n");
+        assert!(started.elapsed() >= Duration::from_millis(150));
+    }
+
+    #[tokio::test]
+    async fn test_disconnecting_while_blocked_unregisters_the_waiter() {
+        let server: SharedServer = Arc::new(Server::default());
+        let (mut client, server_stream) = tokio::io::duplex(4096);
+        let handle = tokio::spawn(handle_connection(server_stream, server.clone(), peer()));
+
+        client.write_all(b"BRPOP list 0\r\n").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(server.db(0).waiters.queued(b"list"), 1);
+
+        drop(client);
+        handle.await.unwrap();
+        assert_eq!(server.db(0).waiters.queued(b"list"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_partial_request_closes_connection() {
+        let server: SharedServer = Arc::new(Server::default());
+        server
+            .config
+            .write()
+            .unwrap()
+            .set("proto-max-bulk-len", "1mb")
+            .unwrap();
+        let (mut client, server_stream) = tokio::io::duplex(64 * 1024);
+        let handle = tokio::spawn(handle_connection(server_stream, server.clone(), peer()));
+
+        // A large value under the limit still goes through.
+        let mut request = b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$900000\r\n".to_vec();
+        request.extend(vec![b'x'; 900_000]);
+        request.extend(b"\r\n");
+        client.write_all(&request).await.unwrap();
+        let mut reply = [0; 5];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"+OK\r\n");
+
+        // A length header beyond the limit, followed by data that never ends it.
+        client
+            .write_all(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$2000000\r\n")
+            .await
+            .unwrap();
+        // The server hangs up partway through, failing the rest of this write.
+        let _ = client.write_all(&vec![b'x'; 1_500_000]).await;
+        handle.await.unwrap();
+
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).await.unwrap();
+        assert_eq!(
+            reply,
+            b"-ERR Protocol error: too big inline/bulk request\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_oversized_input_while_blocked_closes_connection() {
+        let server: SharedServer = Arc::new(Server::default());
+        server
+            .config
+            .write()
+            .unwrap()
+            .set("proto-max-bulk-len", "1mb")
+            .unwrap();
+        let (mut client, server_stream) = tokio::io::duplex(64 * 1024);
+        let handle = tokio::spawn(handle_connection(server_stream, server.clone(), peer()));
+
+        client.write_all(b"BLPOP list 0\r\n").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        // Input that piles up behind the BLPOP counts against the same limit.
+        client
+            .write_all(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$2000000\r\n")
+            .await
+            .unwrap();
+        let _ = client.write_all(&vec![b'x'; 1_500_000]).await;
+        tokio::time::timeout(Duration::from_secs(1), handle)
+            .await
+            .expect("blocked client kept buffering")
+            .unwrap();
+
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).await.unwrap();
+        assert_eq!(
+            reply,
+            b"-ERR Protocol error: too big inline/bulk request\r\n"
+        );
+        assert_eq!(server.db(0).waiters.queued(b"list"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_multi_exec_runs_queued_commands() {
+        let server: SharedServer = Arc::new(Server::default());
+        let (mut client, server_stream) = tokio::io::duplex(4096);
+
+        let handle = tokio::spawn(handle_connection(server_stream, server.clone(), peer()));
+        client
+            .write_all(b"MULTI\r\nSET a 1\r\nSET b 2\r\nEXEC\r\n")
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+        handle.await.unwrap();
+
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).await.unwrap();
+        assert_eq!(reply, b"+OK\r\n+QUEUED\r\n+QUEUED\r\n*2\r\n+OK\r\n+OK\r\n");
+        assert_eq!(
+            server.db(0).lock(b"a").len() + server.db(0).lock(b"b").len(),
+            2
+        );
+    }
+
+    /// Serves each of `reads` in turn, counts the `writes` that succeed and
+    /// fails every write after the first `writes_left`.
+    struct FailingStream {
+        reads: std::collections::VecDeque<&'static [u8]>,
+        writes: usize,
+        writes_left: usize,
+    }
+
+    impl AsyncRead for FailingStream {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<Result<()>> {
+            if let Some(read) = self.reads.pop_front() {
+                buf.put_slice(read);
+            }
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for FailingStream {
+        fn poll_write(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<Result<usize>> {
+            if self.writes_left == 0 {
+                return std::task::Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into()));
+            }
+            self.writes_left -= 1;
+            self.writes += 1;
+            std::task::Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_error_stops_pipeline() {
+        let server: SharedServer = Arc::new(Server::default());
+        let socket = FailingStream {
+            reads: [
+                &b"SET a 1\r\nSET b 1\r\n"[..],
+                b"SET c 1\r\n",
+                b"SET d 1\r\n",
+            ]
+            .into(),
+            writes: 0,
+            writes_left: 1,
+        };
+        handle_connection(socket, server.clone(), peer()).await;
+
+        // The first read's two replies got out in one write. The command in
+        // the second ran but its reply was lost, and the third was never run.
+        let keys: Vec<Vec<u8>> = server
+            .db(0)
+            .lock_all()
+            .iter()
+            .map(|(key, _)| key.clone())
+            .collect();
+        let executed = Stats::get(&server.stats.total_commands_processed);
+        assert_eq!(executed, 3);
+        assert_eq!(keys.len() as u64, executed);
+        assert!(!keys.contains(&b"d".to_vec()));
+        // Ending on an error still unregisters the client.
+        assert_eq!(server.clients.list(), "");
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_writes_are_answered_in_one_write() {
+        let server: SharedServer = Arc::new(Server::default());
+        let mut socket = FailingStream {
+            reads: [&b"SET a 1\r\nSET b 2\r\nSET c 3\r\nSET d 4\r\nSET e 5\r\n"[..]].into(),
+            writes: 0,
+            writes_left: usize::MAX,
+        };
+        handle_connection(&mut socket, server.clone(), peer()).await;
+
+        assert_eq!(Stats::get(&server.stats.total_commands_processed), 5);
+        assert_eq!(socket.writes, 1);
+    }
+
+    #[tokio::test]
+    async fn test_large_pipeline_answers_every_command_in_order() {
+        let server: SharedServer = Arc::new(Server::default());
+        let (mut client, server_stream) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(handle_connection(server_stream, server.clone(), peer()));
+
+        let request = b"*2\r\n$4\r\nINCR\r\n$7\r\ncounter\r\n".repeat(1000);
+        client.write_all(&request).await.unwrap();
+
+        let expected: Vec<u8> = (1..=1000)
+            .flat_map(|n| format!(":{n}\r\n").into_bytes())
+            .collect();
+        let mut replies = vec![0; expected.len()];
+        client.read_exact(&mut replies).await.unwrap();
+        assert_eq!(replies, expected);
+    }
+
+    #[tokio::test]
+    async fn test_batched_replies_keep_command_order() {
+        let server: SharedServer = Arc::new(Server::default());
+        let (mut client, server_stream) = tokio::io::duplex(4096);
+        tokio::spawn(handle_connection(server_stream, server.clone(), peer()));
+
+        client
+            .write_all(
+                b"SET k v\r\nGET k\r\nAPPEND k w\r\nNOPE\r\nGET k\r\nDEBUG SLEEP 0\r\nECHO end\r\n",
+            )
+            .await
+            .unwrap();
+        let expected = b"+OK\r\n$1\r\nv\r\n:2\r\n-ERR unknown command `NOPE`\r\n$2\r\nvw\r\n+OK\r\n$3\r\nend\r\n";
+        let mut replies = vec![0; expected.len()];
+        client.read_exact(&mut replies).await.unwrap();
+        assert_eq!(replies, expected);
+
+        // A malformed request still comes after the replies ahead of it.
+        client.write_all(b"PING\r\n*1\r\nx\r\n").await.unwrap();
+        let mut replies = Vec::new();
+        client.read_to_end(&mut replies).await.unwrap();
+        assert!(replies.starts_with(b"+PONG\r\n-ERR "), "{replies:?}");
+    }
+
+    #[tokio::test]
+    async fn test_publish_reaches_subscriber() {
+        let server: SharedServer = Arc::new(Server::default());
+        let (mut client, server_stream) = tokio::io::duplex(4096);
+        let handle = tokio::spawn(handle_connection(server_stream, server.clone(), peer()));
+
+        client.write_all(b"SUBSCRIBE news\r\n").await.unwrap();
+        let confirmation = b"*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n";
+        let mut reply = vec![0; confirmation.len()];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply, confirmation);
+
+        let publish = commands::run(&server, &[b"PUBLISH", b"news", b"hello"]);
+        assert_eq!(publish, b":1\r\n");
+        let message = b"*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$5\r\nhello\r\n";
+        let mut reply = vec![0; message.len()];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply, message);
+
+        // A subscriber that goes away is taken out of the registry.
+        drop(client);
+        handle.await.unwrap();
+        assert_eq!(server.pubsub.channel_count(), 0);
+        assert_eq!(
+            commands::run(&server, &[b"PUBLISH", b"news", b"hello"]),
+            b":0\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resp3_subscriber_gets_pushes_between_replies() {
+        let server: SharedServer = Arc::new(Server::default());
+        let (mut client, server_stream) = tokio::io::duplex(4096);
+        tokio::spawn(handle_connection(server_stream, server.clone(), peer()));
+
+        commands::run(&server, &[b"SET", b"key", b"value"]);
+        client
+            .write_all(b"HELLO 3\r\nSUBSCRIBE news\r\nGET key\r\n")
+            .await
+            .unwrap();
+        // Skip HELLO's map to the confirmation and the GET reply after it.
+        let expected = b">3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n$5\r\nvalue\r\n";
+        let mut replies = Vec::new();
+        while !replies.ends_with(expected) {
+            let mut byte = [0; 1];
+            client.read_exact(&mut byte).await.unwrap();
+            replies.push(byte[0]);
+        }
+
+        commands::run(&server, &[b"PUBLISH", b"news", b"hello"]);
+        let message = b">3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$5\r\nhello\r\n";
+        let mut reply = vec![0; message.len()];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply, message);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_stops_accepting() {
+        let server: SharedServer = Arc::new(Server::default());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (trigger, shutdown) = tokio::sync::oneshot::channel::<()>();
+        let accepting = tokio::spawn(accept_loop(listener, server.clone(), async {
+            let _ = shutdown.await;
+        }));
+
+        // A connection open at shutdown is still served until it closes.
+        let mut client = TcpStream::connect(address).await.unwrap();
+        client.write_all(b"PING\r\n").await.unwrap();
+        let mut reply = [0; 7];
+        client.read_exact(&mut reply).await.unwrap();
+        trigger.send(()).unwrap();
+        client.write_all(b"PING\r\n").await.unwrap();
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"+PONG\r\n");
+        drop(client);
+
+        tokio::time::timeout(Duration::from_secs(1), accepting)
+            .await
+            .expect("accept loop kept running")
+            .unwrap();
+        assert!(TcpStream::connect(address).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_clients_get_distinct_ids() {
+        let server: SharedServer = Arc::new(Server::default());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(accept_loop(
+            listener,
+            server.clone(),
+            std::future::pending(),
+        ));
+
+        let mut first = TcpStream::connect(address).await.unwrap();
+        let mut second = TcpStream::connect(address).await.unwrap();
+        let mut reply = [0; 4];
+        first.write_all(b"CLIENT ID\r\n").await.unwrap();
+        first.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b":1\r\n");
+        second.write_all(b"CLIENT ID\r\n").await.unwrap();
+        second.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b":2\r\n");
+
+        second
+            .write_all(b"CLIENT SETNAME second\r\n")
+            .await
+            .unwrap();
+        second.read_exact(&mut [0; 5]).await.unwrap();
+        let list = server.clients.list();
+        let lines: Vec<&str> = list.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("id=1 addr=127.0.0.1:") && lines[0].contains(" name= "));
+        assert!(lines[1].starts_with("id=2 ") && lines[1].contains(" name=second age=0"));
+
+        // A peer that goes away is dropped from the list.
+        drop(first);
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while server.clients.list().lines().count() > 1 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .expect("closed client stayed listed");
+        assert!(server.clients.list().starts_with("id=2 "));
+    }
+
+    #[tokio::test]
+    async fn test_accept_limits_come_from_config() {
+        let server: SharedServer = Arc::new(Server::default());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(accept_loop(
+            listener,
+            server.clone(),
+            std::future::pending(),
+        ));
+        commands::run(&server, &[b"CONFIG", b"SET", b"accept-rate", b"1"]);
+        commands::run(&server, &[b"CONFIG", b"SET", b"accept-burst", b"2"]);
+
+        let mut clients = Vec::new();
+        for _ in 0..4 {
+            clients.push(TcpStream::connect(address).await.unwrap());
+        }
+        let mut answered = 0;
+        for client in &mut clients {
+            let _ = client.write_all(b"PING\r\n").await;
+            let mut reply = [0; 7];
+            if client.read_exact(&mut reply).await.is_ok() {
+                answered += 1;
+            }
+        }
+        assert_eq!(answered, 2);
+        assert_eq!(Stats::get(&server.stats.rejected_connections), 2);
+    }
+
+    #[tokio::test]
+    async fn test_vanished_peer_is_cleaned_up() {
+        let server: SharedServer = Arc::new(Server::default());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(accept_loop(
+            listener,
+            server.clone(),
+            std::future::pending(),
+        ));
+
+        let mut client = TcpStream::connect(address).await.unwrap();
+        set_keepalive(&client, 60).unwrap();
+        let probes = socket2::SockRef::from(&client);
+        assert!(probes.keepalive().unwrap());
+        assert_eq!(probes.keepalive_time().unwrap(), Duration::from_secs(60));
+
+        client.write_all(b"SUBSCRIBE news\r\n").await.unwrap();
+        let confirmation = b"*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n";
+        let mut reply = vec![0; confirmation.len()];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(server.clients.list().lines().count(), 1);
+        assert_eq!(server.pubsub.channel_count(), 1);
+
+        // Resetting the connection fails the server's read, as a keepalive
+        // probe that goes unanswered does.
+        client.set_linger(Some(Duration::ZERO)).unwrap();
+        drop(client);
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while !server.clients.list().is_empty() || server.pubsub.channel_count() > 0 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .expect("vanished peer stayed registered");
+        assert_eq!(
+            commands::run(&server, &[b"PUBLISH", b"news", b"hello"]),
+            b":0\r\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_address() {
+        let parse = |args: &[&str]| parse_address(args.iter().map(|arg| arg.to_string()));
+        assert_eq!(parse(&[]), Ok("127.0.0.1:6379".parse().unwrap()));
+        assert_eq!(
+            parse(&["--debug-compat", "--port", "7000", "--bind", "0.0.0.0"]),
+            Ok("0.0.0.0:7000".parse().unwrap())
+        );
+        assert_eq!(
+            parse(&["--port", "70000"]),
+            Err("invalid port '70000'".to_string())
+        );
+        assert!(parse(&["--port"]).is_err());
+        assert!(parse(&["--bind", "localhost"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let parse = |args: &[&str]| parse_config(args.iter().map(|arg| arg.to_string()));
+        assert_eq!(parse(&["--port", "7000"]), Ok(Config::default()));
+
+        let config = parse(&[
+            "--appendonly",
+            "yes",
+            "--dir",
+            "/",
+            "--appendfilename",
+            "writes.aof",
+            "--verbose",
+        ])
+        .unwrap();
+        assert!(config.appendonly);
+        assert_eq!(config.aof_path(), std::path::PathBuf::from("/writes.aof"));
+
+        assert!(parse(&["--appendonly"]).is_err());
+        assert!(parse(&["--appendonly", "maybe"]).is_err());
+        assert!(parse(&["--dir", "/no/such/directory"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_log_level() {
+        let parse = |args: &[&str]| parse_log_level(args.iter().map(|arg| arg.to_string()));
+        assert_eq!(parse(&["--port", "7000"]), Ok(Level::Notice));
+        assert_eq!(parse(&["--verbose"]), Ok(Level::Verbose));
+        assert_eq!(parse(&["--loglevel", "DEBUG"]), Ok(Level::Debug));
+        assert_eq!(
+            parse(&["--loglevel", "loud"]),
+            Err("invalid log level 'loud'".to_string())
+        );
+        assert!(parse(&["--loglevel"]).is_err());
+        // Per-request messages only show above the default.
+        assert!(Level::Debug > Level::default() && Level::Warning < Level::default());
+    }
+}
@@ -0,0 +1,111 @@
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::sync::broadcast;
+
+/// How many lines a MONITOR connection may fall behind. A monitor that can't
+/// keep up loses the oldest lines rather than slowing down the commands.
+const FEED_CAPACITY: usize = 1024;
+
+/// The feed of executed commands that MONITOR connections follow.
+#[derive(Debug)]
+pub struct Monitor {
+    feed: broadcast::Sender<String>,
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Monitor {
+            feed: broadcast::channel(FEED_CAPACITY).0,
+        }
+    }
+}
+
+impl Monitor {
+    pub fn watch(&self) -> broadcast::Receiver<String> {
+        self.feed.subscribe()
+    }
+
+    /// Whether any connection is monitoring, so lines only get formatted for
+    /// someone.
+    pub fn is_watched(&self) -> bool {
+        self.feed.receiver_count() > 0
+    }
+
+    /// Sends a command to every monitor, formatted as Redis does:
+    /// `1339518083.107412 [0 127.0.0.1:60866] "SET" "key" "value"`.
+    pub fn feed(&self, db_index: usize, addr: SocketAddr, args: &[&[u8]]) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut line = format!(
+            "{}.{:06} [{} {}]",
+            now.as_secs(),
+            now.subsec_micros(),
+            db_index,
+            addr
+        );
+        for arg in args {
+            line.push(' ');
+            quote(&mut line, arg);
+        }
+        // Fails only when nobody is watching anymore.
+        let _ = self.feed.send(line);
+    }
+}
+
+/// Appends `arg` in double quotes, escaping it the way Redis's `sdscatrepr` does.
+fn quote(line: &mut String, arg: &[u8]) {
+    line.push('"');
+    for &byte in arg {
+        match byte {
+            b'\\' => line.push_str("\\\\"),
+            b'"' => line.push_str("\\\""),
+            b'\n' => line.push_str("\\n"),
+            b'\r' => line.push_str("\\r"),
+            b'\t' => line.push_str("\\t"),
+            0x07 => line.push_str("\\a"),
+            0x08 => line.push_str("\\b"),
+            b' '..=b'~' => line.push(byte as char),
+            _ => line.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    line.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feed_quotes_arguments() {
+        let monitor = Monitor::default();
+        let mut watcher = monitor.watch();
+        monitor.feed(
+            2,
+            SocketAddr::from(([127, 0, 0, 1], 6000)),
+            &[b"SET", b"say \"hi\"", b"a\r\n\x00"],
+        );
+
+        let line = watcher.try_recv().unwrap();
+        let (timestamp, rest) = line.split_once(' ').unwrap();
+        assert!(timestamp.parse::<f64>().is_ok(), "{line}");
+        assert_eq!(rest, r#"[2 127.0.0.1:6000] "SET" "say \"hi\"" "a\r\n\x00""#);
+    }
+
+    #[test]
+    fn test_slow_monitor_loses_oldest_lines() {
+        let monitor = Monitor::default();
+        let mut watcher = monitor.watch();
+        let addr = SocketAddr::from(([127, 0, 0, 1], 6000));
+        for _ in 0..FEED_CAPACITY + 10 {
+            monitor.feed(0, addr, &[b"PING"]);
+        }
+
+        assert!(matches!(
+            watcher.try_recv(),
+            Err(broadcast::error::TryRecvError::Lagged(10))
+        ));
+        assert!(watcher.try_recv().unwrap().ends_with("\"PING\""));
+    }
+}
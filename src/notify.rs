@@ -0,0 +1,144 @@
+use std::fmt;
+
+use crate::db::{unpoisoned, Database};
+use crate::server::Server;
+
+/// Which keyspace notifications `notify-keyspace-events` asks for, as the
+/// letters Redis uses: K and E pick the channels, the rest the events.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Flags(u16);
+
+impl Flags {
+    /// To `__keyspace@<db>__:<key>`, with the event as the message.
+    pub const KEYSPACE: Flags = Flags(1 << 0);
+    /// To `__keyevent@<db>__:<event>`, with the key as the message.
+    pub const KEYEVENT: Flags = Flags(1 << 1);
+    pub const GENERIC: Flags = Flags(1 << 2);
+    pub const STRING: Flags = Flags(1 << 3);
+    pub const LIST: Flags = Flags(1 << 4);
+    pub const SET: Flags = Flags(1 << 5);
+    pub const HASH: Flags = Flags(1 << 6);
+    pub const ZSET: Flags = Flags(1 << 7);
+    pub const EXPIRED: Flags = Flags(1 << 8);
+    pub const EVICTED: Flags = Flags(1 << 9);
+    pub const STREAM: Flags = Flags(1 << 10);
+    pub const KEY_MISS: Flags = Flags(1 << 11);
+    pub const NEW: Flags = Flags(1 << 12);
+
+    /// What `A` stands for: every event but key misses and new keys.
+    const ALL: Flags = Flags(
+        Flags::GENERIC.0
+            | Flags::STRING.0
+            | Flags::LIST.0
+            | Flags::SET.0
+            | Flags::HASH.0
+            | Flags::ZSET.0
+            | Flags::EXPIRED.0
+            | Flags::EVICTED.0
+            | Flags::STREAM.0,
+    );
+
+    const LETTERS: [(char, Flags); 10] = [
+        ('g', Flags::GENERIC),
+        ('$', Flags::STRING),
+        ('l', Flags::LIST),
+        ('s', Flags::SET),
+        ('h', Flags::HASH),
+        ('z', Flags::ZSET),
+        ('x', Flags::EXPIRED),
+        ('e', Flags::EVICTED),
+        ('t', Flags::STREAM),
+        ('m', Flags::KEY_MISS),
+    ];
+
+    /// Parses the CONFIG SET form, `None` on a letter Redis doesn't know.
+    pub fn parse(value: &str) -> Option<Flags> {
+        value.chars().try_fold(Flags::default(), |flags, letter| {
+            let flag = match letter {
+                'K' => Flags::KEYSPACE,
+                'E' => Flags::KEYEVENT,
+                'A' => Flags::ALL,
+                'n' => Flags::NEW,
+                _ => Flags::LETTERS.iter().find(|(l, _)| *l == letter)?.1,
+            };
+            Some(Flags(flags.0 | flag.0))
+        })
+    }
+
+    pub fn contains(self, other: Flags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// The form CONFIG GET shows, which spells a full set of events as `A`.
+impl fmt::Display for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut letters = String::new();
+        let all = self.contains(Flags::ALL);
+        if all {
+            letters.push('A');
+        }
+        for (letter, flag) in Flags::LETTERS {
+            if self.contains(flag) && !(all && Flags::ALL.contains(flag)) {
+                letters.push(letter);
+            }
+        }
+        for (letter, flag) in [
+            ('K', Flags::KEYSPACE),
+            ('E', Flags::KEYEVENT),
+            ('n', Flags::NEW),
+        ] {
+            if self.contains(flag) {
+                letters.push(letter);
+            }
+        }
+        f.write_str(&letters)
+    }
+}
+
+/// Publishes that `event` happened to `key` in `db`, if `notify-keyspace-events`
+/// asks for `class` and for either channel. Like Redis, a write only notifies
+/// once it has actually changed something.
+pub fn keyspace_event(server: &Server, db: &Database, class: Flags, event: &str, key: &[u8]) {
+    let flags = unpoisoned(server.config.read()).notify_keyspace_events;
+    if !flags.contains(class)
+        || !(flags.contains(Flags::KEYSPACE) || flags.contains(Flags::KEYEVENT))
+    {
+        return;
+    }
+    // SWAPDB may have moved `db` since the command resolved it; the index it
+    // has now is the one its subscribers listen on.
+    let Some(index) = unpoisoned(server.databases.read())
+        .iter()
+        .position(|candidate| std::ptr::eq(&**candidate, db))
+    else {
+        return;
+    };
+    if flags.contains(Flags::KEYSPACE) {
+        let mut channel = format!("__keyspace@{index}__:").into_bytes();
+        channel.extend_from_slice(key);
+        server.pubsub.publish(&channel, event.as_bytes());
+    }
+    if flags.contains(Flags::KEYEVENT) {
+        let channel = format!("__keyevent@{index}__:{event}");
+        server.pubsub.publish(channel.as_bytes(), key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_round_trip() {
+        assert_eq!(Flags::parse(""), Some(Flags::default()));
+        assert_eq!(Flags::parse("KEA").unwrap().to_string(), "AKE");
+        assert_eq!(Flags::parse("Eg$lshzxet").unwrap().to_string(), "AE");
+        assert_eq!(Flags::parse("E$g").unwrap().to_string(), "g$E");
+        assert_eq!(Flags::parse("Km").unwrap().to_string(), "mK");
+        assert_eq!(Flags::parse("Kq"), None);
+        let flags = Flags::parse("E$").unwrap();
+        assert!(flags.contains(Flags::STRING));
+        assert!(!flags.contains(Flags::GENERIC));
+    }
+}
@@ -1,10 +1,34 @@
 #[derive(Debug, PartialEq, Clone)]
 pub enum RespValue {
     SimpleString(String),
+    /// Error reply, e.g. `-ERR syntax error`. Holds the text after the `-`.
+    Error(String),
     Integer(i64),
     BulkString(Vec<u8>),
     Array(Vec<RespValue>),
+    /// Null bulk string, `$-1`.
     Null,
+    /// Null array, `*-1`.
+    NullArray,
+    /// RESP3 double, `,3.14`.
+    Double(f64),
+    /// RESP3 boolean, `#t` or `#f`.
+    Boolean(bool),
+    /// RESP3 null, `_`, which replaces both RESP2 nulls.
+    Nil,
+    /// RESP3 big number, `(` followed by digits. Kept as its decimal text.
+    BigNumber(String),
+    /// RESP3 verbatim string: a three-letter format such as `txt`, then the data.
+    VerbatimString {
+        format: String,
+        data: Vec<u8>,
+    },
+    /// RESP3 map, `%`. The length on the wire counts pairs.
+    Map(Vec<(RespValue, RespValue)>),
+    /// RESP3 set, `~`.
+    Set(Vec<RespValue>),
+    /// RESP3 out-of-band push, `>`, e.g. a pub/sub message.
+    Push(Vec<RespValue>),
 }
 
 #[derive(Debug, PartialEq)]
@@ -15,36 +39,275 @@ pub enum ParserError {
 
 type ParseResult = Result<(RespValue, usize), ParserError>;
 
+/// Splits an inline command into arguments the way redis-cli quotes them:
+/// on whitespace, except inside `"..."`, which understands `\xNN`, `\n`,
+/// `\r`, `\t`, `\b` and `\a` escapes, or `'...'`, where only `\'` is one.
+/// Returns `None` for an unclosed quote, or a closing one not followed by
+/// whitespace.
+fn split_inline_args(line: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let hex_digit = |byte: Option<&u8>| {
+        byte.and_then(|&byte| (byte as char).to_digit(16))
+            .map(|digit| digit as u8)
+    };
+    let mut args = Vec::new();
+    let mut i = 0;
+    loop {
+        while line.get(i).is_some_and(u8::is_ascii_whitespace) {
+            i += 1;
+        }
+        if i == line.len() {
+            return Some(args);
+        }
+
+        let mut arg = Vec::new();
+        while let Some(&byte) = line.get(i) {
+            i += 1;
+            match byte {
+                b'"' => loop {
+                    match *line.get(i)? {
+                        b'"' => break,
+                        b'\\' => {
+                            let escaped = *line.get(i + 1)?;
+                            match (
+                                escaped,
+                                hex_digit(line.get(i + 2)),
+                                hex_digit(line.get(i + 3)),
+                            ) {
+                                (b'x', Some(high), Some(low)) => {
+                                    arg.push(high << 4 | low);
+                                    i += 2;
+                                }
+                                (b'n', ..) => arg.push(b'\n'),
+                                (b'r', ..) => arg.push(b'\r'),
+                                (b't', ..) => arg.push(b'\t'),
+                                (b'b', ..) => arg.push(0x08),
+                                (b'a', ..) => arg.push(0x07),
+                                (other, ..) => arg.push(other),
+                            }
+                            i += 1;
+                        }
+                        other => arg.push(other),
+                    }
+                    i += 1;
+                },
+                b'\'' => loop {
+                    match *line.get(i)? {
+                        b'\'' => break,
+                        b'\\' if line.get(i + 1) == Some(&b'\'') => {
+                            arg.push(b'\'');
+                            i += 1;
+                        }
+                        other => arg.push(other),
+                    }
+                    i += 1;
+                },
+                byte if byte.is_ascii_whitespace() => break,
+                byte => {
+                    arg.push(byte);
+                    continue;
+                }
+            }
+            // Only reached past a closing quote, which has to end the argument.
+            i += 1;
+            if line.get(i).is_some_and(|next| !next.is_ascii_whitespace()) {
+                return None;
+            }
+            break;
+        }
+        args.push(arg);
+    }
+}
+
+/// Largest bulk string accepted from the wire (Redis' default `proto-max-bulk-len`).
+pub const MAX_BULK_LEN: i64 = 512 * 1024 * 1024;
+/// Largest number of elements accepted in a single array.
+pub const MAX_ARRAY_LEN: i64 = 1024 * 1024;
+/// Deepest array nesting accepted, so hostile input can't exhaust the stack.
+pub const MAX_NESTING_DEPTH: usize = 128;
+/// The shortest possible encoded element, e.g. `+\r\n`.
+const MIN_ELEMENT_LEN: usize = 3;
+
 impl RespValue {
     pub fn parse(buffer: &[u8]) -> ParseResult {
+        Self::parse_nested(buffer, 0)
+    }
+
+    /// Parses a value found `depth` arrays deep.
+    fn parse_nested(buffer: &[u8], depth: usize) -> ParseResult {
         if buffer.is_empty() {
             return Err(ParserError::Incomplete);
         }
         match buffer[0] {
             b':' => Self::parse_integer(buffer),
             b'+' => Self::parse_simple_string(buffer),
+            b'-' => Self::parse_error(buffer),
             b'$' => Self::parse_bulk_string(buffer),
-            b'*' => Self::parse_array(buffer),
+            b'*' => Self::parse_array(buffer, depth),
+            b',' => Self::parse_double(buffer),
+            b'#' => Self::parse_boolean(buffer),
+            b'_' => Self::parse_nil(buffer),
+            b'(' => Self::parse_big_number(buffer),
+            b'=' => Self::parse_verbatim_string(buffer),
+            b'%' => Self::parse_map(buffer, depth),
+            b'~' => {
+                let (elements, consumed) = Self::parse_non_null_aggregate(buffer, depth, 1)?;
+                Ok((RespValue::Set(elements), consumed))
+            }
+            b'>' => {
+                let (elements, consumed) = Self::parse_non_null_aggregate(buffer, depth, 1)?;
+                Ok((RespValue::Push(elements), consumed))
+            }
             _ => Err(ParserError::InvalidFormat("Unknown prefix".to_string())),
         }
     }
 
+    /// Parses a client request: a RESP frame, or an inline command such as
+    /// `SET foo bar\r\n` from telnet, returned as an array of bulk strings.
+    pub fn parse_request(buffer: &[u8]) -> ParseResult {
+        match buffer.first() {
+            Some(b':' | b'+' | b'-' | b'$' | b'*' | b',' | b'#' | b'_' | b'(' | b'=' | b'%')
+            | Some(b'~' | b'>')
+            | None => Self::parse(buffer),
+            Some(_) => Self::parse_inline(buffer),
+        }
+    }
+
+    fn parse_inline(buffer: &[u8]) -> ParseResult {
+        let Some(pos) = buffer.iter().position(|&b| b == b'\n') else {
+            return Err(ParserError::Incomplete);
+        };
+        let line = buffer[..pos].strip_suffix(b"\r").unwrap_or(&buffer[..pos]);
+        let Some(args) = split_inline_args(line) else {
+            return Err(ParserError::InvalidFormat(
+                "Protocol error: unbalanced quotes in request".to_string(),
+            ));
+        };
+        let args = args.into_iter().map(RespValue::BulkString).collect();
+        Ok((RespValue::Array(args), pos + 1))
+    }
+
+    /// Parses every complete request at the start of `buffer`, returning them with
+    /// the total bytes consumed and why parsing stopped short of the end, if it
+    /// did: an incomplete or invalid frame, which is left in place.
+    pub fn parse_many(buffer: &[u8]) -> (Vec<RespValue>, usize, Option<ParserError>) {
+        let mut frames = Vec::new();
+        let mut consumed = 0;
+        while consumed < buffer.len() {
+            match Self::parse_request(&buffer[consumed..]) {
+                Ok((frame, len)) => {
+                    frames.push(frame);
+                    consumed += len;
+                }
+                Err(e) => return (frames, consumed, Some(e)),
+            }
+        }
+        (frames, consumed, None)
+    }
+
+    /// Serializes the value into its wire form. RESP3-only variants are written
+    /// as RESP3, so only send them to clients that negotiated it.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_into(&mut out);
+        out
+    }
+
+    /// Appends the wire form to `out`, for batching several replies into one write.
+    pub fn encode_into(&self, out: &mut Vec<u8>) {
+        match self {
+            RespValue::SimpleString(s) => {
+                out.push(b'+');
+                out.extend_from_slice(s.as_bytes());
+                out.extend_from_slice(b"\r\n");
+            }
+            RespValue::Error(message) => {
+                out.push(b'-');
+                out.extend_from_slice(message.as_bytes());
+                out.extend_from_slice(b"\r\n");
+            }
+            RespValue::Integer(n) => out.extend_from_slice(format!(":{}\r\n", n).as_bytes()),
+            RespValue::BulkString(data) => {
+                out.extend_from_slice(format!("${}\r\n", data.len()).as_bytes());
+                out.extend_from_slice(data);
+                out.extend_from_slice(b"\r\n");
+            }
+            RespValue::Array(elements) => {
+                out.extend_from_slice(format!("*{}\r\n", elements.len()).as_bytes());
+                for element in elements {
+                    element.encode_into(out);
+                }
+            }
+            RespValue::Null => out.extend_from_slice(b"$-1\r\n"),
+            RespValue::NullArray => out.extend_from_slice(b"*-1\r\n"),
+            RespValue::Double(value) => {
+                let text = if value.is_nan() {
+                    "nan".to_string()
+                } else {
+                    value.to_string()
+                };
+                out.extend_from_slice(format!(",{}\r\n", text).as_bytes());
+            }
+            RespValue::Boolean(value) => {
+                out.extend_from_slice(if *value { b"#t\r\n" } else { b"#f\r\n" })
+            }
+            RespValue::Nil => out.extend_from_slice(b"_\r\n"),
+            RespValue::BigNumber(digits) => {
+                out.extend_from_slice(format!("({}\r\n", digits).as_bytes())
+            }
+            RespValue::VerbatimString { format, data } => {
+                out.extend_from_slice(format!("={}\r\n{}:", data.len() + 4, format).as_bytes());
+                out.extend_from_slice(data);
+                out.extend_from_slice(b"\r\n");
+            }
+            RespValue::Map(pairs) => {
+                out.extend_from_slice(format!("%{}\r\n", pairs.len()).as_bytes());
+                for (key, value) in pairs {
+                    key.encode_into(out);
+                    value.encode_into(out);
+                }
+            }
+            RespValue::Set(elements) | RespValue::Push(elements) => {
+                let prefix = if matches!(self, RespValue::Set(_)) {
+                    '~'
+                } else {
+                    '>'
+                };
+                out.extend_from_slice(format!("{}{}\r\n", prefix, elements.len()).as_bytes());
+                for element in elements {
+                    element.encode_into(out);
+                }
+            }
+        }
+    }
+
     fn parse_line(buffer: &[u8]) -> Result<(&[u8], usize), ParserError> {
-        if let Some(pos) = buffer.windows(2).position(|window| window == b"\r\n") {
-            let line = &buffer[1..pos];
-            let consumed = pos + 2;
-            Ok((line, consumed))
-        } else {
-            Err(ParserError::Incomplete)
+        // Scanning for single bytes is much faster than comparing pairs.
+        let mut from = 0;
+        while let Some(offset) = buffer[from..].iter().position(|&byte| byte == b'\r') {
+            let pos = from + offset;
+            if buffer.get(pos + 1) == Some(&b'\n') {
+                return Ok((&buffer[1..pos], pos + 2));
+            }
+            from = pos + 1;
+        }
+        Err(ParserError::Incomplete)
+    }
+
+    /// Parses a signed decimal the way Redis does: no leading `+`, no whitespace.
+    fn parse_decimal(line: &[u8]) -> Result<i64, ParserError> {
+        if line.first() == Some(&b'+') {
+            return Err(ParserError::InvalidFormat(
+                "Unexpected '+' in integer".to_string(),
+            ));
         }
+        let s = std::str::from_utf8(line).map_err(|e| ParserError::InvalidFormat(e.to_string()))?;
+        s.parse::<i64>()
+            .map_err(|e| ParserError::InvalidFormat(e.to_string()))
     }
 
     fn parse_integer(buffer: &[u8]) -> ParseResult {
         let (line, consumed) = Self::parse_line(buffer)?;
-        let s = std::str::from_utf8(line).map_err(|e| ParserError::InvalidFormat(e.to_string()))?;
-        let val = s
-            .parse::<i64>()
-            .map_err(|e| ParserError::InvalidFormat(e.to_string()))?;
+        let val = Self::parse_decimal(line)?;
         Ok((RespValue::Integer(val), consumed))
     }
 
@@ -55,18 +318,79 @@ impl RespValue {
         Ok((RespValue::SimpleString(s), consumed))
     }
 
-    fn parse_bulk_string(buffer: &[u8]) -> ParseResult {
-        let (len_bytes, header_consumed) = Self::parse_line(buffer)?;
-        let len_str = std::str::from_utf8(len_bytes)
+    fn parse_error(buffer: &[u8]) -> ParseResult {
+        let (line, consumed) = Self::parse_line(buffer)?;
+        let s = String::from_utf8(line.to_vec())
             .map_err(|e| ParserError::InvalidFormat(e.to_string()))?;
+        Ok((RespValue::Error(s), consumed))
+    }
 
-        let len = len_str
-            .parse::<isize>()
-            .map_err(|e| ParserError::InvalidFormat(e.to_string()))?;
+    fn parse_double(buffer: &[u8]) -> ParseResult {
+        let (line, consumed) = Self::parse_line(buffer)?;
+        let value = std::str::from_utf8(line)
+            .ok()
+            .and_then(|s| s.parse::<f64>().ok())
+            .ok_or_else(|| ParserError::InvalidFormat("invalid double".to_string()))?;
+        Ok((RespValue::Double(value), consumed))
+    }
+
+    fn parse_boolean(buffer: &[u8]) -> ParseResult {
+        let (line, consumed) = Self::parse_line(buffer)?;
+        match line {
+            b"t" => Ok((RespValue::Boolean(true), consumed)),
+            b"f" => Ok((RespValue::Boolean(false), consumed)),
+            _ => Err(ParserError::InvalidFormat("invalid boolean".to_string())),
+        }
+    }
+
+    fn parse_nil(buffer: &[u8]) -> ParseResult {
+        let (line, consumed) = Self::parse_line(buffer)?;
+        if !line.is_empty() {
+            return Err(ParserError::InvalidFormat("invalid null".to_string()));
+        }
+        Ok((RespValue::Nil, consumed))
+    }
+
+    fn parse_big_number(buffer: &[u8]) -> ParseResult {
+        let (line, consumed) = Self::parse_line(buffer)?;
+        let digits = line.strip_prefix(b"-").unwrap_or(line);
+        if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+            return Err(ParserError::InvalidFormat("invalid big number".to_string()));
+        }
+        // Only ASCII digits and a sign are left, so this can't fail.
+        let text = String::from_utf8(line.to_vec()).unwrap();
+        Ok((RespValue::BigNumber(text), consumed))
+    }
+
+    /// Same framing as a bulk string, with a `fmt:` prefix inside the data.
+    fn parse_verbatim_string(buffer: &[u8]) -> ParseResult {
+        let (RespValue::BulkString(mut data), consumed) = Self::parse_bulk_string(buffer)? else {
+            return Err(ParserError::InvalidFormat(
+                "invalid verbatim string".to_string(),
+            ));
+        };
+        if data.len() < 4 || data[3] != b':' || !data[..3].is_ascii() {
+            return Err(ParserError::InvalidFormat(
+                "invalid verbatim string".to_string(),
+            ));
+        }
+        let format = String::from_utf8(data[..3].to_vec()).unwrap();
+        data.drain(..4);
+        Ok((RespValue::VerbatimString { format, data }, consumed))
+    }
+
+    fn parse_bulk_string(buffer: &[u8]) -> ParseResult {
+        let (len_bytes, header_consumed) = Self::parse_line(buffer)?;
+        let len = Self::parse_decimal(len_bytes)?;
 
         if len == -1 {
             return Ok((RespValue::Null, header_consumed));
         }
+        if !(0..=MAX_BULK_LEN).contains(&len) {
+            return Err(ParserError::InvalidFormat(
+                "invalid bulk length".to_string(),
+            ));
+        }
 
         let len = len as usize;
         let total_len = header_consumed + len + 2; // +CRLF
@@ -84,35 +408,82 @@ impl RespValue {
         Ok((RespValue::BulkString(data), total_len))
     }
 
-    fn parse_array(buffer: &[u8]) -> ParseResult {
-        let (len_bytes, mut consumed) = Self::parse_line(buffer)?;
-        let len_str = std::str::from_utf8(len_bytes)
-            .map_err(|e| ParserError::InvalidFormat(e.to_string()))?;
+    /// Never reserve more elements than the bytes already buffered could encode,
+    /// so a large declared length only grows the vector as elements actually arrive.
+    fn array_capacity(declared_len: usize, remaining: usize) -> usize {
+        declared_len.min(remaining / MIN_ELEMENT_LEN)
+    }
 
-        let len = len_str
-            .parse::<isize>()
-            .map_err(|e| ParserError::InvalidFormat(e.to_string()))?;
+    fn parse_array(buffer: &[u8], depth: usize) -> ParseResult {
+        match Self::parse_aggregate(buffer, depth, 1)? {
+            (Some(elements), consumed) => Ok((RespValue::Array(elements), consumed)),
+            (None, consumed) => Ok((RespValue::NullArray, consumed)),
+        }
+    }
+
+    fn parse_map(buffer: &[u8], depth: usize) -> ParseResult {
+        let (elements, consumed) = Self::parse_non_null_aggregate(buffer, depth, 2)?;
+        let mut elements = elements.into_iter();
+        let mut pairs = Vec::with_capacity(elements.len() / 2);
+        while let (Some(key), Some(value)) = (elements.next(), elements.next()) {
+            pairs.push((key, value));
+        }
+        Ok((RespValue::Map(pairs), consumed))
+    }
+
+    /// Maps, sets and pushes have no null form; RESP3 uses `_` instead.
+    fn parse_non_null_aggregate(
+        buffer: &[u8],
+        depth: usize,
+        per_entry: usize,
+    ) -> Result<(Vec<RespValue>, usize), ParserError> {
+        match Self::parse_aggregate(buffer, depth, per_entry)? {
+            (Some(elements), consumed) => Ok((elements, consumed)),
+            (None, _) => Err(ParserError::InvalidFormat(
+                "invalid multibulk length".to_string(),
+            )),
+        }
+    }
+
+    /// Parses the header and elements shared by arrays, maps, sets and pushes,
+    /// with `None` for a length of -1. The declared length is multiplied by
+    /// `per_entry`, two for a map's pairs.
+    fn parse_aggregate(
+        buffer: &[u8],
+        depth: usize,
+        per_entry: usize,
+    ) -> Result<(Option<Vec<RespValue>>, usize), ParserError> {
+        if depth >= MAX_NESTING_DEPTH {
+            return Err(ParserError::InvalidFormat("nesting too deep".to_string()));
+        }
+        let (len_bytes, mut consumed) = Self::parse_line(buffer)?;
+        let len = Self::parse_decimal(len_bytes)?;
 
         if len == -1 {
-            return Ok((RespValue::Null, consumed));
+            return Ok((None, consumed));
+        }
+        if !(0..=MAX_ARRAY_LEN).contains(&len) {
+            return Err(ParserError::InvalidFormat(
+                "invalid multibulk length".to_string(),
+            ));
         }
 
-        let len = len as usize;
-        let mut elements = Vec::with_capacity(len);
+        let len = len as usize * per_entry;
+        let mut elements = Vec::with_capacity(Self::array_capacity(len, buffer.len() - consumed));
 
         for _ in 0..len {
-            let (element, element_consumed) = Self::parse(&buffer[consumed..])?;
+            let (element, element_consumed) = Self::parse_nested(&buffer[consumed..], depth + 1)?;
             elements.push(element);
             consumed += element_consumed;
         }
 
-        Ok((RespValue::Array(elements), consumed))
+        Ok((Some(elements), consumed))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::parser::RespValue;
+    use crate::parser::{ParserError, RespValue, MAX_NESTING_DEPTH};
 
     #[test]
     fn test_integer_parsing() {
@@ -158,4 +529,286 @@ mod tests {
         assert_eq!(value, expected_array);
         assert_eq!(consumed, buffer.len());
     }
+
+    #[test]
+    fn test_malformed_inputs_fail_cleanly() {
+        let malformed: &[&[u8]] = &[
+            b"?\r\n",
+            b":+1\r\n",
+            b": 1\r\n",
+            b":1 \r\n",
+            b":\r\n",
+            b":abc\r\n",
+            b"$+3\r\nabc\r\n",
+            b"$abc\r\n",
+            b"$99999999999\r\n",
+            b"$-2\r\n",
+            b"$-5\r\n",
+            b"$3\r\nabcd\r\n",
+            b"*+1\r\n:1\r\n",
+            b"*2\r\n:1\r\nX\r\n",
+            b"*99999999999\r\n",
+            b"*2000000000\r\n",
+            b"*-3\r\n",
+            b"*-5\r\n",
+            b"*1\r\n*1\r\n$1\r\nab\r\n",
+        ];
+        for input in malformed {
+            assert!(
+                matches!(RespValue::parse(input), Err(ParserError::InvalidFormat(_))),
+                "expected format error for {:?}",
+                String::from_utf8_lossy(input)
+            );
+        }
+
+        let incomplete: &[&[u8]] = &[b"", b":12", b"$3\r\nab", b"*2\r\n:1\r\n", b"*1\r\n$"];
+        for input in incomplete {
+            assert_eq!(RespValue::parse(input), Err(ParserError::Incomplete));
+        }
+    }
+
+    #[test]
+    fn test_large_array_header_with_short_buffer() {
+        let buffer = format!("*{}\r\n:1\r\n:2\r\n", crate::parser::MAX_ARRAY_LEN);
+        assert_eq!(
+            RespValue::parse(buffer.as_bytes()),
+            Err(ParserError::Incomplete)
+        );
+        assert_eq!(RespValue::array_capacity(1024 * 1024, 8), 2);
+        assert_eq!(RespValue::array_capacity(2, 1024), 2);
+    }
+
+    #[test]
+    fn test_parse_many_leaves_partial_tail() {
+        let buffer = b"*1\r\n$4\r\nPING\r\n*2\r\n$4\r\nECHO\r\n$2\r\nhi\r\n:7\r\n*1\r\n$4\r\nPI";
+        let (frames, consumed, stopped_at) = RespValue::parse_many(buffer);
+        assert_eq!(stopped_at, Some(ParserError::Incomplete));
+        assert_eq!(
+            frames,
+            vec![
+                RespValue::Array(vec![RespValue::BulkString(b"PING".to_vec())]),
+                RespValue::Array(vec![
+                    RespValue::BulkString(b"ECHO".to_vec()),
+                    RespValue::BulkString(b"hi".to_vec()),
+                ]),
+                RespValue::Integer(7),
+            ]
+        );
+        assert_eq!(consumed, buffer.len() - b"*1\r\n$4\r\nPI".len());
+
+        let (frames, consumed, stopped_at) = RespValue::parse_many(b"");
+        assert!(frames.is_empty());
+        assert_eq!(consumed, 0);
+        assert_eq!(stopped_at, None);
+
+        let (frames, consumed, stopped_at) = RespValue::parse_many(b":1\r\n\"oops\r\n:2\r\n");
+        assert_eq!(frames, vec![RespValue::Integer(1)]);
+        assert_eq!(consumed, 4);
+        assert!(matches!(stopped_at, Some(ParserError::InvalidFormat(_))));
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let frames: &[&[u8]] = &[
+            b"+OK\r\n",
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+            b":-42\r\n",
+            b"$5\r\nhello\r\n",
+            b"$0\r\n\r\n",
+            b"$-1\r\n",
+            b"*-1\r\n",
+            b"*0\r\n",
+            b"*3\r\n$3\r\nSET\r\n*2\r\n:1\r\n$-1\r\n+x\r\n",
+        ];
+        for &frame in frames {
+            let (value, consumed) = RespValue::parse(frame).unwrap();
+            assert_eq!(consumed, frame.len());
+            assert_eq!(value.encode(), frame, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn test_null_kinds_stay_distinct() {
+        assert_eq!(RespValue::parse(b"$-1\r\n"), Ok((RespValue::Null, 5)));
+        assert_eq!(RespValue::parse(b"*-1\r\n"), Ok((RespValue::NullArray, 5)));
+        assert_eq!(
+            RespValue::parse(b"*0\r\n"),
+            Ok((RespValue::Array(vec![]), 4))
+        );
+
+        let nested: &[u8] = b"*3\r\n*-1\r\n$-1\r\n*0\r\n";
+        let (value, _) = RespValue::parse(nested).unwrap();
+        assert_eq!(
+            value,
+            RespValue::Array(vec![
+                RespValue::NullArray,
+                RespValue::Null,
+                RespValue::Array(vec![]),
+            ])
+        );
+        assert_eq!(value.encode(), nested);
+    }
+
+    #[test]
+    #[allow(clippy::approx_constant)] // 3.14 is the wire example, not an attempt at pi.
+    fn test_resp3_types() {
+        assert_eq!(
+            RespValue::parse(b",3.14\r\n"),
+            Ok((RespValue::Double(3.14), 7))
+        );
+        assert_eq!(
+            RespValue::parse(b"#t\r\n"),
+            Ok((RespValue::Boolean(true), 4))
+        );
+        let map = b"%2\r\n+first\r\n:1\r\n$6\r\nsecond\r\n#f\r\n";
+        assert_eq!(
+            RespValue::parse(map),
+            Ok((
+                RespValue::Map(vec![
+                    (
+                        RespValue::SimpleString("first".to_string()),
+                        RespValue::Integer(1)
+                    ),
+                    (
+                        RespValue::BulkString(b"second".to_vec()),
+                        RespValue::Boolean(false)
+                    ),
+                ]),
+                map.len()
+            ))
+        );
+
+        let frames: &[&[u8]] = &[
+            b",-inf\r\n",
+            b",nan\r\n",
+            b"_\r\n",
+            b"(-3492890328409238509324850943850943825024385\r\n",
+            b"=15\r\ntxt:Some string\r\n",
+            b"~2\r\n:1\r\n_\r\n",
+            b">3\r\n$7\r\nmessage\r\n$1\r\nc\r\n$1\r\nm\r\n",
+        ];
+        for &frame in frames {
+            let (value, consumed) = RespValue::parse(frame).unwrap();
+            assert_eq!(consumed, frame.len());
+            assert_eq!(value.encode(), frame, "{:?}", value);
+        }
+
+        let malformed: &[&[u8]] = &[
+            b",pi\r\n",
+            b"#x\r\n",
+            b"_0\r\n",
+            b"(12a\r\n",
+            b"(\r\n",
+            b"=3\r\ntxt\r\n",
+            b"%-1\r\n",
+            b"%1\r\n:1\r\nX\r\n",
+        ];
+        for input in malformed {
+            assert!(
+                matches!(RespValue::parse(input), Err(ParserError::InvalidFormat(_))),
+                "expected format error for {:?}",
+                String::from_utf8_lossy(input)
+            );
+        }
+        assert_eq!(
+            RespValue::parse(b"%1\r\n:1\r\n"),
+            Err(ParserError::Incomplete)
+        );
+    }
+
+    #[test]
+    fn test_error_parsing() {
+        let buffer = b"-ERR unknown command\r\n";
+        assert_eq!(
+            RespValue::parse(buffer),
+            Ok((
+                RespValue::Error("ERR unknown command".to_string()),
+                buffer.len()
+            ))
+        );
+        assert_eq!(
+            RespValue::parse(b"-ERR unknown"),
+            Err(ParserError::Incomplete)
+        );
+    }
+
+    #[test]
+    fn test_inline_commands() {
+        assert_eq!(
+            RespValue::parse_request(b"PING\r\n"),
+            Ok((
+                RespValue::Array(vec![RespValue::BulkString(b"PING".to_vec())]),
+                6
+            ))
+        );
+        assert_eq!(
+            RespValue::parse_request(b"SET  foo\tbar\r\n"),
+            Ok((
+                RespValue::Array(vec![
+                    RespValue::BulkString(b"SET".to_vec()),
+                    RespValue::BulkString(b"foo".to_vec()),
+                    RespValue::BulkString(b"bar".to_vec()),
+                ]),
+                14
+            ))
+        );
+        assert_eq!(
+            RespValue::parse_request(b"SET foo"),
+            Err(ParserError::Incomplete)
+        );
+        assert_eq!(
+            RespValue::parse_request(b"SET \"two words\" 'it\\'s' \"\\x41\\tb\\\"\"\r\n"),
+            Ok((
+                RespValue::Array(vec![
+                    RespValue::BulkString(b"SET".to_vec()),
+                    RespValue::BulkString(b"two words".to_vec()),
+                    RespValue::BulkString(b"it's".to_vec()),
+                    RespValue::BulkString(b"A\tb\"".to_vec()),
+                ]),
+                37
+            ))
+        );
+        assert_eq!(
+            RespValue::parse_request(b"ECHO \"\"\r\n"),
+            Ok((
+                RespValue::Array(vec![
+                    RespValue::BulkString(b"ECHO".to_vec()),
+                    RespValue::BulkString(Vec::new()),
+                ]),
+                9
+            ))
+        );
+        for unbalanced in [
+            &b"SET \"foo bar\r\n"[..],
+            b"SET 'foo\r\n",
+            b"SET \"foo\"bar\r\n",
+        ] {
+            assert_eq!(
+                RespValue::parse_request(unbalanced),
+                Err(ParserError::InvalidFormat(
+                    "Protocol error: unbalanced quotes in request".to_string()
+                )),
+                "{:?}",
+                String::from_utf8_lossy(unbalanced)
+            );
+        }
+        // Inline syntax is only for whole requests, never nested elements.
+        assert!(matches!(
+            RespValue::parse_request(b"*1\r\nPING\r\n"),
+            Err(ParserError::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_deep_nesting_is_rejected() {
+        let mut nested = b"*1\r\n".repeat(MAX_NESTING_DEPTH - 1);
+        nested.extend_from_slice(b":1\r\n");
+        assert!(RespValue::parse(&nested).is_ok());
+
+        let hostile = b"*1\r\n".repeat(1_000_000);
+        assert!(matches!(
+            RespValue::parse(&hostile),
+            Err(ParserError::InvalidFormat(_))
+        ));
+    }
 }
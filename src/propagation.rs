@@ -0,0 +1,267 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tokio::sync::broadcast;
+
+use crate::parser::RespValue;
+use crate::server::Server;
+
+/// How many writes a follower may fall behind before it loses the oldest.
+const FEED_CAPACITY: usize = 1024;
+
+/// One successful write, in the form a follower should run to repeat it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Write {
+    pub db_index: usize,
+    pub frame: RespValue,
+}
+
+/// The stream of effective writes, for whatever follows the dataset besides
+/// the AOF. While the AOF is on, writes are fed under its lock, so followers
+/// see them in the order the log has. Otherwise each is fed right after it
+/// ran, and two writes racing on one key may arrive in either order.
+#[derive(Debug)]
+pub struct Propagation {
+    feed: broadcast::Sender<Write>,
+    /// Receivers that stand in for a follower, which nothing is fed to.
+    placeholders: AtomicUsize,
+}
+
+impl Default for Propagation {
+    fn default() -> Self {
+        Propagation {
+            feed: broadcast::channel(FEED_CAPACITY).0,
+            placeholders: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Propagation {
+    // Only the tests follow the writes until replication does.
+    #[allow(dead_code)]
+    pub fn follow(&self) -> broadcast::Receiver<Write> {
+        self.feed.subscribe()
+    }
+
+    /// A receiver that doesn't count as following, so writes aren't copied
+    /// for it. It only notices the feed closing.
+    pub fn placeholder(&self) -> broadcast::Receiver<Write> {
+        self.placeholders.fetch_add(1, Ordering::Relaxed);
+        self.feed.subscribe()
+    }
+
+    /// Whether anything follows the writes, so they only get copied for someone.
+    pub fn is_followed(&self) -> bool {
+        self.feed.receiver_count() > self.placeholders.load(Ordering::Relaxed)
+    }
+
+    fn feed(&self, db_index: usize, frames: &[RespValue]) {
+        if !self.is_followed() {
+            return;
+        }
+        for frame in frames {
+            // Fails only when nobody is following anymore.
+            let _ = self.feed.send(Write {
+                db_index,
+                frame: frame.clone(),
+            });
+        }
+    }
+}
+
+/// Runs a write and, unless it fails, appends what `propagate` says repeats
+/// it to the AOF and feeds that to the followers. `propagate` gets the reply,
+/// and returns `None` for a write that changed nothing.
+pub fn run_propagated(
+    server: &Server,
+    db_index: usize,
+    execute: impl FnOnce() -> RespValue,
+    propagate: impl FnOnce(&RespValue) -> Option<Vec<RespValue>>,
+) -> RespValue {
+    let logging = server.aof.is_enabled();
+    if !logging && !server.propagation.is_followed() {
+        return execute();
+    }
+    let run = || {
+        let reply = execute();
+        let frames = match reply {
+            RespValue::Error(_) => None,
+            _ => propagate(&reply),
+        };
+        if let Some(frames) = &frames {
+            server.propagation.feed(db_index, frames);
+        }
+        (reply, frames)
+    };
+    if logging {
+        server.aof.run_logged(server.log_level, db_index, run)
+    } else {
+        run().0
+    }
+}
+
+/// Holds a placeholder receiver without doing anything, standing in for a
+/// replication link until there is one.
+pub async fn discard(mut writes: broadcast::Receiver<Write>) {
+    loop {
+        match writes.recv().await {
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::unix_millis_now;
+    use crate::session::Session;
+    use crate::test_support::command;
+
+    /// The arguments of every write fed so far.
+    fn fed(writes: &mut broadcast::Receiver<Write>) -> Vec<Vec<Vec<u8>>> {
+        let mut fed = Vec::new();
+        while let Ok(write) = writes.try_recv() {
+            let RespValue::Array(args) = write.frame else {
+                panic!("expected a command, got {:?}", write.frame);
+            };
+            fed.push(
+                args.into_iter()
+                    .map(|arg| match arg {
+                        RespValue::BulkString(arg) => arg,
+                        other => panic!("unexpected argument {:?}", other),
+                    })
+                    .collect(),
+            );
+        }
+        fed
+    }
+
+    /// Parses a propagated deadline and checks it is `millis` from now.
+    fn assert_deadline(arg: &[u8], millis: u64) {
+        let deadline: u64 = std::str::from_utf8(arg).unwrap().parse().unwrap();
+        let expected = unix_millis_now() + millis;
+        assert!(
+            deadline.abs_diff(expected) < 1000,
+            "{deadline} vs {expected}"
+        );
+    }
+
+    #[test]
+    fn test_followers_see_only_successful_writes() {
+        let server = Server::default();
+        let mut writes = server.propagation.follow();
+        let mut session = Session::default();
+        session.execute(command(&[b"SET", b"key", b"v"]), &server);
+        session.execute(command(&[b"GET", b"key"]), &server);
+        session.execute(command(&[b"LPUSH", b"key", b"a"]), &server);
+        session.execute(command(&[b"SELECT", b"3"]), &server);
+        session.execute(command(&[b"SET", b"key", b"w"]), &server);
+
+        assert_eq!(
+            writes.try_recv().unwrap(),
+            Write {
+                db_index: 0,
+                frame: command(&[b"SET", b"key", b"v"]),
+            }
+        );
+        assert_eq!(
+            writes.try_recv().unwrap(),
+            Write {
+                db_index: 3,
+                frame: command(&[b"SET", b"key", b"w"]),
+            }
+        );
+        assert!(writes.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_writes_that_change_nothing_are_not_fed() {
+        let server = Server::default();
+        let mut writes = server.propagation.follow();
+        let mut session = Session::default();
+        for args in [
+            &[&b"SADD"[..], b"set", b"a"][..],
+            &[b"SADD", b"set", b"a"],
+            &[b"DEL", b"missing"],
+            &[b"SET", b"set", b"v", b"NX"],
+            &[b"SET", b"missing", b"v", b"XX", b"GET"],
+            &[b"EXPIRE", b"missing", b"10"],
+            &[b"LPOP", b"missing"],
+            &[b"GETEX", b"set"],
+        ] {
+            session.execute(command(args), &server);
+        }
+        assert_eq!(
+            fed(&mut writes),
+            vec![vec![b"SADD".to_vec(), b"set".to_vec(), b"a".to_vec()]]
+        );
+    }
+
+    #[test]
+    fn test_relative_expiries_propagate_as_deadlines() {
+        let server = Server::default();
+        let mut writes = server.propagation.follow();
+        let mut session = Session::default();
+        session.execute(
+            command(&[b"SET", b"key", b"v", b"PX", b"5000", b"NX"]),
+            &server,
+        );
+        session.execute(command(&[b"EXPIRE", b"key", b"100"]), &server);
+        session.execute(command(&[b"PEXPIRE", b"key", b"7000"]), &server);
+        session.execute(command(&[b"GETEX", b"key", b"EX", b"20"]), &server);
+        let dump = session.execute(command(&[b"DUMP", b"key"]), &server);
+        let [RespValue::BulkString(payload)] = dump.as_slice() else {
+            panic!("expected a payload, got {:?}", dump);
+        };
+        session.execute(
+            command(&[b"RESTORE", b"copy", b"3000", payload, b"REPLACE"]),
+            &server,
+        );
+        session.execute(command(&[b"EXPIRE", b"copy", b"-1"]), &server);
+        session.execute(
+            command(&[b"SET", b"at", b"v", b"PXAT", b"99999999999999"]),
+            &server,
+        );
+
+        let fed = fed(&mut writes);
+        assert_eq!(fed.len(), 7);
+        assert_eq!(fed[0][..4], [&b"SET"[..], b"key", b"v", b"PXAT"]);
+        assert_eq!(fed[0].len(), 5);
+        assert_deadline(&fed[0][4], 5000);
+        for (write, millis) in [(&fed[1], 100_000), (&fed[2], 7000), (&fed[3], 20_000)] {
+            assert_eq!(write[..2], [&b"PEXPIREAT"[..], b"key"]);
+            assert_deadline(&write[2], millis);
+        }
+        assert_eq!(fed[4][..2], [&b"RESTORE"[..], b"copy"]);
+        assert_deadline(&fed[4][2], 3000);
+        assert_eq!(fed[4][3..], [&payload[..], b"ABSTTL", b"REPLACE"]);
+        assert_eq!(fed[5], [&b"DEL"[..], b"copy"]);
+        assert_eq!(
+            fed[6],
+            [&b"SET"[..], b"at", b"v", b"PXAT", b"99999999999999"]
+        );
+    }
+
+    #[test]
+    fn test_blocking_pop_is_fed_once_as_a_pop() {
+        let server = Server::default();
+        let mut writes = server.propagation.follow();
+        let mut session = Session::default();
+        session.execute(command(&[b"RPUSH", b"list", b"a", b"b"]), &server);
+        session.execute(command(&[b"BLPOP", b"list", b"0"]), &server);
+        session.execute(command(&[b"BRPOP", b"list", b"0"]), &server);
+        let fed = fed(&mut writes);
+        assert_eq!(fed.len(), 3);
+        assert_eq!(fed[1], [&b"LPOP"[..], b"list"]);
+        assert_eq!(fed[2], [&b"RPOP"[..], b"list"]);
+    }
+
+    #[test]
+    fn test_placeholder_does_not_count_as_following() {
+        let server = Server::default();
+        let _placeholder = server.propagation.placeholder();
+        assert!(!server.propagation.is_followed());
+        let _follower = server.propagation.follow();
+        assert!(server.propagation.is_followed());
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::db::unpoisoned;
+use crate::parser::RespValue;
+
+pub type SubscriberId = u64;
+
+type Subscribers = HashMap<SubscriberId, UnboundedSender<RespValue>>;
+
+/// Channel name to the connections subscribed to it. A channel is dropped as
+/// soon as its last subscriber leaves, so the map only holds live channels.
+#[derive(Debug, Default)]
+pub struct PubSub {
+    channels: Mutex<HashMap<Vec<u8>, Subscribers>>,
+    next_id: AtomicU64,
+}
+
+impl PubSub {
+    pub fn next_subscriber_id(&self) -> SubscriberId {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn subscribe(&self, channel: &[u8], id: SubscriberId, sender: &UnboundedSender<RespValue>) {
+        unpoisoned(self.channels.lock())
+            .entry(channel.to_vec())
+            .or_default()
+            .insert(id, sender.clone());
+    }
+
+    pub fn unsubscribe(&self, channel: &[u8], id: SubscriberId) {
+        let mut channels = unpoisoned(self.channels.lock());
+        if let Some(subscribers) = channels.get_mut(channel) {
+            subscribers.remove(&id);
+            if subscribers.is_empty() {
+                channels.remove(channel);
+            }
+        }
+    }
+
+    /// Takes `id` off every channel in `channels` under one lock, so once this
+    /// returns nothing more can be published to it.
+    pub fn unsubscribe_all<'a>(
+        &self,
+        channels: impl IntoIterator<Item = &'a Vec<u8>>,
+        id: SubscriberId,
+    ) {
+        let mut registry = unpoisoned(self.channels.lock());
+        for channel in channels {
+            if let Some(subscribers) = registry.get_mut(channel) {
+                subscribers.remove(&id);
+                if subscribers.is_empty() {
+                    registry.remove(channel);
+                }
+            }
+        }
+    }
+
+    /// Queues `payload` for every subscriber of `channel` and returns how many got it.
+    pub fn publish(&self, channel: &[u8], payload: &[u8]) -> usize {
+        let channels = unpoisoned(self.channels.lock());
+        let Some(subscribers) = channels.get(channel) else {
+            return 0;
+        };
+
+        let message = RespValue::Array(vec![
+            RespValue::BulkString(b"message".to_vec()),
+            RespValue::BulkString(channel.to_vec()),
+            RespValue::BulkString(payload.to_vec()),
+        ]);
+        subscribers
+            .values()
+            .filter(|sender| sender.send(message.clone()).is_ok())
+            .count()
+    }
+
+    /// Every channel with at least one subscriber.
+    pub fn channels(&self) -> Vec<Vec<u8>> {
+        unpoisoned(self.channels.lock()).keys().cloned().collect()
+    }
+
+    #[cfg(test)]
+    pub fn channel_count(&self) -> usize {
+        unpoisoned(self.channels.lock()).len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_last_unsubscribe_drops_channel() {
+        let pubsub = PubSub::default();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let (first, second) = (pubsub.next_subscriber_id(), pubsub.next_subscriber_id());
+
+        pubsub.subscribe(b"news", first, &sender);
+        pubsub.subscribe(b"news", second, &sender);
+        assert_eq!(pubsub.publish(b"news", b"hi"), 2);
+        assert_eq!(pubsub.publish(b"other", b"hi"), 0);
+
+        pubsub.unsubscribe(b"news", first);
+        assert_eq!(pubsub.channel_count(), 1);
+        pubsub.unsubscribe(b"news", second);
+        assert_eq!(pubsub.channel_count(), 0);
+
+        assert_eq!(
+            receiver.try_recv().unwrap().encode(),
+            b"*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$2\r\nhi\r\n"
+        );
+    }
+}
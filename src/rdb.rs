@@ -0,0 +1,548 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::db::{
+    instant_from_unix_millis, unix_millis_from_instant, unix_millis_now, DbData, DbValue,
+};
+use crate::logging::{log, Level};
+use crate::server::Server;
+use crate::sorted_set::SortedSet;
+
+/// Snapshot file layout, all integers little endian:
+///
+/// ```text
+/// MAGIC VERSION
+/// (SELECT_DB u32 | type u8 expiry key value)* EOF
+/// ```
+///
+/// `expiry` is a `0` byte, or `1` followed by the deadline as u64 unix millis.
+/// Byte strings are a u32 length and the bytes; collections are a u32 count and
+/// that many byte strings (hashes alternate field and value, sorted sets member
+/// and score, the score written as text).
+const MAGIC: &[u8] = b"RUSTRDB";
+const VERSION: u8 = 1;
+
+const SELECT_DB: u8 = 0xFE;
+const EOF: u8 = 0xFF;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_HASH: u8 = 2;
+const TYPE_SET: u8 = 3;
+const TYPE_SORTED_SET: u8 = 4;
+
+/// What INFO persistence reports about SAVE and BGSAVE.
+#[derive(Debug, Default)]
+pub struct SnapshotStatus {
+    pub bgsave_in_progress: AtomicBool,
+    pub last_bgsave_failed: AtomicBool,
+    /// Unix seconds of the last successful save, 0 if there hasn't been one.
+    pub last_save_time: AtomicU64,
+}
+
+impl SnapshotStatus {
+    fn record_save(&self) {
+        self.last_save_time
+            .store(unix_millis_now() / 1000, Ordering::Relaxed);
+    }
+}
+
+/// The live keys of every database, in index order.
+pub type Snapshot = Vec<Vec<(Vec<u8>, DbValue)>>;
+
+/// Copies every live key. Each database is copied under its full lock, so it is
+/// captured at a single point in time.
+pub fn snapshot(server: &Server) -> Snapshot {
+    let now = server.clock.now();
+    (0..server.database_count())
+        .map(|index| {
+            server
+                .db(index)
+                .lock_all()
+                .iter()
+                .filter(|(_, db_value)| !db_value.is_expired_at(now))
+                .map(|(key, db_value)| (key.clone(), db_value.clone()))
+                .collect()
+        })
+        .collect()
+}
+
+/// Writes the dataset to `path` in the foreground.
+pub fn save(server: &Server, path: &Path) -> io::Result<()> {
+    write_file(path, &encode(&snapshot(server)))?;
+    server.snapshot.record_save();
+    Ok(())
+}
+
+/// Copies the dataset now and writes it to `path` on another thread. Returns
+/// false without doing anything if a background save is already running.
+pub fn background_save(server: &Server, path: &Path) -> bool {
+    let status = server.snapshot.clone();
+    if status.bgsave_in_progress.swap(true, Ordering::AcqRel) {
+        return false;
+    }
+
+    let snapshot = snapshot(server);
+    let path = path.to_path_buf();
+    let log_level = server.log_level;
+    std::thread::spawn(move || {
+        let result = write_file(&path, &encode(&snapshot));
+        if let Err(e) = &result {
+            log!(
+                log_level,
+                Level::Warning,
+                "Background save to {} failed: {e}",
+                path.display()
+            );
+        } else {
+            status.record_save();
+        }
+        status
+            .last_bgsave_failed
+            .store(result.is_err(), Ordering::Relaxed);
+        status.bgsave_in_progress.store(false, Ordering::Release);
+    });
+    true
+}
+
+/// Replaces the contents of every database with the snapshot at `path`.
+/// Returns how many keys were loaded, or `Ok(0)` if there is no file.
+pub fn load(server: &Server, path: &Path) -> io::Result<usize> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let snapshot = decode(&bytes, server.database_count())?;
+
+    let mut loaded = 0;
+    for (index, entries) in snapshot.into_iter().enumerate() {
+        let db = server.db(index);
+        let mut shards = db.lock_all();
+        shards.shards_mut().for_each(|keyspace| keyspace.clear());
+        for (key, db_value) in entries {
+            shards.shard(&key).insert(key, db_value);
+            loaded += 1;
+        }
+    }
+    Ok(loaded)
+}
+
+/// Writes to a temporary file first and renames it over `path`, so a failed
+/// save leaves the previous snapshot in place.
+fn write_file(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut temp_name = path.as_os_str().to_owned();
+    temp_name.push(".tmp");
+    let temp_path = Path::new(&temp_name);
+
+    let written = fs::write(temp_path, bytes)
+        .and_then(|_| fs::File::open(temp_path)?.sync_all())
+        .and_then(|_| fs::rename(temp_path, path));
+    if written.is_err() {
+        let _ = fs::remove_file(temp_path);
+    }
+    written
+}
+
+pub fn encode(snapshot: &Snapshot) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+
+    for (index, entries) in snapshot.iter().enumerate() {
+        if entries.is_empty() {
+            continue;
+        }
+        out.push(SELECT_DB);
+        out.extend_from_slice(&(index as u32).to_le_bytes());
+
+        for (key, db_value) in entries {
+            out.push(type_tag(&db_value.data));
+            match db_value.expires_at {
+                Some(expires_at) => {
+                    out.push(1);
+                    out.extend_from_slice(&unix_millis_from_instant(expires_at).to_le_bytes());
+                }
+                None => out.push(0),
+            }
+            put_bytes(&mut out, key);
+            put_value(&mut out, &db_value.data);
+        }
+    }
+
+    out.push(EOF);
+    out
+}
+
+fn type_tag(data: &DbData) -> u8 {
+    match data {
+        DbData::String(_) => TYPE_STRING,
+        DbData::List(_) => TYPE_LIST,
+        DbData::Hash(_) => TYPE_HASH,
+        DbData::Set(_) => TYPE_SET,
+        DbData::SortedSet(_) => TYPE_SORTED_SET,
+    }
+}
+
+fn put_value(out: &mut Vec<u8>, data: &DbData) {
+    match data {
+        DbData::String(value) => put_bytes(out, value),
+        DbData::List(list) => put_all(out, list.len(), list.iter()),
+        DbData::Set(set) => put_all(out, set.len(), set.iter()),
+        DbData::Hash(hash) => put_all(
+            out,
+            hash.len() * 2,
+            hash.iter().flat_map(|(field, value)| [field, value]),
+        ),
+        DbData::SortedSet(set) => {
+            let scores: Vec<Vec<u8>> = set
+                .iter()
+                .map(|(_, score)| score.to_string().into_bytes())
+                .collect();
+            put_all(
+                out,
+                set.len() * 2,
+                set.iter()
+                    .zip(&scores)
+                    .flat_map(|((member, _), score)| [member, score]),
+            );
+        }
+    }
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn put_all<'a>(out: &mut Vec<u8>, count: usize, items: impl Iterator<Item = &'a Vec<u8>>) {
+    out.extend_from_slice(&(count as u32).to_le_bytes());
+    for item in items {
+        put_bytes(out, item);
+    }
+}
+
+/// Keys that expired while the server was down are dropped. A snapshot naming
+/// a database at or past `databases` is refused before anything is made for it.
+pub fn decode(bytes: &[u8], databases: usize) -> io::Result<Snapshot> {
+    let mut reader = Reader { bytes, position: 0 };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(invalid_data("not a snapshot file"));
+    }
+    let version = reader.u8()?;
+    if version != VERSION {
+        return Err(invalid_data(&format!(
+            "unsupported snapshot version {version}"
+        )));
+    }
+
+    let mut snapshot: Snapshot = Vec::new();
+    let now = unix_millis_now();
+    loop {
+        let type_tag = match reader.u8()? {
+            EOF => break,
+            SELECT_DB => {
+                let index = reader.u32()? as usize;
+                if index < snapshot.len() {
+                    return Err(invalid_data("databases out of order"));
+                }
+                if index >= databases {
+                    return Err(invalid_data("snapshot has more databases than configured"));
+                }
+                snapshot.resize_with(index + 1, Vec::new);
+                continue;
+            }
+            type_tag => type_tag,
+        };
+
+        let expires_at_millis = match reader.u8()? {
+            0 => None,
+            1 => Some(reader.u64()?),
+            _ => return Err(invalid_data("bad expiry flag")),
+        };
+        let key = reader.bytes()?;
+        let data = reader.value(type_tag)?;
+
+        let Some(entries) = snapshot.last_mut() else {
+            return Err(invalid_data("key before any database"));
+        };
+        if expires_at_millis.is_some_and(|millis| millis <= now) {
+            continue;
+        }
+        let mut db_value = DbValue::new(data);
+        db_value.expires_at = expires_at_millis.and_then(instant_from_unix_millis);
+        entries.push((key, db_value));
+    }
+
+    if reader.position != bytes.len() {
+        return Err(invalid_data("trailing bytes after end of snapshot"));
+    }
+    Ok(snapshot)
+}
+
+/// Bytes the value takes in a snapshot, which DEBUG OBJECT reports.
+pub fn serialized_len(data: &DbData) -> usize {
+    let mut out = Vec::new();
+    put_value(&mut out, data);
+    out.len()
+}
+
+/// DUMP's payload: the value as a snapshot stores it, then a trailer of the
+/// format version as u16 and a u64 FNV-1a checksum of everything before the
+/// checksum, both little endian. Keys and expiries aren't part of it.
+pub fn dump_value(data: &DbData) -> Vec<u8> {
+    let mut out = vec![type_tag(data)];
+    put_value(&mut out, data);
+    out.extend_from_slice(&(VERSION as u16).to_le_bytes());
+    let checksum = fnv1a(&out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+/// The value in a DUMP payload, or `None` if it was made by a newer version
+/// or doesn't match its checksum.
+pub fn restore_value(payload: &[u8]) -> Option<DbData> {
+    let (body, checksum) = payload.split_at_checked(payload.len().checked_sub(8)?)?;
+    if fnv1a(body).to_le_bytes() != checksum {
+        return None;
+    }
+    let (value, version) = body.split_at_checked(body.len().checked_sub(2)?)?;
+    // Every version so far stores values alike, so only a newer one is refused.
+    if u16::from_le_bytes(version.try_into().ok()?) > VERSION as u16 {
+        return None;
+    }
+
+    let mut reader = Reader {
+        bytes: value,
+        position: 0,
+    };
+    let type_tag = reader.u8().ok()?;
+    let data = reader.value(type_tag).ok()?;
+    (reader.position == value.len()).then_some(data)
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| invalid_data("snapshot is truncated"))?;
+        let taken = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> io::Result<Vec<u8>> {
+        let len = self.u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn all(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        let count = self.u32()?;
+        (0..count).map(|_| self.bytes()).collect()
+    }
+
+    /// Items that alternate between two kinds, so there must be an even number.
+    fn pairs(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        let items = self.all()?;
+        if items.len() % 2 != 0 {
+            return Err(invalid_data("odd number of items in a pair list"));
+        }
+        Ok(items)
+    }
+
+    fn value(&mut self, type_tag: u8) -> io::Result<DbData> {
+        Ok(match type_tag {
+            TYPE_STRING => DbData::String(self.bytes()?),
+            TYPE_LIST => DbData::List(self.all()?.into_iter().collect::<VecDeque<_>>()),
+            TYPE_SET => DbData::Set(self.all()?.into_iter().collect::<HashSet<_>>()),
+            TYPE_HASH => {
+                let mut items = self.pairs()?.into_iter();
+                let mut hash = HashMap::new();
+                while let (Some(field), Some(value)) = (items.next(), items.next()) {
+                    hash.insert(field, value);
+                }
+                DbData::Hash(hash)
+            }
+            TYPE_SORTED_SET => {
+                let mut items = self.pairs()?.into_iter();
+                let mut set = SortedSet::default();
+                while let (Some(member), Some(score)) = (items.next(), items.next()) {
+                    let score = str::from_utf8(&score)
+                        .ok()
+                        .and_then(|score| score.parse::<f64>().ok())
+                        .filter(|score| !score.is_nan())
+                        .ok_or_else(|| invalid_data("bad sorted set score"))?;
+                    set.insert(member, score);
+                }
+                DbData::SortedSet(set)
+            }
+            _ => return Err(invalid_data(&format!("unknown value type {type_tag}"))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::run;
+    use crate::test_support::temp_path;
+
+    #[test]
+    fn test_save_and_load_round_trip() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"plain", b"1"]);
+        run(&server, &[b"SET", b"expiring", b"2", b"EX", b"100"]);
+        run(&server, &[b"RPUSH", b"list", b"a", b"b"]);
+        run(&server, &[b"HSET", b"hash", b"field", b"value"]);
+        run(&server, &[b"SADD", b"set", b"member"]);
+        run(
+            &server,
+            &[b"ZADD", b"zset", b"0.1", b"low", b"inf", b"high"],
+        );
+        run(&server, &[b"SWAPDB", b"0", b"3"]);
+        run(&server, &[b"SET", b"in-db0", b"x"]);
+
+        let path = temp_path("round-trip", "rdb");
+        save(&server, &path).unwrap();
+        let restored = Server::default();
+        assert_eq!(load(&restored, &path).unwrap(), 7);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(run(&restored, &[b"GET", b"in-db0"]), b"$1\r\nx\r\n");
+        restored.swap_databases(0, 3);
+        assert_eq!(run(&restored, &[b"GET", b"plain"]), b"$1\r\n1\r\n");
+        assert_eq!(run(&restored, &[b"TTL", b"plain"]), b":-1\r\n");
+        assert_eq!(run(&restored, &[b"TTL", b"expiring"]), b":100\r\n");
+        assert_eq!(
+            run(&restored, &[b"LRANGE", b"list", b"0", b"-1"]),
+            b"*2\r\n$1\r\na\r\n$1\r\nb\r\n"
+        );
+        assert_eq!(
+            run(&restored, &[b"HGET", b"hash", b"field"]),
+            b"$5\r\nvalue\r\n"
+        );
+        assert_eq!(
+            run(&restored, &[b"SISMEMBER", b"set", b"member"]),
+            b":1\r\n"
+        );
+        assert_eq!(
+            run(&restored, &[b"ZRANGE", b"zset", b"0", b"-1", b"WITHSCORES"]),
+            b"*4\r\n$3\r\nlow\r\n$3\r\n0.1\r\n$4\r\nhigh\r\n$3\r\ninf\r\n"
+        );
+    }
+
+    #[test]
+    fn test_failed_save_keeps_previous_snapshot() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"key", b"old"]);
+        let path = temp_path("failed-save", "rdb");
+        save(&server, &path).unwrap();
+
+        // A directory in the way of the temporary file makes the next save fail.
+        let mut temp_name = path.as_os_str().to_owned();
+        temp_name.push(".tmp");
+        fs::create_dir(&temp_name).unwrap();
+        run(&server, &[b"SET", b"key", b"new"]);
+        assert!(save(&server, &path).is_err());
+        fs::remove_dir(&temp_name).unwrap();
+
+        let restored = Server::default();
+        load(&restored, &path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(run(&restored, &[b"GET", b"key"]), b"$3\r\nold\r\n");
+    }
+
+    #[test]
+    fn test_decode_rejects_truncated_file() {
+        let server = Server::default();
+        run(&server, &[b"SET", b"key", b"value"]);
+        let bytes = encode(&snapshot(&server));
+        assert!(decode(&bytes, 16).is_ok());
+        assert!(decode(&bytes[..bytes.len() - 3], 16).is_err());
+        assert!(decode(b"NOTRDB", 16).is_err());
+    }
+
+    #[test]
+    fn test_decode_rejects_database_past_the_count() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.push(SELECT_DB);
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.push(EOF);
+        assert!(decode(&bytes, 16).is_err());
+    }
+
+    #[test]
+    fn test_decode_rejects_hash_with_a_field_but_no_value() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.push(SELECT_DB);
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&[TYPE_HASH, 0]);
+        put_bytes(&mut bytes, b"hash");
+        put_all(
+            &mut bytes,
+            3,
+            [b"field".to_vec(), b"value".to_vec(), b"lost".to_vec()].iter(),
+        );
+        bytes.push(EOF);
+        let error = decode(&bytes, 16).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    /// `payload` with its version replaced and the checksum made to match.
+    fn with_version(payload: &[u8], version: u16) -> Vec<u8> {
+        let mut body = payload[..payload.len() - 10].to_vec();
+        body.extend_from_slice(&version.to_le_bytes());
+        let checksum = fnv1a(&body);
+        body.extend_from_slice(&checksum.to_le_bytes());
+        body
+    }
+
+    #[test]
+    fn test_restore_value_accepts_older_versions_only() {
+        let data = DbData::String(b"value".to_vec());
+        let payload = dump_value(&data);
+        assert_eq!(restore_value(&payload), Some(data.clone()));
+        assert_eq!(
+            restore_value(&with_version(&payload, VERSION as u16 - 1)),
+            Some(data)
+        );
+        assert_eq!(
+            restore_value(&with_version(&payload, VERSION as u16 + 1)),
+            None
+        );
+    }
+}
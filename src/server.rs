@@ -0,0 +1,145 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+use crate::aof::AppendOnly;
+use crate::blocking::Wakeup;
+use crate::clients::Clients;
+use crate::clock::{Clock, SystemClock};
+use crate::config::Config;
+use crate::db::{unpoisoned, Database, DbData};
+use crate::logging::Level;
+use crate::monitor::Monitor;
+use crate::propagation::Propagation;
+use crate::pubsub::PubSub;
+use crate::rdb::SnapshotStatus;
+use crate::stats::Stats;
+
+/// Version reported by INFO and HELLO. Clients gate features on it, so it
+/// names the Redis release whose behaviour we follow rather than this crate's.
+pub const REDIS_VERSION: &str = "7.2.0";
+
+/// State shared by every connection.
+#[derive(Debug)]
+pub struct Server {
+    /// The numbered databases. Each slot holds an `Arc` so SWAPDB can exchange
+    /// two of them without copying, and a command keeps the one it resolved.
+    pub databases: RwLock<Vec<Arc<Database>>>,
+    pub pubsub: PubSub,
+    pub monitor: Monitor,
+    pub clients: Clients,
+    pub stats: Stats,
+    pub config: RwLock<Config>,
+    /// Answer the DEBUG subcommands `debug-compat-noops` lists with `+OK` (`--debug-compat`).
+    pub debug_compat: bool,
+    /// Set with `--loglevel` or `--verbose`.
+    pub log_level: Level,
+    /// Shared by every command, exclusive for EXEC, so a transaction never
+    /// interleaves with commands from other connections.
+    pub exec_lock: RwLock<()>,
+    pub started_at: Instant,
+    /// Whether the background task reaps expired keys; see DEBUG SET-ACTIVE-EXPIRE.
+    pub active_expire: AtomicBool,
+    /// Shared with the thread running a BGSAVE.
+    pub snapshot: Arc<SnapshotStatus>,
+    /// Shared with the thread running a BGREWRITEAOF.
+    pub aof: Arc<AppendOnly>,
+    pub propagation: Propagation,
+    /// What everything deciding expiry takes the time from; the databases
+    /// share it.
+    pub clock: Arc<dyn Clock>,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Server::new(Config::default())
+    }
+}
+
+impl Server {
+    /// A server with no data yet, configured by `config`.
+    pub fn new(config: Config) -> Self {
+        Server::with_clock(config, Arc::new(SystemClock))
+    }
+
+    /// Like `new`, but keys expire by `clock` instead of the system's.
+    pub fn with_clock(config: Config, clock: Arc<dyn Clock>) -> Self {
+        let databases = (0..config.databases)
+            .map(|_| Arc::new(Database::new(clock.clone())))
+            .collect();
+        Server {
+            databases: RwLock::new(databases),
+            pubsub: PubSub::default(),
+            monitor: Monitor::default(),
+            clients: Clients::default(),
+            stats: Stats::default(),
+            config: RwLock::new(config),
+            debug_compat: false,
+            log_level: Level::default(),
+            exec_lock: RwLock::default(),
+            started_at: Instant::now(),
+            active_expire: AtomicBool::new(true),
+            snapshot: Arc::default(),
+            aof: Arc::default(),
+            propagation: Propagation::default(),
+            clock,
+        }
+    }
+
+    /// Resolves database `index`, which must be below `database_count`. The
+    /// result stays valid, and keeps naming the same keyspace, even if SWAPDB
+    /// moves it to another index while the caller is using it.
+    pub fn db(&self, index: usize) -> Arc<Database> {
+        unpoisoned(self.databases.read())[index].clone()
+    }
+
+    pub fn database_count(&self) -> usize {
+        unpoisoned(self.databases.read()).len()
+    }
+
+    /// Queues `wakeup` on `keys` of `db`, unless a SWAPDB has moved `db` away
+    /// from `index` since it was resolved. Checked under the lock SWAPDB takes
+    /// to move waiters, so no swap can come between the check and the queueing.
+    pub fn register_waiter(
+        &self,
+        index: usize,
+        db: &Database,
+        keys: &[Vec<u8>],
+        wakeup: &Arc<Wakeup>,
+    ) -> bool {
+        let databases = unpoisoned(self.databases.read());
+        if !std::ptr::eq(&*databases[index], db) {
+            return false;
+        }
+        db.waiters.register(keys, wakeup);
+        true
+    }
+
+    /// Exchanges two databases for every connection at once. Commands that
+    /// already resolved either one finish against it undisturbed.
+    ///
+    /// Connections blocked in BLPOP or BRPOP stay on their index, so their
+    /// waiters move over to the database now there, and are woken if it
+    /// already has elements for them.
+    pub fn swap_databases(&self, first: usize, second: usize) {
+        let (first, second) = {
+            let mut databases = unpoisoned(self.databases.write());
+            databases.swap(first, second);
+            databases[first].waiters.swap(&databases[second].waiters);
+            (databases[first].clone(), databases[second].clone())
+        };
+        for db in [first, second] {
+            for key in db.waiters.keys() {
+                let len = db.read_live(&key, |db_value| {
+                    match db_value.map(|db_value| &db_value.data) {
+                        Some(DbData::List(list)) => list.len(),
+                        _ => 0,
+                    }
+                });
+                db.waiters.wake(&key, len);
+            }
+        }
+    }
+}
+
+pub type SharedServer = Arc<Server>;
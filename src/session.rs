@@ -0,0 +1,1262 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+
+use crate::blocking::Wakeup;
+use crate::commands::{
+    command_name, error_reply, is_known_command, is_write_command, may_grow_dataset, ok,
+    BlockingPop, ClientSubcommand, Command, ErrorCode,
+};
+use crate::db::unpoisoned;
+use crate::eviction;
+use crate::parser::RespValue;
+use crate::pubsub::SubscriberId;
+use crate::server::{Server, REDIS_VERSION};
+
+/// State that belongs to one connection rather than to the server.
+pub struct Session {
+    /// Commands queued since MULTI, or `None` outside a transaction.
+    queued: Option<Vec<(Command, Effects)>>,
+    /// Set when a command failed to parse while queuing; EXEC then refuses to run.
+    aborted: bool,
+    /// Created on the first SUBSCRIBE and kept for the rest of the connection.
+    subscriber: Option<Subscriber>,
+    /// RESP version negotiated with HELLO; 2 until the client asks otherwise.
+    protocol: i64,
+    /// Database chosen with SELECT.
+    db_index: usize,
+    /// Whether AUTH succeeded. Only consulted while `requirepass` is set.
+    authenticated: bool,
+    /// Set with CLIENT SETNAME.
+    name: Option<Vec<u8>>,
+    /// The connection's entry in `Server::clients`, or 0 for a session that
+    /// isn't serving a connection, such as an AOF replay.
+    client_id: u64,
+    /// Time DEBUG SLEEP asked for, which the connection waits out before sending
+    /// the reply. Waiting there rather than here keeps other connections running.
+    pause: Option<Duration>,
+    /// A BLPOP or BRPOP waiting for a push. The connection reads no further
+    /// requests until `unblocked` answers it.
+    blocked: Option<Blocked>,
+    /// The command feed, once MONITOR ran. The connection's own commands are
+    /// left out of it.
+    monitor: Option<broadcast::Receiver<String>>,
+    /// Replaying the AOF, whose commands aren't a client's: they skip AUTH,
+    /// and neither reach monitors nor count toward the command stats.
+    replaying: bool,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            queued: None,
+            aborted: false,
+            subscriber: None,
+            protocol: 2,
+            db_index: 0,
+            authenticated: false,
+            name: None,
+            client_id: 0,
+            pause: None,
+            blocked: None,
+            monitor: None,
+            replaying: false,
+        }
+    }
+}
+
+struct Blocked {
+    pop: BlockingPop,
+    wakeup: Arc<Wakeup>,
+    woken: oneshot::Receiver<Vec<u8>>,
+    /// `None` waits forever.
+    deadline: Option<Instant>,
+}
+
+/// What running a command involves besides executing it, worked out from its
+/// frame before parsing consumes it.
+struct Effects {
+    /// The frame to propagate, for a write while the AOF or anything else
+    /// follows the writes.
+    propagated: Option<RespValue>,
+    /// Whether it may grow the dataset, so has to fit under maxmemory first.
+    grows: bool,
+}
+
+struct Subscriber {
+    id: SubscriberId,
+    sender: UnboundedSender<RespValue>,
+    receiver: UnboundedReceiver<RespValue>,
+    channels: HashSet<Vec<u8>>,
+}
+
+impl Session {
+    /// A session for the connection registered as `client_id`.
+    pub fn new(client_id: u64) -> Self {
+        Session {
+            client_id,
+            ..Session::default()
+        }
+    }
+
+    /// A session replaying the AOF against a server that may require a password.
+    pub fn replaying() -> Self {
+        Session {
+            authenticated: true,
+            replaying: true,
+            ..Session::default()
+        }
+    }
+
+    /// Runs one request. Most commands answer with a single frame, but
+    /// SUBSCRIBE and UNSUBSCRIBE confirm every channel separately.
+    ///
+    /// Every request naming a known command counts toward INFO's command
+    /// stats, including ones refused or queued by MULTI.
+    pub fn execute(&mut self, frame: RespValue, server: &Server) -> Vec<RespValue> {
+        if self.replaying {
+            return self.dispatch(frame, server);
+        }
+        let name = command_name(&frame)
+            .filter(|name| is_known_command(name))
+            .map(<[u8]>::to_vec);
+        let started = Instant::now();
+        let replies = self.dispatch(frame, server);
+        if let Some(name) = name {
+            server.stats.record_command(&name, started.elapsed());
+        }
+        replies
+    }
+
+    fn dispatch(&mut self, frame: RespValue, server: &Server) -> Vec<RespValue> {
+        if !self.authenticated && !may_run_unauthenticated(&frame) && password_required(server) {
+            // Like any rejected command, this spoils an open transaction.
+            if self.queued.is_some() {
+                self.aborted = true;
+            }
+            return vec![error_reply(ErrorCode::NoAuth, "Authentication required.")];
+        }
+
+        if self.is_subscribed() {
+            if let Some(reply) = subscribed_reply(&frame) {
+                return vec![reply];
+            }
+        }
+
+        // Copied only while someone watches, to feed once the command parses.
+        let monitored = (self.monitor.is_none() && !self.replaying && server.monitor.is_watched())
+            .then(|| frame.clone());
+
+        let effects = Effects {
+            // Only a write needs its original form, and only if someone follows the writes.
+            propagated: ((server.aof.is_enabled() || server.propagation.is_followed())
+                && is_write_command(&frame))
+            .then(|| frame.clone()),
+            grows: may_grow_dataset(&frame),
+        };
+
+        let command = Command::from_resp(frame);
+        if let (Some(frame), Ok(_)) = (&monitored, &command) {
+            self.feed_monitors(frame, server);
+        }
+        match command {
+            Ok(Command::Subscribe(_) | Command::Unsubscribe(_)) if self.queued.is_some() => {
+                vec![error_reply(
+                    ErrorCode::Err,
+                    "SUBSCRIBE and UNSUBSCRIBE inside MULTI is not allowed",
+                )]
+            }
+            Ok(Command::Monitor) if self.queued.is_none() => {
+                self.monitor = Some(server.monitor.watch());
+                vec![ok()]
+            }
+            Ok(Command::Monitor) => vec![error_reply(
+                ErrorCode::Err,
+                "MONITOR inside MULTI is not allowed",
+            )],
+            Ok(Command::Reset) => {
+                self.reset(server);
+                vec![RespValue::SimpleString("RESET".to_string())]
+            }
+            Ok(Command::Hello(protover)) => vec![self.hello(protover)],
+            Ok(Command::Auth { username, password }) => {
+                vec![self.auth(username, password, server)]
+            }
+            Ok(Command::Subscribe(channels)) => {
+                let confirmations = self.subscribe(channels, server);
+                self.pushed(confirmations)
+            }
+            Ok(Command::Unsubscribe(channels)) => {
+                let confirmations = self.unsubscribe(channels, server);
+                self.pushed(confirmations)
+            }
+            // Inside MULTI they never block, so `run` handles them there.
+            Ok(Command::BlockingPop(pop)) if self.queued.is_none() => {
+                let deadline = pop
+                    .timeout
+                    .and_then(|timeout| Instant::now().checked_add(timeout));
+                self.pop_or_block(pop, deadline, server)
+                    .into_iter()
+                    .collect()
+            }
+            command => vec![self.execute_one(command, effects, server)],
+        }
+    }
+
+    /// How long to wait before replying to the last request, if DEBUG SLEEP ran.
+    pub fn take_pause(&mut self) -> Option<Duration> {
+        self.pause.take()
+    }
+
+    /// Subscription confirmations as this connection's protocol delivers them:
+    /// RESP3 pushes them, so they can't be mistaken for replies.
+    fn pushed(&self, confirmations: Vec<RespValue>) -> Vec<RespValue> {
+        confirmations
+            .into_iter()
+            .map(|confirmation| as_push(self.protocol, confirmation))
+            .collect()
+    }
+
+    /// Whether the connection is in RESP2 subscriber mode, where it may only
+    /// manage its subscriptions. RESP3 pushes messages alongside replies, so
+    /// a RESP3 subscriber can still run anything.
+    fn is_subscribed(&self) -> bool {
+        self.protocol == 2
+            && self
+                .subscriber
+                .as_ref()
+                .is_some_and(|subscriber| !subscriber.channels.is_empty())
+    }
+
+    pub fn is_blocked(&self) -> bool {
+        self.blocked.is_some()
+    }
+
+    /// Whether the `timeout` config may disconnect this client when it goes
+    /// quiet. As in Redis, subscribers and monitors are only waiting for
+    /// pushes, so they are left alone.
+    pub fn may_idle_out(&self) -> bool {
+        self.subscriber.is_none() && self.monitor.is_none()
+    }
+
+    /// Waits for a blocked BLPOP or BRPOP to be answered: `Some` once it popped
+    /// or timed out, `None` if another client took the element it was woken for
+    /// and it is waiting again. Never resolves while nothing is blocked, and
+    /// can be cancelled without losing the wakeup.
+    pub async fn unblocked(&mut self, server: &Server) -> Option<RespValue> {
+        let Some(blocked) = &mut self.blocked else {
+            return std::future::pending().await;
+        };
+        let woken = match blocked.deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, &mut blocked.woken)
+                .await
+                .is_ok(),
+            None => (&mut blocked.woken).await.is_ok(),
+        };
+
+        let mut blocked = self.blocked.take()?;
+        server
+            .db(self.db_index)
+            .waiters
+            .unregister(&blocked.pop.keys, &blocked.wakeup);
+        // A wakeup that raced the timeout still owes the client its element.
+        if !woken && blocked.woken.try_recv().is_err() {
+            return Some(RespValue::NullArray);
+        }
+        self.pop_or_block(blocked.pop, blocked.deadline, server)
+    }
+
+    /// Answers a BLPOP or BRPOP if one of its lists has an element, and
+    /// otherwise blocks the session on all of its keys.
+    fn pop_or_block(
+        &mut self,
+        pop: BlockingPop,
+        deadline: Option<Instant>,
+        server: &Server,
+    ) -> Option<RespValue> {
+        let (wakeup, woken) = Wakeup::new();
+        let reply = loop {
+            let _shared = unpoisoned(server.exec_lock.read());
+            let mut registered = false;
+            let reply = pop.try_pop(server, self.db_index, |db| {
+                registered = server.register_waiter(self.db_index, db, &pop.keys, &wakeup);
+            });
+            // A SWAPDB moved the database away before the waiter got queued on it.
+            if reply.is_some() || registered {
+                break reply;
+            }
+        };
+        if reply.is_none() {
+            self.blocked = Some(Blocked {
+                pop,
+                wakeup,
+                woken,
+                deadline,
+            });
+        }
+        reply
+    }
+
+    /// Waits for the next published message or, for a MONITOR connection, the
+    /// next monitored command. Never resolves for a connection that does
+    /// neither.
+    pub async fn next_message(&mut self) -> RespValue {
+        let protocol = self.protocol;
+        let published = async {
+            match &mut self.subscriber {
+                // The subscriber holds a sender itself, so the channel never closes.
+                Some(subscriber) => match subscriber.receiver.recv().await {
+                    Some(message) => as_push(protocol, message),
+                    None => std::future::pending().await,
+                },
+                None => std::future::pending().await,
+            }
+        };
+        let monitored = async {
+            let Some(monitor) = &mut self.monitor else {
+                return std::future::pending().await;
+            };
+            loop {
+                match monitor.recv().await {
+                    Ok(line) => return RespValue::SimpleString(line),
+                    // Lines it fell too far behind on are gone; carry on with the rest.
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return std::future::pending().await,
+                }
+            }
+        };
+        tokio::select! {
+            message = published => message,
+            line = monitored => line,
+        }
+    }
+
+    /// Passing the password on to every monitor would leak it, so AUTH is left out.
+    fn feed_monitors(&self, frame: &RespValue, server: &Server) {
+        let RespValue::Array(elements) = frame else {
+            return;
+        };
+        if command_name(frame).is_none_or(|name| name.eq_ignore_ascii_case(b"AUTH")) {
+            return;
+        }
+        // Sessions without a connection, such as an AOF replay, aren't monitored.
+        let Some(addr) = server.clients.addr(self.client_id) else {
+            return;
+        };
+        let args: Vec<&[u8]> = elements
+            .iter()
+            .filter_map(|element| match element {
+                RespValue::BulkString(arg) => Some(arg.as_slice()),
+                _ => None,
+            })
+            .collect();
+        server.monitor.feed(self.db_index, addr, &args);
+    }
+
+    /// Releases everything the connection registered with the server. Messages
+    /// already queued for it are dropped with the receiver, and since it has
+    /// left every channel first, no new ones can arrive.
+    pub fn close(&mut self, server: &Server) {
+        if let Some(mut blocked) = self.blocked.take() {
+            let db = server.db(self.db_index);
+            db.waiters.unregister(&blocked.pop.keys, &blocked.wakeup);
+            // Pass on a wakeup nobody will act on, or its element would sit in
+            // the list while other connections wait for it.
+            if let Ok(key) = blocked.woken.try_recv() {
+                db.waiters.wake(&key, 1);
+            }
+        }
+        if let Some(subscriber) = self.subscriber.take() {
+            server
+                .pubsub
+                .unsubscribe_all(&subscriber.channels, subscriber.id);
+        }
+    }
+
+    /// Puts the connection back the way it was when it connected, except for
+    /// its client id.
+    fn reset(&mut self, server: &Server) {
+        self.close(server);
+        if self.name.is_some() {
+            server.clients.set_name(self.client_id, None);
+        }
+        *self = Session::new(self.client_id);
+    }
+
+    fn execute_one(
+        &mut self,
+        command: Result<Command, crate::commands::CommandError>,
+        effects: Effects,
+        server: &Server,
+    ) -> RespValue {
+        let Some(queued) = &mut self.queued else {
+            return match command {
+                Ok(Command::Multi) => {
+                    self.queued = Some(Vec::new());
+                    self.aborted = false;
+                    ok()
+                }
+                Ok(Command::Exec) => error_reply(ErrorCode::Err, "EXEC without MULTI"),
+                Ok(Command::Discard) => error_reply(ErrorCode::Err, "DISCARD without MULTI"),
+                Ok(command) => {
+                    let _shared = unpoisoned(server.exec_lock.read());
+                    self.run(command, effects, server)
+                }
+                Err(e) => e.to_resp(),
+            };
+        };
+
+        match command {
+            Ok(Command::Multi) => error_reply(ErrorCode::Err, "MULTI calls can not be nested"),
+            Ok(Command::Exec) => {
+                let queued = self.queued.take().unwrap_or_default();
+                if self.aborted {
+                    return error_reply(
+                        ErrorCode::ExecAbort,
+                        "Transaction discarded because of previous errors.",
+                    );
+                }
+                let _exclusive = unpoisoned(server.exec_lock.write());
+                RespValue::Array(
+                    queued
+                        .into_iter()
+                        .map(|(command, effects)| self.run(command, effects, server))
+                        .collect(),
+                )
+            }
+            Ok(Command::Discard) => {
+                self.queued = None;
+                ok()
+            }
+            Ok(command) => {
+                queued.push((command, effects));
+                RespValue::SimpleString("QUEUED".to_string())
+            }
+            Err(e) => {
+                self.aborted = true;
+                e.to_resp()
+            }
+        }
+    }
+
+    /// Runs a command outside of transaction bookkeeping. SELECT is handled here
+    /// so a queued one switches databases for the commands after it.
+    fn run(&mut self, command: Command, effects: Effects, server: &Server) -> RespValue {
+        match command {
+            Command::Select(index) => match usize::try_from(index) {
+                Ok(index) if index < server.database_count() => {
+                    self.db_index = index;
+                    ok()
+                }
+                _ => error_reply(ErrorCode::Err, "DB index is out of range"),
+            },
+            Command::Client(subcommand) => self.client(subcommand, server),
+            Command::BlockingPop(pop) => pop
+                .try_pop(server, self.db_index, |_| {})
+                .unwrap_or(RespValue::NullArray),
+            Command::Sleep(duration) => {
+                *self.pause.get_or_insert(Duration::ZERO) += duration;
+                ok()
+            }
+            command => {
+                if effects.grows && !eviction::make_room(server) {
+                    return error_reply(
+                        ErrorCode::Oom,
+                        "command not allowed when used memory > 'maxmemory'.",
+                    );
+                }
+                match effects.propagated {
+                    Some(frame) => command.execute_propagated(server, self.db_index, frame),
+                    None => command.execute(server, &server.db(self.db_index)),
+                }
+            }
+        }
+    }
+
+    fn auth(&mut self, username: Option<Vec<u8>>, password: Vec<u8>, server: &Server) -> RespValue {
+        let config = unpoisoned(server.config.read());
+        if config.requirepass.is_empty() {
+            return error_reply(
+                ErrorCode::Err,
+                "AUTH <password> called without any password configured for the default user. \
+                 Are you sure your configuration is correct?",
+            );
+        }
+        let user_matches = username.is_none_or(|username| username == b"default");
+        if !user_matches || password != config.requirepass.as_bytes() {
+            return error_reply(
+                ErrorCode::WrongPass,
+                "invalid username-password pair or user is disabled.",
+            );
+        }
+        self.authenticated = true;
+        ok()
+    }
+
+    fn client(&mut self, subcommand: ClientSubcommand, server: &Server) -> RespValue {
+        match subcommand {
+            ClientSubcommand::SetName(name) => {
+                // Anything else would break up the line CLIENT LIST shows it on.
+                if name.iter().any(|byte| !(b'!'..=b'~').contains(byte)) {
+                    return error_reply(
+                        ErrorCode::Err,
+                        "Client names cannot contain spaces, newlines or special characters.",
+                    );
+                }
+                // Setting an empty name clears it.
+                self.name = (!name.is_empty()).then_some(name);
+                server.clients.set_name(self.client_id, self.name.clone());
+                ok()
+            }
+            ClientSubcommand::GetName => {
+                RespValue::BulkString(self.name.clone().unwrap_or_default())
+            }
+            ClientSubcommand::Id => RespValue::Integer(self.client_id as i64),
+            ClientSubcommand::List => RespValue::BulkString(server.clients.list().into_bytes()),
+            ClientSubcommand::Kill(kill) => {
+                let killed = server.clients.kill(|id, addr| {
+                    kill.id.is_none_or(|target| target == id)
+                        && kill
+                            .addr
+                            .as_ref()
+                            .is_none_or(|target| *target == addr.to_string())
+                        && !(kill.skip_me && id == self.client_id)
+                });
+                match (kill.legacy, killed) {
+                    (false, killed) => RespValue::Integer(killed as i64),
+                    (true, 0) => error_reply(ErrorCode::Err, "No such client"),
+                    (true, _) => ok(),
+                }
+            }
+            ClientSubcommand::NoEvict => ok(),
+        }
+    }
+
+    fn hello(&mut self, protover: Option<i64>) -> RespValue {
+        match protover {
+            Some(version @ (2 | 3)) => self.protocol = version,
+            Some(_) => return error_reply(ErrorCode::NoProto, "unsupported protocol version"),
+            None => {}
+        }
+
+        let field = |name: &str| RespValue::BulkString(name.as_bytes().to_vec());
+        let pairs = vec![
+            (field("server"), field("redis")),
+            (field("version"), field(REDIS_VERSION)),
+            (field("proto"), RespValue::Integer(self.protocol)),
+            (field("mode"), field("standalone")),
+            (field("role"), field("master")),
+            (field("modules"), RespValue::Array(Vec::new())),
+        ];
+        // RESP2 has no maps, so there the pairs go out as one flat array.
+        if self.protocol == 3 {
+            RespValue::Map(pairs)
+        } else {
+            RespValue::Array(
+                pairs
+                    .into_iter()
+                    .flat_map(|(key, value)| [key, value])
+                    .collect(),
+            )
+        }
+    }
+
+    fn subscribe(&mut self, channels: Vec<Vec<u8>>, server: &Server) -> Vec<RespValue> {
+        let subscriber = self.subscriber.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            Subscriber {
+                id: server.pubsub.next_subscriber_id(),
+                sender,
+                receiver,
+                channels: HashSet::new(),
+            }
+        });
+
+        channels
+            .into_iter()
+            .map(|channel| {
+                if !subscriber.channels.contains(&channel) {
+                    server
+                        .pubsub
+                        .subscribe(&channel, subscriber.id, &subscriber.sender);
+                    subscriber.channels.insert(channel.clone());
+                }
+                subscription_reply("subscribe", Some(channel), subscriber.channels.len())
+            })
+            .collect()
+    }
+
+    /// With no channels, unsubscribes from all of them.
+    fn unsubscribe(&mut self, channels: Vec<Vec<u8>>, server: &Server) -> Vec<RespValue> {
+        let Some(subscriber) = &mut self.subscriber else {
+            if channels.is_empty() {
+                return vec![subscription_reply("unsubscribe", None, 0)];
+            }
+            return channels
+                .into_iter()
+                .map(|channel| subscription_reply("unsubscribe", Some(channel), 0))
+                .collect();
+        };
+
+        let channels = if channels.is_empty() {
+            subscriber.channels.iter().cloned().collect()
+        } else {
+            channels
+        };
+        if channels.is_empty() {
+            return vec![subscription_reply("unsubscribe", None, 0)];
+        }
+
+        channels
+            .into_iter()
+            .map(|channel| {
+                if subscriber.channels.remove(&channel) {
+                    server.pubsub.unsubscribe(&channel, subscriber.id);
+                }
+                subscription_reply("unsubscribe", Some(channel), subscriber.channels.len())
+            })
+            .collect()
+    }
+}
+
+fn password_required(server: &Server) -> bool {
+    !unpoisoned(server.config.read()).requirepass.is_empty()
+}
+
+/// AUTH itself, plus the commands a client may need before it has
+/// authenticated: HELLO to pick a protocol and PING to check the connection.
+fn may_run_unauthenticated(frame: &RespValue) -> bool {
+    command_name(frame).is_some_and(|name| {
+        [&b"AUTH"[..], b"HELLO", b"PING"]
+            .iter()
+            .any(|allowed| name.eq_ignore_ascii_case(allowed))
+    })
+}
+
+/// The reply to a request made in subscriber mode, or `None` if it runs as
+/// usual. PING answers in the shape of a message, so the client can tell it
+/// apart from the published ones, and other commands are refused. Unknown
+/// commands and bad PING arities fall through to their usual errors.
+fn subscribed_reply(frame: &RespValue) -> Option<RespValue> {
+    let name = command_name(frame)?;
+    if name.eq_ignore_ascii_case(b"PING") {
+        let RespValue::Array(elements) = frame else {
+            return None;
+        };
+        let msg = match elements.as_slice() {
+            [_] => Vec::new(),
+            [_, RespValue::BulkString(msg)] => msg.clone(),
+            _ => return None,
+        };
+        return Some(RespValue::Array(vec![
+            RespValue::BulkString(b"pong".to_vec()),
+            RespValue::BulkString(msg),
+        ]));
+    }
+    let allowed = [&b"SUBSCRIBE"[..], b"UNSUBSCRIBE", b"QUIT", b"RESET"]
+        .iter()
+        .any(|allowed| name.eq_ignore_ascii_case(allowed));
+    if allowed || !is_known_command(name) {
+        return None;
+    }
+    Some(error_reply(
+        ErrorCode::Err,
+        &format!(
+            "Can't execute '{}': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+            String::from_utf8_lossy(name).to_lowercase()
+        ),
+    ))
+}
+
+/// `frame`, an array, as a push for a RESP3 connection.
+fn as_push(protocol: i64, frame: RespValue) -> RespValue {
+    match frame {
+        RespValue::Array(elements) if protocol == 3 => RespValue::Push(elements),
+        frame => frame,
+    }
+}
+
+/// `[kind, channel, count]`, where count is how many channels remain subscribed.
+fn subscription_reply(kind: &str, channel: Option<Vec<u8>>, count: usize) -> RespValue {
+    RespValue::Array(vec![
+        RespValue::BulkString(kind.as_bytes().to_vec()),
+        channel.map_or(RespValue::Null, RespValue::BulkString),
+        RespValue::Integer(count as i64),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::Stats;
+    use crate::test_support::send;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_error_aborts_transaction() {
+        let server = Server::default();
+        let mut session = Session::default();
+
+        send(&mut session, &server, &[b"MULTI"]);
+        send(&mut session, &server, &[b"SET", b"a", b"1"]);
+        assert_eq!(
+            send(&mut session, &server, &[b"GET"]),
+            b"-ERR wrong number of arguments\r\n"
+        );
+        assert_eq!(
+            send(&mut session, &server, &[b"EXEC"]),
+            b"-EXECABORT Transaction discarded because of previous errors.\r\n"
+        );
+        assert!(server.db(0).lock(b"a").is_empty());
+
+        // The failed transaction is over, so the next EXEC has no MULTI to end.
+        assert_eq!(
+            send(&mut session, &server, &[b"EXEC"]),
+            b"-ERR EXEC without MULTI\r\n"
+        );
+    }
+
+    #[test]
+    fn test_exec_reports_failures_of_queued_commands() {
+        let server = Server::default();
+        let mut session = Session::default();
+
+        send(&mut session, &server, &[b"MULTI"]);
+        send(&mut session, &server, &[b"SET", b"a", b"text"]);
+        send(&mut session, &server, &[b"INCR", b"a"]);
+        send(&mut session, &server, &[b"SET", b"b", b"1"]);
+        // Failing as it runs doesn't stop the commands after it.
+        assert_eq!(
+            send(&mut session, &server, &[b"EXEC"]),
+            b"*3\r\n+OK\r\n-ERR value is not an integer or out of range\r\n+OK\r\n"
+        );
+        assert_eq!(
+            send(&mut session, &server, &[b"GET", b"a"]),
+            b"$4\r\ntext\r\n"
+        );
+        assert_eq!(send(&mut session, &server, &[b"GET", b"b"]), b"$1\r\n1\r\n");
+    }
+
+    #[test]
+    fn test_discard_drops_queue() {
+        let server = Server::default();
+        let mut session = Session::default();
+
+        send(&mut session, &server, &[b"MULTI"]);
+        assert_eq!(
+            send(&mut session, &server, &[b"SET", b"a", b"1"]),
+            b"+QUEUED\r\n"
+        );
+        assert_eq!(
+            send(&mut session, &server, &[b"MULTI"]),
+            b"-ERR MULTI calls can not be nested\r\n"
+        );
+        assert_eq!(send(&mut session, &server, &[b"DISCARD"]), b"+OK\r\n");
+        assert!(server.db(0).lock(b"a").is_empty());
+        assert_eq!(
+            send(&mut session, &server, &[b"DISCARD"]),
+            b"-ERR DISCARD without MULTI\r\n"
+        );
+    }
+
+    #[test]
+    fn test_close_releases_subscriptions() {
+        let server = Server::default();
+        let mut session = Session::default();
+
+        assert_eq!(
+            send(&mut session, &server, &[b"SUBSCRIBE", b"a", b"b", b"a"]),
+            b"*3\r\n$9\r\nsubscribe\r\n$1\r\na\r\n:1\r\n\
+              *3\r\n$9\r\nsubscribe\r\n$1\r\nb\r\n:2\r\n\
+              *3\r\n$9\r\nsubscribe\r\n$1\r\na\r\n:2\r\n"
+        );
+        assert_eq!(server.pubsub.channel_count(), 2);
+
+        session.close(&server);
+        assert_eq!(server.pubsub.channel_count(), 0);
+    }
+
+    #[test]
+    fn test_subscription_counts_across_calls() {
+        let server = Server::default();
+        let mut session = Session::default();
+        let counts = |frames: Vec<RespValue>| -> Vec<(Vec<u8>, i64)> {
+            frames
+                .into_iter()
+                .map(|frame| match frame {
+                    RespValue::Array(parts) => match parts.as_slice() {
+                        [_, RespValue::BulkString(channel), RespValue::Integer(count)] => {
+                            (channel.clone(), *count)
+                        }
+                        _ => panic!("not a subscription reply: {:?}", parts),
+                    },
+                    frame => panic!("not a subscription reply: {:?}", frame),
+                })
+                .collect()
+        };
+        let mut request = |args: &[&[u8]]| {
+            let frame = RespValue::Array(
+                args.iter()
+                    .map(|arg| RespValue::BulkString(arg.to_vec()))
+                    .collect(),
+            );
+            counts(session.execute(frame, &server))
+        };
+
+        assert_eq!(request(&[b"SUBSCRIBE", b"a"]), vec![(b"a".to_vec(), 1)]);
+        assert_eq!(
+            request(&[b"SUBSCRIBE", b"b", b"c"]),
+            vec![(b"b".to_vec(), 2), (b"c".to_vec(), 3)]
+        );
+        assert_eq!(request(&[b"UNSUBSCRIBE", b"b"]), vec![(b"b".to_vec(), 2)]);
+        assert_eq!(request(&[b"SUBSCRIBE", b"b"]), vec![(b"b".to_vec(), 3)]);
+
+        let unsubscribed = request(&[b"UNSUBSCRIBE"]);
+        let remaining: Vec<i64> = unsubscribed.iter().map(|(_, count)| *count).collect();
+        assert_eq!(remaining, vec![2, 1, 0]);
+        let mut channels: Vec<Vec<u8>> = unsubscribed
+            .into_iter()
+            .map(|(channel, _)| channel)
+            .collect();
+        channels.sort();
+        assert_eq!(channels, vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+
+        // With nothing left, a single reply with no channel.
+        assert_eq!(
+            send(&mut session, &server, &[b"UNSUBSCRIBE"]),
+            b"*3\r\n$11\r\nunsubscribe\r\n$-1\r\n:0\r\n"
+        );
+        assert_eq!(server.pubsub.channel_count(), 0);
+    }
+
+    #[test]
+    fn test_resetstat_zeroes_command_and_key_counters() {
+        let server = Server::default();
+        let mut session = Session::default();
+        send(&mut session, &server, &[b"SET", b"a", b"1"]);
+        send(&mut session, &server, &[b"GET", b"a"]);
+        send(&mut session, &server, &[b"GET", b"missing"]);
+        send(&mut session, &server, &[b"SET", b"gone", b"v", b"PX", b"1"]);
+        std::thread::sleep(Duration::from_millis(5));
+        send(&mut session, &server, &[b"GET", b"gone"]);
+        send(&mut session, &server, &[b"NOSUCHCOMMAND"]);
+        Stats::incr(&server.stats.evicted_keys);
+
+        let info = |session: &mut Session| {
+            String::from_utf8(send(session, &server, &[b"INFO", b"all"])).unwrap()
+        };
+        let before = info(&mut session);
+        for line in [
+            "total_commands_processed:5\r\n",
+            "keyspace_hits:1\r\n",
+            "keyspace_misses:2\r\n",
+            "expired_keys:1\r\n",
+            "evicted_keys:1\r\n",
+            "cmdstat_get:calls=3,",
+            "cmdstat_set:calls=2,",
+        ] {
+            assert!(before.contains(line), "{line:?} missing from {before}");
+        }
+
+        send(&mut session, &server, &[b"CONFIG", b"RESETSTAT"]);
+        let after = info(&mut session);
+        for line in [
+            "total_commands_processed:1\r\n",
+            "keyspace_hits:0\r\n",
+            "keyspace_misses:0\r\n",
+            "expired_keys:0\r\n",
+            "evicted_keys:0\r\n",
+            "cmdstat_config:calls=1,",
+        ] {
+            assert!(after.contains(line), "{line:?} missing from {after}");
+        }
+        assert!(!after.contains("cmdstat_get"));
+        // The default report leaves out the per-command lines.
+        let default = String::from_utf8(send(&mut session, &server, &[b"INFO"])).unwrap();
+        assert!(!default.contains("cmdstat_"));
+    }
+
+    #[test]
+    fn test_channel_churn_leaves_nothing_behind() {
+        let server = Server::default();
+        let mut sessions: Vec<Session> = (0..8).map(|_| Session::default()).collect();
+        for round in 0..50 {
+            for (i, session) in sessions.iter_mut().enumerate() {
+                let channel = format!("channel:{}", (round + i) % 20);
+                send(session, &server, &[b"SUBSCRIBE", channel.as_bytes()]);
+                if round % 3 == 0 {
+                    send(session, &server, &[b"UNSUBSCRIBE"]);
+                }
+            }
+        }
+        assert!(server.pubsub.channel_count() > 0);
+        let listed = send(
+            &mut Session::default(),
+            &server,
+            &[b"PUBSUB", b"CHANNELS", b"channel:1*"],
+        );
+        assert_ne!(listed, b"*0\r\n");
+
+        for (i, session) in sessions.iter_mut().enumerate() {
+            if i % 2 == 0 {
+                send(session, &server, &[b"UNSUBSCRIBE"]);
+            } else {
+                session.close(&server);
+            }
+        }
+        assert_eq!(server.pubsub.channel_count(), 0);
+        assert_eq!(
+            send(&mut Session::default(), &server, &[b"PUBSUB", b"CHANNELS"]),
+            b"*0\r\n"
+        );
+    }
+
+    #[test]
+    fn test_ping_in_subscriber_mode() {
+        let server = Server::default();
+        let mut session = Session::default();
+        send(&mut session, &server, &[b"SUBSCRIBE", b"a"]);
+
+        assert_eq!(
+            send(&mut session, &server, &[b"PING"]),
+            b"*2\r\n$4\r\npong\r\n$0\r\n\r\n"
+        );
+        assert_eq!(
+            send(&mut session, &server, &[b"ping", b"hi"]),
+            b"*2\r\n$4\r\npong\r\n$2\r\nhi\r\n"
+        );
+
+        send(&mut session, &server, &[b"UNSUBSCRIBE"]);
+        assert_eq!(send(&mut session, &server, &[b"PING"]), b"+PONG\r\n");
+    }
+
+    #[test]
+    fn test_subscriber_mode_rejects_other_commands() {
+        let server = Server::default();
+        let mut session = Session::default();
+        send(&mut session, &server, &[b"SUBSCRIBE", b"a"]);
+
+        assert_eq!(
+            send(&mut session, &server, &[b"GET", b"key"]),
+            b"-ERR Can't execute 'get': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context\r\n"
+        );
+        assert_eq!(
+            send(&mut session, &server, &[b"NOPE"]),
+            b"-ERR unknown command `NOPE`\r\n"
+        );
+        assert_eq!(
+            send(&mut session, &server, &[b"SUBSCRIBE", b"b"]),
+            b"*3\r\n$9\r\nsubscribe\r\n$1\r\nb\r\n:2\r\n"
+        );
+
+        // RESP3 clients can keep running commands while subscribed.
+        let mut session = Session::default();
+        send(&mut session, &server, &[b"HELLO", b"3"]);
+        send(&mut session, &server, &[b"SUBSCRIBE", b"a"]);
+        assert_eq!(send(&mut session, &server, &[b"GET", b"key"]), b"$-1\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_reset_drops_queued_messages() {
+        let server = Server::default();
+        let mut session = Session::default();
+
+        send(&mut session, &server, &[b"SUBSCRIBE", b"news"]);
+        assert_eq!(server.pubsub.publish(b"news", b"stale"), 1);
+        assert_eq!(send(&mut session, &server, &[b"RESET"]), b"+RESET\r\n");
+        assert_eq!(server.pubsub.publish(b"news", b"late"), 0);
+
+        let next = tokio::time::timeout(Duration::from_millis(50), session.next_message());
+        assert!(next.await.is_err(), "message delivered after RESET");
+    }
+
+    #[test]
+    fn test_reset_clears_connection_state() {
+        let server = Server::default();
+        server.config.write().unwrap().requirepass = "secret".to_string();
+        let mut session = Session::default();
+
+        send(&mut session, &server, &[b"AUTH", b"secret"]);
+        send(&mut session, &server, &[b"SELECT", b"3"]);
+        send(&mut session, &server, &[b"CLIENT", b"SETNAME", b"worker"]);
+        send(&mut session, &server, &[b"HELLO", b"3"]);
+        send(&mut session, &server, &[b"MULTI"]);
+        send(&mut session, &server, &[b"SET", b"k", b"v"]);
+        assert_eq!(send(&mut session, &server, &[b"RESET"]), b"+RESET\r\n");
+
+        assert_eq!(
+            send(&mut session, &server, &[b"EXEC"]),
+            b"-NOAUTH Authentication required.\r\n"
+        );
+        send(&mut session, &server, &[b"AUTH", b"secret"]);
+        assert_eq!(
+            send(&mut session, &server, &[b"EXEC"]),
+            b"-ERR EXEC without MULTI\r\n"
+        );
+        assert_eq!(session.db_index, 0);
+        assert_eq!(session.protocol, 2);
+        assert_eq!(
+            send(&mut session, &server, &[b"CLIENT", b"GETNAME"]),
+            b"$0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_hello_negotiates_protocol() {
+        let server = Server::default();
+        let mut session = Session::default();
+
+        assert_eq!(
+            send(&mut session, &server, &[b"HELLO", b"4"]),
+            b"-NOPROTO unsupported protocol version\r\n"
+        );
+        assert_eq!(session.protocol, 2);
+
+        let reply = send(&mut session, &server, &[b"HELLO", b"2"]);
+        assert!(reply.starts_with(b"*12\r\n$6\r\nserver\r\n$5\r\nredis\r\n"));
+        let proto_2 = b"$5\r\nproto\r\n:2\r\n";
+        assert!(reply.windows(proto_2.len()).any(|w| w == proto_2));
+
+        let proto_3 = b"$5\r\nproto\r\n:3\r\n";
+        let reply = send(&mut session, &server, &[b"HELLO", b"3"]);
+        assert!(reply.starts_with(b"%6\r\n$6\r\nserver\r\n$5\r\nredis\r\n"));
+        assert!(reply.windows(proto_3.len()).any(|w| w == proto_3));
+        assert_eq!(session.protocol, 3);
+
+        // Without a version HELLO just reports what is in effect.
+        let reply = send(&mut session, &server, &[b"HELLO"]);
+        assert!(reply.starts_with(b"%6\r\n"));
+        assert!(reply.windows(proto_3.len()).any(|w| w == proto_3));
+    }
+
+    #[test]
+    fn test_flushall_clears_every_database() {
+        let server = Server::default();
+        let mut session = Session::default();
+        send(&mut session, &server, &[b"SET", b"zero", b"v"]);
+        send(&mut session, &server, &[b"SELECT", b"1"]);
+        send(&mut session, &server, &[b"RPUSH", b"one", b"v"]);
+        send(&mut session, &server, &[b"SELECT", b"0"]);
+
+        assert_eq!(
+            send(&mut session, &server, &[b"FLUSHALL", b"SYNC"]),
+            b"+OK\r\n"
+        );
+        assert_eq!(send(&mut session, &server, &[b"DBSIZE"]), b":0\r\n");
+        send(&mut session, &server, &[b"SELECT", b"1"]);
+        assert_eq!(send(&mut session, &server, &[b"DBSIZE"]), b":0\r\n");
+    }
+
+    #[test]
+    fn test_select_switches_database() {
+        let server = Server::default();
+        let mut session = Session::default();
+
+        send(&mut session, &server, &[b"SET", b"key", b"v"]);
+        assert_eq!(send(&mut session, &server, &[b"SELECT", b"1"]), b"+OK\r\n");
+        assert_eq!(send(&mut session, &server, &[b"GET", b"key"]), b"$-1\r\n");
+        assert_eq!(send(&mut session, &server, &[b"SELECT", b"0"]), b"+OK\r\n");
+        assert_eq!(
+            send(&mut session, &server, &[b"GET", b"key"]),
+            b"$1\r\nv\r\n"
+        );
+
+        for index in [&b"16"[..], b"-1"] {
+            assert_eq!(
+                send(&mut session, &server, &[b"SELECT", index]),
+                b"-ERR DB index is out of range\r\n"
+            );
+        }
+    }
+
+    #[test]
+    fn test_swapdb_is_visible_to_other_sessions() {
+        let server = Server::default();
+        let mut first = Session::default();
+        let mut second = Session::default();
+
+        send(&mut first, &server, &[b"SET", b"key", b"v"]);
+        send(&mut second, &server, &[b"SELECT", b"1"]);
+        assert_eq!(send(&mut second, &server, &[b"GET", b"key"]), b"$-1\r\n");
+
+        assert_eq!(
+            send(&mut first, &server, &[b"SWAPDB", b"0", b"1"]),
+            b"+OK\r\n"
+        );
+        assert_eq!(
+            send(&mut second, &server, &[b"GET", b"key"]),
+            b"$1\r\nv\r\n"
+        );
+        assert_eq!(send(&mut first, &server, &[b"GET", b"key"]), b"$-1\r\n");
+    }
+
+    #[test]
+    fn test_auth_unlocks_connection() {
+        let server = Server::default();
+        let mut session = Session::default();
+        assert_eq!(
+            send(&mut session, &server, &[b"AUTH", b"secret"]),
+            b"-ERR AUTH <password> called without any password configured for the default user. \
+              Are you sure your configuration is correct?\r\n"
+        );
+        server
+            .config
+            .write()
+            .unwrap()
+            .set("requirepass", "secret")
+            .unwrap();
+
+        assert_eq!(
+            send(&mut session, &server, &[b"SET", b"a", b"1"]),
+            b"-NOAUTH Authentication required.\r\n"
+        );
+        assert_eq!(send(&mut session, &server, &[b"PING"]), b"+PONG\r\n");
+        assert_eq!(
+            send(&mut session, &server, &[b"AUTH", b"wrong"]),
+            b"-WRONGPASS invalid username-password pair or user is disabled.\r\n"
+        );
+        assert_eq!(
+            send(&mut session, &server, &[b"AUTH", b"default", b"secret"]),
+            b"+OK\r\n"
+        );
+        assert_eq!(
+            send(&mut session, &server, &[b"SET", b"a", b"1"]),
+            b"+OK\r\n"
+        );
+        assert!(server.db(0).lock(b"a").contains_key(b"a".as_slice()));
+
+        // Each connection authenticates on its own.
+        let mut other = Session::default();
+        assert_eq!(
+            send(&mut other, &server, &[b"GET", b"a"]),
+            b"-NOAUTH Authentication required.\r\n"
+        );
+    }
+
+    #[test]
+    fn test_client_setname_getname() {
+        let server = Server::default();
+        let mut session = Session::default();
+        assert_eq!(
+            send(&mut session, &server, &[b"CLIENT", b"GETNAME"]),
+            b"$0\r\n\r\n"
+        );
+
+        assert_eq!(
+            send(&mut session, &server, &[b"CLIENT", b"SETNAME", b"worker-1"]),
+            b"+OK\r\n"
+        );
+        assert_eq!(
+            send(&mut session, &server, &[b"client", b"getname"]),
+            b"$8\r\nworker-1\r\n"
+        );
+        // The name belongs to the connection.
+        assert_eq!(
+            send(&mut Session::default(), &server, &[b"CLIENT", b"GETNAME"]),
+            b"$0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_client_setname_rejects_spaces() {
+        let server = Server::default();
+        let mut session = Session::default();
+        send(&mut session, &server, &[b"CLIENT", b"SETNAME", b"kept"]);
+
+        for name in [&b"two words"[..], b"line\nbreak"] {
+            assert_eq!(
+                send(&mut session, &server, &[b"CLIENT", b"SETNAME", name]),
+                &b"-ERR Client names cannot contain spaces, newlines or special characters.\r\n"[..]
+            );
+        }
+        assert_eq!(
+            send(&mut session, &server, &[b"CLIENT", b"GETNAME"]),
+            b"$4\r\nkept\r\n"
+        );
+        assert_eq!(
+            send(&mut session, &server, &[b"CLIENT", b"PAUSE"]),
+            b"-ERR unknown subcommand 'PAUSE'\r\n"
+        );
+    }
+
+    #[test]
+    fn test_client_kill_forms() {
+        let server = Server::default();
+        let registration = server
+            .clients
+            .register(SocketAddr::from(([127, 0, 0, 1], 6000)));
+        let mut session = Session::new(registration.id());
+
+        // The filter form spares the caller unless told otherwise.
+        assert_eq!(
+            send(
+                &mut session,
+                &server,
+                &[b"CLIENT", b"KILL", b"ADDR", b"127.0.0.1:6000"]
+            ),
+            b":0\r\n"
+        );
+        assert_eq!(
+            send(&mut session, &server, &[b"CLIENT", b"KILL", b"ID", b"0"]),
+            b"-ERR invalid argument: client-id should be greater than 0\r\n"
+        );
+        assert_eq!(
+            send(&mut session, &server, &[b"CLIENT", b"KILL", b"10.0.0.1:1"]),
+            b"-ERR No such client\r\n"
+        );
+        assert_eq!(
+            send(&mut session, &server, &[b"CLIENT", b"NO-EVICT", b"on"]),
+            b"+OK\r\n"
+        );
+        assert_eq!(
+            send(
+                &mut session,
+                &server,
+                &[b"CLIENT", b"KILL", b"127.0.0.1:6000"]
+            ),
+            b"+OK\r\n"
+        );
+        assert_eq!(server.clients.list(), "");
+    }
+
+    #[test]
+    fn test_blpop_with_an_element_answers_at_once() {
+        let server = Server::default();
+        let mut session = Session::default();
+        send(&mut session, &server, &[b"RPUSH", b"list", b"a", b"b"]);
+        send(&mut session, &server, &[b"SET", b"string", b"v"]);
+
+        assert_eq!(
+            send(
+                &mut session,
+                &server,
+                &[b"BRPOP", b"missing", b"list", b"0"]
+            ),
+            b"*2\r\n$4\r\nlist\r\n$1\r\nb\r\n"
+        );
+        assert_eq!(
+            send(&mut session, &server, &[b"BLPOP", b"string", b"0"]),
+            &b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"[..]
+        );
+        assert_eq!(
+            send(&mut session, &server, &[b"BLPOP", b"list", b"-1"]),
+            b"-ERR invalid argument: timeout is negative\r\n"
+        );
+
+        // Inside a transaction an empty list answers null instead of blocking.
+        send(&mut session, &server, &[b"MULTI"]);
+        send(&mut session, &server, &[b"BLPOP", b"list", b"0"]);
+        send(&mut session, &server, &[b"BLPOP", b"list", b"0"]);
+        assert_eq!(
+            send(&mut session, &server, &[b"EXEC"]),
+            b"*2\r\n*2\r\n$4\r\nlist\r\n$1\r\na\r\n*-1\r\n"
+        );
+        assert!(!session.is_blocked());
+    }
+}
@@ -0,0 +1,226 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+
+/// Redis's `zset-max-listpack-entries` and `zset-max-listpack-value`.
+const ZSET_MAX_LISTPACK_ENTRIES: usize = 128;
+const ZSET_MAX_LISTPACK_VALUE: usize = 64;
+
+/// Members with a score each, looked up by member and iterated in score order.
+/// Members with equal scores are ordered by their bytes, as in Redis.
+///
+/// Like Redis, a small set is kept as a flat list that every operation scans,
+/// and moves to a map plus an ordered index once it outgrows the listpack
+/// limits. It never moves back, even if it shrinks.
+#[derive(Debug, Clone)]
+pub struct SortedSet {
+    encoding: Encoding,
+}
+
+#[derive(Debug, Clone)]
+enum Encoding {
+    /// Members in the order they were first added.
+    Listpack(Vec<(Vec<u8>, f64)>),
+    Skiplist {
+        scores: HashMap<Vec<u8>, f64>,
+        ordered: BTreeSet<(Score, Vec<u8>)>,
+    },
+}
+
+impl Default for SortedSet {
+    fn default() -> Self {
+        SortedSet {
+            encoding: Encoding::Listpack(Vec::new()),
+        }
+    }
+}
+
+/// Sets are equal when they hold the same members and scores, whatever their
+/// encoding.
+impl PartialEq for SortedSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+/// A score that can key the ordered index. Scores are never NaN.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Score(f64);
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl SortedSet {
+    /// Sets `member`'s score, returning true if it wasn't a member before.
+    pub fn insert(&mut self, member: Vec<u8>, score: f64) -> bool {
+        // `total_cmp` tells -0 from 0, which Redis treats as the same score.
+        let score = score + 0.0;
+        match &mut self.encoding {
+            Encoding::Listpack(pairs) => {
+                if let Some((_, old)) = pairs.iter_mut().find(|(existing, _)| *existing == member) {
+                    *old = score;
+                    return false;
+                }
+                let too_long = member.len() > ZSET_MAX_LISTPACK_VALUE;
+                pairs.push((member, score));
+                if too_long || pairs.len() > ZSET_MAX_LISTPACK_ENTRIES {
+                    self.convert_to_skiplist();
+                }
+                true
+            }
+            Encoding::Skiplist { scores, ordered } => match scores.insert(member.clone(), score) {
+                Some(old) => {
+                    ordered.remove(&(Score(old), member.clone()));
+                    ordered.insert((Score(score), member));
+                    false
+                }
+                None => {
+                    ordered.insert((Score(score), member));
+                    true
+                }
+            },
+        }
+    }
+
+    fn convert_to_skiplist(&mut self) {
+        let Encoding::Listpack(pairs) = &mut self.encoding else {
+            return;
+        };
+        let pairs = std::mem::take(pairs);
+        self.encoding = Encoding::Skiplist {
+            ordered: pairs
+                .iter()
+                .map(|(member, score)| (Score(*score), member.clone()))
+                .collect(),
+            scores: pairs.into_iter().collect(),
+        };
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        match &self.encoding {
+            Encoding::Listpack(pairs) => pairs
+                .iter()
+                .find(|(existing, _)| existing == member)
+                .map(|(_, score)| *score),
+            Encoding::Skiplist { scores, .. } => scores.get(member).copied(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.encoding {
+            Encoding::Listpack(pairs) => pairs.len(),
+            Encoding::Skiplist { scores, .. } => scores.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// What OBJECT ENCODING reports.
+    pub fn encoding(&self) -> &'static str {
+        match self.encoding {
+            Encoding::Listpack(_) => "listpack",
+            Encoding::Skiplist { .. } => "skiplist",
+        }
+    }
+
+    /// Members and scores from the lowest score up. A listpack is sorted on
+    /// every call, which its size limit keeps cheap.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&Vec<u8>, f64)> + '_> {
+        match &self.encoding {
+            Encoding::Listpack(pairs) => {
+                let mut sorted: Vec<(&Vec<u8>, f64)> = pairs
+                    .iter()
+                    .map(|(member, score)| (member, *score))
+                    .collect();
+                sorted.sort_by(|(a, a_score), (b, b_score)| {
+                    a_score.total_cmp(b_score).then_with(|| a.cmp(b))
+                });
+                Box::new(sorted.into_iter())
+            }
+            Encoding::Skiplist { ordered, .. } => {
+                Box::new(ordered.iter().map(|(score, member)| (member, score.0)))
+            }
+        }
+    }
+}
+
+impl FromIterator<(Vec<u8>, f64)> for SortedSet {
+    fn from_iter<I: IntoIterator<Item = (Vec<u8>, f64)>>(pairs: I) -> Self {
+        let mut set = SortedSet::default();
+        for (member, score) in pairs {
+            set.insert(member, score);
+        }
+        set
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_orders_by_score_then_member() {
+        let mut set: SortedSet = [
+            (b"b".to_vec(), 1.0),
+            (b"a".to_vec(), 1.0),
+            (b"c".to_vec(), -2.5),
+        ]
+        .into_iter()
+        .collect();
+        assert!(!set.insert(b"c".to_vec(), 5.0));
+        assert!(set.insert(b"d".to_vec(), f64::NEG_INFINITY));
+
+        let members: Vec<_> = set
+            .iter()
+            .map(|(member, score)| (member.clone(), score))
+            .collect();
+        assert_eq!(
+            members,
+            vec![
+                (b"d".to_vec(), f64::NEG_INFINITY),
+                (b"a".to_vec(), 1.0),
+                (b"b".to_vec(), 1.0),
+                (b"c".to_vec(), 5.0),
+            ]
+        );
+        assert_eq!(set.len(), 4);
+        assert_eq!(set.score(b"c"), Some(5.0));
+    }
+
+    #[test]
+    fn test_outgrowing_listpack_keeps_contents() {
+        let mut set = SortedSet::default();
+        for i in 0..ZSET_MAX_LISTPACK_ENTRIES {
+            set.insert(format!("m{i}").into_bytes(), (i % 7) as f64);
+        }
+        assert_eq!(set.encoding(), "listpack");
+        let listpack = set.clone();
+
+        set.insert(b"m0".to_vec(), 0.0);
+        assert_eq!(set.encoding(), "listpack");
+        set.insert(b"extra".to_vec(), 100.0);
+        assert_eq!(set.encoding(), "skiplist");
+        assert_eq!(set.len(), ZSET_MAX_LISTPACK_ENTRIES + 1);
+        assert!(set
+            .iter()
+            .take(ZSET_MAX_LISTPACK_ENTRIES)
+            .eq(listpack.iter()));
+        assert_eq!(set.score(b"m9"), listpack.score(b"m9"));
+
+        let mut long = SortedSet::default();
+        long.insert(vec![b'x'; ZSET_MAX_LISTPACK_VALUE + 1], 1.0);
+        assert_eq!(long.encoding(), "skiplist");
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::db::unpoisoned;
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    /// Connections closed by the peer while a partial frame was still buffered.
+    pub incomplete_on_close: AtomicU64,
+    /// Connections dropped by the accept rate limiter.
+    pub rejected_connections: AtomicU64,
+    pub keyspace_hits: AtomicU64,
+    pub keyspace_misses: AtomicU64,
+    /// Keys removed to stay under maxmemory.
+    pub evicted_keys: AtomicU64,
+    pub total_commands_processed: AtomicU64,
+    /// Calls and time spent per command, by lowercase name.
+    commands: Mutex<HashMap<Vec<u8>, CommandStat>>,
+    /// Connections currently being served. A gauge, so RESETSTAT leaves it alone.
+    pub connected_clients: AtomicU64,
+}
+
+/// One command's line in INFO commandstats.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CommandStat {
+    pub calls: u64,
+    pub usec: u64,
+}
+
+impl Stats {
+    pub fn incr(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn decr(counter: &AtomicU64) {
+        counter.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(counter: &AtomicU64) -> u64 {
+        counter.load(Ordering::Relaxed)
+    }
+
+    /// Zeroes every counter, as CONFIG RESETSTAT does.
+    pub fn reset(&self) {
+        for counter in [
+            &self.incomplete_on_close,
+            &self.rejected_connections,
+            &self.keyspace_hits,
+            &self.keyspace_misses,
+            &self.evicted_keys,
+            &self.total_commands_processed,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        unpoisoned(self.commands.lock()).clear();
+    }
+
+    /// Counts one call of the command `name`, which took `elapsed`.
+    pub fn record_command(&self, name: &[u8], elapsed: Duration) {
+        Self::incr(&self.total_commands_processed);
+        let mut commands = unpoisoned(self.commands.lock());
+        let stat = commands.entry(name.to_ascii_lowercase()).or_default();
+        stat.calls += 1;
+        stat.usec += elapsed.as_micros() as u64;
+    }
+
+    /// Every command called since the last reset, by name.
+    pub fn command_stats(&self) -> Vec<(Vec<u8>, CommandStat)> {
+        let mut stats: Vec<_> = unpoisoned(self.commands.lock())
+            .iter()
+            .map(|(name, stat)| (name.clone(), *stat))
+            .collect();
+        stats.sort_by(|(a, _), (b, _)| a.cmp(b));
+        stats
+    }
+
+    /// Records a read lookup as a keyspace hit or miss.
+    pub fn record_lookup(&self, hit: bool) {
+        if hit {
+            Self::incr(&self.keyspace_hits);
+        } else {
+            Self::incr(&self.keyspace_misses);
+        }
+    }
+}
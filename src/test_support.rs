@@ -0,0 +1,31 @@
+//! Fixtures shared by the test modules.
+
+use std::path::PathBuf;
+
+use crate::parser::RespValue;
+use crate::server::Server;
+use crate::session::Session;
+
+/// A request of bulk string arguments, the way clients send them.
+pub fn command(args: &[&[u8]]) -> RespValue {
+    RespValue::Array(
+        args.iter()
+            .map(|arg| RespValue::BulkString(arg.to_vec()))
+            .collect(),
+    )
+}
+
+/// Runs one request and returns every reply frame, encoded back to back.
+pub fn send(session: &mut Session, server: &Server, args: &[&[u8]]) -> Vec<u8> {
+    session
+        .execute(command(args), server)
+        .iter()
+        .flat_map(RespValue::encode)
+        .collect()
+}
+
+/// A file in the temp directory for the test `name`, unique to this process so
+/// concurrent runs don't share it.
+pub fn temp_path(name: &str, extension: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("{}-{}.{}", name, std::process::id(), extension))
+}